@@ -1,6 +1,7 @@
 use harvex_api::{build_router, state::AppState};
 use harvex_config::Settings;
 use harvex_db::DbPool;
+use harvex_services::ingest::{spawn_folder_watchers, spawn_mailbox_pollers};
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
@@ -20,6 +21,15 @@ async fn main() -> anyhow::Result<()> {
 
     let db = DbPool::new(&config.database.path)?;
     let state = AppState::new(config.clone(), db);
+
+    // Pick up batches interrupted by a previous shutdown
+    let resumed = state.pipeline.resume_interrupted()?;
+    if resumed > 0 {
+        info!("Resumed {} interrupted batches", resumed);
+    }
+    spawn_mailbox_pollers(&state.db, &state.pipeline, &config);
+    spawn_folder_watchers(&state.db, &state.pipeline, &config);
+
     let app = build_router(state);
 
     let addr = format!("{}:{}", config.server.host, config.server.port);
@@ -9,15 +9,21 @@ use serde_json::{json, Value};
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
 
+use super::document::{queue_error, ReprocessRequest};
 use crate::error::ApiError;
 use crate::state::AppState;
-use harvex_services::{BatchDao, DocumentDao, ExtractionDao};
+use harvex_config::OcrMode;
+use harvex_services::{BatchDao, BatchFinished, DocumentDao, ExtractionDao, JobDao};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/batch", get(list_batches).post(create_batch))
         .route("/batch/{id}", get(get_batch).delete(delete_batch))
         .route("/batch/{id}/process", post(process_batch))
+        .route("/batch/{id}/cancel", post(cancel_batch))
+        .route("/batch/{id}/pause", post(pause_batch))
+        .route("/batch/{id}/resume", post(resume_batch))
+        .route("/batch/{id}/retry-failed", post(retry_failed))
         .route("/batch/{id}/progress", get(batch_progress))
 }
 
@@ -25,16 +31,31 @@ pub fn routes() -> Router<AppState> {
 struct CreateBatchRequest {
     name: String,
     model_name: Option<String>,
+    /// auto | hybrid; omitted uses the configured OCR mode.
+    ocr_mode: Option<String>,
 }
 
 async fn create_batch(
     State(state): State<AppState>,
     Json(body): Json<CreateBatchRequest>,
 ) -> Result<Json<Value>, ApiError> {
-    let batch = BatchDao::create(&state.db, &body.name, body.model_name.as_deref())?;
+    let ocr_mode = parse_ocr_mode(body.ocr_mode.as_deref())?;
+    let mut batch = BatchDao::create(&state.db, &body.name, body.model_name.as_deref())?;
+    if let Some(mode) = ocr_mode {
+        BatchDao::set_ocr_mode(&state.db, &batch.id, Some(mode.as_str()))?;
+        batch = BatchDao::get_by_id(&state.db, &batch.id)?;
+    }
     Ok(Json(serde_json::to_value(batch).unwrap()))
 }
 
+/// Validate a batch's requested OCR mode. Empty means the configured one.
+pub(crate) fn parse_ocr_mode(mode: Option<&str>) -> Result<Option<OcrMode>, ApiError> {
+    match mode.map(str::trim).filter(|m| !m.is_empty()) {
+        Some(mode) => mode.parse().map(Some).map_err(ApiError::BadRequest),
+        None => Ok(None),
+    }
+}
+
 async fn list_batches(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
     let batches = BatchDao::list(&state.db)?;
     Ok(Json(json!(batches)))
@@ -50,6 +71,9 @@ async fn get_batch(
 }
 
 /// Start processing a batch. Returns immediately; processing runs in background.
+///
+/// A batch interrupted by a restart is resumed automatically at startup;
+/// calling this on such a batch before that happens also resumes it.
 async fn process_batch(
     State(state): State<AppState>,
     Path(id): Path<String>,
@@ -58,19 +82,10 @@ async fn process_batch(
     let batch = BatchDao::get_by_id(&state.db, &id)
         .map_err(|_| ApiError::NotFound(format!("Batch {id} not found")))?;
 
-    if batch.status == "processing" {
-        // Reset stuck batch (e.g. pod restarted mid-processing)
-        tracing::warn!("Resetting stuck batch {} from 'processing' to 'pending'", id);
-        BatchDao::update_status(&state.db, &id, "pending")
-            .map_err(|e| ApiError::Internal(format!("Failed to reset batch: {e}")))?;
-        // Also reset any stuck documents
-        let docs = DocumentDao::list_by_batch(&state.db, &id)
-            .map_err(|e| ApiError::Internal(e.to_string()))?;
-        for doc in &docs {
-            if doc.status == "processing" {
-                let _ = DocumentDao::update_status(&state.db, &doc.id, "pending", None);
-            }
-        }
+    if state.pipeline.is_running(&batch.id) {
+        return Err(ApiError::BadRequest(format!(
+            "Batch {id} is already being processed"
+        )));
     }
 
     // Spawn processing in background
@@ -89,6 +104,115 @@ async fn process_batch(
     })))
 }
 
+/// Cancel a batch. In-flight documents are aborted, queued ones are dropped.
+/// A batch that has already finished is a conflict.
+async fn cancel_batch(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    BatchDao::get_by_id(&state.db, &id)
+        .map_err(|_| ApiError::NotFound(format!("Batch {id} not found")))?;
+
+    let was_running = state
+        .pipeline
+        .cancel_batch(&id)
+        .map_err(|e| match e.downcast_ref::<BatchFinished>() {
+            Some(finished) => ApiError::Conflict(finished.to_string()),
+            None => ApiError::Internal(format!("Failed to cancel batch: {e}")),
+        })?;
+
+    Ok(Json(json!({
+        "status": if was_running { "cancelling" } else { "cancelled" },
+        "batch_id": id,
+        "message": "Batch cancellation requested",
+    })))
+}
+
+/// Pause a running batch. Documents already being processed are allowed to finish.
+async fn pause_batch(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    BatchDao::get_by_id(&state.db, &id)
+        .map_err(|_| ApiError::NotFound(format!("Batch {id} not found")))?;
+
+    if !state.pipeline.pause_batch(&id) {
+        return Err(ApiError::BadRequest(format!(
+            "Batch {id} is not being processed"
+        )));
+    }
+
+    Ok(Json(json!({
+        "status": "pausing",
+        "batch_id": id,
+        "message": "Batch will pause once in-flight documents finish",
+    })))
+}
+
+/// Resume a paused batch from where it stopped.
+async fn resume_batch(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    BatchDao::get_by_id(&state.db, &id)
+        .map_err(|_| ApiError::NotFound(format!("Batch {id} not found")))?;
+
+    let resumed = state
+        .pipeline
+        .resume_batch(&id)
+        .map_err(|e| ApiError::Internal(format!("Failed to resume batch: {e}")))?;
+
+    if !resumed {
+        return Err(ApiError::BadRequest(format!("Batch {id} is not paused")));
+    }
+
+    Ok(Json(json!({
+        "status": "processing",
+        "batch_id": id,
+        "message": "Batch processing resumed",
+    })))
+}
+
+/// Queue every failed document of a batch for another attempt, including
+/// documents whose LLM step failed (`extracted_only`).
+///
+/// Takes the same optional model and document type overrides as reprocessing
+/// a single document. In a paused batch the documents wait for the resume.
+async fn retry_failed(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    body: Option<Json<ReprocessRequest>>,
+) -> Result<Json<Value>, ApiError> {
+    let batch = BatchDao::get_by_id(&state.db, &id)
+        .map_err(|_| ApiError::NotFound(format!("Batch {id} not found")))?;
+    let Json(body) = body.unwrap_or_default();
+    let options = body.into_options(&state)?;
+
+    let failed: Vec<String> = DocumentDao::list_by_batch(&state.db, &id)?
+        .into_iter()
+        .filter(|doc| doc.status == "failed" || doc.status == "extracted_only")
+        .map(|doc| doc.id)
+        .collect();
+
+    let jobs = state
+        .pipeline
+        .reprocess_documents(&id, &failed, &options)
+        .map_err(queue_error)?;
+
+    let status = if jobs.is_empty() {
+        "idle"
+    } else if batch.status == "paused" {
+        "paused"
+    } else {
+        "processing"
+    };
+    Ok(Json(json!({
+        "status": status,
+        "batch_id": id,
+        "queued": jobs.len(),
+    })))
+}
+
 /// SSE endpoint for batch processing progress.
 async fn batch_progress(
     State(state): State<AppState>,
@@ -119,9 +243,17 @@ async fn delete_batch(
     let batch = BatchDao::get_by_id(&state.db, &id)
         .map_err(|_| ApiError::NotFound(format!("Batch {id} not found")))?;
 
-    // Allow deleting stuck "processing" batches (e.g. orphaned after pod restart)
+    // Stop a running worker first so it doesn't write to rows being deleted
+    state
+        .pipeline
+        .cancel_and_wait(&batch.id)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to cancel batch: {e}")))?;
+
+    // Cascade: delete jobs + extractions → delete documents (get file paths) → delete files → delete batch
+    JobDao::delete_by_batch(&state.db, &id)
+        .map_err(|e| ApiError::Internal(format!("Failed to delete jobs: {e}")))?;
 
-    // Cascade: delete extractions → delete documents (get file paths) → delete files → delete batch
     ExtractionDao::delete_by_batch(&state.db, &id)
         .map_err(|e| ApiError::Internal(format!("Failed to delete extractions: {e}")))?;
 
@@ -1,25 +1,74 @@
 use axum::extract::{Multipart, Path, Query, State};
+use axum::http::header;
+use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use axum::Json;
 use axum::Router;
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::path::PathBuf;
 
+use super::batch::parse_ocr_mode;
 use crate::error::ApiError;
 use crate::state::AppState;
-use harvex_services::{BatchDao, DocumentDao};
+use harvex_services::{archive, ingest};
+use harvex_services::pipeline::email::{self, ParsedEmail};
+use harvex_services::pipeline::{image_prep, FileType};
+use harvex_services::{
+    BatchDao, DocumentBusy, DocumentDao, DocumentOptions, DocumentTypeCatalog, ExtractionDao,
+    JobDao,
+};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/document/upload", post(upload_documents))
         .route("/document", get(list_documents))
         .route("/document/{id}", get(get_document).delete(delete_document))
+        .route("/document/{id}/reprocess", post(reprocess_document))
+        .route("/document/{id}/preview", get(preview_document))
 }
 
 #[derive(Deserialize)]
 struct ListDocumentsQuery {
     batch_id: String,
+    status: Option<String>,
+}
+
+/// Overrides for reprocessing documents.
+#[derive(Deserialize, Default)]
+pub(crate) struct ReprocessRequest {
+    model_name: Option<String>,
+    document_type: Option<String>,
+}
+
+impl ReprocessRequest {
+    /// Validate the document type against the catalog. An empty model name
+    /// means the configured one.
+    pub(crate) fn into_options(self, state: &AppState) -> Result<DocumentOptions, ApiError> {
+        if let Some(document_type) = &self.document_type {
+            let catalog = DocumentTypeCatalog::load(&state.db)?;
+            if catalog.get(document_type).is_none() {
+                return Err(ApiError::BadRequest(format!(
+                    "Unknown document type '{document_type}', expected one of: {}",
+                    catalog.names().join(", ")
+                )));
+            }
+        }
+
+        Ok(DocumentOptions {
+            model_name: self.model_name.filter(|m| !m.is_empty()),
+            document_type: self.document_type,
+            ..Default::default()
+        })
+    }
+}
+
+/// Map a failure to queue documents: a document already being processed
+/// with other overrides is a conflict.
+pub(crate) fn queue_error(e: anyhow::Error) -> ApiError {
+    match e.downcast_ref::<DocumentBusy>() {
+        Some(busy) => ApiError::Conflict(busy.to_string()),
+        None => ApiError::Internal(format!("Failed to queue documents: {e}")),
+    }
 }
 
 async fn upload_documents(
@@ -31,7 +80,8 @@ async fn upload_documents(
 
     let mut batch_name: Option<String> = None;
     let mut model_name: Option<String> = None;
-    let mut files: Vec<(String, String, Vec<u8>)> = Vec::new();
+    let mut ocr_mode: Option<String> = None;
+    let mut files: Vec<UploadedFile> = Vec::new();
 
     while let Some(field) = multipart
         .next_field()
@@ -57,6 +107,14 @@ async fn upload_documents(
                         .map_err(|e| ApiError::BadRequest(e.to_string()))?,
                 );
             }
+            "ocr_mode" => {
+                ocr_mode = Some(
+                    field
+                        .text()
+                        .await
+                        .map_err(|e| ApiError::BadRequest(e.to_string()))?,
+                );
+            }
             "files" | "files[]" => {
                 let file_name = field
                     .file_name()
@@ -78,7 +136,58 @@ async fn upload_documents(
                     )));
                 }
 
-                files.push((file_name, content_type, data.to_vec()));
+                let received = if archive::is_archive(&file_name, &content_type) {
+                    // An archive becomes one document per file in it
+                    let settings = state.config.storage.archive.clone();
+                    let entries = tokio::task::spawn_blocking(move || {
+                        archive::expand_zip(&file_name, &data, &settings)
+                    })
+                    .await
+                    .map_err(|e| ApiError::Internal(e.to_string()))?
+                    .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+                    entries
+                        .into_iter()
+                        .map(|entry| (entry.name, entry.content_type, entry.data))
+                        .collect()
+                } else {
+                    vec![(file_name, content_type, data.to_vec())]
+                };
+
+                for (name, content_type, data) in received {
+                    // An email's attachments become documents of their own.
+                    // Parsing a large email is blocking work, so the data is
+                    // handed to the blocking pool and back
+                    let (data, email) = if FileType::detect(&name, &content_type) == FileType::Email {
+                        let (data, parsed) = tokio::task::spawn_blocking(move || {
+                            let parsed = email::parse(&data);
+                            (data, parsed)
+                        })
+                        .await
+                        .map_err(|e| ApiError::Internal(e.to_string()))?;
+                        let parsed =
+                            parsed.map_err(|e| ApiError::BadRequest(format!("{name}: {e}")))?;
+                        (data, Some(parsed))
+                    } else {
+                        (data, None)
+                    };
+                    let attachments = email.iter().flat_map(|e| &e.attachments);
+                    let sizes = std::iter::once((&name, data.len()))
+                        .chain(attachments.map(|a| (&a.name, a.data.len())));
+                    for (name, size) in sizes {
+                        if size as u64 > max_size {
+                            return Err(ApiError::BadRequest(format!(
+                                "File {} exceeds max size of {} MB",
+                                name, state.config.storage.max_file_size_mb
+                            )));
+                        }
+                    }
+                    files.push(UploadedFile {
+                        name,
+                        content_type,
+                        data,
+                        email,
+                    });
+                }
             }
             _ => {}
         }
@@ -87,6 +196,7 @@ async fn upload_documents(
     if files.is_empty() {
         return Err(ApiError::BadRequest("No files provided".to_string()));
     }
+    let ocr_mode = parse_ocr_mode(ocr_mode.as_deref())?;
 
     let batch_name = batch_name.unwrap_or_else(|| {
         format!(
@@ -96,28 +206,45 @@ async fn upload_documents(
     });
 
     // Create batch
-    let batch = BatchDao::create(&state.db, &batch_name, model_name.as_deref())?;
-    let batch_dir = PathBuf::from(upload_dir).join(&batch.id);
-    std::fs::create_dir_all(&batch_dir)?;
-
-    let mut documents = Vec::new();
-
-    for (original_name, content_type, data) in &files {
-        let stored_name = format!("{}_{}", nanoid::nanoid!(10), original_name);
-        let file_path = batch_dir.join(&stored_name);
-        std::fs::write(&file_path, data)?;
-
-        let doc = DocumentDao::create(
-            &state.db,
-            &batch.id,
-            &stored_name,
-            original_name,
-            content_type,
-            data.len() as i64,
-            file_path.to_str().unwrap_or(""),
-        )?;
-        documents.push(doc);
+    let mut batch = BatchDao::create(&state.db, &batch_name, model_name.as_deref())?;
+    if let Some(mode) = ocr_mode {
+        BatchDao::set_ocr_mode(&state.db, &batch.id, Some(mode.as_str()))?;
+        batch = BatchDao::get_by_id(&state.db, &batch.id)?;
     }
+    // Writing files, an email's attachments among them, is blocking I/O
+    let db = state.db.clone();
+    let upload_dir = std::path::PathBuf::from(upload_dir);
+    let batch_id = batch.id.clone();
+    let documents = tokio::task::spawn_blocking(move || -> Result<Vec<_>, anyhow::Error> {
+        let mut documents = Vec::new();
+        for file in &files {
+            let stored = match &file.email {
+                Some(email) => ingest::store_email(
+                    &db,
+                    &upload_dir,
+                    &batch_id,
+                    &file.name,
+                    &file.content_type,
+                    &file.data,
+                    email,
+                ),
+                None => ingest::store_document(
+                    &db,
+                    &upload_dir,
+                    &batch_id,
+                    &file.name,
+                    &file.content_type,
+                    &file.data,
+                )
+                .map(|doc| vec![doc]),
+            };
+            documents.extend(stored?);
+        }
+        Ok(documents)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(e.to_string()))?
+    .map_err(|e| ApiError::Internal(e.to_string()))?;
 
     BatchDao::set_total_files(&state.db, &batch.id, documents.len() as i32)?;
 
@@ -127,11 +254,24 @@ async fn upload_documents(
     })))
 }
 
+/// A file to store as a document.
+struct UploadedFile {
+    name: String,
+    content_type: String,
+    data: Vec<u8>,
+    /// The parsed email, when the file is one; its attachments are stored as
+    /// child documents.
+    email: Option<ParsedEmail>,
+}
+
 async fn list_documents(
     State(state): State<AppState>,
     Query(query): Query<ListDocumentsQuery>,
 ) -> Result<Json<Value>, ApiError> {
-    let docs = DocumentDao::list_by_batch(&state.db, &query.batch_id)?;
+    let mut docs = DocumentDao::list_by_batch(&state.db, &query.batch_id)?;
+    if let Some(status) = &query.status {
+        docs.retain(|doc| &doc.status == status);
+    }
     Ok(Json(json!(docs)))
 }
 
@@ -152,11 +292,81 @@ async fn delete_document(
     let doc = DocumentDao::get_by_id(&state.db, &id)
         .map_err(|_| ApiError::NotFound(format!("Document {id} not found")))?;
 
-    // Delete file from disk
-    let _ = std::fs::remove_file(&doc.file_path);
+    // Documents taken from it (an email's attachments) go with it
+    let mut docs = vec![doc];
+    let mut next = 0;
+    while next < docs.len() {
+        let children = DocumentDao::list_children(&state.db, &docs[next].id)?;
+        docs.extend(children);
+        next += 1;
+    }
+
+    // A worker processing one of them would write to rows deleted here
+    for doc in &docs {
+        if JobDao::open_for_document(&state.db, &doc.id)?.is_some_and(|job| job.status == "leased") {
+            return Err(ApiError::Conflict(format!(
+                "Document {} is being processed; delete it once it has finished",
+                doc.id
+            )));
+        }
+    }
+
+    for doc in &docs {
+        // Delete file from disk, with its prepared image if there is one
+        let _ = std::fs::remove_file(&doc.file_path);
+        let _ = std::fs::remove_file(image_prep::processed_path(std::path::Path::new(&doc.file_path)));
+
+        // Delete from DB: jobs + extractions → document
+        JobDao::delete_by_document(&state.db, &doc.id)?;
+        ExtractionDao::delete_by_document(&state.db, &doc.id)?;
+        DocumentDao::delete(&state.db, &doc.id)?;
+    }
 
-    // Delete from DB
-    DocumentDao::delete(&state.db, &id)?;
+    let batch_id = &docs[0].batch_id;
+    let total = DocumentDao::list_by_batch(&state.db, batch_id)?.len();
+    BatchDao::set_total_files(&state.db, batch_id, total as i32)?;
+    BatchDao::recount_progress(&state.db, batch_id)?;
 
     Ok(Json(json!({"deleted": true, "id": id})))
 }
+
+/// The image as it was sent to the vision model (oriented, cropped,
+/// downscaled and straightened). Available once an image document has been
+/// sent to the vision model.
+async fn preview_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let doc = DocumentDao::get_by_id(&state.db, &id)
+        .map_err(|_| ApiError::NotFound(format!("Document {id} not found")))?;
+
+    let path = image_prep::processed_path(std::path::Path::new(&doc.file_path));
+    let data = std::fs::read(&path)
+        .map_err(|_| ApiError::NotFound(format!("No prepared image for document {id}")))?;
+
+    Ok(([(header::CONTENT_TYPE, "image/jpeg")], data))
+}
+
+/// Re-run extraction for a single document, optionally with another model or
+/// a forced document type. The new extraction supersedes the previous one.
+async fn reprocess_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    body: Option<Json<ReprocessRequest>>,
+) -> Result<Json<Value>, ApiError> {
+    let doc = DocumentDao::get_by_id(&state.db, &id)
+        .map_err(|_| ApiError::NotFound(format!("Document {id} not found")))?;
+    let Json(body) = body.unwrap_or_default();
+    let options = body.into_options(&state)?;
+
+    let jobs = state
+        .pipeline
+        .reprocess_documents(&doc.batch_id, std::slice::from_ref(&doc.id), &options)
+        .map_err(queue_error)?;
+
+    Ok(Json(json!({
+        "status": "queued",
+        "document_id": doc.id,
+        "job_id": jobs.first().map(|job| job.id.clone()),
+    })))
+}
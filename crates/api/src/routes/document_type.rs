@@ -0,0 +1,144 @@
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::Json;
+use axum::Router;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::ApiError;
+use crate::state::AppState;
+use harvex_services::doctypes::{validate_definition, DEFAULT_PRIORITY};
+use harvex_services::{DocumentTypeCatalog, DocumentTypeDao};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/document-type", get(list_document_types).post(create_document_type))
+        .route(
+            "/document-type/{name}",
+            get(get_document_type)
+                .put(update_document_type)
+                .delete(delete_document_type),
+        )
+}
+
+#[derive(Deserialize)]
+struct CreateDocumentTypeRequest {
+    name: String,
+    label: String,
+    instructions: Option<String>,
+    json_schema: Value,
+    #[serde(default)]
+    keywords: Vec<String>,
+    priority: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct UpdateDocumentTypeRequest {
+    label: String,
+    instructions: Option<String>,
+    json_schema: Value,
+    #[serde(default)]
+    keywords: Vec<String>,
+    priority: Option<i32>,
+}
+
+/// List all document types, built-in and user-defined.
+async fn list_document_types(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    let catalog = DocumentTypeCatalog::load(&state.db)?;
+    Ok(Json(json!(catalog.list())))
+}
+
+async fn get_document_type(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let catalog = DocumentTypeCatalog::load(&state.db)?;
+    let doc_type = catalog
+        .get(&name)
+        .ok_or_else(|| ApiError::NotFound(format!("Document type {name} not found")))?;
+    Ok(Json(json!(doc_type)))
+}
+
+/// Create a document type. Using the name of a built-in type overrides it.
+async fn create_document_type(
+    State(state): State<AppState>,
+    Json(body): Json<CreateDocumentTypeRequest>,
+) -> Result<Json<Value>, ApiError> {
+    validate_definition(&body.name, &body.label, &body.json_schema, &body.keywords)
+        .map_err(ApiError::BadRequest)?;
+
+    if DocumentTypeDao::get_by_name(&state.db, &body.name)?.is_some() {
+        return Err(ApiError::BadRequest(format!(
+            "Document type {} already exists",
+            body.name
+        )));
+    }
+
+    let doc_type = DocumentTypeDao::create(
+        &state.db,
+        &body.name,
+        body.label.trim(),
+        body.instructions.as_deref(),
+        &body.json_schema,
+        &body.keywords,
+        body.priority.unwrap_or(DEFAULT_PRIORITY),
+    )?;
+    Ok(Json(serde_json::to_value(doc_type).unwrap()))
+}
+
+/// Update a user-defined document type. Updating a built-in type stores an
+/// override for it.
+async fn update_document_type(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(body): Json<UpdateDocumentTypeRequest>,
+) -> Result<Json<Value>, ApiError> {
+    validate_definition(&name, &body.label, &body.json_schema, &body.keywords)
+        .map_err(ApiError::BadRequest)?;
+
+    let doc_type = match DocumentTypeDao::get_by_name(&state.db, &name)? {
+        Some(existing) => DocumentTypeDao::update(
+            &state.db,
+            &existing.id,
+            body.label.trim(),
+            body.instructions.as_deref(),
+            &body.json_schema,
+            &body.keywords,
+            body.priority.unwrap_or(existing.priority),
+        )?,
+        None => {
+            let builtin = DocumentTypeCatalog::builtin();
+            let Some(original) = builtin.get(&name) else {
+                return Err(ApiError::NotFound(format!("Document type {name} not found")));
+            };
+            DocumentTypeDao::create(
+                &state.db,
+                &name,
+                body.label.trim(),
+                body.instructions.as_deref(),
+                &body.json_schema,
+                &body.keywords,
+                body.priority.unwrap_or(original.priority),
+            )?
+        }
+    };
+    Ok(Json(serde_json::to_value(doc_type).unwrap()))
+}
+
+/// Delete a user-defined document type. Deleting an override restores the
+/// built-in type; built-in types themselves cannot be deleted.
+async fn delete_document_type(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    match DocumentTypeDao::get_by_name(&state.db, &name)? {
+        Some(existing) => {
+            DocumentTypeDao::delete(&state.db, &existing.id)?;
+            Ok(Json(json!({"deleted": true, "name": name})))
+        }
+        None if DocumentTypeCatalog::builtin().get(&name).is_some() => Err(ApiError::BadRequest(
+            format!("Built-in document type {name} cannot be deleted"),
+        )),
+        None => Err(ApiError::NotFound(format!("Document type {name} not found"))),
+    }
+}
@@ -20,6 +20,7 @@ pub fn routes() -> Router<AppState> {
 struct ExportQuery {
     document_type: Option<String>,
     min_confidence: Option<f64>,
+    status: Option<String>,
 }
 
 impl From<ExportQuery> for ExportFilter {
@@ -27,6 +28,7 @@ impl From<ExportQuery> for ExportFilter {
         ExportFilter {
             document_type: q.document_type,
             min_confidence: q.min_confidence,
+            status: q.status,
         }
     }
 }
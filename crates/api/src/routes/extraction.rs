@@ -1,12 +1,13 @@
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::routing::get;
 use axum::Json;
 use axum::Router;
+use serde::Deserialize;
 use serde_json::{json, Value};
 
 use crate::error::ApiError;
 use crate::state::AppState;
-use harvex_services::ExtractionDao;
+use harvex_services::{ExtractionDao, ExtractionPageDao};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -15,13 +16,31 @@ pub fn routes() -> Router<AppState> {
             "/batch/{batch_id}/extraction/{extraction_id}",
             get(get_extraction),
         )
+        .route(
+            "/batch/{batch_id}/extraction/{extraction_id}/pages",
+            get(list_extraction_pages),
+        )
+}
+
+#[derive(Deserialize)]
+struct ListExtractionsQuery {
+    document_type: Option<String>,
+    min_confidence: Option<f64>,
+    status: Option<String>,
 }
 
 async fn list_extractions(
     State(state): State<AppState>,
     Path(batch_id): Path<String>,
+    Query(query): Query<ListExtractionsQuery>,
 ) -> Result<Json<Value>, ApiError> {
-    let extractions = ExtractionDao::list_by_batch(&state.db, &batch_id)?;
+    let extractions = ExtractionDao::list_by_batch_filtered(
+        &state.db,
+        &batch_id,
+        query.document_type.as_deref(),
+        query.min_confidence,
+        query.status.as_deref(),
+    )?;
     Ok(Json(json!(extractions)))
 }
 
@@ -33,3 +52,15 @@ async fn get_extraction(
         .map_err(|_| ApiError::NotFound(format!("Extraction {extraction_id} not found")))?;
     Ok(Json(serde_json::to_value(extraction).unwrap()))
 }
+
+/// Per-page outcome of a vision extraction: status, error, raw model output
+/// and latency. Empty for extractions made from text.
+async fn list_extraction_pages(
+    State(state): State<AppState>,
+    Path((_batch_id, extraction_id)): Path<(String, String)>,
+) -> Result<Json<Value>, ApiError> {
+    ExtractionDao::get_by_id(&state.db, &extraction_id)
+        .map_err(|_| ApiError::NotFound(format!("Extraction {extraction_id} not found")))?;
+    let pages = ExtractionPageDao::list_by_extraction(&state.db, &extraction_id)?;
+    Ok(Json(json!(pages)))
+}
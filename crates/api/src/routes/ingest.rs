@@ -0,0 +1,20 @@
+use axum::extract::State;
+use axum::routing::get;
+use axum::Json;
+use axum::Router;
+use serde_json::{json, Value};
+
+use crate::error::ApiError;
+use crate::state::AppState;
+use harvex_services::IngestSourceDao;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/ingest/sources", get(list_sources))
+}
+
+/// State of every mailbox and watched folder polled so far: its current
+/// batch, how many items it ingested and how its last poll went.
+async fn list_sources(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    let sources = IngestSourceDao::list(&state.db)?;
+    Ok(Json(json!(sources)))
+}
@@ -1,8 +1,10 @@
 pub mod batch;
 pub mod document;
+pub mod document_type;
 pub mod export;
 pub mod extraction;
 pub mod health;
+pub mod ingest;
 pub mod model;
 
 use axum::Router;
@@ -12,8 +14,10 @@ use crate::state::AppState;
 pub fn api_routes() -> Router<AppState> {
     Router::new()
         .merge(document::routes())
+        .merge(document_type::routes())
         .merge(batch::routes())
         .merge(extraction::routes())
         .merge(export::routes())
         .merge(model::routes())
+        .merge(ingest::routes())
 }
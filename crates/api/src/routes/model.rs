@@ -29,6 +29,13 @@ async fn get_model_info(State(state): State<AppState>) -> Json<Value> {
         "vision_model_name": settings.vision_model_name,
         "vision_dpi": settings.vision_dpi,
         "vision_max_pages": settings.vision_max_pages,
+        "vision_page_cap": settings.vision_page_cap,
+        "vision_page_concurrency": settings.vision_page_concurrency,
+        "vision_max_concurrent": settings.vision_max_concurrent,
+        "structured_output": settings.structured_output,
+        "structured_output_active": state.llm.structured_output(),
+        "merge_mode": settings.merge_mode,
+        "tokenizer": state.llm.token_counter_name(),
     }))
 }
 
@@ -18,7 +18,7 @@ impl AppState {
     pub fn new(config: Settings, db: DbPool) -> Self {
         let pipeline = Pipeline::new(
             db.clone(),
-            config.processing.max_concurrent,
+            config.processing.clone(),
             config.llm.clone(),
         );
         let progress_tx = pipeline.progress_sender();
@@ -1,10 +1,14 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     pub server: ServerSettings,
     pub database: DatabaseSettings,
     pub storage: StorageSettings,
+    #[serde(default)]
+    pub ingest: IngestSettings,
     pub processing: ProcessingSettings,
     pub llm: LlmSettings,
 }
@@ -24,11 +28,332 @@ pub struct DatabaseSettings {
 pub struct StorageSettings {
     pub upload_dir: String,
     pub max_file_size_mb: u64,
+    #[serde(default)]
+    pub archive: ArchiveSettings,
+}
+
+/// Limits on uploaded ZIP archives, which are expanded into one document per
+/// file. An archive over any limit is rejected as a whole.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ArchiveSettings {
+    /// Files taken from one upload, across nested archives.
+    pub max_entries: usize,
+    /// Uncompressed size of everything read from one upload.
+    pub max_total_size_mb: u64,
+    /// Levels of archives, counting the uploaded one: 2 expands a ZIP inside
+    /// the ZIP but no deeper.
+    pub max_depth: u32,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            max_total_size_mb: 500,
+            max_depth: 2,
+        }
+    }
+}
+
+/// Sources documents are taken from without an upload.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IngestSettings {
+    pub mailboxes: Vec<MailboxSettings>,
+    pub folders: Vec<WatchFolderSettings>,
+}
+
+/// A mailbox polled for unseen messages. Each message is stored as an
+/// email document with its attachments as child documents.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MailboxSettings {
+    /// Unique name of the source; its polling state is stored under it.
+    pub name: String,
+    pub kind: MailboxKind,
+    /// IMAP server, with TLS from the start (IMAPS) unless `tls` is off.
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub username: String,
+    pub password: String,
+    pub folder: String,
+    /// Maildir directory, the one holding `new/` and `cur/`.
+    pub path: String,
+    pub poll_interval_secs: u64,
+    pub batching: MailBatching,
+    /// Start processing new documents right away.
+    pub auto_process: bool,
+}
+
+impl Default for MailboxSettings {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            kind: MailboxKind::Imap,
+            host: String::new(),
+            port: 993,
+            tls: true,
+            username: String::new(),
+            password: String::new(),
+            folder: "INBOX".into(),
+            path: String::new(),
+            poll_interval_secs: 300,
+            batching: MailBatching::Message,
+            auto_process: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MailboxKind {
+    #[default]
+    Imap,
+    Maildir,
+}
+
+impl MailboxKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailboxKind::Imap => "imap",
+            MailboxKind::Maildir => "maildir",
+        }
+    }
+}
+
+/// Which batch an ingested message goes into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MailBatching {
+    /// A new batch per message.
+    #[default]
+    Message,
+    /// One batch per mailbox and day, messages of the day appended to it.
+    Daily,
+}
+
+/// A directory watched for files dropped into it, e.g. by a scanner. A file
+/// is taken once its size has not changed for `stable_secs`; the original is
+/// then moved into `archive_dir`, or into `failed_dir` if it could not be
+/// ingested.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WatchFolderSettings {
+    /// Unique name of the source; its state is stored under it, shared with
+    /// mailbox names.
+    pub name: String,
+    pub path: String,
+    /// Also take files from subdirectories.
+    pub recursive: bool,
+    pub stable_secs: u64,
+    /// How often the directory is scanned without a change notification.
+    pub poll_interval_secs: u64,
+    /// Only scan, e.g. for network shares that send no change notifications.
+    pub use_polling: bool,
+    /// Subdirectories for ingested and failed originals, relative to `path`.
+    pub archive_dir: String,
+    pub failed_dir: String,
+    pub batching: FolderBatching,
+    /// Length of a batch window with `batching = "window"`.
+    pub batch_window_secs: u64,
+    /// Start processing new documents right away.
+    pub auto_process: bool,
+}
+
+impl Default for WatchFolderSettings {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            path: String::new(),
+            recursive: true,
+            stable_secs: 10,
+            poll_interval_secs: 30,
+            use_polling: false,
+            archive_dir: "archive".into(),
+            failed_dir: "failed".into(),
+            batching: FolderBatching::Folder,
+            batch_window_secs: 3600,
+            auto_process: false,
+        }
+    }
+}
+
+/// Which batch a file taken from a watched directory goes into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderBatching {
+    /// One batch per directory: files dropped into the same directory one
+    /// after another share a batch.
+    #[default]
+    Folder,
+    /// One batch per time window of `batch_window_secs`.
+    Window,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ProcessingSettings {
     pub max_concurrent: usize,
+    /// How long a worker may hold a job before it is considered abandoned
+    /// and handed out again.
+    #[serde(default = "default_job_lease_secs")]
+    pub job_lease_secs: u64,
+    /// Attempts per document before its job is marked as failed.
+    #[serde(default = "default_job_max_attempts")]
+    pub job_max_attempts: u32,
+    #[serde(default)]
+    pub ocr: OcrSettings,
+    #[serde(default)]
+    pub image: ImageSettings,
+    #[serde(default)]
+    pub pdf_render: PdfRenderSettings,
+}
+
+/// Rendering of PDF pages to images, for the vision model and OCR.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PdfRenderSettings {
+    pub renderer: PdfRendererKind,
+    /// Longest a single page may take to render before the document fails.
+    pub page_timeout_secs: u64,
+    /// The pdfium library to load; empty searches the system library path.
+    pub pdfium_library_path: String,
+}
+
+impl Default for PdfRenderSettings {
+    fn default() -> Self {
+        Self {
+            renderer: PdfRendererKind::Auto,
+            page_timeout_secs: 60,
+            pdfium_library_path: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PdfRendererKind {
+    /// pdftoppm if it is installed, otherwise pdfium if built in.
+    #[default]
+    Auto,
+    /// The `pdftoppm` command from poppler-utils.
+    Pdftoppm,
+    /// pdfium, in process; needs a build with the `pdfium` feature.
+    Pdfium,
+}
+
+/// Preparation of photos and scans sent to the vision model. Steps run in
+/// this order: orientation, crop, downscale, deskew, JPEG encoding.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImageSettings {
+    /// Apply the EXIF orientation; phone photos are often stored rotated.
+    pub fix_orientation: bool,
+    /// Crop to the document when it lies on a darker background.
+    pub crop: bool,
+    /// Longest edge in pixels after downscaling; 0 keeps the size.
+    pub max_edge: u32,
+    /// Straighten text lines tilted by up to `max_skew_degrees`.
+    pub deskew: bool,
+    pub max_skew_degrees: f32,
+    pub jpeg_quality: u8,
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        Self {
+            fix_orientation: true,
+            crop: true,
+            max_edge: 2048,
+            deskew: true,
+            max_skew_degrees: 10.0,
+            jpeg_quality: 85,
+        }
+    }
+}
+
+/// OCR of images and scanned PDFs. Recognized text goes through the text
+/// model when no vision model is configured.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OcrSettings {
+    pub engine: OcrEngineKind,
+    /// How OCR and the vision model are combined; batches may override it.
+    pub mode: OcrMode,
+    /// Tesseract language(s), e.g. `eng` or `deu+eng`.
+    pub language: String,
+    /// Directory with the `.traineddata` files. Empty uses Tesseract's default.
+    pub tessdata_path: String,
+    /// Resolution scanned PDF pages are rendered at for OCR.
+    pub dpi: u32,
+    /// Pages of a scanned PDF that are recognized (0 = all).
+    pub max_pages: u32,
+}
+
+impl Default for OcrSettings {
+    fn default() -> Self {
+        Self {
+            engine: OcrEngineKind::None,
+            mode: OcrMode::Auto,
+            language: "eng".into(),
+            tessdata_path: String::new(),
+            dpi: 300,
+            max_pages: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OcrEngineKind {
+    /// No OCR; images and scanned PDFs need the vision model.
+    #[default]
+    None,
+    /// Tesseract; needs a build with the `tesseract` feature.
+    Tesseract,
+}
+
+/// How OCR and the vision model are combined for images and scanned PDFs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OcrMode {
+    /// The vision model reads the pages; OCR is used only without one.
+    #[default]
+    Auto,
+    /// Pages are OCRed and the text is sent with each image so the vision
+    /// model can cross-check it. If vision fails, the OCR text goes to the
+    /// text model instead.
+    Hybrid,
+}
+
+impl OcrMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OcrMode::Auto => "auto",
+            OcrMode::Hybrid => "hybrid",
+        }
+    }
+}
+
+impl std::str::FromStr for OcrMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(OcrMode::Auto),
+            "hybrid" => Ok(OcrMode::Hybrid),
+            other => Err(format!("Unknown OCR mode '{other}', expected auto or hybrid")),
+        }
+    }
+}
+
+fn default_job_lease_secs() -> u64 {
+    3600
+}
+
+fn default_job_max_attempts() -> u32 {
+    3
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -43,8 +368,117 @@ pub struct LlmSettings {
     pub vision_model_name: String,
     #[serde(default = "default_vision_dpi")]
     pub vision_dpi: u32,
+    /// Pages of a scanned PDF rendered at a time; longer documents are
+    /// rendered and sent window by window, holding one window's images at
+    /// a time.
     #[serde(default = "default_vision_max_pages")]
     pub vision_max_pages: u32,
+    /// Most pages of one document sent to the vision model (0 = all). Pages
+    /// past the cap are skipped and the extraction is marked partial.
+    #[serde(default)]
+    pub vision_page_cap: u32,
+    /// Pages of one document sent to the vision model at the same time.
+    #[serde(default = "default_vision_page_concurrency")]
+    pub vision_page_concurrency: u32,
+    /// Vision requests in flight at the same time across all documents.
+    #[serde(default = "default_vision_max_concurrent")]
+    pub vision_max_concurrent: u32,
+    #[serde(default)]
+    pub retry: RetrySettings,
+    /// Times the model is asked to correct output that does not match the
+    /// document type's schema. 0 disables repair; output is still validated.
+    #[serde(default = "default_max_repair_attempts")]
+    pub max_repair_attempts: u32,
+    #[serde(default)]
+    pub structured_output: StructuredOutputMode,
+    /// Characters repeated between consecutive chunks when a document is too
+    /// long for one request, so rows cut at a chunk boundary are not lost.
+    #[serde(default = "default_chunk_overlap_chars")]
+    pub chunk_overlap_chars: u32,
+    #[serde(default)]
+    pub merge_mode: MergeMode,
+    /// Hugging Face `tokenizer.json` of the text model, used to budget the
+    /// context window. Empty uses an estimate of ~3 characters per token.
+    /// Needs a build with the `hf-tokenizer` feature.
+    #[serde(default)]
+    pub tokenizer_path: String,
+    /// Price per model name, used to estimate what extractions cost. Models
+    /// not listed (e.g. local ones) are counted as free.
+    #[serde(default)]
+    pub pricing: HashMap<String, ModelPrice>,
+}
+
+/// Price of a model in USD per million tokens.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ModelPrice {
+    pub prompt_per_million: f64,
+    pub completion_per_million: f64,
+}
+
+/// How the expected JSON shape is enforced on the LLM API.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StructuredOutputMode {
+    /// `response_format: {"type": "json_object"}` — any JSON object; the
+    /// shape is only described in the prompt.
+    #[default]
+    JsonObject,
+    /// `response_format: {"type": "json_schema"}` with the document type's
+    /// schema (vLLM, newer Ollama, llama.cpp server, OpenAI).
+    JsonSchema,
+    /// A GBNF grammar generated from the schema, sent as `grammar` (llama.cpp).
+    Grammar,
+    /// Try `json_schema` and fall back to `json_object` if the backend rejects it.
+    Auto,
+}
+
+/// How the results of several pages or text chunks become one extraction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeMode {
+    /// Merged by rules derived from the document type's schema: arrays
+    /// concatenated and deduplicated, totals taken from the last page.
+    #[default]
+    Rules,
+    /// Sent back to the text model to merge.
+    Llm,
+}
+
+/// Retry policy for LLM API calls.
+///
+/// A failed call is retried after `initial_backoff_ms`, doubling (times
+/// `backoff_multiplier`) on every further attempt up to `max_backoff_ms`.
+/// A `Retry-After` header sent by the server takes precedence over the
+/// computed delay, still capped at `max_backoff_ms`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetrySettings {
+    /// Total attempts per call, including the first one. 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub backoff_multiplier: f64,
+    /// HTTP status codes that are worth another attempt.
+    pub retryable_status_codes: Vec<u16>,
+    /// Retry when the request times out.
+    pub retry_on_timeout: bool,
+    /// Retry when the API cannot be reached (connection refused, DNS, reset).
+    pub retry_on_connect_error: bool,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 30_000,
+            backoff_multiplier: 2.0,
+            retryable_status_codes: vec![408, 429, 500, 502, 503, 504],
+            retry_on_timeout: true,
+            retry_on_connect_error: true,
+        }
+    }
 }
 
 fn default_vision_dpi() -> u32 {
@@ -55,6 +489,22 @@ fn default_vision_max_pages() -> u32 {
     5
 }
 
+fn default_vision_page_concurrency() -> u32 {
+    2
+}
+
+fn default_vision_max_concurrent() -> u32 {
+    4
+}
+
+fn default_max_repair_attempts() -> u32 {
+    2
+}
+
+fn default_chunk_overlap_chars() -> u32 {
+    400
+}
+
 impl Settings {
     pub fn load() -> Result<Self, config::ConfigError> {
         dotenvy::dotenv().ok();
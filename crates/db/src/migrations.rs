@@ -1,4 +1,5 @@
 use crate::DbPool;
+use duckdb::{params, Connection};
 use tracing::info;
 
 pub fn run(pool: &DbPool) -> Result<(), duckdb::Error> {
@@ -43,11 +44,170 @@ pub fn run(pool: &DbPool) -> Result<(), duckdb::Error> {
             confidence          DOUBLE DEFAULT 0.0,
             model_used          VARCHAR,
             processing_time_ms  BIGINT DEFAULT 0,
+            superseded_by       VARCHAR,
+            llm_attempts        INTEGER DEFAULT 0,
+            status              VARCHAR DEFAULT 'structured',
+            llm_error           VARCHAR,
+            validation          JSON,
+            prompt_tokens       BIGINT DEFAULT 0,
+            completion_tokens   BIGINT DEFAULT 0,
+            cost_usd            DOUBLE DEFAULT 0,
+            partial             BOOLEAN DEFAULT false,
+            skipped_pages       JSON,
             created_at          TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
         );
+
+        -- Settings added to batches after release live here: DuckDB cannot
+        -- ALTER batches, which other tables reference
+        CREATE TABLE IF NOT EXISTS batch_settings (
+            batch_id        VARCHAR PRIMARY KEY,
+            ocr_mode        VARCHAR
+        );
+
+        -- Where a document came from, e.g. the email of an attachment. Not
+        -- columns of documents, which jobs and extractions reference
+        CREATE TABLE IF NOT EXISTS document_sources (
+            document_id     VARCHAR PRIMARY KEY,
+            parent_id       VARCHAR,
+            email           JSON
+        );
+
+        -- Polling state of each ingestion source, by its configured name.
+        -- batch_id is the batch of the latest items' batch_key (e.g. the day)
+        CREATE TABLE IF NOT EXISTS ingest_sources (
+            name            VARCHAR PRIMARY KEY,
+            kind            VARCHAR NOT NULL,
+            uid_validity    BIGINT,
+            last_uid        BIGINT,
+            batch_id        VARCHAR,
+            batch_key       VARCHAR,
+            items_ingested  BIGINT NOT NULL DEFAULT 0,
+            last_poll_at    TIMESTAMP,
+            last_error      VARCHAR,
+            created_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+
+        -- Every batch a source has put items into, by its key (e.g. the
+        -- directory), so items of an earlier key go back into its batch
+        CREATE TABLE IF NOT EXISTS ingest_batches (
+            source          VARCHAR NOT NULL,
+            batch_key       VARCHAR NOT NULL,
+            batch_id        VARCHAR NOT NULL,
+            created_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (source, batch_key)
+        );
+
+        -- No foreign key to extractions: DuckDB cannot ALTER a referenced
+        -- table, and extractions still gets columns added (ADDED_COLUMNS)
+        CREATE TABLE IF NOT EXISTS extraction_pages (
+            id              VARCHAR PRIMARY KEY,
+            extraction_id   VARCHAR NOT NULL,
+            page_number     INTEGER NOT NULL,
+            status          VARCHAR NOT NULL,
+            error           VARCHAR,
+            raw_output      TEXT,
+            latency_ms      BIGINT NOT NULL DEFAULT 0,
+            attempts        INTEGER NOT NULL DEFAULT 0,
+            created_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS jobs (
+            id                  VARCHAR PRIMARY KEY,
+            batch_id            VARCHAR NOT NULL REFERENCES batches(id),
+            document_id         VARCHAR NOT NULL REFERENCES documents(id),
+            status              VARCHAR NOT NULL DEFAULT 'queued',
+            attempts            INTEGER NOT NULL DEFAULT 0,
+            max_attempts        INTEGER NOT NULL DEFAULT 3,
+            lease_expires_at    TIMESTAMP,
+            last_error          VARCHAR,
+            model_override          VARCHAR,
+            document_type_override  VARCHAR,
+            created_at          TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at          TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS document_types (
+            id              VARCHAR PRIMARY KEY,
+            name            VARCHAR NOT NULL UNIQUE,
+            label           VARCHAR NOT NULL,
+            instructions    TEXT,
+            json_schema     JSON NOT NULL,
+            keywords        JSON,
+            priority        INTEGER NOT NULL DEFAULT 100,
+            created_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
         ",
     )?;
 
+    add_missing_columns(&conn)?;
+
     info!("Database migrations completed");
     Ok(())
 }
+
+/// Columns added after their table was first released, as (table, column, type).
+///
+/// `CREATE TABLE IF NOT EXISTS` leaves existing tables untouched, so databases
+/// created by an older version get these columns added here. New columns must
+/// also appear in the `CREATE TABLE` statement above.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("extractions", "superseded_by", "VARCHAR"),
+    ("extractions", "llm_attempts", "INTEGER DEFAULT 0"),
+    ("extractions", "status", "VARCHAR DEFAULT 'structured'"),
+    ("extractions", "llm_error", "VARCHAR"),
+    ("extractions", "validation", "JSON"),
+    ("extractions", "prompt_tokens", "BIGINT DEFAULT 0"),
+    ("extractions", "completion_tokens", "BIGINT DEFAULT 0"),
+    ("extractions", "cost_usd", "DOUBLE DEFAULT 0"),
+    ("extractions", "partial", "BOOLEAN DEFAULT false"),
+    ("extractions", "skipped_pages", "JSON"),
+    ("jobs", "model_override", "VARCHAR"),
+    ("jobs", "document_type_override", "VARCHAR"),
+];
+
+/// Statements that give rows existing before a column was added the value
+/// their data implies, where the column default would mislabel them. Run
+/// once, right after the column is added, as (table, column, statement).
+const BACKFILLS: &[(&str, &str, &str)] = &[
+    // Extract-only results stored no structured data
+    (
+        "extractions",
+        "status",
+        "UPDATE extractions SET status = 'llm_failed' WHERE structured_data IS NULL",
+    ),
+    // ...and their documents were reported as plain completed
+    (
+        "extractions",
+        "status",
+        "UPDATE documents SET status = 'extracted_only' WHERE status = 'completed' AND id IN \
+         (SELECT document_id FROM extractions WHERE structured_data IS NULL AND superseded_by IS NULL)",
+    ),
+];
+
+fn add_missing_columns(conn: &Connection) -> Result<(), duckdb::Error> {
+    for (table, column, column_type) in ADDED_COLUMNS {
+        let exists: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM information_schema.columns WHERE table_name = ? AND column_name = ?",
+            params![table, column],
+            |row| row.get(0),
+        )?;
+
+        if exists == 0 {
+            conn.execute_batch(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {column_type}"
+            ))?;
+            info!("Added column {}.{}", table, column);
+
+            for (_, _, backfill) in BACKFILLS
+                .iter()
+                .filter(|(t, c, _)| t == table && c == column)
+            {
+                let updated = conn.execute(backfill, [])?;
+                info!("Backfilled {} rows of {}.{}", updated, table, column);
+            }
+        }
+    }
+    Ok(())
+}
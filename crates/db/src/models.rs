@@ -8,7 +8,18 @@ pub struct Batch {
     pub total_files: i32,
     pub processed_files: i32,
     pub failed_files: i32,
+    /// Documents (included in `processed_files`) whose text was extracted but
+    /// could not be structured by the LLM.
+    pub extracted_only_files: i32,
     pub model_name: Option<String>,
+    /// How OCR and the vision model are combined (auto | hybrid); `None`
+    /// uses the configured mode.
+    pub ocr_mode: Option<String>,
+    /// Tokens and estimated cost of all extractions of the batch, including
+    /// superseded ones, since their LLM calls were paid for too.
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cost_usd: f64,
     pub created_at: String,
     pub updated_at: String,
     pub completed_at: Option<String>,
@@ -25,10 +36,25 @@ pub struct Document {
     pub file_path: String,
     pub status: String,
     pub error_message: Option<String>,
+    /// The document this one was taken from, e.g. the email an attachment
+    /// came with.
+    pub parent_id: Option<String>,
+    /// Sender, subject and date of the email the document is or came with.
+    pub email: Option<EmailMetadata>,
     pub created_at: String,
     pub updated_at: String,
 }
 
+/// Headers of an ingested email, kept with the email and its attachments.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmailMetadata {
+    /// Sender as `Name <address>`, or just the address.
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    /// Sent date in RFC 3339.
+    pub date: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Extraction {
     pub id: String,
@@ -40,5 +66,117 @@ pub struct Extraction {
     pub confidence: f64,
     pub model_used: Option<String>,
     pub processing_time_ms: i64,
+    /// ID of the newer extraction of the same document that replaced this one.
+    pub superseded_by: Option<String>,
+    /// HTTP attempts made against the LLM API, including retries.
+    pub llm_attempts: i32,
+    /// pending → structured | llm_failed. A failed extraction keeps its raw text.
+    pub status: String,
+    pub llm_error: Option<String>,
+    /// Schema validation report of `structured_data`: remaining errors, errors
+    /// before repair and the number of repair attempts.
+    pub validation: Option<serde_json::Value>,
+    /// Tokens sent to and received from the LLM across all calls.
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    /// Estimated cost of those tokens in USD.
+    pub cost_usd: f64,
+    /// Not every page of the document was processed; see `skipped_pages`.
+    pub partial: bool,
+    /// 1-based numbers of the pages left out, e.g. past the vision page cap.
+    pub skipped_pages: Vec<u32>,
     pub created_at: String,
 }
+
+/// Polling state of an ingestion source, a mailbox or watched folder, by its
+/// configured name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestSource {
+    pub name: String,
+    /// imap | maildir | folder
+    pub kind: String,
+    /// IMAP UIDVALIDITY of the folder and the highest UID ingested under it.
+    pub uid_validity: Option<i64>,
+    pub last_uid: Option<i64>,
+    /// Batch the latest items were added to, and their `batch_key`, e.g.
+    /// the day.
+    pub batch_id: Option<String>,
+    pub batch_key: Option<String>,
+    pub items_ingested: i64,
+    pub last_poll_at: Option<String>,
+    /// Error of the last poll; `None` if it succeeded.
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Outcome of one page of a vision extraction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractionPage {
+    pub id: String,
+    pub extraction_id: String,
+    /// 1-based page number.
+    pub page_number: i32,
+    /// ok | failed | invalid_json. Only `ok` pages contribute to the result.
+    pub status: String,
+    pub error: Option<String>,
+    pub raw_output: Option<String>,
+    pub latency_ms: i64,
+    pub attempts: i32,
+    pub created_at: String,
+}
+
+/// A page outcome to be stored as an [`ExtractionPage`].
+#[derive(Debug, Clone, Copy)]
+pub struct NewExtractionPage<'a> {
+    /// 1-based page number.
+    pub page_number: u32,
+    pub status: &'a str,
+    pub error: Option<&'a str>,
+    pub raw_output: Option<&'a str>,
+    pub latency_ms: i64,
+    pub attempts: u32,
+}
+
+/// A unit of work in the durable processing queue: one document of a batch.
+///
+/// Status moves `queued` → `leased` → `done` | `failed`, or `cancelled` when
+/// the batch is cancelled. A leased job whose lease has expired is handed out again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub batch_id: String,
+    pub document_id: String,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub lease_expires_at: Option<String>,
+    pub last_error: Option<String>,
+    /// Model to use instead of the configured one (set when reprocessing).
+    pub model_override: Option<String>,
+    /// Document type to use instead of classification (set when reprocessing).
+    pub document_type_override: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A user-defined document type. Built-in types live in code; a row with the
+/// same name as a built-in type overrides it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentType {
+    pub id: String,
+    /// Identifier stored on extractions, e.g. `purchase_order`.
+    pub name: String,
+    /// Human-readable name used in prompts, e.g. "purchase order".
+    pub label: String,
+    /// Extra extraction rules appended to the generated system prompt.
+    pub instructions: Option<String>,
+    /// JSON Schema of the structured data to extract.
+    pub json_schema: serde_json::Value,
+    /// Classification keywords; `a+b` matches only if all terms appear.
+    pub keywords: Vec<String>,
+    /// Types are tried in ascending priority order during classification.
+    pub priority: i32,
+    pub created_at: String,
+    pub updated_at: String,
+}
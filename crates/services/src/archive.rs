@@ -0,0 +1,245 @@
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use harvex_config::ArchiveSettings;
+use tracing::debug;
+
+/// A file taken from an uploaded archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    /// Path inside the archive, e.g. `march/receipt-01.pdf`. Files of a
+    /// nested archive are prefixed with its path, e.g. `scans.zip/page1.jpg`.
+    pub name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Why an archive was not expanded.
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("{0} is not a valid ZIP archive: {1}")]
+    Invalid(String, String),
+    #[error("Archive has more than {0} files")]
+    TooManyEntries(usize),
+    #[error("Archive expands to more than {0} MB")]
+    TooLarge(u64),
+    #[error("{0} nests archives more than {1} levels deep")]
+    TooDeep(String, u32),
+}
+
+/// Whether an upload is a ZIP archive to expand.
+pub fn is_archive(file_name: &str, content_type: &str) -> bool {
+    matches!(
+        content_type,
+        "application/zip" | "application/x-zip-compressed"
+    ) || has_zip_extension(file_name)
+}
+
+fn has_zip_extension(file_name: &str) -> bool {
+    Path::new(file_name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Expand a ZIP archive into its files, nested archives included, within
+/// the limits of `settings`.
+///
+/// Directories, macOS resource forks (`__MACOSX/`) and hidden files are
+/// skipped, as are entries whose path would leave the archive. Sizes are
+/// counted while reading, not taken from the archive's own headers.
+pub fn expand_zip(
+    archive_name: &str,
+    data: &[u8],
+    settings: &ArchiveSettings,
+) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let mut expansion = Expansion {
+        settings,
+        entries: Vec::new(),
+        total_bytes: 0,
+    };
+    expansion.expand(archive_name, "", data, 1)?;
+    debug!(
+        "Expanded {} into {} files ({} bytes)",
+        archive_name,
+        expansion.entries.len(),
+        expansion.total_bytes
+    );
+    Ok(expansion.entries)
+}
+
+struct Expansion<'a> {
+    settings: &'a ArchiveSettings,
+    entries: Vec<ArchiveEntry>,
+    total_bytes: u64,
+}
+
+impl Expansion<'_> {
+    fn expand(
+        &mut self,
+        archive_name: &str,
+        prefix: &str,
+        data: &[u8],
+        depth: u32,
+    ) -> Result<(), ArchiveError> {
+        let invalid = |e: zip::result::ZipError| {
+            ArchiveError::Invalid(archive_name.to_string(), e.to_string())
+        };
+        let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(invalid)?;
+        let max_bytes = self.settings.max_total_size_mb * 1024 * 1024;
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).map_err(invalid)?;
+            if file.is_dir() {
+                continue;
+            }
+            let Some(path) = file.enclosed_name() else {
+                debug!("Skipping {}: path leaves the archive", file.name());
+                continue;
+            };
+            let name = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if name.starts_with("__MACOSX/") || name.split('/').any(|part| part.starts_with('.')) {
+                continue;
+            }
+            let name = format!("{prefix}{name}");
+
+            let nested = has_zip_extension(&name);
+            if nested && depth >= self.settings.max_depth {
+                return Err(ArchiveError::TooDeep(name, self.settings.max_depth));
+            }
+            if !nested && self.entries.len() >= self.settings.max_entries {
+                return Err(ArchiveError::TooManyEntries(self.settings.max_entries));
+            }
+
+            // Read one byte past the remaining budget to notice going over it
+            let remaining = max_bytes.saturating_sub(self.total_bytes);
+            let mut content = Vec::new();
+            (&mut file)
+                .take(remaining + 1)
+                .read_to_end(&mut content)
+                .map_err(|e| ArchiveError::Invalid(archive_name.to_string(), e.to_string()))?;
+            if content.len() as u64 > remaining {
+                return Err(ArchiveError::TooLarge(self.settings.max_total_size_mb));
+            }
+            self.total_bytes += content.len() as u64;
+
+            if nested {
+                self.expand(&name, &format!("{name}/"), &content, depth + 1)?;
+            } else {
+                let content_type = mime_guess::from_path(&name)
+                    .first_or_octet_stream()
+                    .essence_str()
+                    .to_string();
+                self.entries.push(ArchiveEntry {
+                    name,
+                    content_type,
+                    data: content,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            if name.ends_with('/') {
+                writer
+                    .add_directory(*name, SimpleFileOptions::default())
+                    .unwrap();
+            } else {
+                writer
+                    .start_file(*name, SimpleFileOptions::default())
+                    .unwrap();
+                writer.write_all(data).unwrap();
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn files_keep_their_relative_paths() {
+        let inner = zip_of(&[("scan.jpg", b"jpeg")]);
+        let data = zip_of(&[
+            ("march/", b""),
+            ("march/receipt-01.pdf", b"pdf"),
+            ("__MACOSX/march/._receipt-01.pdf", b"fork"),
+            ("march/.DS_Store", b"junk"),
+            ("scans.zip", &inner),
+        ]);
+
+        let entries = expand_zip("month.zip", &data, &ArchiveSettings::default()).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["march/receipt-01.pdf", "scans.zip/scan.jpg"]);
+        assert_eq!(entries[0].content_type, "application/pdf");
+        assert_eq!(entries[1].data, b"jpeg");
+    }
+
+    #[test]
+    fn limits_reject_the_archive() {
+        let data = zip_of(&[("a.pdf", b"a"), ("b.pdf", b"b"), ("c.pdf", b"c")]);
+        let settings = ArchiveSettings {
+            max_entries: 2,
+            ..ArchiveSettings::default()
+        };
+        assert!(matches!(
+            expand_zip("x.zip", &data, &settings),
+            Err(ArchiveError::TooManyEntries(2))
+        ));
+
+        // Highly compressible, as zip bombs are
+        let big = vec![0u8; 2 * 1024 * 1024];
+        let data = zip_of(&[("big.pdf", &big)]);
+        assert!(data.len() < 64 * 1024);
+        let settings = ArchiveSettings {
+            max_total_size_mb: 1,
+            ..ArchiveSettings::default()
+        };
+        assert!(matches!(
+            expand_zip("x.zip", &data, &settings),
+            Err(ArchiveError::TooLarge(1))
+        ));
+    }
+
+    #[test]
+    fn nesting_past_the_limit_is_rejected() {
+        let level3 = zip_of(&[("deep.pdf", b"pdf")]);
+        let level2 = zip_of(&[("level3.zip", &level3)]);
+        let data = zip_of(&[("level2.zip", &level2)]);
+
+        let err = expand_zip("x.zip", &data, &ArchiveSettings::default()).unwrap_err();
+        assert!(
+            matches!(err, ArchiveError::TooDeep(ref name, 2) if name == "level2.zip/level3.zip")
+        );
+
+        let settings = ArchiveSettings {
+            max_depth: 3,
+            ..ArchiveSettings::default()
+        };
+        let entries = expand_zip("x.zip", &data, &settings).unwrap();
+        assert_eq!(entries[0].name, "level2.zip/level3.zip/deep.pdf");
+    }
+
+    #[test]
+    fn zip_uploads_are_detected() {
+        assert!(is_archive("Receipts.ZIP", "application/octet-stream"));
+        assert!(is_archive("upload", "application/zip"));
+        assert!(!is_archive("invoice.pdf", "application/pdf"));
+        assert!(matches!(
+            expand_zip("fake.zip", b"not a zip", &ArchiveSettings::default()),
+            Err(ArchiveError::Invalid(..))
+        ));
+    }
+}
@@ -4,6 +4,16 @@ use harvex_db::DbPool;
 
 pub struct BatchDao;
 
+const SELECT_COLUMNS: &str = "SELECT id, name, status, total_files, processed_files, failed_files,
+        (SELECT COUNT(*) FROM documents d WHERE d.batch_id = batches.id AND d.status = 'extracted_only'),
+        model_name,
+        (SELECT s.ocr_mode FROM batch_settings s WHERE s.batch_id = batches.id),
+        (SELECT CAST(COALESCE(SUM(e.prompt_tokens), 0) AS BIGINT) FROM extractions e WHERE e.batch_id = batches.id),
+        (SELECT CAST(COALESCE(SUM(e.completion_tokens), 0) AS BIGINT) FROM extractions e WHERE e.batch_id = batches.id),
+        (SELECT COALESCE(SUM(e.cost_usd), 0) FROM extractions e WHERE e.batch_id = batches.id),
+        CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR), CAST(completed_at AS VARCHAR)
+     FROM batches";
+
 impl BatchDao {
     pub fn create(pool: &DbPool, name: &str, model_name: Option<&str>) -> Result<Batch, duckdb::Error> {
         let id = nanoid::nanoid!();
@@ -20,50 +30,17 @@ impl BatchDao {
     pub fn get_by_id(pool: &DbPool, id: &str) -> Result<Batch, duckdb::Error> {
         let conn = pool.conn();
         conn.query_row(
-            "SELECT id, name, status, total_files, processed_files, failed_files,
-                    model_name, CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR), CAST(completed_at AS VARCHAR)
-             FROM batches WHERE id = ?",
+            &format!("{SELECT_COLUMNS} WHERE id = ?"),
             params![id],
-            |row| {
-                Ok(Batch {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    status: row.get(2)?,
-                    total_files: row.get(3)?,
-                    processed_files: row.get(4)?,
-                    failed_files: row.get(5)?,
-                    model_name: row.get(6)?,
-                    created_at: row.get(7)?,
-                    updated_at: row.get(8)?,
-                    completed_at: row.get(9)?,
-                })
-            },
+            Self::map_row,
         )
     }
 
     pub fn list(pool: &DbPool) -> Result<Vec<Batch>, duckdb::Error> {
         let conn = pool.conn();
-        let mut stmt = conn.prepare(
-            "SELECT id, name, status, total_files, processed_files, failed_files,
-                    model_name, CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR), CAST(completed_at AS VARCHAR)
-             FROM batches ORDER BY created_at DESC",
-        )?;
-
-        let rows = stmt.query_map([], |row| {
-            Ok(Batch {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                status: row.get(2)?,
-                total_files: row.get(3)?,
-                processed_files: row.get(4)?,
-                failed_files: row.get(5)?,
-                model_name: row.get(6)?,
-                created_at: row.get(7)?,
-                updated_at: row.get(8)?,
-                completed_at: row.get(9)?,
-            })
-        })?;
+        let mut stmt = conn.prepare(&format!("{SELECT_COLUMNS} ORDER BY created_at DESC"))?;
 
+        let rows = stmt.query_map([], Self::map_row)?;
         rows.collect()
     }
 
@@ -90,8 +67,42 @@ impl BatchDao {
         Ok(())
     }
 
+    /// Recompute processed/failed counters from the batch's document statuses.
+    ///
+    /// Counting from the documents (rather than in memory) keeps progress
+    /// correct when a batch is resumed after a restart. Documents that were
+    /// only extracted (LLM failed) count as processed.
+    pub fn recount_progress(pool: &DbPool, id: &str) -> Result<(i32, i32), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE batches SET
+                processed_files = (SELECT COUNT(*) FROM documents WHERE batch_id = ? AND status IN ('completed', 'extracted_only')),
+                failed_files = (SELECT COUNT(*) FROM documents WHERE batch_id = ? AND status = 'failed'),
+                updated_at = CURRENT_TIMESTAMP
+             WHERE id = ?",
+            params![id, id, id],
+        )?;
+        conn.query_row(
+            "SELECT processed_files, failed_files FROM batches WHERE id = ?",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    /// Set the batch's OCR mode; `None` goes back to the configured one.
+    pub fn set_ocr_mode(pool: &DbPool, id: &str, ocr_mode: Option<&str>) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "INSERT INTO batch_settings (batch_id, ocr_mode) VALUES (?, ?)
+             ON CONFLICT (batch_id) DO UPDATE SET ocr_mode = excluded.ocr_mode",
+            params![id, ocr_mode],
+        )?;
+        Ok(())
+    }
+
     pub fn delete(pool: &DbPool, id: &str) -> Result<bool, duckdb::Error> {
         let conn = pool.conn();
+        conn.execute("DELETE FROM batch_settings WHERE batch_id = ?", params![id])?;
         let affected = conn.execute("DELETE FROM batches WHERE id = ?", params![id])?;
         Ok(affected > 0)
     }
@@ -104,4 +115,24 @@ impl BatchDao {
         )?;
         Ok(())
     }
+
+    fn map_row(row: &duckdb::Row<'_>) -> Result<Batch, duckdb::Error> {
+        Ok(Batch {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            status: row.get(2)?,
+            total_files: row.get(3)?,
+            processed_files: row.get(4)?,
+            failed_files: row.get(5)?,
+            extracted_only_files: row.get(6)?,
+            model_name: row.get(7)?,
+            ocr_mode: row.get(8)?,
+            prompt_tokens: row.get(9)?,
+            completion_tokens: row.get(10)?,
+            cost_usd: row.get(11)?,
+            created_at: row.get(12)?,
+            updated_at: row.get(13)?,
+            completed_at: row.get(14)?,
+        })
+    }
 }
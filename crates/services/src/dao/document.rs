@@ -1,9 +1,16 @@
 use duckdb::params;
-use harvex_db::models::Document;
+use harvex_db::models::{Document, EmailMetadata};
 use harvex_db::DbPool;
 
 pub struct DocumentDao;
 
+const SELECT_COLUMNS: &str = "SELECT id, batch_id, filename, original_name, content_type, file_size,
+        file_path, status, error_message,
+        (SELECT s.parent_id FROM document_sources s WHERE s.document_id = documents.id),
+        (SELECT CAST(s.email AS VARCHAR) FROM document_sources s WHERE s.document_id = documents.id),
+        CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR)
+     FROM documents";
+
 impl DocumentDao {
     pub fn create(
         pool: &DbPool,
@@ -29,57 +36,57 @@ impl DocumentDao {
     pub fn get_by_id(pool: &DbPool, id: &str) -> Result<Document, duckdb::Error> {
         let conn = pool.conn();
         conn.query_row(
-            "SELECT id, batch_id, filename, original_name, content_type, file_size,
-                    file_path, status, error_message, CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR)
-             FROM documents WHERE id = ?",
+            &format!("{SELECT_COLUMNS} WHERE id = ?"),
             params![id],
-            |row| {
-                Ok(Document {
-                    id: row.get(0)?,
-                    batch_id: row.get(1)?,
-                    filename: row.get(2)?,
-                    original_name: row.get(3)?,
-                    content_type: row.get(4)?,
-                    file_size: row.get(5)?,
-                    file_path: row.get(6)?,
-                    status: row.get(7)?,
-                    error_message: row.get(8)?,
-                    created_at: row.get(9)?,
-                    updated_at: row.get(10)?,
-                })
-            },
+            Self::map_row,
         )
     }
 
     pub fn list_by_batch(pool: &DbPool, batch_id: &str) -> Result<Vec<Document>, duckdb::Error> {
         let conn = pool.conn();
-        let mut stmt = conn.prepare(
-            "SELECT id, batch_id, filename, original_name, content_type, file_size,
-                    file_path, status, error_message, CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR)
-             FROM documents WHERE batch_id = ? ORDER BY created_at ASC",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "{SELECT_COLUMNS} WHERE batch_id = ? ORDER BY created_at ASC"
+        ))?;
 
-        let rows = stmt.query_map(params![batch_id], |row| {
-            Ok(Document {
-                id: row.get(0)?,
-                batch_id: row.get(1)?,
-                filename: row.get(2)?,
-                original_name: row.get(3)?,
-                content_type: row.get(4)?,
-                file_size: row.get(5)?,
-                file_path: row.get(6)?,
-                status: row.get(7)?,
-                error_message: row.get(8)?,
-                created_at: row.get(9)?,
-                updated_at: row.get(10)?,
-            })
-        })?;
+        let rows = stmt.query_map(params![batch_id], Self::map_row)?;
 
         rows.collect()
     }
 
+    /// Documents taken from the given one, such as an email's attachments.
+    pub fn list_children(pool: &DbPool, parent_id: &str) -> Result<Vec<Document>, duckdb::Error> {
+        let conn = pool.conn();
+        let mut stmt = conn.prepare(&format!(
+            "{SELECT_COLUMNS} WHERE id IN (SELECT document_id FROM document_sources WHERE parent_id = ?)
+             ORDER BY created_at ASC"
+        ))?;
+
+        let rows = stmt.query_map(params![parent_id], Self::map_row)?;
+
+        rows.collect()
+    }
+
+    /// Record where a document came from: the document it was taken from
+    /// and the email headers it carries.
+    pub fn set_source(
+        pool: &DbPool,
+        id: &str,
+        parent_id: Option<&str>,
+        email: Option<&EmailMetadata>,
+    ) -> Result<(), duckdb::Error> {
+        let email = email.map(|e| serde_json::to_string(e).unwrap_or_default());
+        let conn = pool.conn();
+        conn.execute(
+            "INSERT INTO document_sources (document_id, parent_id, email) VALUES (?, ?, ?)
+             ON CONFLICT (document_id) DO UPDATE SET parent_id = excluded.parent_id, email = excluded.email",
+            params![id, parent_id, email],
+        )?;
+        Ok(())
+    }
+
     pub fn delete(pool: &DbPool, id: &str) -> Result<bool, duckdb::Error> {
         let conn = pool.conn();
+        conn.execute("DELETE FROM document_sources WHERE document_id = ?", params![id])?;
         let affected = conn.execute("DELETE FROM documents WHERE id = ?", params![id])?;
         Ok(affected > 0)
     }
@@ -90,6 +97,10 @@ impl DocumentDao {
         let paths: Vec<String> = docs.iter().map(|d| d.file_path.clone()).collect();
 
         let conn = pool.conn();
+        conn.execute(
+            "DELETE FROM document_sources WHERE document_id IN (SELECT id FROM documents WHERE batch_id = ?)",
+            params![batch_id],
+        )?;
         conn.execute(
             "DELETE FROM documents WHERE batch_id = ?",
             params![batch_id],
@@ -111,4 +122,47 @@ impl DocumentDao {
         )?;
         Ok(())
     }
+
+    /// Mark every document of a batch that has not finished as cancelled.
+    pub fn cancel_unfinished(pool: &DbPool, batch_id: &str) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE documents SET status = 'cancelled', updated_at = CURRENT_TIMESTAMP
+             WHERE batch_id = ? AND status IN ('pending', 'processing')",
+            params![batch_id],
+        )
+    }
+
+    /// Number of documents of a batch with the given status.
+    pub fn count_by_status(pool: &DbPool, batch_id: &str, status: &str) -> Result<i32, duckdb::Error> {
+        let conn = pool.conn();
+        conn.query_row(
+            "SELECT COUNT(*) FROM documents WHERE batch_id = ? AND status = ?",
+            params![batch_id, status],
+            |row| row.get(0),
+        )
+    }
+
+    fn map_row(row: &duckdb::Row<'_>) -> Result<Document, duckdb::Error> {
+        let email_str: Option<String> = row.get(10)?;
+        let email = email_str
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok());
+
+        Ok(Document {
+            id: row.get(0)?,
+            batch_id: row.get(1)?,
+            filename: row.get(2)?,
+            original_name: row.get(3)?,
+            content_type: row.get(4)?,
+            file_size: row.get(5)?,
+            file_path: row.get(6)?,
+            status: row.get(7)?,
+            error_message: row.get(8)?,
+            parent_id: row.get(9)?,
+            email,
+            created_at: row.get(11)?,
+            updated_at: row.get(12)?,
+        })
+    }
 }
@@ -0,0 +1,123 @@
+use duckdb::{params, OptionalExt};
+use harvex_db::models::DocumentType;
+use harvex_db::DbPool;
+
+pub struct DocumentTypeDao;
+
+const SELECT_COLUMNS: &str = "SELECT id, name, label, instructions, json_schema, keywords, priority,
+        CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR)
+     FROM document_types";
+
+impl DocumentTypeDao {
+    pub fn create(
+        pool: &DbPool,
+        name: &str,
+        label: &str,
+        instructions: Option<&str>,
+        json_schema: &serde_json::Value,
+        keywords: &[String],
+        priority: i32,
+    ) -> Result<DocumentType, duckdb::Error> {
+        let id = nanoid::nanoid!();
+        {
+            let conn = pool.conn();
+            let keywords_json = serde_json::to_string(keywords).unwrap_or_else(|_| "[]".into());
+
+            conn.execute(
+                "INSERT INTO document_types (id, name, label, instructions, json_schema, keywords, priority)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    id,
+                    name,
+                    label,
+                    instructions,
+                    json_schema.to_string(),
+                    keywords_json,
+                    priority
+                ],
+            )?;
+        }
+        Self::get_by_id(pool, &id)
+    }
+
+    pub fn get_by_id(pool: &DbPool, id: &str) -> Result<DocumentType, duckdb::Error> {
+        let conn = pool.conn();
+        conn.query_row(
+            &format!("{SELECT_COLUMNS} WHERE id = ?"),
+            params![id],
+            Self::map_row,
+        )
+    }
+
+    pub fn get_by_name(pool: &DbPool, name: &str) -> Result<Option<DocumentType>, duckdb::Error> {
+        let conn = pool.conn();
+        conn.query_row(
+            &format!("{SELECT_COLUMNS} WHERE name = ?"),
+            params![name],
+            Self::map_row,
+        )
+        .optional()
+    }
+
+    pub fn list(pool: &DbPool) -> Result<Vec<DocumentType>, duckdb::Error> {
+        let conn = pool.conn();
+        let mut stmt = conn.prepare(&format!("{SELECT_COLUMNS} ORDER BY priority ASC, name ASC"))?;
+        let rows = stmt.query_map([], Self::map_row)?;
+        rows.collect()
+    }
+
+    pub fn update(
+        pool: &DbPool,
+        id: &str,
+        label: &str,
+        instructions: Option<&str>,
+        json_schema: &serde_json::Value,
+        keywords: &[String],
+        priority: i32,
+    ) -> Result<DocumentType, duckdb::Error> {
+        {
+            let conn = pool.conn();
+            let keywords_json = serde_json::to_string(keywords).unwrap_or_else(|_| "[]".into());
+
+            conn.execute(
+                "UPDATE document_types SET label = ?, instructions = ?, json_schema = ?, keywords = ?,
+                 priority = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+                params![
+                    label,
+                    instructions,
+                    json_schema.to_string(),
+                    keywords_json,
+                    priority,
+                    id
+                ],
+            )?;
+        }
+        Self::get_by_id(pool, id)
+    }
+
+    pub fn delete(pool: &DbPool, id: &str) -> Result<bool, duckdb::Error> {
+        let conn = pool.conn();
+        let affected = conn.execute("DELETE FROM document_types WHERE id = ?", params![id])?;
+        Ok(affected > 0)
+    }
+
+    fn map_row(row: &duckdb::Row<'_>) -> Result<DocumentType, duckdb::Error> {
+        let schema_str: String = row.get(4)?;
+        let keywords_str: Option<String> = row.get(5)?;
+
+        Ok(DocumentType {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            label: row.get(2)?,
+            instructions: row.get(3)?,
+            json_schema: serde_json::from_str(&schema_str).unwrap_or(serde_json::Value::Null),
+            keywords: keywords_str
+                .as_deref()
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+            priority: row.get(6)?,
+            created_at: row.get(7)?,
+            updated_at: row.get(8)?,
+        })
+    }
+}
@@ -1,9 +1,15 @@
-use duckdb::params;
+use duckdb::{params, OptionalExt};
 use harvex_db::models::Extraction;
 use harvex_db::DbPool;
 
 pub struct ExtractionDao;
 
+const SELECT_COLUMNS: &str = "SELECT id, document_id, batch_id, document_type, raw_text,
+        structured_data, confidence, model_used, processing_time_ms, superseded_by, llm_attempts,
+        status, llm_error, validation, prompt_tokens, completion_tokens, cost_usd,
+        partial, skipped_pages, CAST(created_at AS VARCHAR)
+     FROM extractions";
+
 impl ExtractionDao {
     pub fn create(
         pool: &DbPool,
@@ -21,10 +27,13 @@ impl ExtractionDao {
             let conn = pool.conn();
             let structured_json = structured_data.map(|v| v.to_string());
 
+            // Without structured data the extraction still awaits the LLM
+            let status = if structured_data.is_some() { "structured" } else { "pending" };
+
             conn.execute(
                 "INSERT INTO extractions (id, document_id, batch_id, document_type, raw_text,
-                 structured_data, confidence, model_used, processing_time_ms)
-                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                 structured_data, confidence, model_used, processing_time_ms, status)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
                 params![
                     id,
                     document_id,
@@ -34,7 +43,8 @@ impl ExtractionDao {
                     structured_json,
                     confidence,
                     model_used,
-                    processing_time_ms
+                    processing_time_ms,
+                    status
                 ],
             )?;
         }
@@ -44,9 +54,7 @@ impl ExtractionDao {
     pub fn get_by_id(pool: &DbPool, id: &str) -> Result<Extraction, duckdb::Error> {
         let conn = pool.conn();
         conn.query_row(
-            "SELECT id, document_id, batch_id, document_type, raw_text,
-                    structured_data, confidence, model_used, processing_time_ms, CAST(created_at AS VARCHAR)
-             FROM extractions WHERE id = ?",
+            &format!("{SELECT_COLUMNS} WHERE id = ?"),
             params![id],
             Self::map_row,
         )
@@ -54,30 +62,36 @@ impl ExtractionDao {
 
     pub fn list_by_batch(pool: &DbPool, batch_id: &str) -> Result<Vec<Extraction>, duckdb::Error> {
         let conn = pool.conn();
-        let mut stmt = conn.prepare(
-            "SELECT id, document_id, batch_id, document_type, raw_text,
-                    structured_data, confidence, model_used, processing_time_ms, CAST(created_at AS VARCHAR)
-             FROM extractions WHERE batch_id = ? ORDER BY created_at ASC",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "{SELECT_COLUMNS} WHERE batch_id = ? AND superseded_by IS NULL ORDER BY created_at ASC"
+        ))?;
 
         let rows = stmt.query_map(params![batch_id], Self::map_row)?;
         rows.collect()
     }
 
-    /// List extractions with optional filtering by document_type and min confidence.
+    /// All extractions of a document, newest first, including superseded ones.
+    pub fn list_by_document(pool: &DbPool, document_id: &str) -> Result<Vec<Extraction>, duckdb::Error> {
+        let conn = pool.conn();
+        let mut stmt = conn.prepare(&format!(
+            "{SELECT_COLUMNS} WHERE document_id = ? ORDER BY created_at DESC, id DESC"
+        ))?;
+
+        let rows = stmt.query_map(params![document_id], Self::map_row)?;
+        rows.collect()
+    }
+
+    /// List extractions with optional filtering by document_type, min confidence and status.
     pub fn list_by_batch_filtered(
         pool: &DbPool,
         batch_id: &str,
         document_type: Option<&str>,
         min_confidence: Option<f64>,
+        status: Option<&str>,
     ) -> Result<Vec<Extraction>, duckdb::Error> {
         let conn = pool.conn();
 
-        let mut sql = String::from(
-            "SELECT id, document_id, batch_id, document_type, raw_text,
-                    structured_data, confidence, model_used, processing_time_ms, CAST(created_at AS VARCHAR)
-             FROM extractions WHERE batch_id = ?",
-        );
+        let mut sql = format!("{SELECT_COLUMNS} WHERE batch_id = ? AND superseded_by IS NULL");
 
         let mut param_values: Vec<Box<dyn duckdb::ToSql>> = vec![Box::new(batch_id.to_string())];
 
@@ -91,6 +105,11 @@ impl ExtractionDao {
             param_values.push(Box::new(mc));
         }
 
+        if let Some(st) = status {
+            sql.push_str(" AND status = ?");
+            param_values.push(Box::new(st.to_string()));
+        }
+
         sql.push_str(" ORDER BY created_at ASC");
 
         let mut stmt = conn.prepare(&sql)?;
@@ -113,26 +132,162 @@ impl ExtractionDao {
 
         conn.execute(
             "UPDATE extractions SET document_type = ?, structured_data = ?, confidence = ?,
-             model_used = ?, processing_time_ms = ? WHERE id = ?",
+             model_used = ?, processing_time_ms = ?, status = 'structured', llm_error = NULL WHERE id = ?",
             params![document_type, structured_json, confidence, model_used, processing_time_ms, id],
         )?;
         Ok(())
     }
 
-    /// Delete all extractions for a batch.
+    /// Mark an extraction as not structured because the LLM step failed.
+    /// Its raw text is kept.
+    pub fn mark_llm_failed(pool: &DbPool, id: &str, error: &str) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE extractions SET status = 'llm_failed', llm_error = ? WHERE id = ?",
+            params![error, id],
+        )?;
+        Ok(())
+    }
+
+    /// Record how many attempts the LLM call(s) for this extraction took.
+    pub fn set_llm_attempts(pool: &DbPool, id: &str, attempts: u32) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE extractions SET llm_attempts = ? WHERE id = ?",
+            params![attempts as i32, id],
+        )?;
+        Ok(())
+    }
+
+    /// Record the tokens the LLM call(s) for this extraction used and their
+    /// estimated cost.
+    pub fn set_usage(
+        pool: &DbPool,
+        id: &str,
+        prompt_tokens: u64,
+        completion_tokens: u64,
+        cost_usd: f64,
+    ) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE extractions SET prompt_tokens = ?, completion_tokens = ?, cost_usd = ? WHERE id = ?",
+            params![prompt_tokens as i64, completion_tokens as i64, cost_usd, id],
+        )?;
+        Ok(())
+    }
+
+    /// Mark an extraction as partial: `skipped_pages` of the document were
+    /// not processed.
+    pub fn set_partial(pool: &DbPool, id: &str, skipped_pages: &[u32]) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE extractions SET partial = true, skipped_pages = ? WHERE id = ?",
+            params![serde_json::json!(skipped_pages).to_string(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Store the schema validation report of the structured data.
+    pub fn set_validation(
+        pool: &DbPool,
+        id: &str,
+        report: &serde_json::Value,
+    ) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE extractions SET validation = ? WHERE id = ?",
+            params![report.to_string(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Mark every extraction of a document except the newest as superseded by it.
+    ///
+    /// Superseded extractions are kept for history but no longer listed or exported.
+    pub fn supersede_older(pool: &DbPool, document_id: &str) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        let latest: Option<String> = conn
+            .query_row(
+                "SELECT id FROM extractions WHERE document_id = ?
+                 ORDER BY created_at DESC, id DESC LIMIT 1",
+                params![document_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        let Some(latest) = latest else {
+            return Ok(0);
+        };
+
+        conn.execute(
+            "UPDATE extractions SET superseded_by = ?
+             WHERE document_id = ? AND id <> ? AND superseded_by IS NULL",
+            params![latest, document_id, latest],
+        )
+    }
+
+    /// Delete all extractions for a batch, with their page results.
     pub fn delete_by_batch(pool: &DbPool, batch_id: &str) -> Result<usize, duckdb::Error> {
         let conn = pool.conn();
+        conn.execute(
+            "DELETE FROM extraction_pages
+             WHERE extraction_id IN (SELECT id FROM extractions WHERE batch_id = ?)",
+            params![batch_id],
+        )?;
         conn.execute(
             "DELETE FROM extractions WHERE batch_id = ?",
             params![batch_id],
         )
     }
 
+    /// Delete all extractions of a document, with their page results.
+    pub fn delete_by_document(pool: &DbPool, document_id: &str) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "DELETE FROM extraction_pages
+             WHERE extraction_id IN (SELECT id FROM extractions WHERE document_id = ?)",
+            params![document_id],
+        )?;
+        conn.execute(
+            "DELETE FROM extractions WHERE document_id = ?",
+            params![document_id],
+        )
+    }
+
+    /// Delete a document's extractions created at or after `since` (a timestamp
+    /// string as returned by the DAOs). Used to discard partial results of an
+    /// aborted attempt. Their page results are deleted too.
+    pub fn delete_by_document_since(
+        pool: &DbPool,
+        document_id: &str,
+        since: &str,
+    ) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "DELETE FROM extraction_pages WHERE extraction_id IN (
+                SELECT id FROM extractions WHERE document_id = ? AND created_at >= CAST(? AS TIMESTAMP))",
+            params![document_id, since],
+        )?;
+        conn.execute(
+            "DELETE FROM extractions WHERE document_id = ? AND created_at >= CAST(? AS TIMESTAMP)",
+            params![document_id, since],
+        )
+    }
+
     fn map_row(row: &duckdb::Row<'_>) -> Result<Extraction, duckdb::Error> {
         let structured_str: Option<String> = row.get(5)?;
         let structured_data = structured_str
             .as_deref()
             .and_then(|s| serde_json::from_str(s).ok());
+        let validation_str: Option<String> = row.get(13)?;
+        let validation = validation_str
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok());
+        let skipped_str: Option<String> = row.get(18)?;
+        let skipped_pages = skipped_str
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or_default();
 
         Ok(Extraction {
             id: row.get(0)?,
@@ -144,7 +299,17 @@ impl ExtractionDao {
             confidence: row.get(6)?,
             model_used: row.get(7)?,
             processing_time_ms: row.get(8)?,
-            created_at: row.get(9)?,
+            superseded_by: row.get(9)?,
+            llm_attempts: row.get::<_, Option<i32>>(10)?.unwrap_or(0),
+            status: row.get::<_, Option<String>>(11)?.unwrap_or_else(|| "structured".into()),
+            llm_error: row.get(12)?,
+            validation,
+            prompt_tokens: row.get::<_, Option<i64>>(14)?.unwrap_or(0),
+            completion_tokens: row.get::<_, Option<i64>>(15)?.unwrap_or(0),
+            cost_usd: row.get::<_, Option<f64>>(16)?.unwrap_or(0.0),
+            partial: row.get::<_, Option<bool>>(17)?.unwrap_or(false),
+            skipped_pages,
+            created_at: row.get(19)?,
         })
     }
 }
@@ -0,0 +1,70 @@
+use duckdb::params;
+use harvex_db::models::{ExtractionPage, NewExtractionPage};
+use harvex_db::DbPool;
+
+pub struct ExtractionPageDao;
+
+const SELECT_COLUMNS: &str = "SELECT id, extraction_id, page_number, status, error, raw_output,
+        latency_ms, attempts, CAST(created_at AS VARCHAR)
+     FROM extraction_pages";
+
+impl ExtractionPageDao {
+    pub fn create(
+        pool: &DbPool,
+        extraction_id: &str,
+        page: &NewExtractionPage,
+    ) -> Result<ExtractionPage, duckdb::Error> {
+        let id = nanoid::nanoid!();
+        {
+            let conn = pool.conn();
+            conn.execute(
+                "INSERT INTO extraction_pages (id, extraction_id, page_number, status, error,
+                 raw_output, latency_ms, attempts) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    id,
+                    extraction_id,
+                    page.page_number as i32,
+                    page.status,
+                    page.error,
+                    page.raw_output,
+                    page.latency_ms,
+                    page.attempts as i32
+                ],
+            )?;
+        }
+        let conn = pool.conn();
+        conn.query_row(
+            &format!("{SELECT_COLUMNS} WHERE id = ?"),
+            params![id],
+            Self::map_row,
+        )
+    }
+
+    /// Pages of an extraction in page order.
+    pub fn list_by_extraction(
+        pool: &DbPool,
+        extraction_id: &str,
+    ) -> Result<Vec<ExtractionPage>, duckdb::Error> {
+        let conn = pool.conn();
+        let mut stmt = conn.prepare(&format!(
+            "{SELECT_COLUMNS} WHERE extraction_id = ? ORDER BY page_number ASC"
+        ))?;
+
+        let rows = stmt.query_map(params![extraction_id], Self::map_row)?;
+        rows.collect()
+    }
+
+    fn map_row(row: &duckdb::Row<'_>) -> Result<ExtractionPage, duckdb::Error> {
+        Ok(ExtractionPage {
+            id: row.get(0)?,
+            extraction_id: row.get(1)?,
+            page_number: row.get(2)?,
+            status: row.get(3)?,
+            error: row.get(4)?,
+            raw_output: row.get(5)?,
+            latency_ms: row.get(6)?,
+            attempts: row.get(7)?,
+            created_at: row.get(8)?,
+        })
+    }
+}
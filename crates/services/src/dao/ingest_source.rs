@@ -0,0 +1,125 @@
+use duckdb::{params, OptionalExt};
+use harvex_db::models::IngestSource;
+use harvex_db::DbPool;
+
+pub struct IngestSourceDao;
+
+const SELECT_COLUMNS: &str = "SELECT name, kind, uid_validity, last_uid, batch_id, batch_key,
+        items_ingested, CAST(last_poll_at AS VARCHAR), last_error,
+        CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR)
+     FROM ingest_sources";
+
+impl IngestSourceDao {
+    /// The state of a source, created empty on its first poll.
+    pub fn get_or_create(pool: &DbPool, name: &str, kind: &str) -> Result<IngestSource, duckdb::Error> {
+        {
+            let conn = pool.conn();
+            conn.execute(
+                "INSERT INTO ingest_sources (name, kind) VALUES (?, ?)
+                 ON CONFLICT (name) DO UPDATE SET kind = excluded.kind",
+                params![name, kind],
+            )?;
+        }
+        Self::get(pool, name)
+    }
+
+    pub fn get(pool: &DbPool, name: &str) -> Result<IngestSource, duckdb::Error> {
+        let conn = pool.conn();
+        conn.query_row(
+            &format!("{SELECT_COLUMNS} WHERE name = ?"),
+            params![name],
+            Self::map_row,
+        )
+    }
+
+    pub fn list(pool: &DbPool) -> Result<Vec<IngestSource>, duckdb::Error> {
+        let conn = pool.conn();
+        let mut stmt = conn.prepare(&format!("{SELECT_COLUMNS} ORDER BY name ASC"))?;
+        let rows = stmt.query_map([], Self::map_row)?;
+        rows.collect()
+    }
+
+    /// Record a finished poll that ingested `ingested` items, and its error
+    /// if it failed part way.
+    pub fn record_poll(
+        pool: &DbPool,
+        name: &str,
+        ingested: usize,
+        error: Option<&str>,
+    ) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE ingest_sources SET items_ingested = items_ingested + ?, last_error = ?,
+                last_poll_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+             WHERE name = ?",
+            params![ingested as i64, error, name],
+        )?;
+        Ok(())
+    }
+
+    /// Remember the highest IMAP UID ingested, valid under `uid_validity` only.
+    pub fn set_imap_position(
+        pool: &DbPool,
+        name: &str,
+        uid_validity: i64,
+        last_uid: i64,
+    ) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE ingest_sources SET uid_validity = ?, last_uid = ?, updated_at = CURRENT_TIMESTAMP
+             WHERE name = ?",
+            params![uid_validity, last_uid, name],
+        )?;
+        Ok(())
+    }
+
+    /// Set the batch new items go into while `batch_key` stays the same,
+    /// and remember it as the batch of that key.
+    pub fn set_batch(
+        pool: &DbPool,
+        name: &str,
+        batch_id: &str,
+        batch_key: &str,
+    ) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE ingest_sources SET batch_id = ?, batch_key = ?, updated_at = CURRENT_TIMESTAMP
+             WHERE name = ?",
+            params![batch_id, batch_key, name],
+        )?;
+        conn.execute(
+            "INSERT INTO ingest_batches (source, batch_key, batch_id) VALUES (?, ?, ?)
+             ON CONFLICT (source, batch_key) DO UPDATE SET batch_id = excluded.batch_id",
+            params![name, batch_key, batch_id],
+        )?;
+        Ok(())
+    }
+
+    /// The batch a source last put items of `batch_key` into, if any. It
+    /// may have been deleted since.
+    pub fn batch_for_key(pool: &DbPool, name: &str, batch_key: &str) -> Result<Option<String>, duckdb::Error> {
+        let conn = pool.conn();
+        conn.query_row(
+            "SELECT batch_id FROM ingest_batches WHERE source = ? AND batch_key = ?",
+            params![name, batch_key],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    fn map_row(row: &duckdb::Row<'_>) -> Result<IngestSource, duckdb::Error> {
+        Ok(IngestSource {
+            name: row.get(0)?,
+            kind: row.get(1)?,
+            uid_validity: row.get(2)?,
+            last_uid: row.get(3)?,
+            batch_id: row.get(4)?,
+            batch_key: row.get(5)?,
+            items_ingested: row.get(6)?,
+            last_poll_at: row.get(7)?,
+            last_error: row.get(8)?,
+            created_at: row.get(9)?,
+            updated_at: row.get(10)?,
+        })
+    }
+}
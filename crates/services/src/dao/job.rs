@@ -0,0 +1,279 @@
+use duckdb::{params, OptionalExt};
+use harvex_db::models::Job;
+use harvex_db::DbPool;
+
+pub struct JobDao;
+
+const SELECT_COLUMNS: &str = "SELECT id, batch_id, document_id, status, attempts, max_attempts,
+        CAST(lease_expires_at AS VARCHAR), last_error, model_override, document_type_override,
+        CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR)
+     FROM jobs";
+
+impl JobDao {
+    /// Queue a document for processing. If the document already has an open
+    /// (queued or leased) job, that job is returned instead of a duplicate.
+    pub fn enqueue(
+        pool: &DbPool,
+        batch_id: &str,
+        document_id: &str,
+        max_attempts: u32,
+    ) -> Result<Job, duckdb::Error> {
+        Self::enqueue_with(pool, batch_id, document_id, max_attempts, None, None)
+    }
+
+    /// Like [`JobDao::enqueue`], with a model and/or document type override
+    /// applied when the job is processed.
+    pub fn enqueue_with(
+        pool: &DbPool,
+        batch_id: &str,
+        document_id: &str,
+        max_attempts: u32,
+        model_override: Option<&str>,
+        document_type_override: Option<&str>,
+    ) -> Result<Job, duckdb::Error> {
+        let id = {
+            let conn = pool.conn();
+            let existing: Option<String> = conn
+                .query_row(
+                    "SELECT id FROM jobs WHERE document_id = ? AND status IN ('queued', 'leased')",
+                    params![document_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            match existing {
+                Some(id) => id,
+                None => {
+                    let id = nanoid::nanoid!();
+                    conn.execute(
+                        "INSERT INTO jobs (id, batch_id, document_id, max_attempts,
+                         model_override, document_type_override) VALUES (?, ?, ?, ?, ?, ?)",
+                        params![
+                            id,
+                            batch_id,
+                            document_id,
+                            max_attempts as i32,
+                            model_override,
+                            document_type_override
+                        ],
+                    )?;
+                    id
+                }
+            }
+        };
+        Self::get_by_id(pool, &id)
+    }
+
+    /// The document's open (queued or leased) job, if it has one.
+    pub fn open_for_document(pool: &DbPool, document_id: &str) -> Result<Option<Job>, duckdb::Error> {
+        let conn = pool.conn();
+        conn.query_row(
+            &format!("{SELECT_COLUMNS} WHERE document_id = ? AND status IN ('queued', 'leased')"),
+            params![document_id],
+            Self::map_row,
+        )
+        .optional()
+    }
+
+    /// Replace the overrides of a job that is still queued. Returns `false`
+    /// if it is no longer queued.
+    pub fn set_overrides(
+        pool: &DbPool,
+        id: &str,
+        model_override: Option<&str>,
+        document_type_override: Option<&str>,
+    ) -> Result<bool, duckdb::Error> {
+        let conn = pool.conn();
+        let updated = conn.execute(
+            "UPDATE jobs SET model_override = ?, document_type_override = ?,
+                updated_at = CURRENT_TIMESTAMP
+             WHERE id = ? AND status = 'queued'",
+            params![model_override, document_type_override, id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    pub fn get_by_id(pool: &DbPool, id: &str) -> Result<Job, duckdb::Error> {
+        let conn = pool.conn();
+        conn.query_row(
+            &format!("{SELECT_COLUMNS} WHERE id = ?"),
+            params![id],
+            Self::map_row,
+        )
+    }
+
+    pub fn list_by_batch(pool: &DbPool, batch_id: &str) -> Result<Vec<Job>, duckdb::Error> {
+        let conn = pool.conn();
+        let mut stmt = conn.prepare(&format!(
+            "{SELECT_COLUMNS} WHERE batch_id = ? ORDER BY created_at ASC"
+        ))?;
+        let rows = stmt.query_map(params![batch_id], Self::map_row)?;
+        rows.collect()
+    }
+
+    /// Lease the oldest queued job of a batch for `lease_secs` seconds.
+    ///
+    /// Selection and update happen under the same connection lock, so two
+    /// workers can never lease the same job.
+    pub fn lease_next(
+        pool: &DbPool,
+        batch_id: &str,
+        lease_secs: u64,
+    ) -> Result<Option<Job>, duckdb::Error> {
+        let id = {
+            let conn = pool.conn();
+            let id: Option<String> = conn
+                .query_row(
+                    "SELECT id FROM jobs WHERE batch_id = ? AND status = 'queued'
+                     ORDER BY created_at ASC, id ASC LIMIT 1",
+                    params![batch_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            let Some(id) = id else {
+                return Ok(None);
+            };
+
+            conn.execute(
+                "UPDATE jobs SET status = 'leased', attempts = attempts + 1,
+                 lease_expires_at = CAST(CURRENT_TIMESTAMP AS TIMESTAMP) + to_seconds(?),
+                 updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+                params![lease_secs as i64, id],
+            )?;
+            id
+        };
+        Self::get_by_id(pool, &id).map(Some)
+    }
+
+    /// Mark a job as successfully done.
+    pub fn complete(pool: &DbPool, id: &str) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE jobs SET status = 'done', last_error = NULL, lease_expires_at = NULL,
+             updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            params![id],
+        )?;
+        Ok(())
+    }
+
+    /// Record a failed attempt. The job goes back to the queue while it has
+    /// attempts left, otherwise it is marked as failed.
+    ///
+    /// Returns `true` if the job was re-queued.
+    pub fn fail(pool: &DbPool, id: &str, error: &str) -> Result<bool, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE jobs SET status = CASE WHEN attempts < max_attempts THEN 'queued' ELSE 'failed' END,
+             last_error = ?, lease_expires_at = NULL, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            params![error, id],
+        )?;
+        let status: String =
+            conn.query_row("SELECT status FROM jobs WHERE id = ?", params![id], |row| row.get(0))?;
+        Ok(status == "queued")
+    }
+
+    /// Mark a job as failed without further attempts (e.g. its document is gone).
+    pub fn abandon(pool: &DbPool, id: &str, error: &str) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE jobs SET status = 'failed', last_error = ?, lease_expires_at = NULL,
+             updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            params![error, id],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a single job as cancelled.
+    pub fn cancel(pool: &DbPool, id: &str) -> Result<(), duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE jobs SET status = 'cancelled', lease_expires_at = NULL,
+             updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            params![id],
+        )?;
+        Ok(())
+    }
+
+    /// Cancel every queued or leased job of a batch. Returns the number of cancelled jobs.
+    pub fn cancel_open(pool: &DbPool, batch_id: &str) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE jobs SET status = 'cancelled', lease_expires_at = NULL, updated_at = CURRENT_TIMESTAMP
+             WHERE batch_id = ? AND status IN ('queued', 'leased')",
+            params![batch_id],
+        )
+    }
+
+    /// Return leased jobs whose lease has expired to the queue.
+    pub fn requeue_expired(pool: &DbPool) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE jobs SET status = 'queued', lease_expires_at = NULL, updated_at = CURRENT_TIMESTAMP
+             WHERE status = 'leased' AND lease_expires_at < CAST(CURRENT_TIMESTAMP AS TIMESTAMP)",
+            [],
+        )
+    }
+
+    /// Return every leased job to the queue, regardless of its lease.
+    ///
+    /// Only safe at startup, before any worker of this process is running:
+    /// DuckDB allows a single writer, so leases left behind belong to a dead process.
+    pub fn release_all_leases(pool: &DbPool) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute(
+            "UPDATE jobs SET status = 'queued', lease_expires_at = NULL, updated_at = CURRENT_TIMESTAMP
+             WHERE status = 'leased'",
+            [],
+        )
+    }
+
+    /// Number of queued or leased jobs in a batch.
+    pub fn count_open(pool: &DbPool, batch_id: &str) -> Result<i64, duckdb::Error> {
+        let conn = pool.conn();
+        conn.query_row(
+            "SELECT COUNT(*) FROM jobs WHERE batch_id = ? AND status IN ('queued', 'leased')",
+            params![batch_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// IDs of batches that still have queued or leased jobs.
+    pub fn batches_with_open_jobs(pool: &DbPool) -> Result<Vec<String>, duckdb::Error> {
+        let conn = pool.conn();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT batch_id FROM jobs WHERE status IN ('queued', 'leased') ORDER BY batch_id",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Delete all jobs for a batch.
+    pub fn delete_by_batch(pool: &DbPool, batch_id: &str) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute("DELETE FROM jobs WHERE batch_id = ?", params![batch_id])
+    }
+
+    /// Delete all jobs for a document.
+    pub fn delete_by_document(pool: &DbPool, document_id: &str) -> Result<usize, duckdb::Error> {
+        let conn = pool.conn();
+        conn.execute("DELETE FROM jobs WHERE document_id = ?", params![document_id])
+    }
+
+    fn map_row(row: &duckdb::Row<'_>) -> Result<Job, duckdb::Error> {
+        Ok(Job {
+            id: row.get(0)?,
+            batch_id: row.get(1)?,
+            document_id: row.get(2)?,
+            status: row.get(3)?,
+            attempts: row.get(4)?,
+            max_attempts: row.get(5)?,
+            lease_expires_at: row.get(6)?,
+            last_error: row.get(7)?,
+            model_override: row.get(8)?,
+            document_type_override: row.get(9)?,
+            created_at: row.get(10)?,
+            updated_at: row.get(11)?,
+        })
+    }
+}
@@ -1,7 +1,15 @@
 mod batch;
 mod document;
+mod document_type;
 mod extraction;
+mod extraction_page;
+mod ingest_source;
+mod job;
 
 pub use batch::BatchDao;
 pub use document::DocumentDao;
+pub use document_type::DocumentTypeDao;
 pub use extraction::ExtractionDao;
+pub use extraction_page::ExtractionPageDao;
+pub use ingest_source::IngestSourceDao;
+pub use job::JobDao;
@@ -0,0 +1,226 @@
+use serde_json::json;
+
+use super::{DocumentTypeDef, FALLBACK_TYPE};
+
+/// Document types that ship with Harvex.
+///
+/// Their schemas describe the same fields the hand-written prompts used to
+/// list, so prompts generated from them ask for the same output.
+pub(super) fn builtin_types() -> Vec<DocumentTypeDef> {
+    vec![
+        builtin(
+            "invoice",
+            "invoice",
+            None,
+            &["invoice", "faktura", "bill to", "invoice number", "inv no"],
+            10,
+            json!({
+                "type": "object",
+                "properties": {
+                    "vendor_name": { "type": "string" },
+                    "vendor_address": { "type": ["string", "null"] },
+                    "vendor_tax_id": { "type": ["string", "null"] },
+                    "buyer_name": { "type": ["string", "null"] },
+                    "buyer_address": { "type": ["string", "null"] },
+                    "buyer_tax_id": { "type": ["string", "null"] },
+                    "invoice_number": { "type": "string" },
+                    "invoice_date": { "type": "string", "format": "date" },
+                    "due_date": { "type": ["string", "null"], "format": "date" },
+                    "currency": { "type": "string", "description": "3-letter code" },
+                    "subtotal": { "type": ["number", "null"] },
+                    "tax_amount": { "type": ["number", "null"] },
+                    "tax_rate": { "type": ["string", "null"], "description": "percentage" },
+                    "total_amount": { "type": "number" },
+                    "line_items": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "description": { "type": "string" },
+                                "quantity": { "type": ["number", "null"] },
+                                "unit_price": { "type": ["number", "null"] },
+                                "amount": { "type": "number" }
+                            },
+                            "required": ["description", "amount"]
+                        }
+                    },
+                    "payment_terms": { "type": ["string", "null"] },
+                    "notes": { "type": ["string", "null"] }
+                },
+                "required": ["vendor_name", "invoice_number", "invoice_date", "total_amount"]
+            }),
+        ),
+        builtin(
+            "bank_statement",
+            "bank statement",
+            Some(
+                "Amounts as numbers (positive for credits, negative for debits in the amount field)\n\
+                 Only include last 4 digits of account numbers",
+            ),
+            &[
+                "bank statement",
+                "account statement",
+                "transaction history",
+                "balance+debit",
+                "balance+credit",
+            ],
+            20,
+            json!({
+                "type": "object",
+                "properties": {
+                    "bank_name": { "type": "string" },
+                    "account_holder": { "type": ["string", "null"] },
+                    "account_number": {
+                        "type": "string",
+                        "description": "last 4 digits only for security"
+                    },
+                    "statement_period_start": { "type": "string", "format": "date" },
+                    "statement_period_end": { "type": "string", "format": "date" },
+                    "currency": { "type": "string", "description": "3-letter code" },
+                    "opening_balance": { "type": "number" },
+                    "closing_balance": { "type": "number" },
+                    "total_deposits": { "type": ["number", "null"] },
+                    "total_withdrawals": { "type": ["number", "null"] },
+                    "transactions": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "date": { "type": "string", "format": "date" },
+                                "description": { "type": "string" },
+                                "amount": { "type": "number" },
+                                "type": { "type": "string", "enum": ["credit", "debit"] },
+                                "balance": { "type": ["number", "null"] }
+                            },
+                            "required": ["date", "description", "amount"]
+                        }
+                    }
+                },
+                "required": ["bank_name", "opening_balance", "closing_balance"]
+            }),
+        ),
+        builtin(
+            "payment",
+            "payment",
+            None,
+            &["payment", "paid", "amount due"],
+            30,
+            json!({
+                "type": "object",
+                "properties": {
+                    "payer_name": { "type": "string" },
+                    "payee_name": { "type": "string" },
+                    "payment_date": { "type": "string", "format": "date" },
+                    "payment_method": {
+                        "type": "string",
+                        "description": "bank_transfer, credit_card, cash, check, etc."
+                    },
+                    "reference_number": { "type": ["string", "null"] },
+                    "invoice_reference": { "type": ["string", "null"] },
+                    "currency": { "type": "string", "description": "3-letter code" },
+                    "amount": { "type": "number" },
+                    "status": { "type": "string", "enum": ["completed", "pending", "failed"] },
+                    "notes": { "type": ["string", "null"] }
+                },
+                "required": ["payment_date", "amount"]
+            }),
+        ),
+        builtin(
+            "receipt",
+            "receipt",
+            None,
+            &["receipt", "cash register", "total+tax"],
+            40,
+            json!({
+                "type": "object",
+                "properties": {
+                    "merchant_name": { "type": "string" },
+                    "merchant_address": { "type": ["string", "null"] },
+                    "receipt_number": { "type": ["string", "null"] },
+                    "date": { "type": "string", "format": "date" },
+                    "time": { "type": ["string", "null"], "description": "HH:MM" },
+                    "currency": { "type": "string", "description": "3-letter code" },
+                    "items": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "description": { "type": "string" },
+                                "quantity": { "type": ["number", "null"] },
+                                "unit_price": { "type": ["number", "null"] },
+                                "amount": { "type": "number" }
+                            },
+                            "required": ["description", "amount"]
+                        }
+                    },
+                    "subtotal": { "type": ["number", "null"] },
+                    "tax_amount": { "type": ["number", "null"] },
+                    "total_amount": { "type": "number" },
+                    "payment_method": { "type": ["string", "null"] }
+                },
+                "required": ["merchant_name", "date", "total_amount"]
+            }),
+        ),
+        builtin(
+            FALLBACK_TYPE,
+            "document",
+            Some(
+                "Analyze the document and determine its type, then extract relevant fields\n\
+                 key_fields: extract any important name-value pairs not covered above",
+            ),
+            &[],
+            i32::MAX,
+            json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": ["string", "null"] },
+                    "date": { "type": ["string", "null"], "format": "date" },
+                    "parties": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "list of people/organizations mentioned"
+                    },
+                    "amounts": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "label": { "type": "string" },
+                                "value": { "type": "number" },
+                                "currency": { "type": ["string", "null"], "description": "3-letter code" }
+                            }
+                        }
+                    },
+                    "key_fields": {
+                        "type": "object",
+                        "additionalProperties": true
+                    },
+                    "summary": {
+                        "type": "string",
+                        "description": "brief one-sentence summary of the document"
+                    }
+                }
+            }),
+        ),
+    ]
+}
+
+fn builtin(
+    name: &str,
+    label: &str,
+    instructions: Option<&str>,
+    keywords: &[&str],
+    priority: i32,
+    schema: serde_json::Value,
+) -> DocumentTypeDef {
+    DocumentTypeDef {
+        id: None,
+        name: name.to_string(),
+        label: label.to_string(),
+        instructions: instructions.map(str::to_string),
+        schema,
+        keywords: keywords.iter().map(|k| k.to_string()).collect(),
+        priority,
+        builtin: true,
+    }
+}
@@ -0,0 +1,268 @@
+mod builtin;
+
+use harvex_db::models::DocumentType;
+use harvex_db::DbPool;
+use serde::Serialize;
+
+use crate::dao::DocumentTypeDao;
+
+/// Type used when no other type matches. Always present in the catalog.
+pub const FALLBACK_TYPE: &str = "other";
+
+/// Default classification priority of user-defined types (built-in types use 10–40).
+pub const DEFAULT_PRIORITY: i32 = 100;
+
+/// A document type as used by classification and prompt generation.
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentTypeDef {
+    /// Database ID; `None` for built-in types.
+    pub id: Option<String>,
+    pub name: String,
+    pub label: String,
+    pub instructions: Option<String>,
+    pub schema: serde_json::Value,
+    pub keywords: Vec<String>,
+    pub priority: i32,
+    pub builtin: bool,
+}
+
+impl DocumentTypeDef {
+    pub fn is_fallback(&self) -> bool {
+        self.name == FALLBACK_TYPE
+    }
+
+    /// Schema of the complete model output: the type's schema plus the
+    /// `document_type` and `confidence` fields every prompt asks for.
+    pub fn output_schema(&self) -> serde_json::Value {
+        let mut schema = self.schema.clone();
+        if !schema.is_object() {
+            schema = serde_json::json!({});
+        }
+        schema["type"] = "object".into();
+
+        let document_type = if self.is_fallback() {
+            serde_json::json!({ "type": "string" })
+        } else {
+            serde_json::json!({ "type": "string", "enum": [self.name] })
+        };
+        if !schema["properties"].is_object() {
+            schema["properties"] = serde_json::json!({});
+        }
+        schema["properties"]["document_type"] = document_type;
+        schema["properties"]["confidence"] =
+            serde_json::json!({ "type": "number", "minimum": 0, "maximum": 1 });
+
+        let mut required: Vec<serde_json::Value> = schema["required"].as_array().cloned().unwrap_or_default();
+        for field in ["document_type", "confidence"] {
+            if !required.iter().any(|r| r == field) {
+                required.push(field.into());
+            }
+        }
+        schema["required"] = required.into();
+        schema
+    }
+
+    /// Whether the (lowercased) text matches any keyword. A keyword of the
+    /// form `a+b` matches only if every term appears.
+    fn matches(&self, lower_text: &str) -> bool {
+        self.keywords.iter().any(|keyword| {
+            let mut terms = keyword.split('+').map(str::trim).filter(|t| !t.is_empty()).peekable();
+            terms.peek().is_some() && terms.all(|term| lower_text.contains(&term.to_lowercase()))
+        })
+    }
+}
+
+impl From<DocumentType> for DocumentTypeDef {
+    fn from(row: DocumentType) -> Self {
+        Self {
+            id: Some(row.id),
+            name: row.name,
+            label: row.label,
+            instructions: row.instructions,
+            schema: row.json_schema,
+            keywords: row.keywords,
+            priority: row.priority,
+            builtin: false,
+        }
+    }
+}
+
+/// All known document types: the built-in ones plus those stored in the
+/// `document_types` table. A stored type with the name of a built-in one
+/// replaces it.
+#[derive(Debug, Clone)]
+pub struct DocumentTypeCatalog {
+    /// Sorted by priority, then name.
+    types: Vec<DocumentTypeDef>,
+}
+
+impl DocumentTypeCatalog {
+    /// Catalog of the built-in types only.
+    pub fn builtin() -> Self {
+        Self::with_types(builtin::builtin_types())
+    }
+
+    /// Load the catalog, merging user-defined types over the built-in ones.
+    pub fn load(pool: &DbPool) -> Result<Self, duckdb::Error> {
+        let mut types = builtin::builtin_types();
+        for row in DocumentTypeDao::list(pool)? {
+            let def = DocumentTypeDef::from(row);
+            match types.iter_mut().find(|t| t.name == def.name) {
+                Some(existing) => *existing = def,
+                None => types.push(def),
+            }
+        }
+        Ok(Self::with_types(types))
+    }
+
+    fn with_types(mut types: Vec<DocumentTypeDef>) -> Self {
+        types.sort_by(|a, b| a.priority.cmp(&b.priority).then_with(|| a.name.cmp(&b.name)));
+        Self { types }
+    }
+
+    pub fn list(&self) -> &[DocumentTypeDef] {
+        &self.types
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DocumentTypeDef> {
+        self.types.iter().find(|t| t.name == name)
+    }
+
+    /// Look up a type, falling back to the generic one for unknown names.
+    pub fn resolve(&self, name: &str) -> &DocumentTypeDef {
+        self.get(name).unwrap_or_else(|| self.fallback())
+    }
+
+    pub fn fallback(&self) -> &DocumentTypeDef {
+        self.get(FALLBACK_TYPE)
+            .expect("built-in fallback document type is always present")
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.types.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    /// Classify text by keywords: the first type (by priority) with a matching
+    /// keyword wins, otherwise the fallback type.
+    pub fn classify(&self, text: &str) -> &DocumentTypeDef {
+        let lower = text.to_lowercase();
+        self.types
+            .iter()
+            .filter(|t| !t.is_fallback())
+            .find(|t| t.matches(&lower))
+            .unwrap_or_else(|| self.fallback())
+    }
+}
+
+/// Check a user-supplied type definition. Returns a message describing the
+/// first problem found.
+pub fn validate_definition(
+    name: &str,
+    label: &str,
+    schema: &serde_json::Value,
+    keywords: &[String],
+) -> Result<(), String> {
+    let valid_name = name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid_name {
+        return Err(format!(
+            "Invalid name '{name}': use lowercase letters, digits and underscores, starting with a letter"
+        ));
+    }
+
+    if label.trim().is_empty() {
+        return Err("Label must not be empty".into());
+    }
+
+    let Some(obj) = schema.as_object() else {
+        return Err("json_schema must be a JSON object".into());
+    };
+    if let Some(ty) = obj.get("type")
+        && ty != "object"
+    {
+        return Err("json_schema must describe an object (\"type\": \"object\")".into());
+    }
+    match obj.get("properties").and_then(|p| p.as_object()) {
+        Some(props) if !props.is_empty() => {}
+        _ => return Err("json_schema must define at least one property".into()),
+    }
+
+    if keywords.iter().any(|k| k.split('+').all(|t| t.trim().is_empty())) {
+        return Err("Keywords must not be empty".into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_classification_matches_previous_heuristics() {
+        let catalog = DocumentTypeCatalog::builtin();
+        assert_eq!(catalog.classify("INVOICE #123 Bill To: Acme").name, "invoice");
+        assert_eq!(catalog.classify("Account statement for March").name, "bank_statement");
+        assert_eq!(catalog.classify("Closing balance 100, debit 20").name, "bank_statement");
+        assert_eq!(catalog.classify("Amount due: 50 EUR").name, "payment");
+        assert_eq!(catalog.classify("Total 10.00 incl. tax").name, "receipt");
+        assert_eq!(catalog.classify("Dear Sir or Madam").name, "other");
+    }
+
+    #[test]
+    fn combined_keywords_need_every_term() {
+        let catalog = DocumentTypeCatalog::builtin();
+        // "balance" alone is not enough for a bank statement
+        assert_eq!(catalog.classify("Remaining balance of the project").name, "other");
+    }
+
+    #[test]
+    fn priority_decides_between_matches() {
+        let mut custom = DocumentTypeCatalog::builtin().get("invoice").unwrap().clone();
+        custom.name = "purchase_order".into();
+        custom.keywords = vec!["purchase order".into()];
+        custom.priority = 5;
+        custom.builtin = false;
+
+        let mut types = builtin::builtin_types();
+        types.push(custom);
+        let catalog = DocumentTypeCatalog::with_types(types);
+
+        assert_eq!(catalog.classify("Purchase order referencing invoice 12").name, "purchase_order");
+        assert_eq!(catalog.classify("Invoice 12").name, "invoice");
+    }
+
+    #[test]
+    fn output_schema_adds_prompt_fields() {
+        let catalog = DocumentTypeCatalog::builtin();
+        let schema = catalog.resolve("invoice").output_schema();
+        assert_eq!(schema["properties"]["document_type"]["enum"], serde_json::json!(["invoice"]));
+        assert_eq!(schema["properties"]["confidence"]["type"], "number");
+        let required = schema["required"].as_array().unwrap();
+        assert!(required.iter().any(|r| r == "invoice_number"));
+        assert!(required.iter().any(|r| r == "confidence"));
+
+        let generic = catalog.fallback().output_schema();
+        assert!(generic["properties"]["document_type"].get("enum").is_none());
+        assert_eq!(generic["required"], serde_json::json!(["document_type", "confidence"]));
+    }
+
+    #[test]
+    fn resolve_unknown_falls_back() {
+        let catalog = DocumentTypeCatalog::builtin();
+        assert_eq!(catalog.resolve("spaceship").name, FALLBACK_TYPE);
+        assert!(catalog.get("receipt").is_some());
+    }
+
+    #[test]
+    fn validate_definition_rules() {
+        let schema = serde_json::json!({"type": "object", "properties": {"po_number": {"type": "string"}}});
+        assert!(validate_definition("purchase_order", "purchase order", &schema, &["po number".into()]).is_ok());
+        assert!(validate_definition("Purchase Order", "purchase order", &schema, &[]).is_err());
+        assert!(validate_definition("po", " ", &schema, &[]).is_err());
+        assert!(validate_definition("po", "po", &serde_json::json!({"type": "object"}), &[]).is_err());
+        assert!(validate_definition("po", "po", &serde_json::json!([]), &[]).is_err());
+        assert!(validate_definition("po", "po", &schema, &["+".into()]).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use harvex_db::models::Extraction;
+use harvex_db::models::{Document, EmailMetadata, Extraction};
 use harvex_db::DbPool;
 use rust_xlsxwriter::{Format, Workbook};
 use serde::Serialize;
@@ -9,6 +9,8 @@ use crate::dao::{BatchDao, DocumentDao, ExtractionDao};
 pub struct ExportFilter {
     pub document_type: Option<String>,
     pub min_confidence: Option<f64>,
+    /// Extraction status: `structured` or `llm_failed`.
+    pub status: Option<String>,
 }
 
 /// Enriched export record combining batch, document, and extraction data.
@@ -20,11 +22,18 @@ struct ExportRecord {
     confidence: f64,
     model_used: Option<String>,
     processing_time_ms: i64,
+    extraction_status: String,
+    llm_error: Option<String>,
+    partial: bool,
+    skipped_pages: Vec<u32>,
     // Document fields
     document_id: String,
     original_name: String,
     content_type: String,
     file_size: i64,
+    /// The email an attachment came with.
+    parent_document_id: Option<String>,
+    email: Option<EmailMetadata>,
     // Structured data (flattened at top level)
     #[serde(flatten)]
     structured_data: Option<serde_json::Value>,
@@ -41,7 +50,11 @@ struct BatchExport {
     total_files: i32,
     processed_files: i32,
     failed_files: i32,
+    extracted_only_files: i32,
     model_name: Option<String>,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    cost_usd: f64,
     created_at: String,
     extractions: Vec<ExportRecord>,
 }
@@ -66,7 +79,11 @@ impl ExportService {
             total_files: batch.total_files,
             processed_files: batch.processed_files,
             failed_files: batch.failed_files,
+            extracted_only_files: batch.extracted_only_files,
             model_name: batch.model_name,
+            prompt_tokens: batch.prompt_tokens,
+            completion_tokens: batch.completion_tokens,
+            cost_usd: batch.cost_usd,
             created_at: batch.created_at,
             extractions: records,
         };
@@ -83,9 +100,10 @@ impl ExportService {
     ) -> Result<Vec<u8>, anyhow::Error> {
         let extractions = get_filtered_extractions(pool, batch_id, filter)?;
         let documents = DocumentDao::list_by_batch(pool, batch_id)?;
-        let doc_map: std::collections::HashMap<String, String> = documents
+        let has_email = documents.iter().any(|d| d.email.is_some());
+        let doc_map: std::collections::HashMap<String, Document> = documents
             .into_iter()
-            .map(|d| (d.id, d.original_name))
+            .map(|d| (d.id.clone(), d))
             .collect();
 
         // Collect all unique keys from structured_data across extractions
@@ -99,10 +117,15 @@ impl ExportService {
             "document_id",
             "filename",
             "document_type",
+            "status",
             "confidence",
             "model_used",
             "processing_time_ms",
+            "llm_error",
         ];
+        if has_email {
+            headers.extend_from_slice(&["email_from", "email_subject", "email_date"]);
+        }
         let key_strings: Vec<String> = all_keys.iter().cloned().collect();
         let key_refs: Vec<&str> = key_strings.iter().map(|s| s.as_str()).collect();
         headers.extend_from_slice(&key_refs);
@@ -111,18 +134,24 @@ impl ExportService {
 
         // Data rows
         for ext in &extractions {
-            let filename = doc_map.get(&ext.document_id).cloned().unwrap_or_default();
+            let doc = doc_map.get(&ext.document_id);
+            let filename = doc.map(|d| d.original_name.as_str()).unwrap_or_default();
             let model = ext.model_used.as_deref().unwrap_or("");
 
             let mut row = vec![
                 csv_escape(&ext.id),
                 csv_escape(&ext.document_id),
-                csv_escape(&filename),
+                csv_escape(filename),
                 csv_escape(&ext.document_type),
+                csv_escape(&ext.status),
                 format!("{:.2}", ext.confidence),
                 csv_escape(model),
                 ext.processing_time_ms.to_string(),
+                csv_escape(ext.llm_error.as_deref().unwrap_or("")),
             ];
+            if has_email {
+                row.extend(email_fields(doc).iter().map(|field| csv_escape(field)));
+            }
 
             // Structured data columns
             for key in &all_keys {
@@ -152,9 +181,10 @@ impl ExportService {
             .map_err(|_| anyhow::anyhow!("Batch not found"))?;
         let extractions = get_filtered_extractions(pool, batch_id, filter)?;
         let documents = DocumentDao::list_by_batch(pool, batch_id)?;
-        let doc_map: std::collections::HashMap<String, String> = documents
+        let has_email = documents.iter().any(|d| d.email.is_some());
+        let doc_map: std::collections::HashMap<String, Document> = documents
             .into_iter()
-            .map(|d| (d.id, d.original_name))
+            .map(|d| (d.id.clone(), d))
             .collect();
 
         let mut workbook = Workbook::new();
@@ -182,9 +212,17 @@ impl ExportService {
         summary.write_number(r + 1, 1, batch.processed_files as f64)?;
         summary.write_string_with_format(r + 2, 0, "Failed", &header_fmt)?;
         summary.write_number(r + 2, 1, batch.failed_files as f64)?;
+        summary.write_string_with_format(r + 3, 0, "Without LLM", &header_fmt)?;
+        summary.write_number(r + 3, 1, batch.extracted_only_files as f64)?;
+        summary.write_string_with_format(r + 4, 0, "Prompt Tokens", &header_fmt)?;
+        summary.write_number(r + 4, 1, batch.prompt_tokens as f64)?;
+        summary.write_string_with_format(r + 5, 0, "Completion Tokens", &header_fmt)?;
+        summary.write_number(r + 5, 1, batch.completion_tokens as f64)?;
+        summary.write_string_with_format(r + 6, 0, "Estimated Cost (USD)", &header_fmt)?;
+        summary.write_number_with_format(r + 6, 1, batch.cost_usd, &Format::new().set_num_format("0.0000"))?;
         if let Some(ref model) = batch.model_name {
-            summary.write_string_with_format(r + 3, 0, "Model", &header_fmt)?;
-            summary.write_string(r + 3, 1, model)?;
+            summary.write_string_with_format(r + 7, 0, "Model", &header_fmt)?;
+            summary.write_string(r + 7, 1, model)?;
         }
 
         // --- All Extractions sheet ---
@@ -193,14 +231,19 @@ impl ExportService {
         all_sheet.set_name("Extractions")?;
 
         // Write headers
-        let base_headers = [
+        let mut base_headers = vec![
             "Extraction ID",
             "Filename",
             "Document Type",
+            "Status",
             "Confidence",
             "Model",
             "Time (ms)",
+            "LLM Error",
         ];
+        if has_email {
+            base_headers.extend_from_slice(&["Email From", "Email Subject", "Email Date"]);
+        }
         for (col, h) in base_headers.iter().enumerate() {
             all_sheet.write_string_with_format(0, col as u16, *h, &header_fmt)?;
         }
@@ -212,14 +255,22 @@ impl ExportService {
         // Write data
         for (row_idx, ext) in extractions.iter().enumerate() {
             let row = (row_idx + 1) as u32;
-            let filename = doc_map.get(&ext.document_id).cloned().unwrap_or_default();
+            let doc = doc_map.get(&ext.document_id);
+            let filename = doc.map(|d| d.original_name.as_str()).unwrap_or_default();
 
             all_sheet.write_string(row, 0, &ext.id)?;
-            all_sheet.write_string(row, 1, &filename)?;
+            all_sheet.write_string(row, 1, filename)?;
             all_sheet.write_string(row, 2, &ext.document_type)?;
-            all_sheet.write_number(row, 3, ext.confidence)?;
-            all_sheet.write_string(row, 4, ext.model_used.as_deref().unwrap_or(""))?;
-            all_sheet.write_number(row, 5, ext.processing_time_ms as f64)?;
+            all_sheet.write_string(row, 3, &ext.status)?;
+            all_sheet.write_number(row, 4, ext.confidence)?;
+            all_sheet.write_string(row, 5, ext.model_used.as_deref().unwrap_or(""))?;
+            all_sheet.write_number(row, 6, ext.processing_time_ms as f64)?;
+            all_sheet.write_string(row, 7, ext.llm_error.as_deref().unwrap_or(""))?;
+            if has_email {
+                for (i, field) in email_fields(doc).iter().enumerate() {
+                    all_sheet.write_string(row, 8 + i as u16, *field)?;
+                }
+            }
 
             // Structured data columns
             for (i, key) in all_keys.iter().enumerate() {
@@ -280,9 +331,12 @@ impl ExportService {
             // Data
             for (row_idx, ext) in typed.iter().enumerate() {
                 let row = (row_idx + 1) as u32;
-                let filename = doc_map.get(&ext.document_id).cloned().unwrap_or_default();
+                let filename = doc_map
+                    .get(&ext.document_id)
+                    .map(|d| d.original_name.as_str())
+                    .unwrap_or_default();
 
-                sheet.write_string(row, 0, &filename)?;
+                sheet.write_string(row, 0, filename)?;
                 sheet.write_number(row, 1, ext.confidence)?;
                 sheet.write_string(row, 2, ext.model_used.as_deref().unwrap_or(""))?;
 
@@ -324,10 +378,16 @@ fn build_export_records(
             confidence: ext.confidence,
             model_used: ext.model_used,
             processing_time_ms: ext.processing_time_ms,
+            extraction_status: ext.status,
+            llm_error: ext.llm_error,
+            partial: ext.partial,
+            skipped_pages: ext.skipped_pages,
             document_id: ext.document_id,
             original_name: doc.map(|d| d.original_name.clone()).unwrap_or_default(),
             content_type: doc.map(|d| d.content_type.clone()).unwrap_or_default(),
             file_size: doc.map(|d| d.file_size).unwrap_or(0),
+            parent_document_id: doc.and_then(|d| d.parent_id.clone()),
+            email: doc.and_then(|d| d.email.clone()),
             structured_data: ext.structured_data,
             raw_text: ext.raw_text,
         });
@@ -335,6 +395,16 @@ fn build_export_records(
     Ok(records)
 }
 
+/// Sender, subject and date of a document's email, empty when unknown.
+fn email_fields(doc: Option<&Document>) -> [&str; 3] {
+    let email = doc.and_then(|d| d.email.as_ref());
+    [
+        email.and_then(|e| e.from.as_deref()).unwrap_or(""),
+        email.and_then(|e| e.subject.as_deref()).unwrap_or(""),
+        email.and_then(|e| e.date.as_deref()).unwrap_or(""),
+    ]
+}
+
 /// Get extractions with optional filtering.
 fn get_filtered_extractions(
     pool: &DbPool,
@@ -346,6 +416,7 @@ fn get_filtered_extractions(
         batch_id,
         filter.document_type.as_deref(),
         filter.min_confidence,
+        filter.status.as_deref(),
     )?;
     Ok(extractions)
 }
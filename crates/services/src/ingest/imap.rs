@@ -0,0 +1,338 @@
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+/// Longest a single response line may be before the server is considered
+/// misbehaving; message bodies arrive as literals and are not limited by it.
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+trait ImapStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ImapStream for T {}
+
+/// A minimal IMAP4rev1 client: just what polling a folder for unseen
+/// messages needs.
+pub struct ImapClient {
+    stream: BufStream<Box<dyn ImapStream>>,
+    next_tag: u32,
+    /// Largest literal accepted from the server. The untagged responses to
+    /// one command may add up to this plus [`MAX_LINE_BYTES`].
+    max_literal: usize,
+}
+
+/// An untagged server response, with the literals (e.g. message bodies) it
+/// carried.
+#[derive(Debug, Default)]
+struct Untagged {
+    text: String,
+    literals: Vec<Vec<u8>>,
+}
+
+impl Untagged {
+    /// Bytes of text and literals held.
+    fn size(&self) -> usize {
+        self.text.len() + self.literals.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+impl ImapClient {
+    /// Connect and read the server greeting. With `tls` the connection is
+    /// encrypted from the start (IMAPS, usually port 993). A literal, e.g. a
+    /// message body, larger than `max_literal` bytes fails the response.
+    pub async fn connect(host: &str, port: u16, tls: bool, max_literal: usize) -> Result<Self, anyhow::Error> {
+        let tcp = TcpStream::connect((host, port)).await?;
+        let stream: Box<dyn ImapStream> = if tls {
+            let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+            Box::new(connector.connect(host, tcp).await?)
+        } else {
+            Box::new(tcp)
+        };
+
+        let mut client = Self {
+            stream: BufStream::new(stream),
+            next_tag: 1,
+            max_literal,
+        };
+        let greeting = client.read_response(client.response_cap()).await?;
+        if !greeting.text.starts_with("* OK") && !greeting.text.starts_with("* PREAUTH") {
+            return Err(anyhow::anyhow!("Unexpected IMAP greeting: {}", greeting.text));
+        }
+        Ok(client)
+    }
+
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<(), anyhow::Error> {
+        let command = format!("LOGIN {} {}", quote(username, "username")?, quote(password, "password")?);
+        self.command(&command, "LOGIN").await?;
+        Ok(())
+    }
+
+    /// Select a folder. Returns its UIDVALIDITY: UIDs seen under another
+    /// value no longer name the same messages.
+    pub async fn select(&mut self, folder: &str) -> Result<u32, anyhow::Error> {
+        let responses = self.command(&format!("SELECT {}", quote(folder, "folder")?), "SELECT").await?;
+        responses
+            .iter()
+            .find_map(|r| {
+                let rest = r.text.split("[UIDVALIDITY ").nth(1)?;
+                rest.split(']').next()?.trim().parse().ok()
+            })
+            .ok_or_else(|| anyhow::anyhow!("Server did not report UIDVALIDITY for {folder}"))
+    }
+
+    /// UIDs of the selected folder's messages without the `\Seen` flag.
+    pub async fn search_unseen(&mut self) -> Result<Vec<u32>, anyhow::Error> {
+        let responses = self.command("UID SEARCH UNSEEN", "SEARCH").await?;
+        let mut uids: Vec<u32> = responses
+            .iter()
+            .filter_map(|r| r.text.strip_prefix("* SEARCH"))
+            .flat_map(|rest| rest.split_whitespace().filter_map(|uid| uid.parse().ok()))
+            .collect();
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    /// Size of a message in bytes.
+    pub async fn message_size(&mut self, uid: u32) -> Result<u64, anyhow::Error> {
+        let responses = self.command(&format!("UID FETCH {uid} RFC822.SIZE"), "FETCH").await?;
+        responses
+            .iter()
+            .filter(|r| r.text.contains(" FETCH "))
+            .find_map(|r| {
+                let rest = r.text.split("RFC822.SIZE ").nth(1)?;
+                rest.split([' ', ')']).next()?.parse().ok()
+            })
+            .ok_or_else(|| anyhow::anyhow!("Server returned no size for message {uid}"))
+    }
+
+    /// The full message, without setting `\Seen`.
+    pub async fn fetch(&mut self, uid: u32) -> Result<Vec<u8>, anyhow::Error> {
+        let responses = self.command(&format!("UID FETCH {uid} BODY.PEEK[]"), "FETCH").await?;
+        responses
+            .into_iter()
+            .filter(|r| r.text.contains(" FETCH "))
+            .find_map(|r| r.literals.into_iter().next())
+            .ok_or_else(|| anyhow::anyhow!("Server returned no body for message {uid}"))
+    }
+
+    pub async fn mark_seen(&mut self, uid: u32) -> Result<(), anyhow::Error> {
+        self.command(&format!("UID STORE {uid} +FLAGS.SILENT (\\Seen)"), "STORE").await?;
+        Ok(())
+    }
+
+    pub async fn logout(mut self) -> Result<(), anyhow::Error> {
+        self.command("LOGOUT", "LOGOUT").await?;
+        Ok(())
+    }
+
+    /// Send a command and collect the untagged responses up to its tagged
+    /// completion, failing unless that is `OK`. `name` stands in for the
+    /// command in errors and logs, keeping credentials out of them. The
+    /// responses may add up to [`Self::response_cap`] bytes.
+    async fn command(&mut self, command: &str, name: &str) -> Result<Vec<Untagged>, anyhow::Error> {
+        let tag = format!("A{:04}", self.next_tag);
+        self.next_tag += 1;
+        debug!("IMAP {} {}", tag, name);
+
+        self.stream
+            .write_all(format!("{tag} {command}\r\n").as_bytes())
+            .await?;
+        self.stream.flush().await?;
+
+        let mut responses = Vec::new();
+        let mut remaining = self.response_cap();
+        loop {
+            let response = self.read_response(remaining).await?;
+            let Some(status) = response.text.strip_prefix(&format!("{tag} ")) else {
+                remaining -= response.size();
+                responses.push(response);
+                continue;
+            };
+            if status.starts_with("OK") {
+                return Ok(responses);
+            }
+            return Err(anyhow::anyhow!("IMAP {name} failed: {status}"));
+        }
+    }
+
+    /// Room for a literal of the largest allowed size and the lines around it.
+    fn response_cap(&self) -> usize {
+        self.max_literal.saturating_add(MAX_LINE_BYTES)
+    }
+
+    /// Read one response: a line, continued after each literal (`{n}` at
+    /// the end of a line, followed by n bytes) it announces. A response of
+    /// more than `limit` bytes fails.
+    async fn read_response(&mut self, limit: usize) -> Result<Untagged, anyhow::Error> {
+        let too_large = || anyhow::anyhow!("IMAP response larger than the {limit} bytes allowed");
+        let mut response = Untagged::default();
+        loop {
+            let mut line = Vec::new();
+            let read = (&mut self.stream)
+                .take(MAX_LINE_BYTES as u64)
+                .read_until(b'\n', &mut line)
+                .await?;
+            if read == 0 {
+                return Err(anyhow::anyhow!("IMAP server closed the connection"));
+            }
+            if !line.ends_with(b"\n") {
+                return Err(anyhow::anyhow!("IMAP response line too long"));
+            }
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            response.text.push_str(line);
+            if response.size() > limit {
+                return Err(too_large());
+            }
+
+            let Some(size) = literal_size(line) else {
+                return Ok(response);
+            };
+            // Checked before allocating what the server announced
+            if size > self.max_literal {
+                return Err(anyhow::anyhow!(
+                    "IMAP server sent a {size}-byte literal, more than the {} bytes allowed",
+                    self.max_literal
+                ));
+            }
+            if response.size() + size > limit {
+                return Err(too_large());
+            }
+            let mut literal = vec![0; size];
+            self.stream.read_exact(&mut literal).await?;
+            response.literals.push(literal);
+        }
+    }
+}
+
+/// Size of the literal announced at the end of a response line, if any.
+fn literal_size(line: &str) -> Option<usize> {
+    let open = line.rfind('{')?;
+    line.strip_suffix('}')?[open + 1..].trim_end_matches('+').parse().ok()
+}
+
+/// An IMAP quoted string. A quoted string cannot hold a line break, which
+/// would end the command; `what` names the value in the error.
+fn quote(value: &str, what: &str) -> Result<String, anyhow::Error> {
+    if value.contains(['\r', '\n']) {
+        return Err(anyhow::anyhow!("IMAP {what} must not contain line breaks"));
+    }
+    Ok(format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A local IMAP stand-in answering each expected command with a canned
+    /// response; `{tag}` in a response is replaced with the command's tag.
+    async fn fake_server(script: Vec<(&'static str, String)>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut socket = BufStream::new(socket);
+            socket.write_all(b"* OK IMAP4rev1 ready\r\n").await.unwrap();
+            socket.flush().await.unwrap();
+            for (expected, response) in script {
+                let mut line = String::new();
+                socket.read_line(&mut line).await.unwrap();
+                let (tag, command) = line.trim_end().split_once(' ').unwrap();
+                assert_eq!(command, expected);
+                socket
+                    .write_all(response.replace("{tag}", tag).as_bytes())
+                    .await
+                    .unwrap();
+                socket.flush().await.unwrap();
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn fetches_unseen_messages() {
+        let message = "Subject: Invoice\r\n\r\nTotal: 42.00\r\n";
+        let port = fake_server(vec![
+            ("LOGIN \"ap@example.com\" \"p\\\"w\"", "{tag} OK LOGIN completed\r\n".into()),
+            (
+                "SELECT \"INBOX\"",
+                "* 3 EXISTS\r\n* OK [UIDVALIDITY 1700] UIDs valid\r\n{tag} OK [READ-WRITE] SELECT completed\r\n".into(),
+            ),
+            ("UID SEARCH UNSEEN", "* SEARCH 12 7\r\n{tag} OK SEARCH completed\r\n".into()),
+            (
+                "UID FETCH 7 RFC822.SIZE",
+                "* 2 FETCH (UID 7 RFC822.SIZE 34)\r\n{tag} OK FETCH completed\r\n".into(),
+            ),
+            (
+                "UID FETCH 7 BODY.PEEK[]",
+                format!(
+                    "* 2 FETCH (UID 7 BODY[] {{{}}}\r\n{message})\r\n{{tag}} OK FETCH completed\r\n",
+                    message.len()
+                ),
+            ),
+            ("UID STORE 7 +FLAGS.SILENT (\\Seen)", "{tag} OK STORE completed\r\n".into()),
+            ("UID FETCH 12 BODY.PEEK[]", "{tag} NO Message expunged\r\n".into()),
+            ("LOGOUT", "* BYE\r\n{tag} OK LOGOUT completed\r\n".into()),
+        ])
+        .await;
+
+        let mut client = ImapClient::connect("127.0.0.1", port, false, 1024).await.unwrap();
+        client.login("ap@example.com", "p\"w").await.unwrap();
+        assert_eq!(client.select("INBOX").await.unwrap(), 1700);
+        assert_eq!(client.search_unseen().await.unwrap(), [7, 12]);
+        assert_eq!(client.message_size(7).await.unwrap(), 34);
+        assert_eq!(client.fetch(7).await.unwrap(), message.as_bytes());
+        client.mark_seen(7).await.unwrap();
+
+        let err = client.fetch(12).await.unwrap_err();
+        assert_eq!(err.to_string(), "IMAP FETCH failed: NO Message expunged");
+        client.logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn literals_over_the_limit_are_refused() {
+        let port = fake_server(vec![(
+            "UID FETCH 7 BODY.PEEK[]",
+            "* 2 FETCH (UID 7 BODY[] {4294967296}\r\n".into(),
+        )])
+        .await;
+
+        let mut client = ImapClient::connect("127.0.0.1", port, false, 1024).await.unwrap();
+        let err = client.fetch(7).await.unwrap_err();
+        assert!(err.to_string().contains("4294967296-byte literal"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn untagged_responses_over_the_limit_are_refused() {
+        let search = format!("* SEARCH {}\r\n", "1 ".repeat(1000));
+        let port = fake_server(vec![(
+            "UID SEARCH UNSEEN",
+            format!("{}{{tag}} OK SEARCH completed\r\n", search.repeat(40)),
+        )])
+        .await;
+
+        let mut client = ImapClient::connect("127.0.0.1", port, false, 1024).await.unwrap();
+        let err = client.search_unseen().await.unwrap_err();
+        assert!(err.to_string().contains("IMAP response larger than"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn line_breaks_in_quoted_values_are_refused() {
+        let port = fake_server(Vec::new()).await;
+
+        let mut client = ImapClient::connect("127.0.0.1", port, false, 1024).await.unwrap();
+        let err = client.login("ap@example.com\r\nA9 DELETE INBOX", "pw").await.unwrap_err();
+        assert_eq!(err.to_string(), "IMAP username must not contain line breaks");
+        let err = client.select("INBOX\n").await.unwrap_err();
+        assert_eq!(err.to_string(), "IMAP folder must not contain line breaks");
+    }
+
+    #[test]
+    fn literal_sizes() {
+        assert_eq!(literal_size("* 2 FETCH (UID 7 BODY[] {342}"), Some(342));
+        assert_eq!(literal_size("* 2 FETCH (UID 7 BODY[] {342+}"), Some(342));
+        assert_eq!(literal_size("* OK [UIDVALIDITY 1700]"), None);
+    }
+}
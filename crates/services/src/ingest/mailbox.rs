@@ -0,0 +1,274 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use harvex_config::{MailBatching, MailboxKind, MailboxSettings, Settings};
+use harvex_db::models::{Batch, Document};
+use harvex_db::DbPool;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::imap::ImapClient;
+use super::maildir::Maildir;
+use crate::dao::{BatchDao, IngestSourceDao};
+use crate::pipeline::email;
+use crate::pipeline::Pipeline;
+
+/// Polls one configured mailbox and ingests its unseen messages into
+/// batches.
+#[derive(Clone)]
+pub struct MailboxPoller {
+    db: DbPool,
+    pipeline: Arc<Pipeline>,
+    mailbox: MailboxSettings,
+    upload_dir: PathBuf,
+    /// Largest message or attachment ingested, in bytes.
+    max_file_size: u64,
+}
+
+/// Start a poller for every configured mailbox.
+pub fn spawn_mailbox_pollers(db: &DbPool, pipeline: &Arc<Pipeline>, settings: &Settings) -> Vec<JoinHandle<()>> {
+    settings
+        .ingest
+        .mailboxes
+        .iter()
+        .map(|mailbox| {
+            MailboxPoller::new(
+                db.clone(),
+                pipeline.clone(),
+                mailbox.clone(),
+                PathBuf::from(&settings.storage.upload_dir),
+                settings.storage.max_file_size_mb * 1024 * 1024,
+            )
+            .spawn()
+        })
+        .collect()
+}
+
+impl MailboxPoller {
+    pub fn new(
+        db: DbPool,
+        pipeline: Arc<Pipeline>,
+        mailbox: MailboxSettings,
+        upload_dir: PathBuf,
+        max_file_size: u64,
+    ) -> Self {
+        Self {
+            db,
+            pipeline,
+            mailbox,
+            upload_dir,
+            max_file_size,
+        }
+    }
+
+    /// Poll every `poll_interval_secs`, starting now, for as long as the
+    /// task runs.
+    pub fn spawn(self) -> JoinHandle<()> {
+        info!(
+            "Polling {} mailbox '{}' every {}s",
+            self.mailbox.kind.as_str(),
+            self.mailbox.name,
+            self.mailbox.poll_interval_secs
+        );
+        tokio::spawn(async move {
+            let period = Duration::from_secs(self.mailbox.poll_interval_secs.max(1));
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.poll().await {
+                    warn!("Polling mailbox '{}' failed: {e}", self.mailbox.name);
+                }
+            }
+        })
+    }
+
+    /// Ingest every unseen message once. Returns the number of messages
+    /// ingested; on error, the ones before it are kept.
+    pub async fn poll(&self) -> Result<usize, anyhow::Error> {
+        let name = &self.mailbox.name;
+        IngestSourceDao::get_or_create(&self.db, name, self.mailbox.kind.as_str())?;
+
+        let mut ingested = 0;
+        let result = match self.mailbox.kind {
+            MailboxKind::Imap => self.poll_imap(&mut ingested).await,
+            MailboxKind::Maildir => {
+                // Reading and storing files is blocking I/O
+                let poller = self.clone();
+                let (count, result) = tokio::task::spawn_blocking(move || {
+                    let mut ingested = 0;
+                    let result = poller.poll_maildir(&mut ingested);
+                    (ingested, result)
+                })
+                .await?;
+                ingested = count;
+                result
+            }
+        };
+        let error = result.as_ref().err().map(|e| e.to_string());
+        IngestSourceDao::record_poll(&self.db, name, ingested, error.as_deref())?;
+        if ingested > 0 {
+            info!("Ingested {} messages from mailbox '{}'", ingested, name);
+        }
+        result.map(|_| ingested)
+    }
+
+    async fn poll_imap(&self, ingested: &mut usize) -> Result<(), anyhow::Error> {
+        let mailbox = &self.mailbox;
+        let source = IngestSourceDao::get(&self.db, &mailbox.name)?;
+
+        let max_literal = usize::try_from(self.max_file_size).unwrap_or(usize::MAX);
+        let mut client = ImapClient::connect(&mailbox.host, mailbox.port, mailbox.tls, max_literal).await?;
+        client.login(&mailbox.username, &mailbox.password).await?;
+        let uid_validity = client.select(&mailbox.folder).await? as i64;
+
+        // Messages ingested before are skipped even if flagging them seen
+        // failed; UIDs only compare within one UIDVALIDITY
+        let last_uid = match source.uid_validity {
+            Some(validity) if validity == uid_validity => source.last_uid.unwrap_or(0),
+            _ => 0,
+        };
+        for uid in client.search_unseen().await? {
+            if (uid as i64) <= last_uid {
+                continue;
+            }
+            let size = client.message_size(uid).await?;
+            let result = match super::check_size(&format!("message {uid}"), size, self.max_file_size) {
+                Ok(()) => {
+                    let data = client.fetch(uid).await?;
+                    let poller = self.clone();
+                    let fallback_name = format!("message-{uid}");
+                    tokio::task::spawn_blocking(move || poller.ingest(&data, &fallback_name)).await?
+                }
+                Err(e) => Err(e.into()),
+            };
+            // A message too large stays unseen for someone to look at, but
+            // is not fetched again
+            let taken = self.taken(result)?;
+            IngestSourceDao::set_imap_position(&self.db, &mailbox.name, uid_validity, uid as i64)?;
+            if taken {
+                *ingested += 1;
+                client.mark_seen(uid).await?;
+            }
+        }
+
+        client.logout().await
+    }
+
+    fn poll_maildir(&self, ingested: &mut usize) -> Result<(), anyhow::Error> {
+        let maildir = Maildir::new(&self.mailbox.path);
+        for path in maildir.unseen()? {
+            let file_name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let fallback_name = file_name.split(':').next().unwrap_or("message");
+            let size = std::fs::metadata(&path)?.len();
+            let result = match super::check_size(fallback_name, size, self.max_file_size) {
+                Ok(()) => self.ingest(&std::fs::read(&path)?, fallback_name),
+                Err(e) => Err(e.into()),
+            };
+            // A message too large is marked seen as well, so it is not
+            // read again
+            if self.taken(result)? {
+                *ingested += 1;
+            }
+            maildir.mark_seen(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a message was ingested. One too large is skipped with a
+    /// warning instead of failing the poll, as it would fail every poll.
+    fn taken(&self, result: Result<Vec<Document>, anyhow::Error>) -> Result<bool, anyhow::Error> {
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.is::<super::TooLarge>() => {
+                warn!("Mailbox '{}' skipped a message: {e}", self.mailbox.name);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store a message and its attachments in the batch it belongs to, and
+    /// queue them if the mailbox processes automatically. A message that
+    /// cannot be parsed is still stored, to fail visibly in its batch.
+    fn ingest(&self, data: &[u8], fallback_name: &str) -> Result<Vec<Document>, anyhow::Error> {
+        let parsed = match email::parse(data) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                warn!("Mailbox '{}': {fallback_name} is not a valid email: {e}", self.mailbox.name);
+                None
+            }
+        };
+        let subject = parsed
+            .as_ref()
+            .and_then(|p| p.metadata.subject.as_deref())
+            .map(str::trim)
+            .filter(|s| !s.is_empty());
+        let file_name = format!("{}.eml", safe_file_name(subject.unwrap_or(fallback_name)));
+        // Rejected before a batch is created for it
+        for attachment in parsed.iter().flat_map(|p| &p.attachments) {
+            super::check_size(&attachment.name, attachment.data.len() as u64, self.max_file_size)?;
+        }
+
+        let batch = self.target_batch(subject.unwrap_or(fallback_name))?;
+        let documents = match &parsed {
+            Some(parsed) => super::store_email(
+                &self.db,
+                &self.upload_dir,
+                &batch.id,
+                &file_name,
+                "message/rfc822",
+                data,
+                parsed,
+            )?,
+            None => vec![super::store_document(
+                &self.db,
+                &self.upload_dir,
+                &batch.id,
+                &file_name,
+                "message/rfc822",
+                data,
+            )?],
+        };
+
+        super::finish_ingest(&self.db, &self.pipeline, &batch.id, &documents, self.mailbox.auto_process)?;
+        Ok(documents)
+    }
+
+    /// A new batch per message, or the mailbox's batch of the day.
+    fn target_batch(&self, subject: &str) -> Result<Batch, anyhow::Error> {
+        let name = &self.mailbox.name;
+        if self.mailbox.batching == MailBatching::Message {
+            return Ok(BatchDao::create(&self.db, &format!("{name}: {subject}"), None)?);
+        }
+
+        let today = chrono::Local::now().date_naive().to_string();
+        super::keyed_batch(&self.db, name, &today, &format!("{name} {today}"))
+    }
+}
+
+/// A subject made safe to use as a file name.
+fn safe_file_name(subject: &str) -> String {
+    let name: String = subject
+        .chars()
+        .map(|c| if c.is_alphanumeric() || " -_.".contains(c) { c } else { '_' })
+        .take(100)
+        .collect();
+    name.trim().trim_start_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subjects_become_safe_file_names() {
+        assert_eq!(safe_file_name("Invoice 2024/03: ACME"), "Invoice 2024_03_ ACME");
+        assert_eq!(safe_file_name("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(safe_file_name(&"x".repeat(300)).len(), 100);
+    }
+}
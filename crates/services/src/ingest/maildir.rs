@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+/// A maildir: messages are delivered into `new/` and moved to `cur/` once
+/// seen, with flags after `:2,` in the file name (`S` for seen).
+pub struct Maildir {
+    root: PathBuf,
+}
+
+impl Maildir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Messages not flagged as seen, oldest delivery first by name: all of
+    /// `new/`, and those in `cur/` without the `S` flag.
+    pub fn unseen(&self) -> std::io::Result<Vec<PathBuf>> {
+        if !self.root.join("new").is_dir() || !self.root.join("cur").is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is not a maildir (no new/ and cur/)", self.root.display()),
+            ));
+        }
+
+        let mut messages = Vec::new();
+        for dir in ["new", "cur"] {
+            for entry in std::fs::read_dir(self.root.join(dir))? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') || !entry.file_type()?.is_file() {
+                    continue;
+                }
+                if dir == "cur" && flags(&name).contains('S') {
+                    continue;
+                }
+                messages.push(entry.path());
+            }
+        }
+        messages.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        Ok(messages)
+    }
+
+    /// Flag a message as seen, moving it to `cur/`. Returns its new path.
+    pub fn mark_seen(&self, message: &Path) -> std::io::Result<PathBuf> {
+        let name = message
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (base, flags) = match name.split_once(":2,") {
+            Some((base, flags)) => (base, flags.to_string()),
+            None => (name.as_str(), String::new()),
+        };
+
+        // Flags are kept in ASCII order
+        let mut flags: Vec<char> = flags.chars().chain(['S']).collect();
+        flags.sort_unstable();
+        flags.dedup();
+        let flags: String = flags.into_iter().collect();
+
+        let target = self.root.join("cur").join(format!("{base}:2,{flags}"));
+        std::fs::rename(message, &target)?;
+        Ok(target)
+    }
+}
+
+/// Flags of a message file name, empty without any.
+fn flags(file_name: &str) -> &str {
+    file_name.split_once(":2,").map(|(_, flags)| flags).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unseen_messages_are_marked_seen() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["new", "cur", "tmp"] {
+            std::fs::create_dir(dir.path().join(sub)).unwrap();
+        }
+        std::fs::write(dir.path().join("new/1700000001.a.host"), "a").unwrap();
+        std::fs::write(dir.path().join("cur/1700000000.b.host:2,F"), "b").unwrap();
+        std::fs::write(dir.path().join("cur/1699999999.c.host:2,S"), "c").unwrap();
+        std::fs::write(dir.path().join("tmp/1700000002.d.host"), "d").unwrap();
+
+        let maildir = Maildir::new(dir.path());
+        let unseen = maildir.unseen().unwrap();
+        let names: Vec<_> = unseen.iter().map(|p| p.file_name().unwrap().to_owned()).collect();
+        assert_eq!(names, ["1700000000.b.host:2,F", "1700000001.a.host"]);
+
+        let seen = maildir.mark_seen(&unseen[0]).unwrap();
+        assert_eq!(seen, dir.path().join("cur/1700000000.b.host:2,FS"));
+        let seen = maildir.mark_seen(&unseen[1]).unwrap();
+        assert_eq!(seen, dir.path().join("cur/1700000001.a.host:2,S"));
+        assert!(maildir.unseen().unwrap().is_empty());
+
+        assert!(Maildir::new(dir.path().join("new")).unseen().is_err());
+    }
+}
@@ -0,0 +1,132 @@
+pub mod imap;
+pub mod mailbox;
+pub mod maildir;
+pub mod watch;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use harvex_db::models::{Batch, Document};
+use harvex_db::DbPool;
+
+use crate::dao::{BatchDao, DocumentDao, IngestSourceDao};
+use crate::pipeline::email::ParsedEmail;
+use crate::pipeline::{DocumentOptions, Pipeline};
+
+pub use mailbox::{spawn_mailbox_pollers, MailboxPoller};
+pub use watch::{spawn_folder_watchers, FolderWatcher};
+
+/// A file over `storage.max_file_size_mb`, which ingestion skips.
+#[derive(Debug, thiserror::Error)]
+#[error("{name} is {size} bytes, over the limit of {max_size} bytes")]
+pub struct TooLarge {
+    pub name: String,
+    pub size: u64,
+    pub max_size: u64,
+}
+
+/// Fail with [`TooLarge`] if a file of `size` bytes is over `max_size`.
+pub fn check_size(name: &str, size: u64, max_size: u64) -> Result<(), TooLarge> {
+    if size > max_size {
+        return Err(TooLarge {
+            name: name.to_string(),
+            size,
+            max_size,
+        });
+    }
+    Ok(())
+}
+
+/// Write a file into the batch's upload directory and create its document.
+pub fn store_document(
+    db: &DbPool,
+    upload_dir: &Path,
+    batch_id: &str,
+    original_name: &str,
+    content_type: &str,
+    data: &[u8],
+) -> Result<Document, anyhow::Error> {
+    let batch_dir = upload_dir.join(batch_id);
+    std::fs::create_dir_all(&batch_dir)?;
+
+    // Files from archives are named by their path; only the file name is
+    // used on disk
+    let file_name = original_name.rsplit('/').next().unwrap_or(original_name);
+    let stored_name = format!("{}_{}", nanoid::nanoid!(10), file_name);
+    let file_path = batch_dir.join(&stored_name);
+    std::fs::write(&file_path, data)?;
+
+    Ok(DocumentDao::create(
+        db,
+        batch_id,
+        &stored_name,
+        original_name,
+        content_type,
+        data.len() as i64,
+        file_path.to_str().unwrap_or(""),
+    )?)
+}
+
+/// Store an email and its attachments, the attachments as children of the
+/// email, all carrying its headers. Returns the email document first.
+pub fn store_email(
+    db: &DbPool,
+    upload_dir: &Path,
+    batch_id: &str,
+    original_name: &str,
+    content_type: &str,
+    data: &[u8],
+    email: &ParsedEmail,
+) -> Result<Vec<Document>, anyhow::Error> {
+    let parent = store_document(db, upload_dir, batch_id, original_name, content_type, data)?;
+    DocumentDao::set_source(db, &parent.id, None, Some(&email.metadata))?;
+
+    let mut documents = vec![DocumentDao::get_by_id(db, &parent.id)?];
+    for attachment in &email.attachments {
+        let child = store_document(
+            db,
+            upload_dir,
+            batch_id,
+            &attachment.name,
+            &attachment.content_type,
+            &attachment.data,
+        )?;
+        DocumentDao::set_source(db, &child.id, Some(&parent.id), Some(&email.metadata))?;
+        documents.push(DocumentDao::get_by_id(db, &child.id)?);
+    }
+    Ok(documents)
+}
+
+/// The batch a source's items of `key` go into: the batch earlier items of
+/// the key went into if it still exists, otherwise a new batch named
+/// `batch_name`.
+pub fn keyed_batch(db: &DbPool, source: &str, key: &str, batch_name: &str) -> Result<Batch, anyhow::Error> {
+    // The batch may have been deleted since
+    let existing = IngestSourceDao::batch_for_key(db, source, key)?
+        .and_then(|id| BatchDao::get_by_id(db, &id).ok());
+    let batch = match existing {
+        Some(batch) => batch,
+        None => BatchDao::create(db, batch_name, None)?,
+    };
+    IngestSourceDao::set_batch(db, source, &batch.id, key)?;
+    Ok(batch)
+}
+
+/// Count documents added to a batch into its total, and queue them right
+/// away with `auto_process`.
+pub fn finish_ingest(
+    db: &DbPool,
+    pipeline: &Arc<Pipeline>,
+    batch_id: &str,
+    documents: &[Document],
+    auto_process: bool,
+) -> Result<(), anyhow::Error> {
+    let total = DocumentDao::list_by_batch(db, batch_id)?.len();
+    BatchDao::set_total_files(db, batch_id, total as i32)?;
+
+    if auto_process && !documents.is_empty() {
+        let ids: Vec<String> = documents.iter().map(|d| d.id.clone()).collect();
+        pipeline.reprocess_documents(batch_id, &ids, &DocumentOptions::default())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,413 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use harvex_config::{ArchiveSettings, FolderBatching, Settings, WatchFolderSettings};
+use harvex_db::models::{Batch, Document};
+use harvex_db::DbPool;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::archive;
+use crate::dao::IngestSourceDao;
+use crate::pipeline::email;
+use crate::pipeline::{FileType, Pipeline};
+
+/// Shortest pause between two scans, so a burst of change notifications
+/// (e.g. a large file being written) does not rescan for every one.
+const MIN_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watches one configured directory and ingests the files dropped into it
+/// once they are completely written.
+pub struct FolderWatcher {
+    db: DbPool,
+    pipeline: Arc<Pipeline>,
+    folder: WatchFolderSettings,
+    upload_dir: PathBuf,
+    archive: ArchiveSettings,
+    /// Largest file, archive entry or attachment ingested, in bytes.
+    max_file_size: u64,
+    files: StableFiles,
+    /// Files taken that could not be moved away; skipped until restart
+    /// instead of being ingested again on every scan.
+    stuck: HashSet<PathBuf>,
+}
+
+/// Start a watcher for every configured folder.
+pub fn spawn_folder_watchers(db: &DbPool, pipeline: &Arc<Pipeline>, settings: &Settings) -> Vec<JoinHandle<()>> {
+    settings
+        .ingest
+        .folders
+        .iter()
+        .map(|folder| {
+            FolderWatcher::new(
+                db.clone(),
+                pipeline.clone(),
+                folder.clone(),
+                PathBuf::from(&settings.storage.upload_dir),
+                settings.storage.archive.clone(),
+                settings.storage.max_file_size_mb * 1024 * 1024,
+            )
+            .spawn()
+        })
+        .collect()
+}
+
+impl FolderWatcher {
+    pub fn new(
+        db: DbPool,
+        pipeline: Arc<Pipeline>,
+        folder: WatchFolderSettings,
+        upload_dir: PathBuf,
+        archive: ArchiveSettings,
+        max_file_size: u64,
+    ) -> Self {
+        Self {
+            db,
+            pipeline,
+            folder,
+            upload_dir,
+            archive,
+            max_file_size,
+            files: StableFiles::default(),
+            stuck: HashSet::new(),
+        }
+    }
+
+    /// Scan on every change notification, and every `poll_interval_secs`
+    /// or when a growing file may have become stable, for as long as the
+    /// task runs. Without notifications (not supported, or `use_polling`)
+    /// only the scans on a timer remain.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut watcher = self;
+            let changed = Arc::new(Notify::new());
+            let folder = watcher.folder.clone();
+            let _notifier = if folder.use_polling {
+                None
+            } else {
+                match watcher.watch(changed.clone()) {
+                    Ok(notifier) => Some(notifier),
+                    Err(e) => {
+                        warn!(
+                            "Cannot watch '{}' for changes, scanning every {}s instead: {e}",
+                            folder.path, folder.poll_interval_secs
+                        );
+                        None
+                    }
+                }
+            };
+            info!("Watching folder '{}' ({})", folder.name, folder.path);
+
+            loop {
+                // Listing, reading and moving files is blocking I/O; the
+                // watcher is handed to the blocking pool and back
+                let scanned = tokio::task::spawn_blocking(move || {
+                    let result = watcher.scan();
+                    (watcher, result)
+                })
+                .await;
+                let (returned, result) = match scanned {
+                    Ok(scanned) => scanned,
+                    Err(e) => {
+                        warn!("Watching folder '{}' stopped: {e}", folder.name);
+                        return;
+                    }
+                };
+                watcher = returned;
+                if let Err(e) = result {
+                    warn!("Scanning watched folder '{}' failed: {e}", folder.name);
+                }
+
+                tokio::time::sleep(MIN_SCAN_INTERVAL).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(watcher.next_scan_in()) => {}
+                    _ = changed.notified() => {}
+                }
+            }
+        })
+    }
+
+    /// Ingest the files that have become stable since the last scan, moving
+    /// each original into the archive or failed directory. Returns the number
+    /// of files ingested.
+    pub fn scan(&mut self) -> Result<usize, anyhow::Error> {
+        let name = self.folder.name.clone();
+        IngestSourceDao::get_or_create(&self.db, &name, "folder")?;
+
+        let mut ingested = 0;
+        let result = self.take_stable(&mut ingested);
+        let error = result.as_ref().err().map(|e| e.to_string());
+        IngestSourceDao::record_poll(&self.db, &name, ingested, error.as_deref())?;
+        if ingested > 0 {
+            info!("Ingested {} files from watched folder '{}'", ingested, name);
+        }
+        result.map(|_| ingested)
+    }
+
+    fn take_stable(&mut self, ingested: &mut usize) -> Result<(), anyhow::Error> {
+        let mut files = self.list_files()?;
+        files.retain(|(path, _)| !self.stuck.contains(path));
+        let mut stable = self.files.update(files, Instant::now(), self.stable_for());
+
+        // Files of one directory in a row, so they share its batch
+        stable.sort_by(|a, b| (a.parent(), a).cmp(&(b.parent(), b)));
+        for path in stable {
+            self.files.forget(&path);
+            let target = match self.ingest(&path) {
+                Ok(_) => {
+                    *ingested += 1;
+                    &self.folder.archive_dir
+                }
+                Err(e) => {
+                    warn!("Watched folder '{}' rejected {}: {e}", self.folder.name, path.display());
+                    &self.folder.failed_dir
+                }
+            };
+            if let Err(e) = self.move_original(&path, target) {
+                warn!("Cannot move {} into {target}: {e}", path.display());
+                self.stuck.insert(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Store a file as documents of its batch: an archive's files and an
+    /// email's attachments become documents of their own. Files of a type
+    /// that cannot be extracted are rejected.
+    fn ingest(&self, path: &Path) -> Result<Vec<Document>, anyhow::Error> {
+        let relative = path.strip_prefix(&self.folder.path).unwrap_or(path);
+        let name = relative.to_string_lossy().to_string();
+        let content_type = mime_guess::from_path(path).first_or_octet_stream().to_string();
+        if let FileType::Unknown(ext) = FileType::detect(&name, &content_type)
+            && !archive::is_archive(&name, &content_type)
+        {
+            return Err(anyhow::anyhow!("Unsupported file type '{ext}'"));
+        }
+
+        super::check_size(&name, std::fs::metadata(path)?.len(), self.max_file_size)?;
+        let data = std::fs::read(path)?;
+        let files = if archive::is_archive(&name, &content_type) {
+            archive::expand_zip(&name, &data, &self.archive)?
+                .into_iter()
+                .map(|entry| (entry.name, entry.content_type, entry.data))
+                .collect()
+        } else {
+            vec![(name, content_type, data)]
+        };
+        // Rejected before a batch is created for it
+        let mut received = Vec::with_capacity(files.len());
+        for (name, content_type, data) in files {
+            super::check_size(&name, data.len() as u64, self.max_file_size)?;
+            let email = if FileType::detect(&name, &content_type) == FileType::Email {
+                Some(email::parse(&data)?)
+            } else {
+                None
+            };
+            for attachment in email.iter().flat_map(|e| &e.attachments) {
+                super::check_size(&attachment.name, attachment.data.len() as u64, self.max_file_size)?;
+            }
+            received.push((name, content_type, data, email));
+        }
+
+        let batch = self.target_batch(relative.parent().unwrap_or(Path::new("")))?;
+        let mut documents = Vec::new();
+        for (name, content_type, data, email) in &received {
+            match email {
+                Some(email) => documents.extend(super::store_email(
+                    &self.db,
+                    &self.upload_dir,
+                    &batch.id,
+                    name,
+                    content_type,
+                    data,
+                    email,
+                )?),
+                None => documents.push(super::store_document(
+                    &self.db,
+                    &self.upload_dir,
+                    &batch.id,
+                    name,
+                    content_type,
+                    data,
+                )?),
+            }
+        }
+
+        super::finish_ingest(&self.db, &self.pipeline, &batch.id, &documents, self.folder.auto_process)?;
+        Ok(documents)
+    }
+
+    /// The batch of the directory a file was dropped into (relative to the
+    /// watched one), or of the current time window.
+    fn target_batch(&self, directory: &Path) -> Result<Batch, anyhow::Error> {
+        let name = &self.folder.name;
+        match self.folder.batching {
+            FolderBatching::Folder => {
+                let directory = directory.to_string_lossy();
+                let batch_name = if directory.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{name}: {directory}")
+                };
+                super::keyed_batch(&self.db, name, &format!("folder:{directory}"), &batch_name)
+            }
+            FolderBatching::Window => {
+                let now = chrono::Local::now().timestamp();
+                let start = now - now.rem_euclid(self.folder.batch_window_secs.max(1) as i64);
+                let started = chrono::DateTime::from_timestamp(start, 0)
+                    .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default();
+                super::keyed_batch(&self.db, name, &format!("window:{start}"), &format!("{name} {started}"))
+            }
+        }
+    }
+
+    /// Files under the watched directory with their sizes, leaving out
+    /// hidden files and the archive and failed directories.
+    fn list_files(&self) -> std::io::Result<Vec<(PathBuf, u64)>> {
+        let root = Path::new(&self.folder.path);
+        let skipped = [root.join(&self.folder.archive_dir), root.join(&self.folder.failed_dir)];
+
+        let mut files = Vec::new();
+        let mut directories = vec![root.to_path_buf()];
+        while let Some(directory) = directories.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let path = entry.path();
+                // Gone since it was listed
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    if self.folder.recursive && !skipped.contains(&path) {
+                        directories.push(path);
+                    }
+                } else if metadata.is_file() {
+                    files.push((path, metadata.len()));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Move an original into `directory` under the watched one, keeping its
+    /// relative path. A file already there is not overwritten.
+    fn move_original(&self, path: &Path, directory: &str) -> std::io::Result<PathBuf> {
+        let root = Path::new(&self.folder.path);
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let mut target = root.join(directory).join(relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if target.exists() {
+            let file_name = relative.file_name().unwrap_or_default().to_string_lossy();
+            target.set_file_name(format!("{}_{}", nanoid::nanoid!(6), file_name));
+        }
+        std::fs::rename(path, &target)?;
+        Ok(target)
+    }
+
+    fn watch(&self, changed: Arc<Notify>) -> notify::Result<RecommendedWatcher> {
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok() {
+                changed.notify_one();
+            }
+        })?;
+        let mode = if self.folder.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(Path::new(&self.folder.path), mode)?;
+        Ok(watcher)
+    }
+
+    fn stable_for(&self) -> Duration {
+        Duration::from_secs(self.folder.stable_secs)
+    }
+
+    /// Time until the next scan without a change notification: the poll
+    /// interval, or sooner when a file may have become stable by then.
+    fn next_scan_in(&self) -> Duration {
+        let interval = Duration::from_secs(self.folder.poll_interval_secs.max(1));
+        match self.files.next_due(self.stable_for()) {
+            Some(due) => due.saturating_duration_since(Instant::now()).min(interval),
+            None => interval,
+        }
+    }
+}
+
+/// Files seen in a watched directory, with their size and since when it has
+/// not changed.
+#[derive(Debug, Default)]
+struct StableFiles {
+    seen: HashMap<PathBuf, (u64, Instant)>,
+}
+
+impl StableFiles {
+    /// Record the files of a scan. Returns those whose size has not changed
+    /// for `stable_for`; files gone since the last scan are forgotten.
+    fn update(&mut self, files: Vec<(PathBuf, u64)>, now: Instant, stable_for: Duration) -> Vec<PathBuf> {
+        let mut seen = HashMap::with_capacity(files.len());
+        let mut stable = Vec::new();
+        for (path, size) in files {
+            let since = match self.seen.get(&path) {
+                Some(&(last_size, since)) if last_size == size => since,
+                _ => now,
+            };
+            if now.duration_since(since) >= stable_for {
+                stable.push(path.clone());
+            }
+            seen.insert(path, (size, since));
+        }
+        self.seen = seen;
+        stable
+    }
+
+    fn forget(&mut self, path: &Path) {
+        self.seen.remove(path);
+    }
+
+    /// When the first file not yet stable may become stable.
+    fn next_due(&self, stable_for: Duration) -> Option<Instant> {
+        self.seen.values().map(|&(_, since)| since + stable_for).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_stable_once_their_size_stops_changing() {
+        let stable_for = Duration::from_secs(10);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let scan = |sizes: &[(&str, u64)]| -> Vec<(PathBuf, u64)> {
+            sizes.iter().map(|&(name, size)| (PathBuf::from(name), size)).collect()
+        };
+
+        let mut files = StableFiles::default();
+        assert!(files.update(scan(&[("a.pdf", 100)]), at(0), stable_for).is_empty());
+        assert_eq!(files.next_due(stable_for), Some(at(10)));
+
+        // Still growing: the wait starts over
+        assert!(files.update(scan(&[("a.pdf", 200), ("b.pdf", 5)]), at(8), stable_for).is_empty());
+        assert_eq!(files.next_due(stable_for), Some(at(18)));
+
+        let stable = files.update(scan(&[("a.pdf", 200), ("b.pdf", 5)]), at(18), stable_for);
+        assert_eq!(stable, [PathBuf::from("a.pdf"), PathBuf::from("b.pdf")]);
+
+        files.forget(Path::new("a.pdf"));
+        // b.pdf was removed before it was taken
+        assert!(files.update(Vec::new(), at(20), stable_for).is_empty());
+        assert_eq!(files.next_due(stable_for), None);
+    }
+}
@@ -1,8 +1,14 @@
+pub mod archive;
 pub mod dao;
+pub mod doctypes;
 pub mod export;
+pub mod ingest;
 pub mod llm;
 pub mod pipeline;
 
-pub use dao::{BatchDao, DocumentDao, ExtractionDao};
+pub use dao::{
+    BatchDao, DocumentDao, DocumentTypeDao, ExtractionDao, ExtractionPageDao, IngestSourceDao, JobDao,
+};
+pub use doctypes::{DocumentTypeCatalog, DocumentTypeDef};
 pub use llm::{LlmEngine, LlmResponse};
-pub use pipeline::{Pipeline, ProgressEvent};
+pub use pipeline::{BatchFinished, DocumentBusy, DocumentOptions, Pipeline, ProgressEvent};
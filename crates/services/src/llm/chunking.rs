@@ -0,0 +1,114 @@
+/// Separators a chunk preferably ends after, best first.
+const BREAKS: &[&str] = &["\n\n", "\n", " "];
+
+/// Split `text` into chunks of at most `max_chars` characters.
+///
+/// Each chunk after the first starts up to `overlap_chars` (at most half a
+/// chunk) before the previous one ended, at a line start where possible, so
+/// a row cut at a boundary appears whole in one of the chunks. Chunks end
+/// after a blank line, line break or space when one exists in the second
+/// half of the window. Always splits on character boundaries.
+pub fn split_text(text: &str, max_chars: usize, overlap_chars: usize) -> Vec<&str> {
+    let max_chars = max_chars.max(2);
+    let overlap = overlap_chars.min(max_chars / 2);
+
+    // Byte offset of every character, plus the end of the text
+    let offsets: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect();
+    let total = offsets.len() - 1;
+    let char_at = |byte: usize| offsets.partition_point(|&o| o < byte);
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    loop {
+        let hard_end = (start + max_chars).min(total);
+        if hard_end == total {
+            chunks.push(&text[offsets[start]..]);
+            return chunks;
+        }
+
+        let window = &text[offsets[start]..offsets[hard_end]];
+        let min_end = offsets[start + max_chars / 2];
+        let end_byte = BREAKS
+            .iter()
+            .find_map(|sep| {
+                window
+                    .rfind(sep)
+                    .map(|i| offsets[start] + i + sep.len())
+                    .filter(|&end| end > min_end)
+            })
+            .unwrap_or(offsets[hard_end]);
+        chunks.push(&text[offsets[start]..end_byte]);
+
+        let end = char_at(end_byte);
+        let mut next = end.saturating_sub(overlap).max(start + 1);
+        if let Some(i) = text[offsets[next]..end_byte].find('\n') {
+            let line_start = offsets[next] + i + 1;
+            if line_start < end_byte {
+                next = char_at(line_start);
+            }
+        }
+        start = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(rows: usize) -> String {
+        (1..=rows)
+            .map(|i| format!("2024-01-{:02} Transfer ref {i:04} -12.50 EUR\n", i % 28 + 1))
+            .collect()
+    }
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        assert_eq!(split_text("hello world", 100, 10), vec!["hello world"]);
+        assert_eq!(split_text("", 100, 10), vec![""]);
+    }
+
+    #[test]
+    fn chunks_respect_size_and_cover_every_row() {
+        let text = statement(200);
+        let chunks = split_text(&text, 1000, 150);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.chars().count() <= 1000));
+        for line in text.lines() {
+            assert!(chunks.iter().any(|c| c.contains(line)), "missing row: {line}");
+        }
+    }
+
+    #[test]
+    fn chunks_break_at_lines_and_overlap() {
+        let text = statement(100);
+        let chunks = split_text(&text, 800, 120);
+
+        for pair in chunks.windows(2) {
+            assert!(pair[0].ends_with('\n'));
+            let last_line = pair[0].lines().last().unwrap();
+            assert!(pair[1].contains(last_line), "consecutive chunks should overlap");
+        }
+    }
+
+    #[test]
+    fn multibyte_text_splits_on_char_boundaries() {
+        let text = "Überweisung €12,50 — Kaffee ☕ ".repeat(200);
+        let chunks = split_text(&text, 97, 13);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.chars().count() <= 97));
+        assert!(chunks.last().unwrap().ends_with("☕ "));
+    }
+
+    #[test]
+    fn text_without_breaks_is_cut_hard() {
+        let text = "x".repeat(250);
+        let chunks = split_text(&text, 100, 0);
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![100, 100, 50]);
+    }
+}
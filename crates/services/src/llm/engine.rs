@@ -1,12 +1,18 @@
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
 use base64::Engine as _;
-use harvex_config::LlmSettings;
+use futures_util::{stream, StreamExt};
+use harvex_config::{LlmSettings, MergeMode, StructuredOutputMode};
 use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
 use tracing::{debug, info, warn};
 
-use super::prompts;
+use super::tokens::{self, TokenCounter, TokenUsage};
+use super::{grammar, merge, prompts};
+use crate::doctypes::{DocumentTypeCatalog, DocumentTypeDef};
+use super::retry::{self, LlmCallError};
+use super::validate::{self, ValidationIssue, ValidationReport};
 
 /// Response from LLM inference.
 pub struct LlmResponse {
@@ -15,6 +21,58 @@ pub struct LlmResponse {
     pub confidence: f64,
     pub model_used: String,
     pub processing_time_ms: i64,
+    /// HTTP attempts made across all calls, including retries.
+    pub attempts: u32,
+    /// Tokens sent and received across all successful calls, as reported by
+    /// the API or, where it reports none, counted locally.
+    pub usage: TokenUsage,
+    /// Estimated cost of `usage` in USD from the configured price table.
+    pub cost_usd: f64,
+    /// Schema validation of `structured_data`, after any repair attempts.
+    pub validation: ValidationReport,
+    /// Outcome of every page sent to the vision model; empty for text.
+    pub pages: Vec<PageResult>,
+}
+
+/// Outcome of one page sent to the vision model.
+#[derive(Debug, Clone, Serialize)]
+pub struct PageResult {
+    /// 1-based page number.
+    pub page_number: u32,
+    /// `ok`, `failed` (the request failed) or `invalid_json` (the reply
+    /// could not be parsed). Only `ok` pages are merged.
+    pub status: &'static str,
+    pub error: Option<String>,
+    /// The model's reply as received.
+    pub raw_output: Option<String>,
+    pub latency_ms: i64,
+    /// HTTP attempts made for this page, including retries.
+    pub attempts: u32,
+}
+
+/// A vision extraction in progress: the pages sent so far and their results.
+pub struct VisionPages {
+    settings: LlmSettings,
+    document_type_hint: String,
+    /// Pages that will be sent, if known.
+    total_pages: Option<usize>,
+    start: Instant,
+    /// Parsed results of the pages that succeeded.
+    page_results: Vec<serde_json::Value>,
+    pages: Vec<PageResult>,
+    stats: CallStats,
+}
+
+/// One page image for the vision model.
+struct VisionPage<'a> {
+    /// JPEG bytes.
+    image: &'a [u8],
+    /// OCR text sent along with the image, in hybrid mode.
+    ocr_text: Option<&'a str>,
+    /// 1-based page number.
+    number: usize,
+    /// Pages in the document; 0 if unknown.
+    total: usize,
 }
 
 /// LLM engine that calls an OpenAI-compatible API endpoint.
@@ -23,6 +81,28 @@ pub struct LlmResponse {
 pub struct LlmEngine {
     client: reqwest::Client,
     settings: RwLock<LlmSettings>,
+    /// Mode found to work in `auto` structured-output mode; `None` until the
+    /// first call has been answered.
+    detected_output: RwLock<Option<StructuredOutputMode>>,
+    token_counter: Arc<dyn TokenCounter>,
+    /// Vision page requests in flight across all documents.
+    vision_permits: Semaphore,
+}
+
+/// API attempts, token usage and cost accumulated over one or more calls.
+#[derive(Debug, Clone, Copy, Default)]
+struct CallStats {
+    attempts: u32,
+    usage: TokenUsage,
+    cost_usd: f64,
+}
+
+impl std::ops::AddAssign for CallStats {
+    fn add_assign(&mut self, other: Self) {
+        self.attempts += other.attempts;
+        self.usage += other.usage;
+        self.cost_usd += other.cost_usd;
+    }
 }
 
 #[derive(Serialize)]
@@ -33,11 +113,25 @@ struct ChatRequest {
     max_tokens: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     response_format: Option<ResponseFormat>,
+    /// GBNF grammar constraining the output (llama.cpp server).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grammar: Option<String>,
 }
 
 #[derive(Serialize)]
 struct ResponseFormat {
     r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json_schema: Option<JsonSchemaFormat>,
+}
+
+#[derive(Serialize)]
+struct JsonSchemaFormat {
+    name: String,
+    schema: serde_json::Value,
+    /// Strict mode requires every property to be required, which the
+    /// document type schemas do not guarantee.
+    strict: bool,
 }
 
 #[derive(Serialize, Clone)]
@@ -72,6 +166,17 @@ struct ImageUrl {
 #[derive(Deserialize)]
 struct ChatResponse {
     choices: Vec<ChatChoice>,
+    /// Token counts as billed by the API; not every backend sends them.
+    #[serde(default)]
+    usage: Option<ApiUsage>,
+}
+
+#[derive(Deserialize)]
+struct ApiUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
 }
 
 #[derive(Deserialize)]
@@ -102,9 +207,15 @@ impl LlmEngine {
             settings.api_url
         );
 
+        let token_counter = tokens::load_counter(&settings.tokenizer_path);
+        let vision_permits = Semaphore::new((settings.vision_max_concurrent as usize).max(1));
+
         Self {
             client,
             settings: RwLock::new(settings),
+            detected_output: RwLock::new(None),
+            token_counter,
+            vision_permits,
         }
     }
 
@@ -118,6 +229,21 @@ impl LlmEngine {
         self.settings.read().unwrap().clone()
     }
 
+    /// Structured-output mode in effect: the configured one, or in `auto`
+    /// mode the detected one once known.
+    pub fn structured_output(&self) -> StructuredOutputMode {
+        let configured = self.settings.read().unwrap().structured_output;
+        match configured {
+            StructuredOutputMode::Auto => (*self.detected_output.read().unwrap()).unwrap_or(configured),
+            mode => mode,
+        }
+    }
+
+    /// Description of the token counter used for context budgeting.
+    pub fn token_counter_name(&self) -> String {
+        self.token_counter.name()
+    }
+
     /// Check if vision model is configured.
     pub fn has_vision(&self) -> bool {
         let s = self.settings.read().unwrap();
@@ -148,6 +274,8 @@ impl LlmEngine {
         if let Some(url) = api_url {
             info!("Updating LLM api_url: {}", url);
             settings.api_url = url.to_string();
+            // A different backend may support different output modes
+            *self.detected_output.write().unwrap() = None;
         }
         if let Some(key) = api_key {
             settings.api_key = key.to_string();
@@ -219,17 +347,27 @@ impl LlmEngine {
     }
 
     /// Extract structured data from raw text using the LLM.
+    ///
+    /// The prompt is generated from the hint's type in `catalog` (the generic
+    /// type if unknown). `model_override` replaces the configured text model
+    /// for this call. Text too long for the context window is split into
+    /// overlapping chunks whose results are merged.
     pub async fn extract_structured(
         &self,
         raw_text: &str,
+        catalog: &DocumentTypeCatalog,
         document_type_hint: &str,
+        model_override: Option<&str>,
     ) -> Result<LlmResponse, anyhow::Error> {
-        let settings = self.settings.read().unwrap().clone();
+        let mut settings = self.settings.read().unwrap().clone();
+        if let Some(model) = model_override {
+            settings.model_name = model.to_string();
+        }
         let start = Instant::now();
 
         // Build the prompt
-        let system_prompt = prompts::system_prompt(document_type_hint);
-        let user_prompt = prompts::user_prompt(raw_text, document_type_hint);
+        let doc_type = catalog.resolve(document_type_hint);
+        let system_prompt = prompts::system_prompt(doc_type, &catalog.names());
 
         debug!(
             "LLM inference: model={}, doc_type={}, text_len={}",
@@ -238,49 +376,45 @@ impl LlmEngine {
             raw_text.len()
         );
 
-        // Truncate text if it exceeds context window (rough char estimate)
-        let max_chars = (settings.context_size as usize) * 3;
-        let truncated_text = if user_prompt.len() > max_chars {
-            format!("{}...[truncated]", &user_prompt[..max_chars])
-        } else {
-            user_prompt
-        };
-
-        let request = ChatRequest {
-            model: settings.model_name.clone(),
-            messages: vec![
-                ChatMessage {
-                    role: "system".into(),
-                    content: MessageContent::Text(system_prompt),
-                },
-                ChatMessage {
-                    role: "user".into(),
-                    content: MessageContent::Text(truncated_text),
-                },
-            ],
-            temperature: settings.temperature,
-            max_tokens: settings.max_tokens,
-            response_format: Some(ResponseFormat {
-                r#type: "json_object".into(),
-            }),
-        };
-
-        let url = format!("{}/chat/completions", settings.api_url);
-
-        let mut req = self.client.post(&url).json(&request);
-        if !settings.api_key.is_empty() {
-            req = req.bearer_auth(&settings.api_key);
-        }
-
-        let response = req.send().await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(anyhow::anyhow!("LLM API returned {status}: {body}"));
+        // Room for the document text in the context window after the system
+        // prompt, the instructions around the text (the longer chunk variant,
+        // in case the text must be split) and the completion
+        let counter = self.token_counter.as_ref();
+        let prompt_tokens = counter.count(&system_prompt)
+            + counter.count(&prompts::chunk_user_prompt("", doc_type, 999, 999));
+        let text_budget = tokens::text_budget(settings.context_size, settings.max_tokens, prompt_tokens);
+        let chunks = tokens::split_to_budget(
+            raw_text,
+            text_budget,
+            settings.chunk_overlap_chars as usize,
+            counter,
+        );
+        if chunks.len() > 1 {
+            return self
+                .extract_chunked(&settings, doc_type, document_type_hint, system_prompt, &chunks, start)
+                .await;
         }
 
-        let chat_response: ChatResponse = response.json().await?;
+        let messages = vec![
+            ChatMessage {
+                role: "system".into(),
+                content: MessageContent::Text(system_prompt),
+            },
+            ChatMessage {
+                role: "user".into(),
+                content: MessageContent::Text(prompts::user_prompt(raw_text, doc_type)),
+            },
+        ];
+
+        let (chat_response, mut stats) = self
+            .send_json_chat(
+                &settings,
+                &settings.model_name,
+                messages.clone(),
+                &doc_type.output_schema(),
+                "LLM API",
+            )
+            .await?;
 
         let content = chat_response
             .choices
@@ -288,11 +422,16 @@ impl LlmEngine {
             .map(|c| c.message.content.clone())
             .unwrap_or_default();
 
-        let elapsed_ms = start.elapsed().as_millis() as i64;
-
         // Parse the LLM response as JSON
         let (structured_data, confidence) = parse_llm_response(&content);
 
+        let (structured_data, confidence, validation, repair_stats) = self
+            .validate_and_repair(&settings, doc_type, &messages, structured_data, confidence)
+            .await;
+        stats += repair_stats;
+
+        let elapsed_ms = start.elapsed().as_millis() as i64;
+
         // Let the LLM's classification override heuristics if it provided one
         let final_doc_type = structured_data
             .get("document_type")
@@ -311,19 +450,52 @@ impl LlmEngine {
             confidence,
             model_used: settings.model_name,
             processing_time_ms: elapsed_ms,
+            attempts: stats.attempts,
+            usage: stats.usage,
+            cost_usd: stats.cost_usd,
+            validation,
+            pages: Vec::new(),
         })
     }
 
     /// Extract structured data from page images using the vision LLM.
     ///
-    /// Processes each page individually, then merges multi-page results
-    /// using the text model.
+    /// Processes each page individually, several at a time, then merges
+    /// multi-page results using the text model. `model_override` replaces the configured vision
+    /// model for this call.
+    ///
+    /// `page_texts` holds each page's OCR text in hybrid mode and is sent
+    /// along with the image; it is empty otherwise.
     pub async fn extract_structured_with_vision(
         &self,
         page_images: &[Vec<u8>],
+        page_texts: &[String],
+        catalog: &DocumentTypeCatalog,
         document_type_hint: &str,
+        model_override: Option<&str>,
     ) -> Result<LlmResponse, anyhow::Error> {
-        let settings = self.settings.read().unwrap().clone();
+        let mut vision = self.start_vision(document_type_hint, Some(page_images.len()), model_override)?;
+        self.extract_vision_pages(&mut vision, page_images, page_texts, catalog)
+            .await;
+        self.finish_vision(vision, catalog).await
+    }
+
+    /// Start a vision extraction whose pages are sent in several calls to
+    /// [`extract_vision_pages`](Self::extract_vision_pages), e.g. one
+    /// rendered window at a time, and merged by
+    /// [`finish_vision`](Self::finish_vision).
+    ///
+    /// `total_pages` is the number of pages that will be sent, if known.
+    pub fn start_vision(
+        &self,
+        document_type_hint: &str,
+        total_pages: Option<usize>,
+        model_override: Option<&str>,
+    ) -> Result<VisionPages, anyhow::Error> {
+        let mut settings = self.settings.read().unwrap().clone();
+        if let Some(model) = model_override {
+            settings.vision_model_name = model.to_string();
+        }
 
         if settings.vision_model_name.is_empty() {
             return Err(anyhow::anyhow!(
@@ -331,92 +503,142 @@ impl LlmEngine {
             ));
         }
 
-        let start = Instant::now();
-        let total_pages = page_images.len();
-        let system_prompt = prompts::system_prompt(document_type_hint);
-
         info!(
             "Vision inference: model={}, pages={}, doc_type={}",
-            settings.vision_model_name, total_pages, document_type_hint
+            settings.vision_model_name,
+            total_pages.map_or_else(|| "?".to_string(), |n| n.to_string()),
+            document_type_hint
         );
 
-        let mut page_results: Vec<serde_json::Value> = Vec::new();
-
-        for (i, image_bytes) in page_images.iter().enumerate() {
-            let page_num = i + 1;
-            let b64 = base64::engine::general_purpose::STANDARD.encode(image_bytes);
-            let data_url = format!("data:image/jpeg;base64,{b64}");
-
-            let user_prompt =
-                prompts::vision_user_prompt(document_type_hint, page_num, total_pages);
+        Ok(VisionPages {
+            settings,
+            document_type_hint: document_type_hint.to_string(),
+            total_pages,
+            start: Instant::now(),
+            page_results: Vec::new(),
+            pages: Vec::with_capacity(total_pages.unwrap_or_default()),
+            stats: CallStats::default(),
+        })
+    }
 
-            let request = ChatRequest {
-                model: settings.vision_model_name.clone(),
-                messages: vec![
-                    ChatMessage {
-                        role: "system".into(),
-                        content: MessageContent::Text(system_prompt.clone()),
-                    },
-                    ChatMessage {
-                        role: "user".into(),
-                        content: MessageContent::Parts(vec![
-                            ContentPart::Text { text: user_prompt },
-                            ContentPart::ImageUrl {
-                                image_url: ImageUrl { url: data_url },
-                            },
-                        ]),
-                    },
-                ],
-                temperature: settings.temperature,
-                max_tokens: settings.max_tokens,
-                response_format: Some(ResponseFormat {
-                    r#type: "json_object".into(),
-                }),
+    /// Send the next pages of a vision extraction, numbered on from the
+    /// pages sent before. A page that fails is recorded, not returned as an
+    /// error; `finish_vision` fails if no page succeeded.
+    pub async fn extract_vision_pages(
+        &self,
+        vision: &mut VisionPages,
+        page_images: &[Vec<u8>],
+        page_texts: &[String],
+        catalog: &DocumentTypeCatalog,
+    ) {
+        let settings = &vision.settings;
+        let doc_type = catalog.resolve(&vision.document_type_hint);
+        let system_prompt = prompts::system_prompt(doc_type, &catalog.names());
+        // A single page of a longer document need not contain every required field
+        let page_schema = if vision.total_pages == Some(1) {
+            doc_type.output_schema()
+        } else {
+            without_required(&doc_type.output_schema())
+        };
+        // Without a page count the prompt leaves out the page's position
+        let total_pages = vision.total_pages.unwrap_or_default();
+        let first_page = vision.pages.len() + 1;
+
+        // Pages are sent concurrently, up to the per-document limit here and
+        // the engine-wide limit across documents; `buffered` keeps page order.
+        let page_limit = (settings.vision_page_concurrency as usize).max(1);
+        let requests: Vec<_> = page_images
+            .iter()
+            .enumerate()
+            .map(|(i, image_bytes)| {
+                let page = VisionPage {
+                    image: image_bytes,
+                    ocr_text: page_texts.get(i).map(String::as_str),
+                    number: first_page + i,
+                    total: total_pages,
+                };
+                self.send_vision_page(settings, doc_type, &system_prompt, &page_schema, page)
+            })
+            .collect();
+        let responses: Vec<_> = stream::iter(requests).buffered(page_limit).collect().await;
+
+        for (page_num, (result, latency_ms)) in (first_page as u32..).zip(responses) {
+            let mut page = PageResult {
+                page_number: page_num,
+                status: "ok",
+                error: None,
+                raw_output: None,
+                latency_ms,
+                attempts: 0,
             };
-
-            let url = format!("{}/chat/completions", settings.api_url);
-
-            let mut req = self.client.post(&url).json(&request);
-            if !settings.api_key.is_empty() {
-                req = req.bearer_auth(&settings.api_key);
+            match result {
+                Ok((chat_response, page_stats)) => {
+                    vision.stats += page_stats;
+                    page.attempts = page_stats.attempts;
+                    let content = chat_response
+                        .choices
+                        .first()
+                        .map(|c| c.message.content.clone())
+                        .unwrap_or_default();
+
+                    let (page_data, _) = parse_llm_response(&content);
+                    if is_parse_failure(&page_data) {
+                        warn!("Vision LLM returned invalid JSON for page {}", page_num);
+                        page.status = "invalid_json";
+                        page.error = Some("response was not valid JSON".into());
+                    } else {
+                        debug!("Vision: page {} extracted", page_num);
+                        vision.page_results.push(page_data);
+                    }
+                    page.raw_output = Some(content);
+                }
+                Err(e) => {
+                    vision.stats.attempts += e.attempts;
+                    warn!("Vision LLM failed for page {}: {}", page_num, e);
+                    page.status = "failed";
+                    page.attempts = e.attempts;
+                    page.error = Some(e.to_string());
+                }
             }
+            vision.pages.push(page);
+        }
+    }
 
-            debug!(
-                "Vision: sending page {}/{} ({} bytes)",
-                page_num,
-                total_pages,
-                image_bytes.len()
-            );
-
-            let response = req.send().await?;
+    /// Merge the pages of a vision extraction into one result and validate
+    /// it. Fails if no page was extracted.
+    pub async fn finish_vision(
+        &self,
+        vision: VisionPages,
+        catalog: &DocumentTypeCatalog,
+    ) -> Result<LlmResponse, anyhow::Error> {
+        let VisionPages {
+            settings,
+            document_type_hint,
+            start,
+            page_results,
+            pages,
+            mut stats,
+            ..
+        } = vision;
+        let doc_type = catalog.resolve(&document_type_hint);
+        let system_prompt = prompts::system_prompt(doc_type, &catalog.names());
+        let total_pages = pages.len();
 
-            if !response.status().is_success() {
-                let status = response.status();
-                let body = response.text().await.unwrap_or_default();
-                warn!(
-                    "Vision LLM failed for page {}: {} {}",
-                    page_num, status, body
-                );
-                continue;
+        if page_results.is_empty() {
+            let errors: Vec<String> = pages
+                .iter()
+                .map(|p| format!("page {}: {}", p.page_number, p.error.as_deref().unwrap_or("no result")))
+                .collect();
+            return Err(LlmCallError {
+                attempts: stats.attempts,
+                message: format!("Vision LLM returned no results for any page ({})", errors.join("; ")),
+                status: None,
+                pages,
             }
-
-            let chat_response: ChatResponse = response.json().await?;
-            let content = chat_response
-                .choices
-                .first()
-                .map(|c| c.message.content.clone())
-                .unwrap_or_default();
-
-            let (page_data, _) = parse_llm_response(&content);
-            debug!("Vision: page {}/{} extracted", page_num, total_pages);
-            page_results.push(page_data);
+            .into());
         }
 
-        if page_results.is_empty() {
-            return Err(anyhow::anyhow!(
-                "Vision LLM returned no results for any page"
-            ));
-        }
+        let pages_merged = page_results.len();
 
         // Single page — use directly; multi-page — merge via text model
         let (structured_data, confidence, model_used) = if page_results.len() == 1 {
@@ -430,14 +652,54 @@ impl LlmEngine {
                 settings.vision_model_name.clone(),
             )
         } else {
-            self.merge_page_results(&page_results, document_type_hint, &settings)
-                .await?
+            let merge_prompt = || prompts::merge_pages_prompt(doc_type, &page_results);
+            let merged = self
+                .merge_partial_results(&settings, doc_type, &system_prompt, &page_results, merge_prompt)
+                .await;
+            let (data, confidence, merge_stats) = match merged {
+                Ok(merged) => merged,
+                Err(e) => {
+                    return Err(LlmCallError {
+                        attempts: stats.attempts + e.attempts,
+                        pages,
+                        ..e
+                    }
+                    .into());
+                }
+            };
+            stats += merge_stats;
+            let model_used = match settings.merge_mode {
+                MergeMode::Rules => settings.vision_model_name.clone(),
+                MergeMode::Llm => format!("{}+{}", settings.vision_model_name, settings.model_name),
+            };
+            (data, confidence, model_used)
         };
 
+        // Repairs go to the text model with the system prompt only; the
+        // current data is included in the repair prompt.
+        let context = [ChatMessage {
+            role: "system".into(),
+            content: MessageContent::Text(system_prompt),
+        }];
+        let (structured_data, confidence, validation, repair_stats) = self
+            .validate_and_repair(&settings, doc_type, &context, structured_data, confidence)
+            .await;
+        stats += repair_stats;
+
+        // Missing pages lower the confidence by the share of pages lost
+        let missing = total_pages - pages_merged;
+        let confidence = confidence * pages_merged as f64 / total_pages as f64;
+        if missing > 0 {
+            warn!(
+                "Vision result is missing {} of {} pages; confidence lowered to {:.2}",
+                missing, total_pages, confidence
+            );
+        }
+
         let final_doc_type = structured_data
             .get("document_type")
             .and_then(|v| v.as_str())
-            .unwrap_or(document_type_hint)
+            .unwrap_or(&document_type_hint)
             .to_string();
 
         let elapsed_ms = start.elapsed().as_millis() as i64;
@@ -453,62 +715,386 @@ impl LlmEngine {
             confidence,
             model_used,
             processing_time_ms: elapsed_ms,
+            attempts: stats.attempts,
+            usage: stats.usage,
+            cost_usd: stats.cost_usd,
+            validation,
+            pages,
         })
     }
 
-    /// Merge per-page extraction results into a single JSON using the text model.
-    async fn merge_page_results(
+    /// Extract a PDF of text and scanned pages: the text pages go to the text
+    /// model as one document, and the scanned pages, already sent to the
+    /// vision model through `vision`, are merged with them like pages.
+    ///
+    /// `scanned_pages` holds the 1-based page numbers of the pages sent, so
+    /// the per-page results keep the document's numbering. If one side
+    /// fails, the other's result is kept with its confidence lowered by the
+    /// share of pages lost.
+    pub async fn extract_structured_mixed(
         &self,
-        page_results: &[serde_json::Value],
+        text: &str,
+        vision: VisionPages,
+        scanned_pages: &[u32],
+        total_pages: u32,
+        catalog: &DocumentTypeCatalog,
         document_type_hint: &str,
+    ) -> Result<LlmResponse, anyhow::Error> {
+        let start = Instant::now();
+        info!(
+            "Mixed inference: pages={}, scanned={}, doc_type={}",
+            total_pages,
+            scanned_pages.len(),
+            document_type_hint
+        );
+
+        // The model override names a vision model, so the text model is the configured one
+        let (text_result, vision_result) = tokio::join!(
+            self.extract_structured(text, catalog, document_type_hint, None),
+            self.finish_vision(vision, catalog),
+        );
+        // Pages keep the document's numbering, on failure too
+        let renumber = |pages: &mut Vec<PageResult>| {
+            for page in pages {
+                if let Some(&number) = scanned_pages.get(page.page_number as usize - 1) {
+                    page.page_number = number;
+                }
+            }
+        };
+        let vision_result = match vision_result {
+            Ok(mut response) => {
+                renumber(&mut response.pages);
+                Ok(response)
+            }
+            Err(mut e) => {
+                if let Some(call_error) = e.downcast_mut::<LlmCallError>() {
+                    renumber(&mut call_error.pages);
+                }
+                Err(e)
+            }
+        };
+
+        let scanned_share = scanned_pages.len() as f64 / total_pages.max(1) as f64;
+        let (text_response, vision_response) = match (text_result, vision_result) {
+            (Ok(text_response), Ok(vision_response)) => (text_response, vision_response),
+            (Ok(mut response), Err(e)) => {
+                warn!("Vision failed for the scanned pages: {e}. Keeping the text pages.");
+                response.attempts += LlmCallError::attempts_of(&e);
+                response.confidence *= 1.0 - scanned_share;
+                response.pages = LlmCallError::pages_of(&e).to_vec();
+                return Ok(response);
+            }
+            (Err(e), Ok(mut response)) => {
+                warn!("Text model failed for the text pages: {e}. Keeping the scanned pages.");
+                response.attempts += LlmCallError::attempts_of(&e);
+                response.confidence *= scanned_share;
+                return Ok(response);
+            }
+            (Err(text_error), Err(vision_error)) => {
+                return Err(LlmCallError {
+                    attempts: LlmCallError::attempts_of(&text_error)
+                        + LlmCallError::attempts_of(&vision_error),
+                    message: format!("text pages: {text_error}; scanned pages: {vision_error}"),
+                    status: None,
+                    pages: LlmCallError::pages_of(&vision_error).to_vec(),
+                }
+                .into());
+            }
+        };
+
+        let settings = self.settings.read().unwrap().clone();
+        let mut stats = CallStats::default();
+        for response in [&text_response, &vision_response] {
+            stats += CallStats {
+                attempts: response.attempts,
+                usage: response.usage,
+                cost_usd: response.cost_usd,
+            };
+        }
+
+        // The text model saw the most text, so its classification wins
+        let doc_type = catalog.resolve(&text_response.document_type);
+        let system_prompt = prompts::system_prompt(doc_type, &catalog.names());
+        // Parts in document order: a scanned first page (e.g. a cover) leads
+        let mut parts = vec![
+            text_response.structured_data.clone(),
+            vision_response.structured_data.clone(),
+        ];
+        if scanned_pages.first() == Some(&1) {
+            parts.reverse();
+        }
+
+        let merge_prompt = || prompts::merge_pages_prompt(doc_type, &parts);
+        let merged = self
+            .merge_partial_results(&settings, doc_type, &system_prompt, &parts, merge_prompt)
+            .await;
+        let (data, confidence, merge_stats) = match merged {
+            Ok(merged) => merged,
+            Err(e) => {
+                return Err(LlmCallError {
+                    attempts: stats.attempts + e.attempts,
+                    pages: vision_response.pages,
+                    ..e
+                }
+                .into());
+            }
+        };
+        stats += merge_stats;
+
+        let context = [ChatMessage {
+            role: "system".into(),
+            content: MessageContent::Text(system_prompt),
+        }];
+        let (structured_data, confidence, validation, repair_stats) = self
+            .validate_and_repair(&settings, doc_type, &context, data, confidence)
+            .await;
+        stats += repair_stats;
+
+        let final_doc_type = structured_data
+            .get("document_type")
+            .and_then(|v| v.as_str())
+            .unwrap_or(&text_response.document_type)
+            .to_string();
+        let model_used = format!("{}+{}", text_response.model_used, vision_response.model_used);
+        let elapsed_ms = start.elapsed().as_millis() as i64;
+
+        info!(
+            "Mixed inference complete: model={}, doc_type={}, confidence={:.2}, time={}ms",
+            model_used, final_doc_type, confidence, elapsed_ms
+        );
+
+        Ok(LlmResponse {
+            structured_data,
+            document_type: final_doc_type,
+            confidence,
+            model_used,
+            processing_time_ms: elapsed_ms,
+            attempts: stats.attempts,
+            usage: stats.usage,
+            cost_usd: stats.cost_usd,
+            validation,
+            pages: vision_response.pages,
+        })
+    }
+
+    /// Send one page image to the vision model, waiting for a slot under the
+    /// engine-wide vision limit first. Also returns the request's latency,
+    /// not counting the wait.
+    async fn send_vision_page(
+        &self,
         settings: &LlmSettings,
-    ) -> Result<(serde_json::Value, f64, String), anyhow::Error> {
-        let system_prompt = prompts::system_prompt(document_type_hint);
-        let merge_prompt = prompts::merge_pages_prompt(document_type_hint, page_results);
+        doc_type: &DocumentTypeDef,
+        system_prompt: &str,
+        page_schema: &serde_json::Value,
+        page: VisionPage<'_>,
+    ) -> (Result<(ChatResponse, CallStats), LlmCallError>, i64) {
+        let b64 = base64::engine::general_purpose::STANDARD.encode(page.image);
+        let data_url = format!("data:image/jpeg;base64,{b64}");
+
+        let user_prompt = prompts::vision_user_prompt(doc_type, page.number, page.total, page.ocr_text);
+
+        let messages = vec![
+            ChatMessage {
+                role: "system".into(),
+                content: MessageContent::Text(system_prompt.to_string()),
+            },
+            ChatMessage {
+                role: "user".into(),
+                content: MessageContent::Parts(vec![
+                    ContentPart::Text { text: user_prompt },
+                    ContentPart::ImageUrl {
+                        image_url: ImageUrl { url: data_url },
+                    },
+                ]),
+            },
+        ];
+
+        let _permit = self.vision_permits.acquire().await.unwrap();
+        debug!(
+            "Vision: sending page {}/{} ({} bytes)",
+            page.number,
+            page.total,
+            page.image.len()
+        );
+        let sent = Instant::now();
+        let result = self
+            .send_json_chat(
+                settings,
+                &settings.vision_model_name,
+                messages,
+                page_schema,
+                "Vision LLM",
+            )
+            .await;
+        (result, sent.elapsed().as_millis() as i64)
+    }
 
+    /// Extract a document too long for one request: each chunk separately,
+    /// then merged into one result with the text model.
+    async fn extract_chunked(
+        &self,
+        settings: &LlmSettings,
+        doc_type: &DocumentTypeDef,
+        document_type_hint: &str,
+        system_prompt: String,
+        chunks: &[&str],
+        start: Instant,
+    ) -> Result<LlmResponse, anyhow::Error> {
+        let total_chunks = chunks.len();
         info!(
-            "Merging {} page results via text model: {}",
-            page_results.len(),
-            settings.model_name
+            "Chunked inference: model={}, doc_type={}, chunks={}",
+            settings.model_name, document_type_hint, total_chunks
         );
 
-        let request = ChatRequest {
-            model: settings.model_name.clone(),
-            messages: vec![
+        // A section need not contain every required field
+        let chunk_schema = without_required(&doc_type.output_schema());
+        let mut chunk_results = Vec::with_capacity(total_chunks);
+        let mut stats = CallStats::default();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let messages = vec![
                 ChatMessage {
                     role: "system".into(),
-                    content: MessageContent::Text(system_prompt),
+                    content: MessageContent::Text(system_prompt.clone()),
                 },
                 ChatMessage {
                     role: "user".into(),
-                    content: MessageContent::Text(merge_prompt),
+                    content: MessageContent::Text(prompts::chunk_user_prompt(
+                        chunk,
+                        doc_type,
+                        i + 1,
+                        total_chunks,
+                    )),
                 },
-            ],
-            temperature: settings.temperature,
-            max_tokens: settings.max_tokens,
-            response_format: Some(ResponseFormat {
-                r#type: "json_object".into(),
-            }),
-        };
-
-        let url = format!("{}/chat/completions", settings.api_url);
+            ];
+
+            // Every section is needed for a complete result
+            let (chat_response, chunk_stats) = self
+                .send_json_chat(settings, &settings.model_name, messages, &chunk_schema, "LLM API")
+                .await
+                .map_err(|e| LlmCallError {
+                    attempts: stats.attempts + e.attempts,
+                    message: format!("Section {}/{}: {}", i + 1, total_chunks, e.message),
+                    ..e
+                })?;
+            stats += chunk_stats;
 
-        let mut req = self.client.post(&url).json(&request);
-        if !settings.api_key.is_empty() {
-            req = req.bearer_auth(&settings.api_key);
+            let content = chat_response
+                .choices
+                .first()
+                .map(|c| c.message.content.clone())
+                .unwrap_or_default();
+            let (chunk_data, _) = parse_llm_response(&content);
+            debug!("Chunked: section {}/{} extracted", i + 1, total_chunks);
+            chunk_results.push(chunk_data);
         }
 
-        let response = req.send().await?;
+        let merge_prompt = || prompts::merge_chunks_prompt(doc_type, &chunk_results);
+        let (structured_data, confidence, merge_stats) = self
+            .merge_partial_results(settings, doc_type, &system_prompt, &chunk_results, merge_prompt)
+            .await
+            .map_err(|e| LlmCallError {
+                attempts: stats.attempts + e.attempts,
+                ..e
+            })?;
+        stats += merge_stats;
+
+        let context = [ChatMessage {
+            role: "system".into(),
+            content: MessageContent::Text(system_prompt),
+        }];
+        let (structured_data, confidence, validation, repair_stats) = self
+            .validate_and_repair(settings, doc_type, &context, structured_data, confidence)
+            .await;
+        stats += repair_stats;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(anyhow::anyhow!(
-                "LLM merge API returned {status}: {body}"
-            ));
+        let final_doc_type = structured_data
+            .get("document_type")
+            .and_then(|v| v.as_str())
+            .unwrap_or(document_type_hint)
+            .to_string();
+
+        let elapsed_ms = start.elapsed().as_millis() as i64;
+
+        info!(
+            "Chunked inference complete: model={}, doc_type={}, confidence={:.2}, chunks={}, time={}ms",
+            settings.model_name, final_doc_type, confidence, total_chunks, elapsed_ms
+        );
+
+        Ok(LlmResponse {
+            structured_data,
+            document_type: final_doc_type,
+            confidence,
+            model_used: settings.model_name.clone(),
+            processing_time_ms: elapsed_ms,
+            attempts: stats.attempts,
+            usage: stats.usage,
+            cost_usd: stats.cost_usd,
+            validation,
+            pages: Vec::new(),
+        })
+    }
+
+    /// Merge partial extraction results (pages or text chunks) as configured
+    /// by `settings.merge_mode`: by rules, or with the text model and the
+    /// merge prompt built by `merge_prompt`.
+    async fn merge_partial_results(
+        &self,
+        settings: &LlmSettings,
+        doc_type: &DocumentTypeDef,
+        system_prompt: &str,
+        results: &[serde_json::Value],
+        merge_prompt: impl FnOnce() -> String,
+    ) -> Result<(serde_json::Value, f64, CallStats), LlmCallError> {
+        match settings.merge_mode {
+            MergeMode::Rules => {
+                info!("Merging {} partial results by rules", results.len());
+                let (data, confidence) = merge::merge_results(&doc_type.output_schema(), results);
+                Ok((data, confidence, CallStats::default()))
+            }
+            MergeMode::Llm => {
+                info!(
+                    "Merging {} partial results via text model: {}",
+                    results.len(),
+                    settings.model_name
+                );
+                self.merge_results(merge_prompt(), doc_type, system_prompt, settings)
+                    .await
+            }
         }
+    }
 
-        let chat_response: ChatResponse = response.json().await?;
+    /// Merge partial extraction results (pages or text chunks) into a single
+    /// JSON using the text model and the given merge prompt.
+    ///
+    /// Returns the merged data, its confidence and the call's stats.
+    async fn merge_results(
+        &self,
+        merge_prompt: String,
+        doc_type: &DocumentTypeDef,
+        system_prompt: &str,
+        settings: &LlmSettings,
+    ) -> Result<(serde_json::Value, f64, CallStats), LlmCallError> {
+        let messages = vec![
+            ChatMessage {
+                role: "system".into(),
+                content: MessageContent::Text(system_prompt.to_string()),
+            },
+            ChatMessage {
+                role: "user".into(),
+                content: MessageContent::Text(merge_prompt),
+            },
+        ];
+
+        let (chat_response, stats) = self
+            .send_json_chat(
+                settings,
+                &settings.model_name,
+                messages,
+                &doc_type.output_schema(),
+                "LLM merge API",
+            )
+            .await?;
         let content = chat_response
             .choices
             .first()
@@ -516,12 +1102,347 @@ impl LlmEngine {
             .unwrap_or_default();
 
         let (data, confidence) = parse_llm_response(&content);
-        let model_used = format!(
-            "{}+{}",
-            settings.vision_model_name, settings.model_name
+
+        Ok((data, confidence, stats))
+    }
+
+    /// Validate `data` against the type's schema and, while it does not match,
+    /// ask the text model to correct it, up to `settings.max_repair_attempts`
+    /// times. `context` holds the messages the data was produced from.
+    ///
+    /// Returns the final data and confidence, the validation report and the
+    /// stats of the repair calls. A failed repair call ends the loop
+    /// and keeps the data as it was.
+    async fn validate_and_repair(
+        &self,
+        settings: &LlmSettings,
+        doc_type: &DocumentTypeDef,
+        context: &[ChatMessage],
+        mut data: serde_json::Value,
+        mut confidence: f64,
+    ) -> (serde_json::Value, f64, ValidationReport, CallStats) {
+        let output_schema = doc_type.output_schema();
+        let mut issues = output_issues(&doc_type.schema, &data);
+        let mut report = ValidationReport {
+            initial_errors: issues.clone(),
+            ..ValidationReport::default()
+        };
+        let mut stats = CallStats::default();
+
+        while !issues.is_empty() && report.repair_attempts < settings.max_repair_attempts {
+            report.repair_attempts += 1;
+            warn!(
+                "LLM output does not match the {} schema ({} problems), repair attempt {}/{}",
+                doc_type.name,
+                issues.len(),
+                report.repair_attempts,
+                settings.max_repair_attempts
+            );
+
+            let mut messages = context.to_vec();
+            messages.push(ChatMessage {
+                role: "user".into(),
+                content: MessageContent::Text(prompts::repair_prompt(&data, &issues)),
+            });
+
+            let chat_response = match self
+                .send_json_chat(
+                    settings,
+                    &settings.model_name,
+                    messages,
+                    &output_schema,
+                    "LLM repair API",
+                )
+                .await
+            {
+                Ok((chat_response, call_stats)) => {
+                    stats += call_stats;
+                    chat_response
+                }
+                Err(e) => {
+                    stats.attempts += e.attempts;
+                    warn!("Repair request failed, keeping unrepaired output: {}", e);
+                    break;
+                }
+            };
+            let content = chat_response
+                .choices
+                .first()
+                .map(|c| c.message.content.clone())
+                .unwrap_or_default();
+
+            let (repaired, repaired_confidence) = parse_llm_response(&content);
+            if is_parse_failure(&repaired) {
+                continue;
+            }
+            data = repaired;
+            confidence = repaired_confidence;
+            issues = output_issues(&doc_type.schema, &data);
+        }
+
+        if !issues.is_empty() {
+            warn!(
+                "LLM output still has {} schema problems for {}",
+                issues.len(),
+                doc_type.name
+            );
+        }
+        report.valid = issues.is_empty();
+        report.errors = issues;
+        (data, confidence, report, stats)
+    }
+
+    /// Send a chat request expecting a JSON object matching `schema`.
+    ///
+    /// Returns the response with the attempts made, the tokens used and their
+    /// estimated cost. Tokens are taken from the API's `usage` block; without
+    /// one they are counted from the request messages (images are not
+    /// counted) and the reply.
+    async fn send_json_chat(
+        &self,
+        settings: &LlmSettings,
+        model: &str,
+        messages: Vec<ChatMessage>,
+        schema: &serde_json::Value,
+        label: &str,
+    ) -> Result<(ChatResponse, CallStats), LlmCallError> {
+        let prompt_tokens = self.count_message_tokens(&messages);
+        let (response, attempts) = self
+            .send_with_output_mode(settings, model, messages, schema, label)
+            .await?;
+
+        // Prefer the API's own counts; count locally only if it sent none
+        let usage = match &response.usage {
+            Some(api) => TokenUsage {
+                prompt_tokens: api.prompt_tokens,
+                completion_tokens: api.completion_tokens,
+            },
+            None => {
+                let completion_tokens = response
+                    .choices
+                    .first()
+                    .map(|c| self.token_counter.count(&c.message.content))
+                    .unwrap_or(0);
+                TokenUsage {
+                    prompt_tokens: prompt_tokens as u64,
+                    completion_tokens: completion_tokens as u64,
+                }
+            }
+        };
+        let cost_usd = settings
+            .pricing
+            .get(model)
+            .map(|price| tokens::cost_usd(usage, price))
+            .unwrap_or(0.0);
+
+        Ok((response, CallStats { attempts, usage, cost_usd }))
+    }
+
+    /// Tokens of a request's message text, including the chat template overhead.
+    fn count_message_tokens(&self, messages: &[ChatMessage]) -> usize {
+        let text: usize = messages
+            .iter()
+            .map(|m| match &m.content {
+                MessageContent::Text(text) => self.token_counter.count(text),
+                MessageContent::Parts(parts) => parts
+                    .iter()
+                    .map(|part| match part {
+                        ContentPart::Text { text } => self.token_counter.count(text),
+                        ContentPart::ImageUrl { .. } => 0,
+                    })
+                    .sum(),
+            })
+            .sum();
+        text + tokens::MESSAGE_OVERHEAD_TOKENS
+    }
+
+    /// Send a chat request with the output constrained to `schema` as
+    /// configured by `settings.structured_output`.
+    ///
+    /// In `auto` mode the first call tries `json_schema`; if the backend
+    /// rejects it and the same call succeeds with `json_object`, the engine
+    /// keeps using `json_object` until the API URL changes.
+    async fn send_with_output_mode(
+        &self,
+        settings: &LlmSettings,
+        model: &str,
+        messages: Vec<ChatMessage>,
+        schema: &serde_json::Value,
+        label: &str,
+    ) -> Result<(ChatResponse, u32), LlmCallError> {
+        let mode = match settings.structured_output {
+            StructuredOutputMode::Auto => {
+                (*self.detected_output.read().unwrap()).unwrap_or(StructuredOutputMode::Auto)
+            }
+            mode => mode,
+        };
+        if mode != StructuredOutputMode::Auto {
+            let request = json_chat_request(settings, model, messages, schema, mode);
+            return self.send_chat(settings, &request, label).await;
+        }
+
+        let request = json_chat_request(
+            settings,
+            model,
+            messages.clone(),
+            schema,
+            StructuredOutputMode::JsonSchema,
         );
+        match self.send_chat(settings, &request, label).await {
+            Ok(result) => {
+                *self.detected_output.write().unwrap() = Some(StructuredOutputMode::JsonSchema);
+                Ok(result)
+            }
+            Err(e) if e.status.is_some_and(rejects_response_format) => {
+                let request =
+                    json_chat_request(settings, model, messages, schema, StructuredOutputMode::JsonObject);
+                let (response, attempts) = self
+                    .send_chat(settings, &request, label)
+                    .await
+                    .map_err(|fallback| LlmCallError {
+                        attempts: fallback.attempts + e.attempts,
+                        ..fallback
+                    })?;
+                info!("{label} rejected json_schema output ({e}), using json_object from now on");
+                *self.detected_output.write().unwrap() = Some(StructuredOutputMode::JsonObject);
+                Ok((response, attempts + e.attempts))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Send a chat completion request, retrying transient failures according
+    /// to `settings.retry`. Returns the response and the number of attempts made.
+    async fn send_chat(
+        &self,
+        settings: &LlmSettings,
+        request: &ChatRequest,
+        label: &str,
+    ) -> Result<(ChatResponse, u32), LlmCallError> {
+        let policy = &settings.retry;
+        let max_attempts = policy.max_attempts.max(1);
+        let url = format!("{}/chat/completions", settings.api_url);
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            let mut req = self.client.post(&url).json(request);
+            if !settings.api_key.is_empty() {
+                req = req.bearer_auth(&settings.api_key);
+            }
 
-        Ok((data, confidence, model_used))
+            let (message, delay, status) = match req.send().await {
+                Ok(response) if response.status().is_success() => {
+                    return response
+                        .json::<ChatResponse>()
+                        .await
+                        .map(|chat_response| (chat_response, attempt))
+                        .map_err(|e| LlmCallError {
+                            attempts: attempt,
+                            message: format!("{label} returned an invalid response: {e}"),
+                            status: None,
+                            pages: Vec::new(),
+                        });
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retryable = retry::is_retryable_status(policy, status);
+                    let server_delay = retry::retry_after(policy, response.headers());
+                    let body = response.text().await.unwrap_or_default();
+                    let message = format!("{label} returned {status}: {body}");
+
+                    if !retryable {
+                        return Err(LlmCallError {
+                            attempts: attempt,
+                            message,
+                            status: Some(status.as_u16()),
+                            pages: Vec::new(),
+                        });
+                    }
+                    (message, server_delay, Some(status.as_u16()))
+                }
+                Err(e) => {
+                    let message = format!("{label} request failed: {e}");
+                    if !retry::is_retryable_error(policy, &e) {
+                        return Err(LlmCallError { attempts: attempt, message, status: None, pages: Vec::new() });
+                    }
+                    (message, None, None)
+                }
+            };
+
+            if attempt >= max_attempts {
+                return Err(LlmCallError { attempts: attempt, message, status, pages: Vec::new() });
+            }
+
+            let delay = delay.unwrap_or_else(|| retry::backoff_delay(policy, attempt));
+            warn!(
+                "{} (attempt {}/{}), retrying in {}ms",
+                message,
+                attempt,
+                max_attempts,
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Build a chat request whose output is constrained to `schema` in the given mode.
+fn json_chat_request(
+    settings: &LlmSettings,
+    model: &str,
+    messages: Vec<ChatMessage>,
+    schema: &serde_json::Value,
+    mode: StructuredOutputMode,
+) -> ChatRequest {
+    let (response_format, grammar) = match mode {
+        StructuredOutputMode::JsonSchema => (
+            Some(ResponseFormat {
+                r#type: "json_schema".into(),
+                json_schema: Some(JsonSchemaFormat {
+                    name: "document_extraction".into(),
+                    schema: schema.clone(),
+                    strict: false,
+                }),
+            }),
+            None,
+        ),
+        StructuredOutputMode::Grammar => (None, Some(grammar::schema_to_gbnf(schema))),
+        StructuredOutputMode::JsonObject | StructuredOutputMode::Auto => (
+            Some(ResponseFormat {
+                r#type: "json_object".into(),
+                json_schema: None,
+            }),
+            None,
+        ),
+    };
+
+    ChatRequest {
+        model: model.to_string(),
+        messages,
+        temperature: settings.temperature,
+        max_tokens: settings.max_tokens,
+        response_format,
+        grammar,
+    }
+}
+
+/// Status codes with which backends reject an unsupported `response_format`.
+fn rejects_response_format(status: u16) -> bool {
+    matches!(status, 400 | 422 | 501)
+}
+
+/// Copy of a schema without `required` constraints, at any depth.
+fn without_required(schema: &serde_json::Value) -> serde_json::Value {
+    match schema {
+        serde_json::Value::Object(obj) => obj
+            .iter()
+            .filter(|(key, _)| key.as_str() != "required")
+            .map(|(key, value)| (key.clone(), without_required(value)))
+            .collect(),
+        serde_json::Value::Array(items) => items.iter().map(without_required).collect(),
+        other => other.clone(),
     }
 }
 
@@ -572,6 +1493,23 @@ fn parse_llm_response(content: &str) -> (serde_json::Value, f64) {
     )
 }
 
+/// Whether `parse_llm_response` fell back to wrapping non-JSON output.
+fn is_parse_failure(data: &serde_json::Value) -> bool {
+    data.get("parse_error").is_some() && data.get("raw_response").is_some()
+}
+
+/// Schema problems in parsed LLM output. Output that was not JSON at all is
+/// reported as a single problem.
+fn output_issues(schema: &serde_json::Value, data: &serde_json::Value) -> Vec<ValidationIssue> {
+    if is_parse_failure(data) {
+        return vec![ValidationIssue {
+            path: "$".into(),
+            message: "response was not valid JSON".into(),
+        }];
+    }
+    validate::validate(schema, data)
+}
+
 /// Extract JSON from a markdown code fence like ```json ... ```.
 fn extract_json_block(text: &str) -> Option<&str> {
     let start_markers = ["```json\n", "```json\r\n", "```\n", "```\r\n"];
@@ -624,6 +1562,74 @@ mod tests {
         assert!((confidence - 0.3).abs() < 0.01);
     }
 
+    #[test]
+    fn non_json_output_is_a_validation_issue() {
+        let (value, _) = parse_llm_response("no JSON here");
+        let issues = output_issues(&serde_json::json!({"type": "object"}), &value);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].message, "response was not valid JSON");
+    }
+
+    fn test_settings(mode: StructuredOutputMode) -> LlmSettings {
+        LlmSettings {
+            api_url: "http://localhost".into(),
+            api_key: String::new(),
+            model_name: "m".into(),
+            context_size: 2048,
+            temperature: 0.1,
+            max_tokens: 100,
+            vision_model_name: String::new(),
+            vision_dpi: 200,
+            vision_max_pages: 5,
+            vision_page_cap: 0,
+            retry: Default::default(),
+            max_repair_attempts: 0,
+            structured_output: mode,
+            chunk_overlap_chars: 0,
+            merge_mode: MergeMode::Rules,
+            tokenizer_path: String::new(),
+            pricing: Default::default(),
+            vision_page_concurrency: 2,
+            vision_max_concurrent: 4,
+        }
+    }
+
+    #[test]
+    fn request_formats_per_mode() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": { "total": { "type": "number" } },
+            "required": ["total"]
+        });
+        let settings = test_settings(StructuredOutputMode::JsonObject);
+
+        let request = json_chat_request(&settings, "m", vec![], &schema, StructuredOutputMode::JsonObject);
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["response_format"], serde_json::json!({"type": "json_object"}));
+        assert!(json.get("grammar").is_none());
+
+        let request = json_chat_request(&settings, "m", vec![], &schema, StructuredOutputMode::JsonSchema);
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["response_format"]["type"], "json_schema");
+        assert_eq!(json["response_format"]["json_schema"]["schema"], schema);
+
+        let request = json_chat_request(&settings, "m", vec![], &schema, StructuredOutputMode::Grammar);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("response_format").is_none());
+        assert!(json["grammar"].as_str().unwrap().starts_with("root ::="));
+    }
+
+    #[test]
+    fn without_required_strips_nested_constraints() {
+        let schema = serde_json::json!({
+            "required": ["a"],
+            "properties": { "items": { "type": "array", "items": { "required": ["b"] } } }
+        });
+        let stripped = without_required(&schema);
+        assert!(stripped.get("required").is_none());
+        assert!(stripped["properties"]["items"]["items"].get("required").is_none());
+    }
+
     #[test]
     fn message_content_text_serializes_as_string() {
         let msg = ChatMessage {
@@ -656,4 +1662,18 @@ mod tests {
         assert_eq!(parts[1]["type"], "image_url");
         assert_eq!(parts[1]["image_url"]["url"], "data:image/jpeg;base64,abc123");
     }
+
+    #[test]
+    fn response_usage_is_optional() {
+        let with: ChatResponse = serde_json::from_str(
+            r#"{"choices":[{"message":{"content":"{}"}}],"usage":{"prompt_tokens":12,"completion_tokens":3,"total_tokens":15}}"#,
+        )
+        .unwrap();
+        let usage = with.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 3));
+
+        let without: ChatResponse =
+            serde_json::from_str(r#"{"choices":[{"message":{"content":"{}"}}]}"#).unwrap();
+        assert!(without.usage.is_none());
+    }
 }
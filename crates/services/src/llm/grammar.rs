@@ -0,0 +1,166 @@
+use serde_json::Value;
+
+/// Shared rules referenced by the generated ones.
+const BASE_RULES: &str = r#"ws ::= [ \t\n]*
+string ::= "\"" ( [^"\\\x7F\x00-\x1F] | "\\" ( ["\\/bfnrt] | "u" [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] ) )* "\""
+number ::= "-"? ( [0-9] | [1-9] [0-9]* ) ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )?
+integer ::= "-"? ( [0-9] | [1-9] [0-9]* )
+boolean ::= "true" | "false"
+null ::= "null"
+date ::= "\"" [0-9] [0-9] [0-9] [0-9] "-" [0-9] [0-9] "-" [0-9] [0-9] "\""
+value ::= object | array | string | number | boolean | null
+object ::= "{" ws ( string ws ":" ws value ( ws "," ws string ws ":" ws value )* )? ws "}"
+array ::= "[" ws ( value ( ws "," ws value )* )? ws "]""#;
+
+/// Convert a document type's output schema into a GBNF grammar for llama.cpp.
+///
+/// Supports the same subset as the validator. Every property is emitted, in
+/// schema order; properties that are not required also accept null, matching
+/// the prompts' "use null for fields you cannot determine" rule.
+pub fn schema_to_gbnf(schema: &Value) -> String {
+    let mut rules = Vec::new();
+    let root = rule_for(schema, "root", &mut rules);
+    if root != "root" {
+        rules.insert(0, format!("root ::= {root}"));
+    }
+    rules.push(BASE_RULES.to_string());
+    rules.join("\n")
+}
+
+/// Return a rule expression for `schema`, adding named rules for objects and
+/// arrays to `rules`.
+fn rule_for(schema: &Value, name: &str, rules: &mut Vec<String>) -> String {
+    if let Some(values) = schema.get("enum").and_then(|e| e.as_array()) {
+        let alternatives: Vec<String> = values.iter().map(|v| literal(&v.to_string())).collect();
+        return group(&alternatives);
+    }
+
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(ts)) => ts.iter().filter_map(|t| t.as_str()).collect(),
+        _ if schema.get("properties").is_some() => vec!["object"],
+        _ => return "value".into(),
+    };
+
+    let alternatives: Vec<String> = types
+        .iter()
+        .map(|t| match *t {
+            "object" => object_rule(schema, name, rules),
+            "array" => {
+                let item_schema = schema.get("items").cloned().unwrap_or(Value::Null);
+                let item = rule_for(&item_schema, &format!("{name}-item"), rules);
+                rules.push(format!(
+                    "{name} ::= \"[\" ws ( {item} ( ws \",\" ws {item} )* )? ws \"]\""
+                ));
+                name.to_string()
+            }
+            "string" => match schema.get("format").and_then(|f| f.as_str()) {
+                Some("date") => "date".into(),
+                _ => "string".into(),
+            },
+            "number" | "integer" | "boolean" | "null" => t.to_string(),
+            _ => "value".into(),
+        })
+        .collect();
+    group(&alternatives)
+}
+
+fn object_rule(schema: &Value, name: &str, rules: &mut Vec<String>) -> String {
+    let Some(props) = schema.get("properties").and_then(|p| p.as_object()).filter(|p| !p.is_empty())
+    else {
+        return "object".into();
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(|r| r.as_array())
+        .map(|r| r.iter().filter_map(|f| f.as_str()).collect())
+        .unwrap_or_default();
+
+    let fields: Vec<String> = props
+        .iter()
+        .map(|(key, prop)| {
+            let mut value = rule_for(prop, &format!("{name}-{}", rule_name(key)), rules);
+            if !required.contains(&key.as_str()) && value != "value" && !value.contains("null") {
+                value = format!("( {value} | null )");
+            }
+            format!("{} ws \":\" ws {value}", literal(&Value::String(key.clone()).to_string()))
+        })
+        .collect();
+
+    rules.push(format!(
+        "{name} ::= \"{{\" ws {} ws \"}}\"",
+        fields.join(" ws \",\" ws ")
+    ));
+    name.to_string()
+}
+
+fn group(alternatives: &[String]) -> String {
+    match alternatives {
+        [single] => single.clone(),
+        _ => format!("( {} )", alternatives.join(" | ")),
+    }
+}
+
+/// A GBNF string literal matching `text` exactly.
+fn literal(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Rule names may only contain letters, digits and dashes.
+fn rule_name(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn object_fields_and_nullability() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "invoice_number": { "type": "string" },
+                "due_date": { "type": ["string", "null"], "format": "date" },
+                "total_amount": { "type": "number" }
+            },
+            "required": ["invoice_number", "total_amount"]
+        });
+        let grammar = schema_to_gbnf(&schema);
+        let root = grammar.lines().find(|l| l.starts_with("root ::=")).unwrap();
+
+        assert!(root.contains(r#""\"due_date\"" ws ":" ws ( date | null )"#));
+        assert!(root.contains(r#""\"invoice_number\"" ws ":" ws string"#));
+        assert!(root.contains(r#""\"total_amount\"" ws ":" ws number"#));
+        assert!(grammar.contains("\nws ::= "));
+    }
+
+    #[test]
+    fn arrays_and_enums_get_rules() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "transactions": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": { "type": { "type": "string", "enum": ["credit", "debit"] } },
+                        "required": ["type"]
+                    }
+                },
+                "key_fields": { "type": "object", "additionalProperties": true }
+            },
+            "required": ["transactions"]
+        });
+        let grammar = schema_to_gbnf(&schema);
+
+        assert!(grammar.contains(
+            r#"root-transactions ::= "[" ws ( root-transactions-item ( ws "," ws root-transactions-item )* )? ws "]""#
+        ));
+        assert!(grammar.contains(r#"( "\"credit\"" | "\"debit\"" )"#));
+        assert!(grammar.contains(r#""\"key_fields\"" ws ":" ws ( object | null )"#));
+    }
+}
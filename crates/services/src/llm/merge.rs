@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+/// Confidence assumed for a partial result that does not state its own.
+const DEFAULT_CONFIDENCE: f64 = 0.7;
+
+/// Merge partial extraction results (pages or text chunks, in document
+/// order) into one object without calling the LLM, guided by the document
+/// type's `schema`:
+///
+/// - arrays are concatenated; a row identical to one already taken from an
+///   earlier part is dropped (repeated headers, chunk overlap), while
+///   identical rows within one part are kept
+/// - objects are merged field by field with the same rules
+/// - scalars take the first non-null value, except totals, closing values
+///   and period ends (see [`prefers_last`]), which take the last one
+///
+/// Returns the merged data and the mean confidence of the parts.
+pub fn merge_results(schema: &Value, results: &[Value]) -> (Value, f64) {
+    let confidences: Vec<f64> = results
+        .iter()
+        .map(|r| r.get("confidence").and_then(Value::as_f64).unwrap_or(DEFAULT_CONFIDENCE))
+        .collect();
+    let confidence = if confidences.is_empty() {
+        DEFAULT_CONFIDENCE
+    } else {
+        confidences.iter().sum::<f64>() / confidences.len() as f64
+    };
+
+    let parts: Vec<&Value> = results.iter().collect();
+    let mut merged = merge_values(Some(schema), "", &parts);
+    if let Some(obj) = merged.as_object_mut() {
+        obj.insert("confidence".into(), confidence.into());
+    }
+    (merged, confidence)
+}
+
+fn merge_values(schema: Option<&Value>, name: &str, parts: &[&Value]) -> Value {
+    let present: Vec<&Value> = parts.iter().copied().filter(|v| !v.is_null()).collect();
+    if present.is_empty() {
+        return Value::Null;
+    }
+
+    // The schema decides; fields it does not describe go by the values' shape
+    let kind = schema.and_then(schema_type).unwrap_or(match present[0] {
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+        _ => "scalar",
+    });
+
+    match kind {
+        "array" => Value::Array(concat_rows(&present)),
+        "object" => {
+            let objects: Vec<&Value> = present.into_iter().filter(|v| v.is_object()).collect();
+            Value::Object(merge_objects(schema, &objects))
+        }
+        _ => {
+            let chosen = if prefers_last(name) {
+                present.last()
+            } else {
+                present.first()
+            };
+            chosen.map(|v| (*v).clone()).unwrap_or(Value::Null)
+        }
+    }
+}
+
+/// The non-null `type` of a schema, e.g. `"array"` for `["array", "null"]`.
+fn schema_type(schema: &Value) -> Option<&str> {
+    match schema.get("type")? {
+        Value::String(t) => Some(t.as_str()),
+        Value::Array(types) => types.iter().filter_map(Value::as_str).find(|t| *t != "null"),
+        _ => None,
+    }
+}
+
+fn merge_objects(schema: Option<&Value>, parts: &[&Value]) -> Map<String, Value> {
+    let properties = schema.and_then(|s| s.get("properties"));
+
+    // Fields in order of first appearance
+    let mut keys: Vec<&String> = Vec::new();
+    for part in parts {
+        for key in part.as_object().into_iter().flat_map(|o| o.keys()) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+
+    keys.into_iter()
+        .map(|key| {
+            let values: Vec<&Value> = parts.iter().filter_map(|p| p.get(key)).collect();
+            let field_schema = properties.and_then(|p| p.get(key));
+            (key.clone(), merge_values(field_schema, key, &values))
+        })
+        .collect()
+}
+
+/// Concatenate the rows of all parts, dropping rows already taken from an
+/// earlier part. A part with a single value instead of an array counts as
+/// one row.
+fn concat_rows(parts: &[&Value]) -> Vec<Value> {
+    let mut rows = Vec::new();
+    // Rows taken so far, by serialized value, and how many times
+    let mut taken: HashMap<String, usize> = HashMap::new();
+
+    for part in parts {
+        let mut from_earlier = taken.clone();
+        let part_rows = match part {
+            Value::Array(rows) => rows.as_slice(),
+            single => std::slice::from_ref(*single),
+        };
+        for row in part_rows {
+            let key = row.to_string();
+            match from_earlier.get_mut(&key) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => {
+                    *taken.entry(key).or_default() += 1;
+                    rows.push(row.clone());
+                }
+            }
+        }
+    }
+    rows
+}
+
+/// Fields whose value on a later page supersedes earlier ones: totals
+/// (earlier pages may show carried-forward subtotals), closing values and
+/// period ends.
+fn prefers_last(name: &str) -> bool {
+    name.contains("total")
+        || name.contains("closing")
+        || name == "tax_amount"
+        || name == "amount_due"
+        || name.ends_with("_end")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn invoice_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "vendor_name": { "type": "string" },
+                "subtotal": { "type": ["number", "null"] },
+                "total_amount": { "type": "number" },
+                "line_items": { "type": "array", "items": { "type": "object" } }
+            }
+        })
+    }
+
+    #[test]
+    fn concatenates_rows_and_prefers_last_totals() {
+        let pages = [
+            json!({
+                "vendor_name": "ACME",
+                "subtotal": 30.0,
+                "total_amount": 30.0,
+                "line_items": [{ "description": "A", "amount": 10 }, { "description": "B", "amount": 20 }],
+                "confidence": 0.9
+            }),
+            json!({
+                "vendor_name": null,
+                "subtotal": 50.0,
+                "total_amount": 59.5,
+                "line_items": [{ "description": "C", "amount": 20 }],
+                "confidence": 0.7
+            }),
+        ];
+        let (merged, confidence) = merge_results(&invoice_schema(), &pages);
+
+        assert_eq!(merged["vendor_name"], "ACME");
+        assert_eq!(merged["total_amount"], 59.5);
+        assert_eq!(merged["subtotal"], 50.0);
+        assert_eq!(merged["line_items"].as_array().unwrap().len(), 3);
+        assert!((confidence - 0.8).abs() < 1e-9);
+        assert_eq!(merged["confidence"], confidence);
+    }
+
+    #[test]
+    fn drops_rows_repeated_from_earlier_parts_only() {
+        let row = json!({ "date": "2024-01-05", "amount": -12.5 });
+        let other = json!({ "date": "2024-01-06", "amount": 100 });
+        let parts = [
+            json!({ "transactions": [row.clone(), row.clone()] }),
+            json!({ "transactions": [row.clone(), row.clone(), other.clone()] }),
+        ];
+        let (merged, _) = merge_results(&json!({}), &parts);
+
+        // Both identical rows of the first part are genuine; the second part
+        // repeats them (overlap) before its new row
+        assert_eq!(merged["transactions"], json!([row.clone(), row, other]));
+    }
+
+    #[test]
+    fn keeps_first_opening_and_last_closing_balance() {
+        let parts = [
+            json!({ "opening_balance": 100, "closing_balance": 80, "statement_period_end": null }),
+            json!({ "opening_balance": 80, "closing_balance": 40, "statement_period_end": "2024-01-31" }),
+        ];
+        let (merged, _) = merge_results(&json!({}), &parts);
+
+        assert_eq!(merged["opening_balance"], 100);
+        assert_eq!(merged["closing_balance"], 40);
+        assert_eq!(merged["statement_period_end"], "2024-01-31");
+    }
+
+    #[test]
+    fn schema_array_field_accepts_a_single_row() {
+        let parts = [
+            json!({ "line_items": [{ "description": "A", "amount": 10 }] }),
+            json!({ "line_items": { "description": "B", "amount": 5 } }),
+        ];
+        let (merged, _) = merge_results(&invoice_schema(), &parts);
+        assert_eq!(merged["line_items"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn merges_nested_objects() {
+        let parts = [
+            json!({ "key_fields": { "order": "A-1" } }),
+            json!({ "key_fields": { "order": "A-2", "project": "X" } }),
+        ];
+        let (merged, _) = merge_results(&json!({}), &parts);
+        assert_eq!(merged["key_fields"], json!({ "order": "A-1", "project": "X" }));
+    }
+}
@@ -1,4 +1,13 @@
+pub mod chunking;
 pub mod engine;
+pub mod grammar;
+pub mod merge;
 pub mod prompts;
+pub mod retry;
+pub mod tokens;
+pub mod validate;
 
-pub use engine::{LlmEngine, LlmResponse};
+pub use engine::{LlmEngine, LlmResponse, PageResult, VisionPages};
+pub use retry::LlmCallError;
+pub use tokens::TokenUsage;
+pub use validate::ValidationReport;
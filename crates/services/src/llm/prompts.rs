@@ -1,79 +1,177 @@
-/// Build the system prompt based on the document type.
-pub fn system_prompt(document_type: &str) -> String {
-    match document_type {
-        "invoice" => SYSTEM_INVOICE.to_string(),
-        "bank_statement" => SYSTEM_BANK_STATEMENT.to_string(),
-        "payment" => SYSTEM_PAYMENT.to_string(),
-        "receipt" => SYSTEM_RECEIPT.to_string(),
-        _ => SYSTEM_GENERIC.to_string(),
-    }
+use serde_json::Value;
+
+use super::validate::ValidationIssue;
+use crate::doctypes::DocumentTypeDef;
+
+/// Rules that apply to every document type.
+const COMMON_RULES: &[&str] = &[
+    "Use null for fields you cannot determine",
+    "Dates in YYYY-MM-DD format",
+    "Amounts as numbers (not strings)",
+    "confidence: your certainty about the extraction accuracy (0.0 to 1.0)",
+    "Return ONLY the JSON object, no markdown, no explanations",
+];
+
+/// Build the system prompt for a document type from its schema and instructions.
+///
+/// `known_types` lists the catalog's type names; the generic prompt offers
+/// them to the model as possible classifications.
+pub fn system_prompt(doc_type: &DocumentTypeDef, known_types: &[&str]) -> String {
+    let intro = if doc_type.is_fallback() {
+        "You are a document extraction assistant. Extract all key structured data from documents and return valid JSON.".to_string()
+    } else {
+        format!(
+            "You are a document extraction assistant. Extract structured data from {} documents and return valid JSON.",
+            doc_type.label
+        )
+    };
+
+    let document_type_field = if doc_type.is_fallback() {
+        let mut names: Vec<&str> = known_types.iter().copied().filter(|n| *n != doc_type.name).collect();
+        names.extend(["contract", "report", "letter"]);
+        format!("\"string ({}, or {})\"", names.join(", "), doc_type.name)
+    } else {
+        format!("\"{}\"", doc_type.name)
+    };
+
+    let template = schema_template(&doc_type.schema, &document_type_field);
+
+    let instructions: Vec<&str> = doc_type
+        .instructions
+        .iter()
+        .flat_map(|i| i.lines())
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    // An instruction starting like a common rule (e.g. "Amounts as numbers (…)")
+    // replaces it; other instructions go before the closing rules.
+    let mut rules: Vec<&str> = COMMON_RULES
+        .iter()
+        .map(|rule| {
+            instructions
+                .iter()
+                .copied()
+                .find(|i| rule_key(i) == rule_key(rule))
+                .unwrap_or(rule)
+        })
+        .collect();
+    let extra = instructions
+        .iter()
+        .copied()
+        .filter(|i| !COMMON_RULES.iter().any(|rule| rule_key(i) == rule_key(rule)));
+    let closing = rules.len() - 2;
+    rules.splice(closing..closing, extra);
+
+    let rules: Vec<String> = rules.iter().map(|r| format!("- {r}")).collect();
+
+    format!(
+        "{intro}\n\nReturn a JSON object with these fields:\n{template}\n\nRules:\n{}",
+        rules.join("\n")
+    )
+}
+
+/// The part of a rule before any parenthesised detail.
+fn rule_key(rule: &str) -> &str {
+    rule.split(" (").next().unwrap_or(rule).trim()
 }
 
 /// Build the user prompt for a vision model processing a document page image.
-pub fn vision_user_prompt(document_type: &str, page_num: usize, total_pages: usize) -> String {
-    let doc_instruction = match document_type {
-        "invoice" => "Extract the invoice data from this document image.",
-        "bank_statement" => "Extract the bank statement data from this document image.",
-        "payment" => "Extract the payment data from this document image.",
-        "receipt" => "Extract the receipt data from this document image.",
-        _ => "Extract all key information from this document image.",
+///
+/// `ocr_text` is the page's OCR output in hybrid mode, given to the model to
+/// cross-check against the image rather than to trust.
+pub fn vision_user_prompt(
+    doc_type: &DocumentTypeDef,
+    page_num: usize,
+    total_pages: usize,
+    ocr_text: Option<&str>,
+) -> String {
+    let doc_instruction = if doc_type.is_fallback() {
+        "Extract all key information from this document image.".to_string()
+    } else {
+        format!("Extract the {} data from this document image.", doc_type.label)
+    };
+
+    let ocr_section = match ocr_text.map(str::trim).filter(|t| !t.is_empty()) {
+        Some(text) => format!(
+            "\n\nOCR text of this page (may contain recognition errors; the image is \
+             authoritative, use the text to check small print, numbers and handwriting):\n\
+             ---\n{text}\n---"
+        ),
+        None => String::new(),
     };
 
     if total_pages > 1 {
         format!(
-            "{doc_instruction}\n\nThis is page {page_num} of {total_pages}. \
+            "{doc_instruction}{ocr_section}\n\nThis is page {page_num} of {total_pages}. \
              Extract all visible data from this page. \
              Respond with a single JSON object only. No explanations."
         )
     } else {
         format!(
-            "{doc_instruction}\n\nRespond with a single JSON object only. No explanations."
+            "{doc_instruction}{ocr_section}\n\nRespond with a single JSON object only. No explanations."
         )
     }
 }
 
 /// Build the merge prompt for combining per-page extraction results.
 pub fn merge_pages_prompt(
-    document_type: &str,
+    doc_type: &DocumentTypeDef,
     page_results: &[serde_json::Value],
 ) -> String {
-    let pages_json: Vec<String> = page_results
+    merge_prompt(
+        &format!("individual pages of one {} document", doc_type.label),
+        "Page",
+        "",
+        page_results,
+    )
+}
+
+/// Build the merge prompt for combining results extracted from consecutive,
+/// overlapping sections of a long document's text.
+pub fn merge_chunks_prompt(
+    doc_type: &DocumentTypeDef,
+    chunk_results: &[serde_json::Value],
+) -> String {
+    merge_prompt(
+        &format!("consecutive sections of the text of one {} document", doc_type.label),
+        "Section",
+        "- Sections overlap, so a row at the end of one section may repeat at the start of the next: keep it once\n",
+        chunk_results,
+    )
+}
+
+fn merge_prompt(source: &str, part: &str, extra_rules: &str, results: &[serde_json::Value]) -> String {
+    let parts_json: Vec<String> = results
         .iter()
         .enumerate()
-        .map(|(i, v)| format!("Page {}:\n{}", i + 1, serde_json::to_string_pretty(v).unwrap_or_default()))
+        .map(|(i, v)| format!("{part} {}:\n{}", i + 1, serde_json::to_string_pretty(v).unwrap_or_default()))
         .collect();
 
-    let doc_label = match document_type {
-        "invoice" => "invoice",
-        "bank_statement" => "bank statement",
-        "payment" => "payment document",
-        "receipt" => "receipt",
-        _ => "document",
-    };
-
     format!(
-        "The following JSON objects were extracted from individual pages of a {doc_label}. \
+        "The following JSON objects were extracted from {source}. \
          Merge them into a single coherent JSON object.\n\n\
          Rules:\n\
-         - Combine line_items/transactions/items from all pages into one array\n\
-         - Use header fields (vendor, dates, totals) from whichever page has them\n\
-         - If totals appear on multiple pages, prefer the final page's values\n\
+         - Combine line_items/transactions/items from all {parts} into one array\n\
+         - Use header fields (vendor, dates, totals) from whichever {part_lower} has them\n\
+         - If totals appear on multiple {parts}, prefer the final {part_lower}'s values\n\
          - Remove duplicates\n\
-         - Keep the same JSON schema as the individual pages\n\n\
+         {extra_rules}\
+         - Keep the same JSON schema as the individual {parts}\n\n\
          {}\n\n\
          Respond with a single merged JSON object only. No explanations.",
-        pages_json.join("\n\n")
+        parts_json.join("\n\n"),
+        parts = format!("{}s", part.to_lowercase()),
+        part_lower = part.to_lowercase(),
     )
 }
 
 /// Build the user prompt with the document text.
-pub fn user_prompt(raw_text: &str, document_type: &str) -> String {
-    let instruction = match document_type {
-        "invoice" => "Extract the invoice data from the following document text.",
-        "bank_statement" => "Extract the bank statement data from the following document text.",
-        "payment" => "Extract the payment data from the following document text.",
-        "receipt" => "Extract the receipt data from the following document text.",
-        _ => "Extract all key information from the following document text.",
+pub fn user_prompt(raw_text: &str, doc_type: &DocumentTypeDef) -> String {
+    let instruction = if doc_type.is_fallback() {
+        "Extract all key information from the following document text.".to_string()
+    } else {
+        format!("Extract the {} data from the following document text.", doc_type.label)
     };
 
     format!(
@@ -81,166 +179,215 @@ pub fn user_prompt(raw_text: &str, document_type: &str) -> String {
     )
 }
 
-const SYSTEM_INVOICE: &str = r#"You are a document extraction assistant. Extract structured data from invoice documents and return valid JSON.
-
-Return a JSON object with these fields:
-{
-  "document_type": "invoice",
-  "vendor_name": "string",
-  "vendor_address": "string or null",
-  "vendor_tax_id": "string or null",
-  "buyer_name": "string or null",
-  "buyer_address": "string or null",
-  "buyer_tax_id": "string or null",
-  "invoice_number": "string",
-  "invoice_date": "YYYY-MM-DD",
-  "due_date": "YYYY-MM-DD or null",
-  "currency": "3-letter code",
-  "subtotal": number or null,
-  "tax_amount": number or null,
-  "tax_rate": "percentage string or null",
-  "total_amount": number,
-  "line_items": [
-    {
-      "description": "string",
-      "quantity": number or null,
-      "unit_price": number or null,
-      "amount": number
-    }
-  ],
-  "payment_terms": "string or null",
-  "notes": "string or null",
-  "confidence": 0.0-1.0
+/// Build the follow-up prompt asking the model to correct output that failed
+/// schema validation.
+pub fn repair_prompt(data: &Value, issues: &[ValidationIssue]) -> String {
+    let problems: Vec<String> = issues.iter().map(|i| format!("- {i}")).collect();
+
+    format!(
+        "The JSON below does not match the required schema. Fix these problems:\n{}\n\n\
+         JSON:\n{}\n\n\
+         Keep all correct values unchanged and use null only where a value cannot be determined. \
+         Respond with the corrected JSON object only. No explanations.",
+        problems.join("\n"),
+        serde_json::to_string_pretty(data).unwrap_or_default()
+    )
+}
+
+/// Build the user prompt for one section of a document too long for a
+/// single request.
+pub fn chunk_user_prompt(chunk: &str, doc_type: &DocumentTypeDef, part: usize, total_parts: usize) -> String {
+    let instruction = if doc_type.is_fallback() {
+        "Extract all key information from the following document text.".to_string()
+    } else {
+        format!("Extract the {} data from the following document text.", doc_type.label)
+    };
+
+    format!(
+        "{instruction}\n\nThe document is too long for one request: this is section {part} of {total_parts}, \
+         and sections overlap slightly. Extract everything present in this section; \
+         use null for fields that do not appear in it.\n\n\
+         ---\nDOCUMENT TEXT (section {part}/{total_parts}):\n---\n{chunk}\n---\n\n\
+         Respond with a single JSON object only. No explanations."
+    )
 }
 
-Rules:
-- Use null for fields you cannot determine
-- Dates in YYYY-MM-DD format
-- Amounts as numbers (not strings)
-- confidence: your certainty about the extraction accuracy (0.0 to 1.0)
-- Return ONLY the JSON object, no markdown, no explanations"#;
-
-const SYSTEM_BANK_STATEMENT: &str = r#"You are a document extraction assistant. Extract structured data from bank statements and return valid JSON.
-
-Return a JSON object with these fields:
-{
-  "document_type": "bank_statement",
-  "bank_name": "string",
-  "account_holder": "string or null",
-  "account_number": "string (last 4 digits only for security)",
-  "statement_period_start": "YYYY-MM-DD",
-  "statement_period_end": "YYYY-MM-DD",
-  "currency": "3-letter code",
-  "opening_balance": number,
-  "closing_balance": number,
-  "total_deposits": number or null,
-  "total_withdrawals": number or null,
-  "transactions": [
-    {
-      "date": "YYYY-MM-DD",
-      "description": "string",
-      "amount": number,
-      "type": "credit" or "debit",
-      "balance": number or null
+/// Render a JSON Schema as the annotated JSON skeleton shown to the model,
+/// e.g. `"due_date": "YYYY-MM-DD or null"`, with `document_type` first and
+/// `confidence` last.
+pub fn schema_template(schema: &Value, document_type_field: &str) -> String {
+    let mut fields = vec![("document_type".to_string(), document_type_field.to_string())];
+    if let Some(props) = schema.get("properties").and_then(|p| p.as_object()) {
+        for (name, prop) in props {
+            if name != "document_type" && name != "confidence" {
+                fields.push((name.clone(), render_value(prop, 1)));
+            }
+        }
     }
-  ],
-  "confidence": 0.0-1.0
+    fields.push(("confidence".to_string(), "0.0-1.0".to_string()));
+    render_object(&fields, 0)
 }
 
-Rules:
-- Use null for fields you cannot determine
-- Dates in YYYY-MM-DD format
-- Amounts as numbers (positive for credits, negative for debits in the amount field)
-- Only include last 4 digits of account numbers
-- confidence: your certainty about the extraction accuracy (0.0 to 1.0)
-- Return ONLY the JSON object, no markdown, no explanations"#;
-
-const SYSTEM_PAYMENT: &str = r#"You are a document extraction assistant. Extract structured data from payment documents and return valid JSON.
-
-Return a JSON object with these fields:
-{
-  "document_type": "payment",
-  "payer_name": "string",
-  "payee_name": "string",
-  "payment_date": "YYYY-MM-DD",
-  "payment_method": "string (bank_transfer, credit_card, cash, check, etc.)",
-  "reference_number": "string or null",
-  "invoice_reference": "string or null",
-  "currency": "3-letter code",
-  "amount": number,
-  "status": "completed, pending, or failed",
-  "notes": "string or null",
-  "confidence": 0.0-1.0
+fn render_object(fields: &[(String, String)], depth: usize) -> String {
+    let indent = "  ".repeat(depth + 1);
+    let body: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("{indent}\"{name}\": {value}"))
+        .collect();
+    format!("{{\n{}\n{}}}", body.join(",\n"), "  ".repeat(depth))
 }
 
-Rules:
-- Use null for fields you cannot determine
-- Dates in YYYY-MM-DD format
-- Amounts as numbers (not strings)
-- confidence: your certainty about the extraction accuracy (0.0 to 1.0)
-- Return ONLY the JSON object, no markdown, no explanations"#;
-
-const SYSTEM_RECEIPT: &str = r#"You are a document extraction assistant. Extract structured data from receipts and return valid JSON.
-
-Return a JSON object with these fields:
-{
-  "document_type": "receipt",
-  "merchant_name": "string",
-  "merchant_address": "string or null",
-  "receipt_number": "string or null",
-  "date": "YYYY-MM-DD",
-  "time": "HH:MM or null",
-  "currency": "3-letter code",
-  "items": [
-    {
-      "description": "string",
-      "quantity": number or null,
-      "unit_price": number or null,
-      "amount": number
+fn render_value(prop: &Value, depth: usize) -> String {
+    let (types, nullable) = schema_types(prop);
+    let primary = types.first().map(String::as_str).unwrap_or("string");
+    let or_null = if nullable { " or null" } else { "" };
+    let description = prop.get("description").and_then(|d| d.as_str());
+
+    if let Some(values) = prop.get("enum").and_then(|e| e.as_array()) {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        return format!("{}{or_null}", values.join(" or "));
     }
-  ],
-  "subtotal": number or null,
-  "tax_amount": number or null,
-  "total_amount": number,
-  "payment_method": "string or null",
-  "confidence": 0.0-1.0
-}
 
-Rules:
-- Use null for fields you cannot determine
-- Dates in YYYY-MM-DD format
-- Amounts as numbers (not strings)
-- confidence: your certainty about the extraction accuracy (0.0 to 1.0)
-- Return ONLY the JSON object, no markdown, no explanations"#;
-
-const SYSTEM_GENERIC: &str = r#"You are a document extraction assistant. Extract all key structured data from documents and return valid JSON.
-
-Analyze the document and determine its type, then extract relevant fields.
-
-Return a JSON object with these fields:
-{
-  "document_type": "string (invoice, bank_statement, payment, receipt, contract, report, letter, or other)",
-  "title": "string or null",
-  "date": "YYYY-MM-DD or null",
-  "parties": ["list of people/organizations mentioned"],
-  "amounts": [
-    {
-      "label": "string",
-      "value": number,
-      "currency": "3-letter code or null"
+    match primary {
+        "object" => {
+            let fields: Vec<(String, String)> = match prop.get("properties").and_then(|p| p.as_object()) {
+                Some(props) if !props.is_empty() => props
+                    .iter()
+                    .map(|(name, p)| (name.clone(), render_value(p, depth + 1)))
+                    .collect(),
+                _ => vec![("field_name".to_string(), "\"value\"".to_string())],
+            };
+            render_object(&fields, depth)
+        }
+        "array" => {
+            let item = prop.get("items").cloned().unwrap_or(Value::Null);
+            let item_is_object = schema_types(&item).0.first().map(String::as_str) == Some("object");
+            if item_is_object {
+                let inner = render_value(&item, depth + 1);
+                format!("[\n{}{}\n{}]", "  ".repeat(depth + 1), inner, "  ".repeat(depth))
+            } else {
+                let inner = match description {
+                    Some(d) => format!("\"{d}\""),
+                    None => render_value(&item, depth + 1),
+                };
+                format!("[{inner}]")
+            }
+        }
+        "number" | "integer" => format!("number{or_null}"),
+        "boolean" => format!("true/false{or_null}"),
+        _ => {
+            let text = match (prop.get("format").and_then(|f| f.as_str()), description) {
+                (Some("date"), _) => "YYYY-MM-DD".to_string(),
+                (Some("date-time"), _) => "YYYY-MM-DDTHH:MM:SS".to_string(),
+                (_, Some(d)) => format!("string ({d})"),
+                _ => "string".to_string(),
+            };
+            format!("\"{text}{or_null}\"")
+        }
     }
-  ],
-  "key_fields": {
-    "field_name": "value"
-  },
-  "summary": "brief one-sentence summary of the document",
-  "confidence": 0.0-1.0
 }
 
-Rules:
-- Use null for fields you cannot determine
-- Dates in YYYY-MM-DD format
-- Amounts as numbers (not strings)
-- key_fields: extract any important name-value pairs not covered above
-- confidence: your certainty about the extraction accuracy (0.0 to 1.0)
-- Return ONLY the JSON object, no markdown, no explanations"#;
+/// The non-null types a schema allows, and whether it allows null.
+fn schema_types(prop: &Value) -> (Vec<String>, bool) {
+    let mut types = match prop.get("type") {
+        Some(Value::String(t)) => vec![t.clone()],
+        Some(Value::Array(ts)) => ts.iter().filter_map(|t| t.as_str().map(String::from)).collect(),
+        _ if prop.get("properties").is_some() => vec!["object".to_string()],
+        _ => Vec::new(),
+    };
+    let nullable = types.iter().any(|t| t == "null");
+    types.retain(|t| t != "null");
+    (types, nullable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doctypes::DocumentTypeCatalog;
+
+    #[test]
+    fn vision_prompt_includes_ocr_text_in_hybrid_mode() {
+        let catalog = DocumentTypeCatalog::builtin();
+        let invoice = catalog.resolve("invoice");
+
+        let prompt = vision_user_prompt(invoice, 2, 3, Some("Total 1.234,50\n"));
+        assert!(prompt.contains("---\nTotal 1.234,50\n---"));
+        assert!(prompt.contains("This is page 2 of 3."));
+
+        let plain = vision_user_prompt(invoice, 1, 1, Some("  "));
+        assert!(!plain.contains("OCR text"));
+    }
+
+    #[test]
+    fn invoice_prompt_lists_schema_fields() {
+        let catalog = DocumentTypeCatalog::builtin();
+        let prompt = system_prompt(catalog.resolve("invoice"), &catalog.names());
+
+        assert!(prompt.contains("from invoice documents"));
+        assert!(prompt.contains("\"document_type\": \"invoice\""));
+        assert!(prompt.contains("\"vendor_tax_id\": \"string or null\""));
+        assert!(prompt.contains("\"due_date\": \"YYYY-MM-DD or null\""));
+        assert!(prompt.contains("\"total_amount\": number"));
+        assert!(prompt.contains("\"quantity\": number or null"));
+        assert!(prompt.contains("\"confidence\": 0.0-1.0"));
+        assert!(prompt.contains("- Dates in YYYY-MM-DD format"));
+    }
+
+    #[test]
+    fn instructions_refine_common_rules() {
+        let catalog = DocumentTypeCatalog::builtin();
+        let prompt = system_prompt(catalog.resolve("bank_statement"), &catalog.names());
+
+        assert!(prompt.contains("- Amounts as numbers (positive for credits"));
+        assert!(!prompt.contains("- Amounts as numbers (not strings)"));
+        assert!(prompt.contains("- Only include last 4 digits of account numbers"));
+        assert!(prompt.contains("\"type\": \"credit\" or \"debit\""));
+    }
+
+    #[test]
+    fn generic_prompt_offers_known_types() {
+        let catalog = DocumentTypeCatalog::builtin();
+        let prompt = system_prompt(catalog.fallback(), &["invoice", "purchase_order", "other"]);
+
+        assert!(prompt.contains("Extract all key structured data"));
+        assert!(prompt.contains("\"string (invoice, purchase_order, contract, report, letter, or other)\""));
+        assert!(prompt.contains("\"field_name\": \"value\""));
+    }
+
+    #[test]
+    fn repair_prompt_lists_problems() {
+        let issues = vec![ValidationIssue {
+            path: "$.total_amount".into(),
+            message: "expected number, got string".into(),
+        }];
+        let prompt = repair_prompt(&serde_json::json!({"total_amount": "10"}), &issues);
+        assert!(prompt.contains("- $.total_amount: expected number, got string"));
+        assert!(prompt.contains("\"total_amount\": \"10\""));
+    }
+
+    #[test]
+    fn merge_prompts_name_their_parts() {
+        let catalog = DocumentTypeCatalog::builtin();
+        let results = vec![serde_json::json!({"a": 1}), serde_json::json!({"a": 2})];
+
+        let pages = merge_pages_prompt(catalog.resolve("invoice"), &results);
+        assert!(pages.contains("individual pages of one invoice document"));
+        assert!(pages.contains("Page 2:"));
+        assert!(pages.contains("prefer the final page's values"));
+        assert!(!pages.contains("Sections overlap"));
+
+        let chunks = merge_chunks_prompt(catalog.resolve("bank_statement"), &results);
+        assert!(chunks.contains("sections of the text of one bank statement document"));
+        assert!(chunks.contains("Section 1:"));
+        assert!(chunks.contains("from all sections into one array"));
+        assert!(chunks.contains("Sections overlap"));
+    }
+
+    #[test]
+    fn user_prompt_uses_label() {
+        let catalog = DocumentTypeCatalog::builtin();
+        let prompt = user_prompt("hello", catalog.resolve("bank_statement"));
+        assert!(prompt.starts_with("Extract the bank statement data"));
+        assert!(user_prompt("hello", catalog.fallback()).starts_with("Extract all key information"));
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use harvex_config::RetrySettings;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+use super::engine::PageResult;
+
+/// An LLM call that failed for good, after all allowed attempts.
+#[derive(Debug, thiserror::Error)]
+#[error("{message} (after {attempts} attempt(s))")]
+pub struct LlmCallError {
+    /// HTTP attempts made before giving up.
+    pub attempts: u32,
+    pub message: String,
+    /// HTTP status of the last response, if the API answered at all.
+    pub status: Option<u16>,
+    /// Outcome of every page sent to the vision model before the call
+    /// failed; empty for text.
+    pub pages: Vec<PageResult>,
+}
+
+impl LlmCallError {
+    /// Number of attempts recorded on an error, 0 if it did not come from an LLM call.
+    pub fn attempts_of(err: &anyhow::Error) -> u32 {
+        err.downcast_ref::<LlmCallError>()
+            .map(|e| e.attempts)
+            .unwrap_or(0)
+    }
+
+    /// Per-page results recorded on an error, none if it did not come from
+    /// a vision call.
+    pub fn pages_of(err: &anyhow::Error) -> &[PageResult] {
+        err.downcast_ref::<LlmCallError>()
+            .map_or(&[], |e| e.pages.as_slice())
+    }
+}
+
+/// Delay before retry number `retry` (1 = the first retry).
+pub(crate) fn backoff_delay(settings: &RetrySettings, retry: u32) -> Duration {
+    let exponent = retry.saturating_sub(1).min(32) as i32;
+    let delay_ms = settings.initial_backoff_ms as f64 * settings.backoff_multiplier.powi(exponent);
+    Duration::from_millis((delay_ms as u64).min(settings.max_backoff_ms))
+}
+
+/// Delay requested by the server via `Retry-After`, capped at `max_backoff_ms`.
+///
+/// Only the delay-seconds form is supported; an HTTP date falls back to the
+/// regular backoff.
+pub(crate) fn retry_after(settings: &RetrySettings, headers: &HeaderMap) -> Option<Duration> {
+    let secs: u64 = headers.get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(secs).min(Duration::from_millis(settings.max_backoff_ms)))
+}
+
+pub(crate) fn is_retryable_status(settings: &RetrySettings, status: StatusCode) -> bool {
+    settings.retryable_status_codes.contains(&status.as_u16())
+}
+
+pub(crate) fn is_retryable_error(settings: &RetrySettings, err: &reqwest::Error) -> bool {
+    (settings.retry_on_timeout && err.is_timeout())
+        || (settings.retry_on_connect_error && err.is_connect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_cap() {
+        let settings = RetrySettings {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 3000,
+            backoff_multiplier: 2.0,
+            ..RetrySettings::default()
+        };
+        assert_eq!(backoff_delay(&settings, 1), Duration::from_millis(500));
+        assert_eq!(backoff_delay(&settings, 2), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(&settings, 3), Duration::from_millis(2000));
+        assert_eq!(backoff_delay(&settings, 4), Duration::from_millis(3000));
+        assert_eq!(backoff_delay(&settings, 100), Duration::from_millis(3000));
+    }
+
+    #[test]
+    fn retry_after_seconds_is_honoured_and_capped() {
+        let settings = RetrySettings {
+            max_backoff_ms: 10_000,
+            ..RetrySettings::default()
+        };
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&settings, &headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+        assert_eq!(retry_after(&settings, &headers), Some(Duration::from_secs(3)));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&settings, &headers), Some(Duration::from_secs(10)));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(retry_after(&settings, &headers), None);
+    }
+
+    #[test]
+    fn default_retryable_statuses() {
+        let settings = RetrySettings::default();
+        assert!(is_retryable_status(&settings, StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable_status(&settings, StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable_status(&settings, StatusCode::BAD_REQUEST));
+        assert!(!is_retryable_status(&settings, StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn attempts_of_other_errors_is_zero() {
+        let err: anyhow::Error = LlmCallError { attempts: 3, message: "503".into(), status: Some(503), pages: Vec::new() }.into();
+        assert_eq!(LlmCallError::attempts_of(&err), 3);
+        assert_eq!(LlmCallError::attempts_of(&anyhow::anyhow!("boom")), 0);
+    }
+}
@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use harvex_config::ModelPrice;
+use serde::Serialize;
+use tracing::warn;
+
+use super::chunking;
+
+/// Characters per token assumed by the heuristic counter. Deliberately low
+/// so estimates err on the side of more tokens.
+const HEURISTIC_CHARS_PER_TOKEN: f64 = 3.0;
+
+/// Tokens added by the chat template around each request (role markers,
+/// separators), on top of the message text.
+pub const MESSAGE_OVERHEAD_TOKENS: usize = 32;
+
+/// Smallest document-text budget worth a request, whatever the context size.
+pub const MIN_TEXT_TOKENS: usize = 256;
+
+/// Counts tokens the way the model's tokenizer would.
+pub trait TokenCounter: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+
+    /// Short description for logs and the model info endpoint.
+    fn name(&self) -> String;
+}
+
+/// Estimates tokens from the character count.
+pub struct HeuristicCounter;
+
+impl TokenCounter for HeuristicCounter {
+    fn count(&self, text: &str) -> usize {
+        (text.chars().count() as f64 / HEURISTIC_CHARS_PER_TOKEN).ceil() as usize
+    }
+
+    fn name(&self) -> String {
+        "heuristic".into()
+    }
+}
+
+/// Counts tokens with a Hugging Face `tokenizer.json`.
+#[cfg(feature = "hf-tokenizer")]
+pub struct HfTokenCounter {
+    path: String,
+    tokenizer: tokenizers::Tokenizer,
+}
+
+#[cfg(feature = "hf-tokenizer")]
+impl HfTokenCounter {
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let tokenizer = tokenizers::Tokenizer::from_file(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load tokenizer {path}: {e}"))?;
+        Ok(Self {
+            path: path.to_string(),
+            tokenizer,
+        })
+    }
+}
+
+#[cfg(feature = "hf-tokenizer")]
+impl TokenCounter for HfTokenCounter {
+    fn count(&self, text: &str) -> usize {
+        match self.tokenizer.encode(text, false) {
+            Ok(encoding) => encoding.len(),
+            Err(e) => {
+                warn!("Tokenizer failed, estimating instead: {e}");
+                HeuristicCounter.count(text)
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("tokenizer.json ({})", self.path)
+    }
+}
+
+/// Load the counter for `tokenizer_path`: the tokenizer file if one is
+/// configured and can be loaded, otherwise the heuristic.
+pub fn load_counter(tokenizer_path: &str) -> Arc<dyn TokenCounter> {
+    if tokenizer_path.is_empty() {
+        return Arc::new(HeuristicCounter);
+    }
+
+    #[cfg(feature = "hf-tokenizer")]
+    match HfTokenCounter::from_file(tokenizer_path) {
+        Ok(counter) => {
+            tracing::info!("Loaded tokenizer from {}", tokenizer_path);
+            return Arc::new(counter);
+        }
+        Err(e) => warn!("{e}; falling back to estimated token counts"),
+    }
+
+    #[cfg(not(feature = "hf-tokenizer"))]
+    warn!(
+        "tokenizer_path is set ({}) but harvex was built without the hf-tokenizer feature; \
+         falling back to estimated token counts",
+        tokenizer_path
+    );
+
+    Arc::new(HeuristicCounter)
+}
+
+/// Prompt and completion tokens of one or more LLM calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Cost of `usage` in USD at `price`.
+pub fn cost_usd(usage: TokenUsage, price: &ModelPrice) -> f64 {
+    (usage.prompt_tokens as f64 * price.prompt_per_million
+        + usage.completion_tokens as f64 * price.completion_per_million)
+        / 1_000_000.0
+}
+
+/// Tokens left for document text once the prompts around it, the chat
+/// template and the completion (`max_tokens`) are reserved. Never less than
+/// `MIN_TEXT_TOKENS`.
+pub fn text_budget(context_size: u32, max_tokens: u32, prompt_tokens: usize) -> usize {
+    let reserved = prompt_tokens + MESSAGE_OVERHEAD_TOKENS + max_tokens as usize;
+    let available = (context_size as usize).saturating_sub(reserved);
+    if available < MIN_TEXT_TOKENS {
+        warn!(
+            "context_size {} leaves only {} tokens for document text after {} prompt and {} completion tokens; using {}",
+            context_size, available, prompt_tokens, max_tokens, MIN_TEXT_TOKENS
+        );
+    }
+    available.max(MIN_TEXT_TOKENS)
+}
+
+/// Split `text` into chunks of at most `max_tokens` tokens as counted by
+/// `counter`, overlapping by about `overlap_chars` characters.
+///
+/// Chunk sizes are derived from the text's own characters-per-token ratio
+/// and shrunk until every chunk fits.
+pub fn split_to_budget<'a>(
+    text: &'a str,
+    max_tokens: usize,
+    overlap_chars: usize,
+    counter: &dyn TokenCounter,
+) -> Vec<&'a str> {
+    let total_tokens = counter.count(text);
+    if total_tokens <= max_tokens {
+        return vec![text];
+    }
+
+    let mut chars_per_token = text.chars().count() as f64 / total_tokens as f64;
+    let mut chunks = Vec::new();
+    for _ in 0..5 {
+        let max_chars = ((max_tokens as f64 * chars_per_token) as usize).max(1);
+        chunks = chunking::split_text(text, max_chars, overlap_chars);
+        let largest = chunks.iter().map(|c| counter.count(c)).max().unwrap_or(0);
+        if largest <= max_tokens {
+            break;
+        }
+        chars_per_token *= max_tokens as f64 / largest as f64 * 0.95;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts whitespace-separated words, to make budgets easy to reason about.
+    struct WordCounter;
+
+    impl TokenCounter for WordCounter {
+        fn count(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+
+        fn name(&self) -> String {
+            "words".into()
+        }
+    }
+
+    #[test]
+    fn heuristic_rounds_up() {
+        assert_eq!(HeuristicCounter.count(""), 0);
+        assert_eq!(HeuristicCounter.count("abcd"), 2);
+        assert_eq!(HeuristicCounter.count("äöü"), 1);
+    }
+
+    #[test]
+    fn budget_reserves_prompt_template_and_completion() {
+        assert_eq!(text_budget(4096, 1024, 1000), 4096 - 1000 - MESSAGE_OVERHEAD_TOKENS - 1024);
+        assert_eq!(text_budget(2048, 2048, 500), MIN_TEXT_TOKENS);
+    }
+
+    #[test]
+    fn short_text_is_not_split() {
+        assert_eq!(split_to_budget("a b c", 10, 0, &WordCounter), vec!["a b c"]);
+    }
+
+    #[test]
+    fn chunks_fit_the_token_budget() {
+        // Long and short words mixed, so the average ratio underestimates some chunks
+        let text: String = (0..500)
+            .map(|i| if i % 50 < 25 { "a\n".to_string() } else { "abcdefghij\n".to_string() })
+            .collect();
+        let chunks = split_to_budget(&text, 40, 20, &WordCounter);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| WordCounter.count(c) <= 40));
+    }
+
+    #[test]
+    fn usage_adds_up() {
+        let mut usage = TokenUsage::default();
+        usage += TokenUsage { prompt_tokens: 10, completion_tokens: 5 };
+        usage += TokenUsage { prompt_tokens: 1, completion_tokens: 2 };
+        assert_eq!(usage.total(), 18);
+    }
+
+    #[test]
+    fn cost_is_priced_per_million_tokens() {
+        let price = ModelPrice { prompt_per_million: 0.15, completion_per_million: 0.60 };
+        let usage = TokenUsage { prompt_tokens: 2_000_000, completion_tokens: 500_000 };
+        assert!((cost_usd(usage, &price) - 0.60).abs() < 1e-9);
+    }
+
+    #[test]
+    fn empty_path_uses_heuristic() {
+        assert_eq!(load_counter("").name(), "heuristic");
+    }
+}
@@ -0,0 +1,287 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Top-level fields every prompt asks for in addition to the type's schema.
+const PROMPT_FIELDS: &[&str] = &["document_type", "confidence"];
+
+/// Outcome of checking structured data against a document type's schema.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    /// Problems remaining in the final data.
+    pub errors: Vec<ValidationIssue>,
+    /// Repair requests sent to the model.
+    pub repair_attempts: u32,
+    /// Problems found in the first response, before any repair.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub initial_errors: Vec<ValidationIssue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationIssue {
+    /// JSON path of the offending value, e.g. `$.line_items[2].amount`.
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Validate data against the subset of JSON Schema used by document types:
+/// `type` (including `null` unions), `properties`, `required`, `items`,
+/// `enum`, `minimum`/`maximum` and the `date` / `date-time` formats.
+/// Unknown keywords are ignored.
+///
+/// The prompts tell the model to use null for fields it cannot determine, so
+/// null is accepted for any property that is not required.
+pub fn validate(schema: &Value, data: &Value) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    check(schema, data, "$", true, &mut issues);
+    issues
+}
+
+fn check(schema: &Value, data: &Value, path: &str, top_level: bool, issues: &mut Vec<ValidationIssue>) {
+    let mut issue = |message: String| {
+        issues.push(ValidationIssue {
+            path: path.to_string(),
+            message,
+        })
+    };
+
+    if let Some(allowed) = allowed_types(schema)
+        && !allowed.iter().any(|t| matches_type(t, data))
+    {
+        issue(format!("expected {}, got {}", allowed.join(" or "), type_name(data)));
+        return;
+    }
+
+    if let Some(values) = schema.get("enum").and_then(|e| e.as_array())
+        && !data.is_null()
+        && !values.contains(data)
+    {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        issue(format!("must be one of {}", values.join(", ")));
+    }
+
+    match data {
+        Value::String(s) => match schema.get("format").and_then(|f| f.as_str()) {
+            Some("date") if !is_date(s) => issue(format!("'{s}' is not a YYYY-MM-DD date")),
+            Some("date-time") if !is_date_time(s) => {
+                issue(format!("'{s}' is not an ISO 8601 date-time"))
+            }
+            _ => {}
+        },
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(|m| m.as_f64())
+                && n < min
+            {
+                issue(format!("{n} is less than the minimum {min}"));
+            }
+            if let Some(max) = schema.get("maximum").and_then(|m| m.as_f64())
+                && n > max
+            {
+                issue(format!("{n} is greater than the maximum {max}"));
+            }
+        }
+        Value::Object(obj) => {
+            let required: Vec<&str> = schema
+                .get("required")
+                .and_then(|r| r.as_array())
+                .map(|r| r.iter().filter_map(|f| f.as_str()).collect())
+                .unwrap_or_default();
+            for field in &required {
+                if !obj.contains_key(*field) {
+                    issues.push(ValidationIssue {
+                        path: format!("{path}.{field}"),
+                        message: "required field is missing".into(),
+                    });
+                }
+            }
+            if let Some(props) = schema.get("properties").and_then(|p| p.as_object()) {
+                for (key, value) in obj {
+                    if top_level && PROMPT_FIELDS.contains(&key.as_str()) {
+                        continue;
+                    }
+                    if value.is_null() && !required.contains(&key.as_str()) {
+                        continue;
+                    }
+                    if let Some(prop_schema) = props.get(key) {
+                        check(prop_schema, value, &format!("{path}.{key}"), false, issues);
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{path}[{i}]"), false, issues);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn allowed_types(schema: &Value) -> Option<Vec<&str>> {
+    match schema.get("type")? {
+        Value::String(t) => Some(vec![t.as_str()]),
+        Value::Array(ts) => Some(ts.iter().filter_map(|t| t.as_str()).collect()),
+        _ => None,
+    }
+}
+
+fn matches_type(expected: &str, data: &Value) -> bool {
+    match expected {
+        "null" => data.is_null(),
+        "string" => data.is_string(),
+        "number" => data.is_number(),
+        "integer" => data.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "boolean" => data.is_boolean(),
+        "array" => data.is_array(),
+        "object" => data.is_object(),
+        _ => true,
+    }
+}
+
+fn type_name(data: &Value) -> &'static str {
+    match data {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// `YYYY-MM-DD` with a plausible month and day.
+fn is_date(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return false;
+    }
+    let (Ok(year), Ok(month), Ok(day)) = (
+        parts[0].parse::<u32>(),
+        parts[1].parse::<u32>(),
+        parts[2].parse::<u32>(),
+    ) else {
+        return false;
+    };
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days_in_month).contains(&day)
+}
+
+/// A date, `T` or space, and a time starting with `HH:MM`.
+fn is_date_time(s: &str) -> bool {
+    if s.len() < 16 {
+        return false;
+    }
+    let (date, rest) = s.split_at(10);
+    let mut rest = rest.chars();
+    let separator_ok = matches!(rest.next(), Some('T') | Some(' '));
+    let time: String = rest.take(5).collect();
+    let time_ok = time.len() == 5
+        && time.as_bytes()[2] == b':'
+        && time[..2].parse::<u32>().is_ok_and(|h| h < 24)
+        && time[3..].parse::<u32>().is_ok_and(|m| m < 60);
+    is_date(date) && separator_ok && time_ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn invoice_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "invoice_number": { "type": "string" },
+                "invoice_date": { "type": "string", "format": "date" },
+                "due_date": { "type": ["string", "null"], "format": "date" },
+                "total_amount": { "type": "number", "minimum": 0 },
+                "line_items": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": { "amount": { "type": "number" } },
+                        "required": ["amount"]
+                    }
+                },
+                "status": { "type": "string", "enum": ["paid", "open"] }
+            },
+            "required": ["invoice_number", "total_amount"]
+        })
+    }
+
+    #[test]
+    fn valid_data_passes() {
+        let data = json!({
+            "document_type": "invoice",
+            "confidence": 0.9,
+            "invoice_number": "INV-1",
+            "invoice_date": "2024-02-29",
+            "due_date": null,
+            "total_amount": 10.5,
+            "line_items": [{ "amount": 10.5 }],
+            "extra_field": "ignored"
+        });
+        assert!(validate(&invoice_schema(), &data).is_empty());
+    }
+
+    #[test]
+    fn reports_missing_and_mistyped_fields() {
+        let data = json!({
+            "invoice_date": "29.02.2024",
+            "total_amount": "10.50",
+            "line_items": [{ "amount": 1 }, { "description": "x" }],
+            "status": "overdue"
+        });
+        let issues = validate(&invoice_schema(), &data);
+        let rendered: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+
+        assert!(rendered.contains(&"$.invoice_number: required field is missing".to_string()));
+        assert!(rendered.contains(&"$.total_amount: expected number, got string".to_string()));
+        assert!(rendered.contains(&"$.invoice_date: '29.02.2024' is not a YYYY-MM-DD date".to_string()));
+        assert!(rendered.contains(&"$.line_items[1].amount: required field is missing".to_string()));
+        assert!(rendered.iter().any(|r| r.starts_with("$.status: must be one of")));
+        assert_eq!(issues.len(), 5);
+    }
+
+    #[test]
+    fn null_only_rejected_for_required_fields() {
+        let data = json!({ "invoice_number": null, "total_amount": 1, "invoice_date": null });
+        let issues = validate(&invoice_schema(), &data);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].to_string(), "$.invoice_number: expected string, got null");
+    }
+
+    #[test]
+    fn minimum_is_checked() {
+        let data = json!({ "invoice_number": "1", "total_amount": -1 });
+        let issues = validate(&invoice_schema(), &data);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].path, "$.total_amount");
+    }
+
+    #[test]
+    fn date_formats() {
+        assert!(is_date("2024-12-31"));
+        assert!(!is_date("2023-02-29"));
+        assert!(!is_date("2024-13-01"));
+        assert!(!is_date("24-01-01"));
+        assert!(is_date_time("2024-01-01T10:30:00Z"));
+        assert!(is_date_time("2024-01-01 10:30"));
+        assert!(!is_date_time("2024-01-01"));
+    }
+}
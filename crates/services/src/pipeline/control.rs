@@ -0,0 +1,116 @@
+use tokio::sync::watch;
+
+/// What a batch worker has been asked to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchSignal {
+    /// Keep leasing and processing jobs.
+    Run,
+    /// Stop leasing new jobs; let in-flight documents finish.
+    Pause,
+    /// Stop leasing new jobs and abort in-flight documents.
+    Cancel,
+}
+
+/// Per-batch control handle shared between the API and the batch worker.
+///
+/// Acts as a cancellation token that can also pause and resume: the worker
+/// checks [`BatchControl::signal`] before leasing each job, and in-flight
+/// documents race their processing against [`BatchControl::cancelled`].
+pub struct BatchControl {
+    signal: watch::Sender<BatchSignal>,
+    stopped: watch::Sender<bool>,
+}
+
+impl BatchControl {
+    pub fn new() -> Self {
+        Self {
+            signal: watch::Sender::new(BatchSignal::Run),
+            stopped: watch::Sender::new(false),
+        }
+    }
+
+    /// Current signal.
+    pub fn signal(&self) -> BatchSignal {
+        *self.signal.borrow()
+    }
+
+    pub fn pause(&self) {
+        self.set(BatchSignal::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.set(BatchSignal::Run);
+    }
+
+    pub fn cancel(&self) {
+        self.set(BatchSignal::Cancel);
+    }
+
+    /// Resolves once the batch has been cancelled.
+    pub async fn cancelled(&self) {
+        let mut rx = self.signal.subscribe();
+        let _ = rx.wait_for(|s| *s == BatchSignal::Cancel).await;
+    }
+
+    /// Mark the worker as stopped. Called once the worker has written its final state.
+    pub fn mark_stopped(&self) {
+        self.stopped.send_replace(true);
+    }
+
+    /// Resolves once the worker has stopped.
+    pub async fn stopped(&self) {
+        let mut rx = self.stopped.subscribe();
+        let _ = rx.wait_for(|stopped| *stopped).await;
+    }
+
+    fn set(&self, signal: BatchSignal) {
+        // Cancellation is final; a late pause/resume must not undo it.
+        self.signal.send_if_modified(|current| {
+            if *current == BatchSignal::Cancel || *current == signal {
+                false
+            } else {
+                *current = signal;
+                true
+            }
+        });
+    }
+}
+
+impl Default for BatchControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_is_final() {
+        let control = BatchControl::new();
+        assert_eq!(control.signal(), BatchSignal::Run);
+
+        control.pause();
+        assert_eq!(control.signal(), BatchSignal::Pause);
+
+        control.cancel();
+        control.resume();
+        assert_eq!(control.signal(), BatchSignal::Cancel);
+    }
+
+    #[tokio::test]
+    async fn cancelled_resolves_after_cancel() {
+        let control = std::sync::Arc::new(BatchControl::new());
+        let waiter = {
+            let control = control.clone();
+            tokio::spawn(async move { control.cancelled().await })
+        };
+
+        control.cancel();
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiter)
+            .await
+            .expect("cancelled() did not resolve")
+            .unwrap();
+    }
+}
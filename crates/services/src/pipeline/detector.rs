@@ -6,6 +6,10 @@ pub enum FileType {
     Image,
     Excel,
     Word,
+    /// XML, e.g. a standalone UBL or CII e-invoice.
+    Xml,
+    /// A MIME `.eml` or Outlook `.msg` email.
+    Email,
     Unknown(String),
 }
 
@@ -20,6 +24,8 @@ impl FileType {
             | "text/csv" => return Self::Excel,
             "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
             | "application/msword" => return Self::Word,
+            "application/xml" | "text/xml" => return Self::Xml,
+            "message/rfc822" | "application/vnd.ms-outlook" => return Self::Email,
             ct if ct.starts_with("image/") => return Self::Image,
             _ => {}
         }
@@ -36,6 +42,8 @@ impl FileType {
             "png" | "jpg" | "jpeg" | "tiff" | "tif" | "bmp" | "webp" | "gif" => Self::Image,
             "xlsx" | "xls" | "csv" | "ods" => Self::Excel,
             "docx" | "doc" => Self::Word,
+            "xml" => Self::Xml,
+            "eml" | "msg" => Self::Email,
             other => Self::Unknown(other.to_string()),
         }
     }
@@ -46,6 +54,8 @@ impl FileType {
             Self::Image => "Image",
             Self::Excel => "Excel",
             Self::Word => "Word",
+            Self::Xml => "XML",
+            Self::Email => "Email",
             Self::Unknown(_) => "Unknown",
         }
     }
@@ -66,6 +76,8 @@ mod tests {
             ),
             FileType::Excel
         );
+        assert_eq!(FileType::detect("file.bin", "text/xml"), FileType::Xml);
+        assert_eq!(FileType::detect("file.bin", "message/rfc822"), FileType::Email);
     }
 
     #[test]
@@ -86,5 +98,17 @@ mod tests {
             FileType::detect("report.docx", "application/octet-stream"),
             FileType::Word
         );
+        assert_eq!(
+            FileType::detect("xrechnung.xml", "application/octet-stream"),
+            FileType::Xml
+        );
+        assert_eq!(
+            FileType::detect("Invoice.msg", "application/octet-stream"),
+            FileType::Email
+        );
+        assert_eq!(
+            FileType::detect("invoice.eml", "application/octet-stream"),
+            FileType::Email
+        );
     }
 }
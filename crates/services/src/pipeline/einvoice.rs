@@ -0,0 +1,696 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::PrefixDeclaration;
+use quick_xml::Reader;
+use serde_json::{json, Map, Value};
+use tracing::{debug, warn};
+
+/// Machine-readable invoice syntaxes understood without the LLM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EInvoiceFormat {
+    /// UN/CEFACT Cross Industry Invoice: ZUGFeRD, Factur-X and the CII
+    /// flavour of XRechnung.
+    Cii,
+    /// OASIS UBL 2.x Invoice or CreditNote, including the UBL flavour of
+    /// XRechnung and Peppol BIS.
+    Ubl,
+}
+
+impl EInvoiceFormat {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Cii => "CII (ZUGFeRD/Factur-X/XRechnung)",
+            Self::Ubl => "UBL",
+        }
+    }
+}
+
+/// An e-invoice mapped to the `invoice` document type's schema.
+#[derive(Debug, Clone)]
+pub struct EInvoice {
+    pub format: EInvoiceFormat,
+    pub data: Value,
+    /// The XML the data was read from.
+    pub xml: String,
+}
+
+/// Parse `xml` as a CII or UBL invoice. Returns `None` for any other XML.
+pub fn parse_xml(xml: &str) -> Option<EInvoice> {
+    let root = match parse_tree(xml) {
+        Ok(root) => root,
+        Err(e) => {
+            debug!("Not well-formed XML: {e}");
+            return None;
+        }
+    };
+
+    let (format, data) = match root.name.as_str() {
+        "CrossIndustryInvoice" => (EInvoiceFormat::Cii, map_cii(&root)),
+        "Invoice" | "CreditNote" if root.namespace.contains("oasis") => {
+            (EInvoiceFormat::Ubl, map_ubl(&root))
+        }
+        _ => return None,
+    };
+    Some(EInvoice {
+        format,
+        data,
+        xml: xml.to_string(),
+    })
+}
+
+/// Find an e-invoice embedded in a PDF (ZUGFeRD, Factur-X, XRechnung in
+/// PDF/A-3): the first attached file that parses as one.
+pub fn from_pdf(doc: &pdf_extract::Document) -> Option<EInvoice> {
+    doc.objects
+        .values()
+        .filter_map(|object| object.as_stream().ok())
+        .filter(|stream| {
+            stream
+                .dict
+                .get(b"Type")
+                .and_then(|t| t.as_name())
+                .is_ok_and(|name| name == b"EmbeddedFile")
+        })
+        .filter_map(|stream| match stream.get_plain_content() {
+            Ok(content) => Some(content),
+            Err(e) => {
+                warn!("Could not decode a file attached to the PDF: {e}");
+                None
+            }
+        })
+        .filter_map(|content| String::from_utf8(content).ok())
+        .find_map(|xml| parse_xml(xml.trim_start_matches('\u{feff}')))
+}
+
+/// Map a Cross Industry Invoice to the invoice schema.
+fn map_cii(root: &Element) -> Value {
+    let document = root.child("ExchangedDocument");
+    let transaction = root.child("SupplyChainTradeTransaction");
+    let agreement = transaction.and_then(|t| t.child("ApplicableHeaderTradeAgreement"));
+    let settlement = transaction.and_then(|t| t.child("ApplicableHeaderTradeSettlement"));
+    let totals = settlement.and_then(|s| s.child("SpecifiedTradeSettlementHeaderMonetarySummation"));
+    let seller = agreement.and_then(|a| a.child("SellerTradeParty"));
+    let buyer = agreement.and_then(|a| a.child("BuyerTradeParty"));
+    let terms = settlement.and_then(|s| s.child("SpecifiedTradePaymentTerms"));
+    let currency = settlement.and_then(|s| s.text_at(&["InvoiceCurrencyCode"]));
+
+    // The tax total may be given in the accounting currency as well
+    let tax_amount = totals.and_then(|t| {
+        let amounts: Vec<&Element> = t.children("TaxTotalAmount").collect();
+        amounts
+            .iter()
+            .find(|a| currency.is_some() && a.attr("currencyID") == currency)
+            .or(amounts.first())
+            .and_then(|a| number(&a.text))
+    });
+
+    let rates: Vec<&str> = settlement
+        .map(|s| s.children("ApplicableTradeTax").filter_map(|t| t.text_at(&["RateApplicablePercent"])).collect())
+        .unwrap_or_default();
+
+    let line_items: Vec<Value> = transaction
+        .map(|t| t.children("IncludedSupplyChainTradeLineItem").map(cii_line).collect())
+        .unwrap_or_default();
+
+    let mut data = Map::new();
+    data.insert("vendor_name".into(), text_value(seller.and_then(|p| p.text_at(&["Name"]))));
+    data.insert("vendor_address".into(), seller.map_or(Value::Null, cii_address));
+    data.insert("vendor_tax_id".into(), seller.map_or(Value::Null, cii_tax_id));
+    data.insert("buyer_name".into(), text_value(buyer.and_then(|p| p.text_at(&["Name"]))));
+    data.insert("buyer_address".into(), buyer.map_or(Value::Null, cii_address));
+    data.insert("buyer_tax_id".into(), buyer.map_or(Value::Null, cii_tax_id));
+    data.insert("invoice_number".into(), text_value(document.and_then(|d| d.text_at(&["ID"]))));
+    data.insert(
+        "invoice_date".into(),
+        date_value(document.and_then(|d| d.text_at(&["IssueDateTime", "DateTimeString"]))),
+    );
+    data.insert(
+        "due_date".into(),
+        date_value(terms.and_then(|t| t.text_at(&["DueDateDateTime", "DateTimeString"]))),
+    );
+    data.insert("currency".into(), text_value(currency));
+    data.insert("subtotal".into(), number_value(totals.and_then(|t| t.text_at(&["TaxBasisTotalAmount"]))));
+    data.insert("tax_amount".into(), tax_amount.map_or(Value::Null, Value::from));
+    data.insert("tax_rate".into(), rates_value(&rates));
+    data.insert("total_amount".into(), number_value(totals.and_then(|t| t.text_at(&["GrandTotalAmount"]))));
+    data.insert("line_items".into(), Value::Array(line_items));
+    data.insert("payment_terms".into(), text_value(terms.and_then(|t| t.text_at(&["Description"]))));
+    data.insert(
+        "notes".into(),
+        joined_value(document.map(|d| d.children("IncludedNote").filter_map(|n| n.text_at(&["Content"])).collect())),
+    );
+    // Type code 381 is a credit note
+    if document.and_then(|d| d.text_at(&["TypeCode"])).map(str::trim) == Some("381") {
+        negate_amounts(&mut data);
+    }
+    finish(data)
+}
+
+fn cii_line(line: &Element) -> Value {
+    json!({
+        "description": line.text_at(&["SpecifiedTradeProduct", "Name"]).unwrap_or_default(),
+        "quantity": number_value(line.text_at(&["SpecifiedLineTradeDelivery", "BilledQuantity"])),
+        "unit_price": number_value(
+            line.text_at(&["SpecifiedLineTradeAgreement", "NetPriceProductTradePrice", "ChargeAmount"])
+        ),
+        "amount": number_value(line.text_at(&[
+            "SpecifiedLineTradeSettlement",
+            "SpecifiedTradeSettlementLineMonetarySummation",
+            "LineTotalAmount",
+        ])),
+    })
+}
+
+fn cii_address(party: &Element) -> Value {
+    let Some(address) = party.child("PostalTradeAddress") else {
+        return Value::Null;
+    };
+    address_value(&[
+        address.text_at(&["LineOne"]),
+        address.text_at(&["LineTwo"]),
+        address.text_at(&["LineThree"]),
+        join_nonempty(&[address.text_at(&["PostcodeCode"]), address.text_at(&["CityName"])], " ").as_deref(),
+        address.text_at(&["CountryID"]),
+    ])
+}
+
+/// The party's VAT number (scheme `VA`), or its first tax registration.
+fn cii_tax_id(party: &Element) -> Value {
+    let registrations: Vec<&Element> = party
+        .children("SpecifiedTaxRegistration")
+        .filter_map(|r| r.child("ID"))
+        .collect();
+    let id = registrations
+        .iter()
+        .find(|id| id.attr("schemeID") == Some("VA"))
+        .or(registrations.first());
+    text_value(id.map(|id| id.text.as_str()))
+}
+
+/// Map a UBL Invoice or CreditNote to the invoice schema, a CreditNote with
+/// negative amounts.
+fn map_ubl(root: &Element) -> Value {
+    let supplier = root.child("AccountingSupplierParty").and_then(|p| p.child("Party"));
+    let customer = root.child("AccountingCustomerParty").and_then(|p| p.child("Party"));
+    let totals = root.child("LegalMonetaryTotal");
+    let currency = root.text_at(&["DocumentCurrencyCode"]);
+
+    // As with CII, a second TaxTotal may be in the accounting currency
+    let tax_totals: Vec<&Element> = root.children("TaxTotal").collect();
+    let tax_total = tax_totals
+        .iter()
+        .find(|t| currency.is_some() && t.child("TaxAmount").and_then(|a| a.attr("currencyID")) == currency)
+        .or(tax_totals.first());
+    let rates: Vec<&str> = tax_total
+        .map(|t| t.children("TaxSubtotal").filter_map(|s| s.text_at(&["TaxCategory", "Percent"])).collect())
+        .unwrap_or_default();
+
+    let credit_note = root.name == "CreditNote";
+    let line_name = if credit_note { "CreditNoteLine" } else { "InvoiceLine" };
+    let line_items: Vec<Value> = root.children(line_name).map(ubl_line).collect();
+
+    let due_date = root
+        .text_at(&["DueDate"])
+        .or_else(|| root.text_at(&["PaymentMeans", "PaymentDueDate"]));
+
+    let mut data = Map::new();
+    data.insert("vendor_name".into(), text_value(supplier.and_then(ubl_party_name)));
+    data.insert("vendor_address".into(), supplier.map_or(Value::Null, ubl_address));
+    data.insert("vendor_tax_id".into(), text_value(supplier.and_then(|p| p.text_at(&["PartyTaxScheme", "CompanyID"]))));
+    data.insert("buyer_name".into(), text_value(customer.and_then(ubl_party_name)));
+    data.insert("buyer_address".into(), customer.map_or(Value::Null, ubl_address));
+    data.insert("buyer_tax_id".into(), text_value(customer.and_then(|p| p.text_at(&["PartyTaxScheme", "CompanyID"]))));
+    data.insert("invoice_number".into(), text_value(root.text_at(&["ID"])));
+    data.insert("invoice_date".into(), date_value(root.text_at(&["IssueDate"])));
+    data.insert("due_date".into(), date_value(due_date));
+    data.insert("currency".into(), text_value(currency));
+    data.insert("subtotal".into(), number_value(totals.and_then(|t| t.text_at(&["TaxExclusiveAmount"]))));
+    data.insert("tax_amount".into(), number_value(tax_total.and_then(|t| t.text_at(&["TaxAmount"]))));
+    data.insert("tax_rate".into(), rates_value(&rates));
+    data.insert(
+        "total_amount".into(),
+        number_value(
+            totals.and_then(|t| t.text_at(&["TaxInclusiveAmount"]).or_else(|| t.text_at(&["PayableAmount"]))),
+        ),
+    );
+    data.insert("line_items".into(), Value::Array(line_items));
+    data.insert("payment_terms".into(), text_value(root.text_at(&["PaymentTerms", "Note"])));
+    data.insert("notes".into(), joined_value(Some(root.children("Note").map(|n| n.text.as_str()).collect())));
+    if credit_note {
+        negate_amounts(&mut data);
+    }
+    finish(data)
+}
+
+fn ubl_line(line: &Element) -> Value {
+    let quantity = line
+        .text_at(&["InvoicedQuantity"])
+        .or_else(|| line.text_at(&["CreditedQuantity"]));
+    json!({
+        "description": line
+            .text_at(&["Item", "Name"])
+            .or_else(|| line.text_at(&["Item", "Description"]))
+            .unwrap_or_default(),
+        "quantity": number_value(quantity),
+        "unit_price": number_value(line.text_at(&["Price", "PriceAmount"])),
+        "amount": number_value(line.text_at(&["LineExtensionAmount"])),
+    })
+}
+
+fn ubl_party_name(party: &Element) -> Option<&str> {
+    party
+        .text_at(&["PartyName", "Name"])
+        .or_else(|| party.text_at(&["PartyLegalEntity", "RegistrationName"]))
+}
+
+fn ubl_address(party: &Element) -> Value {
+    let Some(address) = party.child("PostalAddress") else {
+        return Value::Null;
+    };
+    address_value(&[
+        address.text_at(&["StreetName"]),
+        address.text_at(&["AdditionalStreetName"]),
+        join_nonempty(&[address.text_at(&["PostalZone"]), address.text_at(&["CityName"])], " ").as_deref(),
+        address.text_at(&["Country", "IdentificationCode"]),
+    ])
+}
+
+/// Turn a credit note's amounts, which it states as positive, into negative
+/// invoice amounts so that they offset the invoice they credit. Line
+/// quantities are negated with the line amounts; unit prices stay positive.
+fn negate_amounts(data: &mut Map<String, Value>) {
+    fn negate(value: &mut Value) {
+        if let Some(n) = value.as_f64().filter(|n| *n != 0.0) {
+            *value = Value::from(-n);
+        }
+    }
+
+    for key in ["subtotal", "tax_amount", "total_amount"] {
+        if let Some(value) = data.get_mut(key) {
+            negate(value);
+        }
+    }
+    if let Some(Value::Array(lines)) = data.get_mut("line_items") {
+        for line in lines.iter_mut().filter_map(Value::as_object_mut) {
+            for key in ["quantity", "amount"] {
+                if let Some(value) = line.get_mut(key) {
+                    negate(value);
+                }
+            }
+        }
+    }
+}
+
+/// Add the fields every extraction carries. Data read from the XML is
+/// exact, hence the full confidence.
+fn finish(mut data: Map<String, Value>) -> Value {
+    data.insert("document_type".into(), "invoice".into());
+    data.insert("confidence".into(), 1.0.into());
+    Value::Object(data)
+}
+
+fn text_value(text: Option<&str>) -> Value {
+    text.map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map_or(Value::Null, Value::from)
+}
+
+fn number(text: &str) -> Option<f64> {
+    text.trim().parse().ok()
+}
+
+fn number_value(text: Option<&str>) -> Value {
+    text.and_then(number).map_or(Value::Null, Value::from)
+}
+
+/// A date as `YYYY-MM-DD`. CII writes dates as `YYYYMMDD` (format 102).
+fn date_value(text: Option<&str>) -> Value {
+    let Some(text) = text.map(str::trim).filter(|t| !t.is_empty()) else {
+        return Value::Null;
+    };
+    if text.len() == 8 && text.bytes().all(|b| b.is_ascii_digit()) {
+        format!("{}-{}-{}", &text[..4], &text[4..6], &text[6..]).into()
+    } else {
+        text.into()
+    }
+}
+
+/// Tax rates as the schema's percentage string, e.g. `19` or `19, 7`.
+fn rates_value(rates: &[&str]) -> Value {
+    let mut distinct: Vec<&str> = Vec::new();
+    for rate in rates.iter().map(|r| r.trim()) {
+        if !distinct.contains(&rate) {
+            distinct.push(rate);
+        }
+    }
+    if distinct.is_empty() {
+        Value::Null
+    } else {
+        distinct.join(", ").into()
+    }
+}
+
+fn joined_value(parts: Option<Vec<&str>>) -> Value {
+    text_value(parts.and_then(|p| join_nonempty(&p.into_iter().map(Some).collect::<Vec<_>>(), "\n")).as_deref())
+}
+
+fn address_value(lines: &[Option<&str>]) -> Value {
+    text_value(join_nonempty(lines, ", ").as_deref())
+}
+
+fn join_nonempty(parts: &[Option<&str>], separator: &str) -> Option<String> {
+    let parts: Vec<&str> = parts
+        .iter()
+        .flatten()
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .collect();
+    (!parts.is_empty()).then(|| parts.join(separator))
+}
+
+/// An XML element reduced to what the mappings need: local names, without
+/// namespace prefixes, and the element's own text.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    /// Namespace URI declared for the element's prefix, on the root only.
+    namespace: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    /// Text of the descendant at `path`, following the first match per step.
+    fn text_at(&self, path: &[&str]) -> Option<&str> {
+        let mut element = self;
+        for name in path {
+            element = element.child(name)?;
+        }
+        Some(element.text.as_str()).filter(|t| !t.trim().is_empty())
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn parse_tree(xml: &str) -> Result<Element, anyhow::Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<Element> = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => stack.push(element(&reader, &start, stack.is_empty())?),
+            Event::Empty(start) => {
+                let empty = element(&reader, &start, stack.is_empty())?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(empty),
+                    None => return Ok(empty),
+                }
+            }
+            Event::End(_) => {
+                let done = stack.pop().ok_or_else(|| anyhow::anyhow!("Unbalanced end tag"))?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(done),
+                    None => return Ok(done),
+                }
+            }
+            Event::Text(text) => {
+                if let Some(current) = stack.last_mut() {
+                    current.text.push_str(&text.unescape()?);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(current) = stack.last_mut() {
+                    current.text.push_str(&String::from_utf8_lossy(&data.into_inner()));
+                }
+            }
+            Event::Eof => return Err(anyhow::anyhow!("No root element")),
+            _ => {}
+        }
+    }
+}
+
+fn element(reader: &Reader<&[u8]>, start: &BytesStart, is_root: bool) -> Result<Element, anyhow::Error> {
+    let name = String::from_utf8_lossy(start.local_name().as_ref()).into_owned();
+    let prefix = start.name().prefix().map(|p| p.as_ref().to_vec());
+
+    let mut element = Element {
+        name,
+        ..Element::default()
+    };
+    for attribute in start.attributes() {
+        let attribute = attribute?;
+        let key = attribute.key;
+        let value = attribute.decode_and_unescape_value(reader)?.into_owned();
+        // xmlns="..." or xmlns:prefix="..." for the root's own prefix
+        let declares_namespace = match (key.as_namespace_binding(), &prefix) {
+            (Some(PrefixDeclaration::Default), None) => true,
+            (Some(PrefixDeclaration::Named(declared)), Some(prefix)) => declared == prefix.as_slice(),
+            _ => false,
+        };
+        if declares_namespace {
+            if is_root {
+                element.namespace = value;
+            }
+            continue;
+        }
+        element
+            .attributes
+            .push((String::from_utf8_lossy(key.local_name().as_ref()).into_owned(), value));
+    }
+    Ok(element)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CII: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rsm:CrossIndustryInvoice xmlns:rsm="urn:un:unece:uncefact:data:standard:CrossIndustryInvoice:100"
+    xmlns:ram="urn:un:unece:uncefact:data:standard:ReusableAggregateBusinessInformationEntity:100"
+    xmlns:udt="urn:un:unece:uncefact:data:standard:UnqualifiedDataType:100">
+  <rsm:ExchangedDocument>
+    <ram:ID>RE-2024-0815</ram:ID>
+    <ram:TypeCode>380</ram:TypeCode>
+    <ram:IssueDateTime><udt:DateTimeString format="102">20240305</udt:DateTimeString></ram:IssueDateTime>
+    <ram:IncludedNote><ram:Content>Thank you &amp; goodbye</ram:Content></ram:IncludedNote>
+  </rsm:ExchangedDocument>
+  <rsm:SupplyChainTradeTransaction>
+    <ram:IncludedSupplyChainTradeLineItem>
+      <ram:SpecifiedTradeProduct><ram:Name>Consulting</ram:Name></ram:SpecifiedTradeProduct>
+      <ram:SpecifiedLineTradeAgreement>
+        <ram:NetPriceProductTradePrice><ram:ChargeAmount>120.00</ram:ChargeAmount></ram:NetPriceProductTradePrice>
+      </ram:SpecifiedLineTradeAgreement>
+      <ram:SpecifiedLineTradeDelivery><ram:BilledQuantity unitCode="HUR">8</ram:BilledQuantity></ram:SpecifiedLineTradeDelivery>
+      <ram:SpecifiedLineTradeSettlement>
+        <ram:SpecifiedTradeSettlementLineMonetarySummation>
+          <ram:LineTotalAmount>960.00</ram:LineTotalAmount>
+        </ram:SpecifiedTradeSettlementLineMonetarySummation>
+      </ram:SpecifiedLineTradeSettlement>
+    </ram:IncludedSupplyChainTradeLineItem>
+    <ram:ApplicableHeaderTradeAgreement>
+      <ram:SellerTradeParty>
+        <ram:Name>Muster GmbH</ram:Name>
+        <ram:PostalTradeAddress>
+          <ram:PostcodeCode>10115</ram:PostcodeCode>
+          <ram:LineOne>Hauptstr. 1</ram:LineOne>
+          <ram:CityName>Berlin</ram:CityName>
+          <ram:CountryID>DE</ram:CountryID>
+        </ram:PostalTradeAddress>
+        <ram:SpecifiedTaxRegistration><ram:ID schemeID="FC">201/113/40209</ram:ID></ram:SpecifiedTaxRegistration>
+        <ram:SpecifiedTaxRegistration><ram:ID schemeID="VA">DE123456789</ram:ID></ram:SpecifiedTaxRegistration>
+      </ram:SellerTradeParty>
+      <ram:BuyerTradeParty><ram:Name>Kunde AG</ram:Name></ram:BuyerTradeParty>
+    </ram:ApplicableHeaderTradeAgreement>
+    <ram:ApplicableHeaderTradeSettlement>
+      <ram:InvoiceCurrencyCode>EUR</ram:InvoiceCurrencyCode>
+      <ram:ApplicableTradeTax><ram:RateApplicablePercent>19</ram:RateApplicablePercent></ram:ApplicableTradeTax>
+      <ram:SpecifiedTradePaymentTerms>
+        <ram:Description>14 days net</ram:Description>
+        <ram:DueDateDateTime><udt:DateTimeString format="102">20240319</udt:DateTimeString></ram:DueDateDateTime>
+      </ram:SpecifiedTradePaymentTerms>
+      <ram:SpecifiedTradeSettlementHeaderMonetarySummation>
+        <ram:TaxBasisTotalAmount>960.00</ram:TaxBasisTotalAmount>
+        <ram:TaxTotalAmount currencyID="USD">198.50</ram:TaxTotalAmount>
+        <ram:TaxTotalAmount currencyID="EUR">182.40</ram:TaxTotalAmount>
+        <ram:GrandTotalAmount>1142.40</ram:GrandTotalAmount>
+      </ram:SpecifiedTradeSettlementHeaderMonetarySummation>
+    </ram:ApplicableHeaderTradeSettlement>
+  </rsm:SupplyChainTradeTransaction>
+</rsm:CrossIndustryInvoice>"#;
+
+    const UBL: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Invoice xmlns="urn:oasis:names:specification:ubl:schema:xsd:Invoice-2"
+    xmlns:cac="urn:oasis:names:specification:ubl:schema:xsd:CommonAggregateComponents-2"
+    xmlns:cbc="urn:oasis:names:specification:ubl:schema:xsd:CommonBasicComponents-2">
+  <cbc:ID>INV-42</cbc:ID>
+  <cbc:IssueDate>2024-03-05</cbc:IssueDate>
+  <cbc:DueDate>2024-04-04</cbc:DueDate>
+  <cbc:DocumentCurrencyCode>EUR</cbc:DocumentCurrencyCode>
+  <cac:AccountingSupplierParty><cac:Party>
+    <cac:PartyName><cbc:Name>Acme BV</cbc:Name></cac:PartyName>
+    <cac:PostalAddress>
+      <cbc:StreetName>Kerkstraat 5</cbc:StreetName>
+      <cbc:CityName>Utrecht</cbc:CityName>
+      <cbc:PostalZone>3511</cbc:PostalZone>
+      <cac:Country><cbc:IdentificationCode>NL</cbc:IdentificationCode></cac:Country>
+    </cac:PostalAddress>
+    <cac:PartyTaxScheme><cbc:CompanyID>NL123456789B01</cbc:CompanyID></cac:PartyTaxScheme>
+  </cac:Party></cac:AccountingSupplierParty>
+  <cac:AccountingCustomerParty><cac:Party>
+    <cac:PartyLegalEntity><cbc:RegistrationName>Buyer Ltd</cbc:RegistrationName></cac:PartyLegalEntity>
+  </cac:Party></cac:AccountingCustomerParty>
+  <cac:TaxTotal>
+    <cbc:TaxAmount currencyID="EUR">21.00</cbc:TaxAmount>
+    <cac:TaxSubtotal><cac:TaxCategory><cbc:Percent>21</cbc:Percent></cac:TaxCategory></cac:TaxSubtotal>
+  </cac:TaxTotal>
+  <cac:LegalMonetaryTotal>
+    <cbc:TaxExclusiveAmount currencyID="EUR">100.00</cbc:TaxExclusiveAmount>
+    <cbc:TaxInclusiveAmount currencyID="EUR">121.00</cbc:TaxInclusiveAmount>
+    <cbc:PayableAmount currencyID="EUR">121.00</cbc:PayableAmount>
+  </cac:LegalMonetaryTotal>
+  <cac:InvoiceLine>
+    <cbc:InvoicedQuantity unitCode="C62">2</cbc:InvoicedQuantity>
+    <cbc:LineExtensionAmount currencyID="EUR">100.00</cbc:LineExtensionAmount>
+    <cac:Item><cbc:Name>Widget</cbc:Name></cac:Item>
+    <cac:Price><cbc:PriceAmount currencyID="EUR">50.00</cbc:PriceAmount></cac:Price>
+  </cac:InvoiceLine>
+</Invoice>"#;
+
+    const UBL_CREDIT_NOTE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<CreditNote xmlns="urn:oasis:names:specification:ubl:schema:xsd:CreditNote-2"
+    xmlns:cac="urn:oasis:names:specification:ubl:schema:xsd:CommonAggregateComponents-2"
+    xmlns:cbc="urn:oasis:names:specification:ubl:schema:xsd:CommonBasicComponents-2">
+  <cbc:ID>CN-7</cbc:ID>
+  <cbc:IssueDate>2024-03-20</cbc:IssueDate>
+  <cbc:DocumentCurrencyCode>EUR</cbc:DocumentCurrencyCode>
+  <cac:BillingReference><cac:InvoiceDocumentReference><cbc:ID>INV-42</cbc:ID></cac:InvoiceDocumentReference></cac:BillingReference>
+  <cac:AccountingSupplierParty><cac:Party>
+    <cac:PartyName><cbc:Name>Acme BV</cbc:Name></cac:PartyName>
+  </cac:Party></cac:AccountingSupplierParty>
+  <cac:TaxTotal>
+    <cbc:TaxAmount currencyID="EUR">21.00</cbc:TaxAmount>
+    <cac:TaxSubtotal><cac:TaxCategory><cbc:Percent>21</cbc:Percent></cac:TaxCategory></cac:TaxSubtotal>
+  </cac:TaxTotal>
+  <cac:LegalMonetaryTotal>
+    <cbc:TaxExclusiveAmount currencyID="EUR">100.00</cbc:TaxExclusiveAmount>
+    <cbc:TaxInclusiveAmount currencyID="EUR">121.00</cbc:TaxInclusiveAmount>
+    <cbc:PayableAmount currencyID="EUR">121.00</cbc:PayableAmount>
+  </cac:LegalMonetaryTotal>
+  <cac:CreditNoteLine>
+    <cbc:CreditedQuantity unitCode="C62">2</cbc:CreditedQuantity>
+    <cbc:LineExtensionAmount currencyID="EUR">100.00</cbc:LineExtensionAmount>
+    <cac:Item><cbc:Name>Widget</cbc:Name></cac:Item>
+    <cac:Price><cbc:PriceAmount currencyID="EUR">50.00</cbc:PriceAmount></cac:Price>
+  </cac:CreditNoteLine>
+</CreditNote>"#;
+
+    #[test]
+    fn cii_maps_to_the_invoice_schema() {
+        let invoice = parse_xml(CII).unwrap();
+        let data = &invoice.data;
+
+        assert_eq!(invoice.format, EInvoiceFormat::Cii);
+        assert_eq!(data["invoice_number"], "RE-2024-0815");
+        assert_eq!(data["invoice_date"], "2024-03-05");
+        assert_eq!(data["due_date"], "2024-03-19");
+        assert_eq!(data["vendor_name"], "Muster GmbH");
+        assert_eq!(data["vendor_address"], "Hauptstr. 1, 10115 Berlin, DE");
+        assert_eq!(data["vendor_tax_id"], "DE123456789");
+        assert_eq!(data["buyer_name"], "Kunde AG");
+        assert_eq!(data["currency"], "EUR");
+        assert_eq!(data["subtotal"], 960.0);
+        assert_eq!(data["tax_amount"], 182.4);
+        assert_eq!(data["tax_rate"], "19");
+        assert_eq!(data["total_amount"], 1142.4);
+        assert_eq!(data["payment_terms"], "14 days net");
+        assert_eq!(data["notes"], "Thank you & goodbye");
+        assert_eq!(
+            data["line_items"],
+            json!([{ "description": "Consulting", "quantity": 8.0, "unit_price": 120.0, "amount": 960.0 }])
+        );
+        assert_eq!(data["confidence"], 1.0);
+    }
+
+    #[test]
+    fn ubl_maps_to_the_invoice_schema() {
+        let invoice = parse_xml(UBL).unwrap();
+        let data = &invoice.data;
+
+        assert_eq!(invoice.format, EInvoiceFormat::Ubl);
+        assert_eq!(data["invoice_number"], "INV-42");
+        assert_eq!(data["invoice_date"], "2024-03-05");
+        assert_eq!(data["due_date"], "2024-04-04");
+        assert_eq!(data["vendor_name"], "Acme BV");
+        assert_eq!(data["vendor_address"], "Kerkstraat 5, 3511 Utrecht, NL");
+        assert_eq!(data["vendor_tax_id"], "NL123456789B01");
+        assert_eq!(data["buyer_name"], "Buyer Ltd");
+        assert_eq!(data["buyer_address"], Value::Null);
+        assert_eq!(data["tax_amount"], 21.0);
+        assert_eq!(data["tax_rate"], "21");
+        assert_eq!(data["total_amount"], 121.0);
+        assert_eq!(data["line_items"][0]["description"], "Widget");
+        assert_eq!(data["line_items"][0]["quantity"], 2.0);
+    }
+
+    #[test]
+    fn credit_notes_have_negative_amounts() {
+        let data = parse_xml(UBL_CREDIT_NOTE).unwrap().data;
+
+        assert_eq!(data["invoice_number"], "CN-7");
+        assert_eq!(data["subtotal"], -100.0);
+        assert_eq!(data["tax_amount"], -21.0);
+        assert_eq!(data["total_amount"], -121.0);
+        assert_eq!(
+            data["line_items"],
+            json!([{ "description": "Widget", "quantity": -2.0, "unit_price": 50.0, "amount": -100.0 }])
+        );
+
+        let cii_credit_note = CII.replace("<ram:TypeCode>380</ram:TypeCode>", "<ram:TypeCode>381</ram:TypeCode>");
+        let data = parse_xml(&cii_credit_note).unwrap().data;
+        assert_eq!(data["total_amount"], -1142.4);
+        assert_eq!(data["line_items"][0]["amount"], -960.0);
+    }
+
+    #[test]
+    fn mapped_invoices_pass_the_invoice_schema() {
+        let catalog = crate::doctypes::DocumentTypeCatalog::builtin();
+        let schema = catalog.resolve("invoice").output_schema();
+        for xml in [CII, UBL, UBL_CREDIT_NOTE] {
+            let issues = crate::llm::validate::validate(&schema, &parse_xml(xml).unwrap().data);
+            assert!(issues.is_empty(), "{issues:?}");
+        }
+    }
+
+    #[test]
+    fn other_xml_is_not_an_invoice() {
+        assert!(parse_xml("<Invoice><ID>1</ID></Invoice>").is_none());
+        assert!(parse_xml(r#"<note xmlns="urn:example"><to>Tove</to></note>"#).is_none());
+        assert!(parse_xml("not xml <").is_none());
+    }
+
+    #[test]
+    fn invoices_attached_to_a_pdf_are_found() {
+        use pdf_extract::{dictionary, Document, Stream};
+
+        let mut doc = Document::with_version("1.7");
+        doc.add_object(Stream::new(dictionary! { "Type" => "XObject" }, b"q Q".to_vec()));
+        assert!(from_pdf(&doc).is_none());
+
+        doc.add_object(Stream::new(dictionary! { "Type" => "EmbeddedFile" }, CII.as_bytes().to_vec()));
+        let invoice = from_pdf(&doc).unwrap();
+        assert_eq!(invoice.format, EInvoiceFormat::Cii);
+    }
+}
@@ -0,0 +1,389 @@
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use harvex_db::models::EmailMetadata;
+use mail_parser::decoders::html::html_to_text;
+use mail_parser::{MessageParser, MimeHeaders};
+use tracing::debug;
+
+/// Signature of a compound file, the container of Outlook `.msg` files.
+const CFB_SIGNATURE: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+/// Seconds between 1601-01-01, the epoch of Windows file times, and 1970-01-01.
+const FILETIME_UNIX_OFFSET_SECS: i64 = 11_644_473_600;
+
+/// An email read from a MIME `.eml` or an Outlook `.msg` file.
+pub struct ParsedEmail {
+    pub metadata: EmailMetadata,
+    /// Plain text body; HTML-only emails are converted to text.
+    pub body: String,
+    pub attachments: Vec<EmailAttachment>,
+}
+
+/// A file attached to an email.
+pub struct EmailAttachment {
+    pub name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl ParsedEmail {
+    /// The email as one text document: its headers, then the body.
+    pub fn text(&self) -> String {
+        let mut text = email_header(&self.metadata);
+        if !self.attachments.is_empty() {
+            let names: Vec<&str> = self.attachments.iter().map(|a| a.name.as_str()).collect();
+            text.push_str(&format!("Attachments: {}\n", names.join(", ")));
+        }
+        text.push('\n');
+        text.push_str(self.body.trim());
+        text
+    }
+}
+
+/// `From`, `Subject` and `Date` lines for the headers that are known.
+pub fn email_header(metadata: &EmailMetadata) -> String {
+    let mut header = String::new();
+    for (label, value) in [
+        ("From", &metadata.from),
+        ("Subject", &metadata.subject),
+        ("Date", &metadata.date),
+    ] {
+        if let Some(value) = value {
+            header.push_str(&format!("{label}: {value}\n"));
+        }
+    }
+    header
+}
+
+/// Read an email file; see [`parse`].
+pub fn extract(file_path: &Path) -> Result<ParsedEmail, anyhow::Error> {
+    debug!("Extracting email: {}", file_path.display());
+    parse(&std::fs::read(file_path)?)
+}
+
+/// Parse an email, telling `.msg` from `.eml` by its content rather than
+/// its name.
+pub fn parse(data: &[u8]) -> Result<ParsedEmail, anyhow::Error> {
+    if data.starts_with(&CFB_SIGNATURE) {
+        parse_msg(data)
+    } else {
+        parse_eml(data)
+    }
+}
+
+fn parse_eml(data: &[u8]) -> Result<ParsedEmail, anyhow::Error> {
+    let message = MessageParser::default()
+        .parse(data)
+        .filter(|m| !m.headers().is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Not a valid email"))?;
+
+    let from = message
+        .from()
+        .and_then(|address| address.first())
+        .and_then(|addr| format_sender(addr.name(), addr.address()));
+    let metadata = EmailMetadata {
+        from,
+        subject: message.subject().map(str::to_string),
+        date: message.date().map(|date| date.to_rfc3339()),
+    };
+    let body = message
+        .body_text(0)
+        .map(|body| body.into_owned())
+        .unwrap_or_default();
+
+    let mut attachments = Vec::new();
+    for (index, part) in message.attachments().enumerate() {
+        // Images shown in the body, such as logos, are not attachments
+        let inline = part
+            .content_disposition()
+            .is_none_or(|cd| !cd.ctype().eq_ignore_ascii_case("attachment"));
+        if inline && part.content_id().is_some() {
+            continue;
+        }
+
+        let content_type = match part.content_type() {
+            Some(ct) => match ct.subtype() {
+                Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                None => ct.ctype().to_string(),
+            },
+            None => "application/octet-stream".to_string(),
+        }
+        .to_lowercase();
+        let name = match (part.attachment_name(), part.message()) {
+            (Some(name), _) => file_name(name),
+            (None, Some(nested)) => format!(
+                "{}.eml",
+                file_name(nested.subject().unwrap_or("message")).trim()
+            ),
+            (None, None) => unnamed_attachment(index, &content_type),
+        };
+
+        attachments.push(EmailAttachment {
+            name,
+            content_type,
+            data: part.contents().to_vec(),
+        });
+    }
+
+    Ok(ParsedEmail {
+        metadata,
+        body,
+        attachments,
+    })
+}
+
+/// Read an Outlook `.msg` file: a compound file holding each MAPI property
+/// in a stream named `__substg1.0_<id><type>`, with one storage per
+/// attachment.
+fn parse_msg(data: &[u8]) -> Result<ParsedEmail, anyhow::Error> {
+    let mut msg = cfb::CompoundFile::open(Cursor::new(data))
+        .map_err(|e| anyhow::anyhow!("Not a valid Outlook message: {e}"))?;
+
+    let from = format_sender(
+        msg_string(&mut msg, "/", 0x0C1A).as_deref(),
+        msg_string(&mut msg, "/", 0x5D01)
+            .or_else(|| msg_string(&mut msg, "/", 0x0C1F))
+            .as_deref(),
+    );
+    let metadata = EmailMetadata {
+        from,
+        subject: msg_string(&mut msg, "/", 0x0037),
+        date: msg_date(&mut msg),
+    };
+    let body = msg_string(&mut msg, "/", 0x1000)
+        .or_else(|| {
+            let html = msg_stream(&mut msg, "/__substg1.0_10130102")
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                .or_else(|| msg_string(&mut msg, "/", 0x1013))?;
+            Some(html_to_text(&html))
+        })
+        .unwrap_or_default();
+
+    let storages: Vec<String> = msg
+        .read_root_storage()
+        .filter(|entry| entry.is_storage() && entry.name().starts_with("__attach_version1.0_"))
+        .map(|entry| entry.path().to_string_lossy().into_owned())
+        .collect();
+    let mut attachments = Vec::new();
+    for (index, storage) in storages.iter().enumerate() {
+        // Attached Outlook items are stored as a nested message, not as data
+        let Some(data) = msg_stream(&mut msg, &format!("{storage}/__substg1.0_37010102")) else {
+            debug!("Skipping attachment {storage} without data");
+            continue;
+        };
+        let name = msg_string(&mut msg, storage, 0x3707)
+            .or_else(|| msg_string(&mut msg, storage, 0x3704))
+            .map(|name| file_name(&name));
+        let content_type = msg_string(&mut msg, storage, 0x370E)
+            .map(|mime| mime.to_lowercase())
+            .or_else(|| {
+                let guess = mime_guess::from_path(name.as_deref()?).first()?;
+                Some(guess.essence_str().to_string())
+            })
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let name = name.unwrap_or_else(|| unnamed_attachment(index, &content_type));
+
+        attachments.push(EmailAttachment {
+            name,
+            content_type,
+            data,
+        });
+    }
+
+    Ok(ParsedEmail {
+        metadata,
+        body,
+        attachments,
+    })
+}
+
+type MsgFile<'a> = cfb::CompoundFile<Cursor<&'a [u8]>>;
+
+fn msg_stream(msg: &mut MsgFile<'_>, path: &str) -> Option<Vec<u8>> {
+    let mut stream = msg.open_stream(path).ok()?;
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).ok()?;
+    Some(data)
+}
+
+/// A string property of `storage`, stored as UTF-16 or as 8-bit text.
+fn msg_string(msg: &mut MsgFile<'_>, storage: &str, property: u16) -> Option<String> {
+    let storage = storage.trim_end_matches('/');
+    let text = if let Some(data) = msg_stream(msg, &format!("{storage}/__substg1.0_{property:04X}001F")) {
+        let units: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        let data = msg_stream(msg, &format!("{storage}/__substg1.0_{property:04X}001E"))?;
+        String::from_utf8_lossy(&data).into_owned()
+    };
+    let text = text.trim_end_matches('\0').trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Sent date, or delivery date for received items without one. Dates are
+/// fixed-size properties, kept in the `__properties_version1.0` stream: a
+/// 32-byte header, then 16 bytes per property (tag, flags, value).
+fn msg_date(msg: &mut MsgFile<'_>) -> Option<String> {
+    let properties = msg_stream(msg, "/__properties_version1.0")?;
+    let entries: Vec<&[u8]> = properties.get(32..)?.chunks_exact(16).collect();
+
+    // PidTagClientSubmitTime, then PidTagMessageDeliveryTime, as PT_SYSTIME
+    [0x0039_0040u32, 0x0E06_0040].iter().find_map(|&tag| {
+        let entry = entries
+            .iter()
+            .find(|entry| u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) == tag)?;
+        let filetime = i64::from_le_bytes(entry[8..16].try_into().ok()?);
+        let secs = filetime / 10_000_000 - FILETIME_UNIX_OFFSET_SECS;
+        let nanos = (filetime % 10_000_000) as u32 * 100;
+        chrono::DateTime::from_timestamp(secs, nanos).map(|date| date.to_rfc3339())
+    })
+}
+
+/// `Name <address>`, or whichever of the two is known.
+fn format_sender(name: Option<&str>, address: Option<&str>) -> Option<String> {
+    let name = name.map(str::trim).filter(|n| !n.is_empty());
+    let address = address.map(str::trim).filter(|a| !a.is_empty());
+    match (name, address) {
+        (Some(name), Some(address)) if name != address => Some(format!("{name} <{address}>")),
+        (Some(name), _) => Some(name.to_string()),
+        (None, address) => address.map(str::to_string),
+    }
+}
+
+/// The last component of an attachment name, which some mailers send as a
+/// full path.
+fn file_name(name: &str) -> String {
+    name.rsplit(['/', '\\']).next().unwrap_or(name).to_string()
+}
+
+fn unnamed_attachment(index: usize, content_type: &str) -> String {
+    let extension = mime_guess::get_mime_extensions_str(content_type)
+        .and_then(|extensions| extensions.first())
+        .unwrap_or(&"bin");
+    format!("attachment-{}.{extension}", index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn eml_body_metadata_and_attachments() {
+        let eml = concat!(
+            "From: \"ACME Billing\" <billing@acme.example>\r\n",
+            "To: ap@example.com\r\n",
+            "Subject: Invoice INV-2024-001\r\n",
+            "Date: Tue, 5 Mar 2024 10:15:00 +0100\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/mixed; boundary=\"b1\"\r\n",
+            "\r\n",
+            "--b1\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "Please find our invoice attached.\r\n",
+            "--b1\r\n",
+            "Content-Type: application/pdf; name=\"INV-2024-001.pdf\"\r\n",
+            "Content-Disposition: attachment; filename=\"INV-2024-001.pdf\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "JVBERi0xLjQK\r\n",
+            "--b1\r\n",
+            "Content-Type: image/png\r\n",
+            "Content-Disposition: inline\r\n",
+            "Content-ID: <logo@acme>\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "iVBORw0KGgo=\r\n",
+            "--b1--\r\n",
+        );
+
+        let email = parse(eml.as_bytes()).unwrap();
+        assert_eq!(
+            email.metadata.from.as_deref(),
+            Some("ACME Billing <billing@acme.example>")
+        );
+        assert_eq!(email.metadata.subject.as_deref(), Some("Invoice INV-2024-001"));
+        assert_eq!(email.metadata.date.as_deref(), Some("2024-03-05T10:15:00+01:00"));
+        assert_eq!(email.body.trim(), "Please find our invoice attached.");
+
+        assert_eq!(email.attachments.len(), 1);
+        assert_eq!(email.attachments[0].name, "INV-2024-001.pdf");
+        assert_eq!(email.attachments[0].content_type, "application/pdf");
+        assert_eq!(email.attachments[0].data, b"%PDF-1.4\n");
+
+        let text = email.text();
+        assert!(text.starts_with("From: ACME Billing <billing@acme.example>\nSubject: Invoice INV-2024-001\n"));
+        assert!(text.contains("Attachments: INV-2024-001.pdf\n"));
+        assert!(text.ends_with("Please find our invoice attached."));
+    }
+
+    #[test]
+    fn html_only_eml_is_read_as_text() {
+        let eml = concat!(
+            "From: billing@acme.example\r\n",
+            "Subject: Receipt\r\n",
+            "Content-Type: text/html\r\n",
+            "\r\n",
+            "<html><body><p>Total: <b>42.00 EUR</b></p></body></html>\r\n",
+        );
+
+        let email = parse(eml.as_bytes()).unwrap();
+        assert_eq!(email.metadata.from.as_deref(), Some("billing@acme.example"));
+        assert!(email.body.contains("Total: 42.00 EUR"));
+        assert!(email.attachments.is_empty());
+        assert!(parse(b"").is_err());
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn msg_body_metadata_and_attachments() {
+        let mut msg = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        let streams: [(&str, Vec<u8>); 6] = [
+            ("/__substg1.0_0037001F", utf16("Invoice INV-7")),
+            ("/__substg1.0_0C1A001F", utf16("ACME Billing")),
+            ("/__substg1.0_5D01001F", utf16("billing@acme.example")),
+            ("/__substg1.0_1000001F", utf16("Invoice attached.")),
+            ("/__attach_version1.0_#00000000/__substg1.0_3707001F", utf16("C:\\Scans\\INV-7.pdf")),
+            ("/__attach_version1.0_#00000000/__substg1.0_37010102", b"%PDF-1.4\n".to_vec()),
+        ];
+        msg.create_storage("/__attach_version1.0_#00000000").unwrap();
+        for (path, data) in &streams {
+            msg.create_stream(path).unwrap().write_all(data).unwrap();
+        }
+
+        // 2024-03-05T09:15:00Z as a Windows file time
+        let filetime = (1_709_630_100i64 + FILETIME_UNIX_OFFSET_SECS) * 10_000_000;
+        let mut properties = vec![0u8; 32];
+        properties.extend_from_slice(&0x0039_0040u32.to_le_bytes());
+        properties.extend_from_slice(&0u32.to_le_bytes());
+        properties.extend_from_slice(&filetime.to_le_bytes());
+        msg.create_stream("/__properties_version1.0")
+            .unwrap()
+            .write_all(&properties)
+            .unwrap();
+        let data = msg.into_inner().into_inner();
+
+        let email = parse(&data).unwrap();
+        assert_eq!(
+            email.metadata.from.as_deref(),
+            Some("ACME Billing <billing@acme.example>")
+        );
+        assert_eq!(email.metadata.subject.as_deref(), Some("Invoice INV-7"));
+        assert_eq!(email.metadata.date.as_deref(), Some("2024-03-05T09:15:00+00:00"));
+        assert_eq!(email.body, "Invoice attached.");
+
+        assert_eq!(email.attachments.len(), 1);
+        assert_eq!(email.attachments[0].name, "INV-7.pdf");
+        assert_eq!(email.attachments[0].content_type, "application/pdf");
+        assert_eq!(email.attachments[0].data, b"%PDF-1.4\n");
+    }
+}
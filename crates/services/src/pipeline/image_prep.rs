@@ -0,0 +1,337 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use harvex_config::ImageSettings;
+use image::{DynamicImage, GrayImage, ImageDecoder, ImageReader, Rgb, RgbImage};
+use tracing::debug;
+
+/// Edge length images are reduced to for skew and bounds detection.
+const ANALYSIS_EDGE: u32 = 600;
+
+/// Skew angles below this are left alone; resampling costs sharpness.
+const MIN_SKEW_DEGREES: f32 = 0.5;
+
+/// Open an image, applying its EXIF orientation when `fix_orientation` is set.
+pub fn open_image(file_path: &Path, fix_orientation: bool) -> Result<DynamicImage, anyhow::Error> {
+    let mut decoder = ImageReader::open(file_path)?
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(|e| anyhow::anyhow!("Failed to open image: {e}"))?;
+    let orientation = decoder.orientation().ok();
+
+    let mut img = DynamicImage::from_decoder(decoder)
+        .map_err(|e| anyhow::anyhow!("Failed to decode image: {e}"))?;
+    if fix_orientation && let Some(orientation) = orientation {
+        img.apply_orientation(orientation);
+    }
+    Ok(img)
+}
+
+/// Prepare an image file for the vision model: fix its orientation, crop it
+/// to the document, downscale, straighten it and encode it as JPEG, each
+/// step as enabled in `settings`.
+///
+/// Cropping comes first so a dark background does not count as text when
+/// measuring skew; downscaling before deskewing keeps the rotation cheap.
+pub fn prepare_for_vision(file_path: &Path, settings: &ImageSettings) -> Result<Vec<u8>, anyhow::Error> {
+    let mut img = open_image(file_path, settings.fix_orientation)?;
+    let original = (img.width(), img.height());
+
+    if settings.crop && let Some((x, y, w, h)) = document_bounds(&img) {
+        debug!("Cropping to document bounds {}x{} at ({}, {})", w, h, x, y);
+        img = img.crop_imm(x, y, w, h);
+    }
+
+    if settings.max_edge > 0 && img.width().max(img.height()) > settings.max_edge {
+        img = img.resize(settings.max_edge, settings.max_edge, image::imageops::FilterType::Lanczos3);
+    }
+
+    if settings.deskew {
+        let skew = detect_skew(&img, settings.max_skew_degrees);
+        if skew.abs() >= MIN_SKEW_DEGREES {
+            debug!("Straightening image skewed by {:.2}°", skew);
+            img = DynamicImage::ImageRgb8(rotate(&img.to_rgb8(), -skew));
+        }
+    }
+
+    debug!(
+        "Prepared {}: {}x{} → {}x{}",
+        file_path.display(),
+        original.0,
+        original.1,
+        img.width(),
+        img.height()
+    );
+    encode_jpeg(&img, settings.jpeg_quality)
+}
+
+/// Encode an image as JPEG at `quality` (1–100).
+pub fn encode_jpeg(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, anyhow::Error> {
+    let mut bytes = Vec::new();
+    let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, quality.clamp(1, 100));
+    // JPEG has no alpha channel
+    img.to_rgb8().write_with_encoder(encoder)?;
+    Ok(bytes)
+}
+
+/// Where the prepared version of an uploaded image is kept for preview.
+pub fn processed_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.as_os_str().to_os_string();
+    name.push(".processed.jpg");
+    PathBuf::from(name)
+}
+
+/// The image reduced to at most `ANALYSIS_EDGE`, in grayscale, and the
+/// factor from its coordinates back to the image's.
+fn analysis_image(img: &DynamicImage) -> (GrayImage, f64) {
+    let longest = img.width().max(img.height());
+    if longest <= ANALYSIS_EDGE {
+        return (img.to_luma8(), 1.0);
+    }
+    let small = img.resize(ANALYSIS_EDGE, ANALYSIS_EDGE, image::imageops::FilterType::Triangle);
+    let scale = img.width() as f64 / small.width() as f64;
+    (small.to_luma8(), scale)
+}
+
+/// Threshold separating dark from light pixels (Otsu's method).
+fn otsu_threshold(gray: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for p in gray.pixels() {
+        histogram[p.0[0] as usize] += 1;
+    }
+    let total = gray.pixels().len() as f64;
+    let sum: f64 = histogram.iter().enumerate().map(|(v, &n)| v as f64 * n as f64).sum();
+
+    let (mut best, mut best_variance) = (0u8, 0.0);
+    let (mut weight_dark, mut sum_dark) = (0.0, 0.0);
+    for (value, &count) in histogram.iter().enumerate() {
+        weight_dark += count as f64;
+        sum_dark += value as f64 * count as f64;
+        let weight_light = total - weight_dark;
+        if weight_dark == 0.0 || weight_light == 0.0 {
+            continue;
+        }
+        let mean_dark = sum_dark / weight_dark;
+        let mean_light = (sum - sum_dark) / weight_light;
+        let variance = weight_dark * weight_light * (mean_dark - mean_light).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = value as u8;
+        }
+    }
+    best
+}
+
+/// Bounds (x, y, width, height) of a light document on a darker
+/// background: the largest connected light region, if it covers a fair
+/// share of the image but leaves a visible margin.
+fn document_bounds(img: &DynamicImage) -> Option<(u32, u32, u32, u32)> {
+    let (gray, scale) = analysis_image(img);
+    let (w, h) = gray.dimensions();
+    let threshold = otsu_threshold(&gray);
+    let light = |x: u32, y: u32| gray.get_pixel(x, y).0[0] > threshold;
+
+    // Largest 4-connected light region
+    let mut seen = vec![false; (w * h) as usize];
+    let mut best: Option<(usize, (u32, u32, u32, u32))> = None;
+    let mut queue = VecDeque::new();
+    for start in 0..w * h {
+        let (sx, sy) = (start % w, start / w);
+        if seen[start as usize] || !light(sx, sy) {
+            continue;
+        }
+        seen[start as usize] = true;
+        queue.push_back((sx, sy));
+        let (mut area, mut min_x, mut min_y, mut max_x, mut max_y) = (0usize, sx, sy, sx, sy);
+        while let Some((x, y)) = queue.pop_front() {
+            area += 1;
+            (min_x, min_y, max_x, max_y) = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y));
+            let neighbours = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for (nx, ny) in neighbours {
+                if nx < w && ny < h && !seen[(ny * w + nx) as usize] && light(nx, ny) {
+                    seen[(ny * w + nx) as usize] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        if best.is_none_or(|(best_area, _)| area > best_area) {
+            best = Some((area, (min_x, min_y, max_x, max_y)));
+        }
+    }
+
+    let (area, (min_x, min_y, max_x, max_y)) = best?;
+    let (box_w, box_h) = (max_x - min_x + 1, max_y - min_y + 1);
+    let image_area = (w * h) as f64;
+    let covers_enough = area as f64 >= image_area * 0.2;
+    let leaves_margin = (box_w as f64) < w as f64 * 0.95 || (box_h as f64) < h as f64 * 0.95;
+    if !covers_enough || !leaves_margin {
+        return None;
+    }
+
+    // Back to full resolution, with a small margin
+    let pad = (w.max(h) / 100).max(1);
+    let x0 = ((min_x.saturating_sub(pad)) as f64 * scale) as u32;
+    let y0 = ((min_y.saturating_sub(pad)) as f64 * scale) as u32;
+    let x1 = (((max_x + pad + 1).min(w)) as f64 * scale).min(img.width() as f64) as u32;
+    let y1 = (((max_y + pad + 1).min(h)) as f64 * scale).min(img.height() as f64) as u32;
+    Some((x0, y0, x1 - x0, y1 - y0))
+}
+
+/// Angle in degrees (clockwise) by which the text lines of `img` are
+/// rotated, within ±`max_degrees`; 0 when it cannot be measured.
+///
+/// Dark pixels are projected onto the vertical axis at each candidate angle;
+/// the angle at which the text lines fall into the sharpest rows wins.
+fn detect_skew(img: &DynamicImage, max_degrees: f32) -> f32 {
+    let (gray, _) = analysis_image(img);
+    let (w, h) = gray.dimensions();
+    let threshold = otsu_threshold(&gray);
+    let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+
+    let ink: Vec<(f32, f32)> = gray
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p.0[0] < threshold)
+        .map(|(x, y, _)| (x as f32 - cx, y as f32 - cy))
+        .collect();
+    // Nothing to measure, or a mostly dark image that is not a page of text
+    if ink.is_empty() || ink.len() > (w * h / 2) as usize {
+        return 0.0;
+    }
+
+    let rows = (w + h) as usize;
+    let offset = rows as f32 / 2.0;
+    let score = |degrees: f32| -> f64 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut profile = vec![0u32; rows];
+        for &(x, y) in &ink {
+            let row = (-sin * x + cos * y + offset) as usize;
+            if let Some(count) = profile.get_mut(row) {
+                *count += 1;
+            }
+        }
+        profile.windows(2).map(|p| (p[1] as f64 - p[0] as f64).powi(2)).sum()
+    };
+
+    let max_degrees = max_degrees.abs();
+    let mut best = (0.0, score(0.0));
+    let mut angle = -max_degrees;
+    while angle <= max_degrees {
+        let s = score(angle);
+        if s > best.1 {
+            best = (angle, s);
+        }
+        angle += 0.25;
+    }
+    best.0
+}
+
+/// Rotate `img` clockwise by `degrees` about its centre, keeping its size
+/// and filling uncovered corners with white.
+fn rotate(img: &RgbImage, degrees: f32) -> RgbImage {
+    let (w, h) = img.dimensions();
+    let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+
+    RgbImage::from_fn(w, h, |x, y| {
+        // Inverse mapping: where this output pixel comes from
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let sx = cos * dx + sin * dy + cx - 0.5;
+        let sy = -sin * dx + cos * dy + cy - 0.5;
+        sample_bilinear(img, sx, sy).unwrap_or(Rgb([255, 255, 255]))
+    })
+}
+
+fn sample_bilinear(img: &RgbImage, x: f32, y: f32) -> Option<Rgb<u8>> {
+    let (w, h) = img.dimensions();
+    if x < 0.0 || y < 0.0 || x > (w - 1) as f32 || y > (h - 1) as f32 {
+        return None;
+    }
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let mut out = [0u8; 3];
+    for (c, value) in out.iter_mut().enumerate() {
+        let top = img.get_pixel(x0, y0).0[c] as f32 * (1.0 - fx) + img.get_pixel(x1, y0).0[c] as f32 * fx;
+        let bottom = img.get_pixel(x0, y1).0[c] as f32 * (1.0 - fx) + img.get_pixel(x1, y1).0[c] as f32 * fx;
+        *value = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Some(Rgb(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A white page with evenly spaced black text lines.
+    fn lined_page(w: u32, h: u32) -> RgbImage {
+        RgbImage::from_fn(w, h, |x, y| {
+            let in_margin = x < w / 10 || x > w - w / 10;
+            if !in_margin && y > h / 10 && y < h - h / 10 && y % 20 < 3 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn measures_and_corrects_skew() {
+        let page = lined_page(500, 400);
+        let skewed = DynamicImage::ImageRgb8(rotate(&page, 3.0));
+
+        let skew = detect_skew(&skewed, 10.0);
+        assert!((skew - 3.0).abs() <= 0.5, "measured {skew}");
+
+        let straightened = DynamicImage::ImageRgb8(rotate(&skewed.to_rgb8(), -skew));
+        assert!(detect_skew(&straightened, 10.0).abs() <= 0.5);
+        assert_eq!(detect_skew(&DynamicImage::ImageRgb8(page), 10.0), 0.0);
+    }
+
+    #[test]
+    fn crops_document_on_dark_background() {
+        let photo = RgbImage::from_fn(400, 300, |x, y| {
+            if (100..300).contains(&x) && (50..250).contains(&y) {
+                Rgb([240, 240, 235])
+            } else {
+                Rgb([40, 35, 30])
+            }
+        });
+        let (x, y, w, h) = document_bounds(&DynamicImage::ImageRgb8(photo)).unwrap();
+
+        assert!((92..=100).contains(&x) && (42..=50).contains(&y), "origin ({x}, {y})");
+        assert!((200..=216).contains(&w) && (200..=216).contains(&h), "size {w}x{h}");
+    }
+
+    #[test]
+    fn full_page_scan_is_not_cropped() {
+        let scan = DynamicImage::ImageRgb8(lined_page(400, 300));
+        assert_eq!(document_bounds(&scan), None);
+    }
+
+    #[test]
+    fn prepared_image_is_downscaled_jpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("receipt.png");
+        DynamicImage::ImageRgb8(lined_page(1200, 900)).save(&path).unwrap();
+
+        let settings = ImageSettings {
+            max_edge: 600,
+            ..ImageSettings::default()
+        };
+        let bytes = prepare_for_vision(&path, &settings).unwrap();
+        let prepared = image::load_from_memory(&bytes).unwrap();
+
+        assert_eq!(image::guess_format(&bytes).unwrap(), image::ImageFormat::Jpeg);
+        assert_eq!((prepared.width(), prepared.height()), (600, 450));
+        assert_eq!(
+            processed_path(&path),
+            dir.path().join("receipt.png.processed.jpg")
+        );
+    }
+}
@@ -1,10 +1,15 @@
+pub mod control;
 pub mod detector;
+pub mod einvoice;
+pub mod email;
 pub mod excel;
+pub mod image_prep;
 pub mod ocr;
 pub mod orchestrator;
 pub mod pdf;
 pub mod pdf_render;
 pub mod word;
 
+pub use control::{BatchControl, BatchSignal};
 pub use detector::FileType;
-pub use orchestrator::{Pipeline, ProgressEvent};
+pub use orchestrator::{BatchFinished, DocumentBusy, DocumentOptions, Pipeline, ProgressEvent};
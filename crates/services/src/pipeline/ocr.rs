@@ -1,7 +1,17 @@
-use image::DynamicImage;
 use std::path::Path;
+use std::sync::Arc;
+
+use harvex_config::{OcrEngineKind, OcrMode, OcrSettings};
+use image::DynamicImage;
+use serde::Serialize;
 use tracing::{debug, warn};
 
+use super::image_prep;
+use super::pdf_render::{PageWindows, PdfRenderer};
+
+/// Scanned PDF pages rendered and recognized at a time.
+const OCR_WINDOW_PAGES: u32 = 10;
+
 /// Preprocess an image for better OCR/LLM readability.
 ///
 /// Converts to grayscale, adjusts contrast, and optionally resizes
@@ -9,9 +19,14 @@ use tracing::{debug, warn};
 pub fn preprocess_image(file_path: &Path) -> Result<DynamicImage, anyhow::Error> {
     debug!("Preprocessing image: {}", file_path.display());
 
-    let img = image::open(file_path)
-        .map_err(|e| anyhow::anyhow!("Failed to open image: {e}"))?;
+    // Upright first: Tesseract expects horizontal text lines
+    let img = image_prep::open_image(file_path, true)?;
+
+    Ok(preprocess(img))
+}
 
+/// [`preprocess_image`] for an image already in memory.
+pub fn preprocess(img: DynamicImage) -> DynamicImage {
     let (w, h) = (img.width(), img.height());
     debug!("Image dimensions: {}x{}", w, h);
 
@@ -33,33 +48,272 @@ pub fn preprocess_image(file_path: &Path) -> Result<DynamicImage, anyhow::Error>
     // Adjust contrast for better text visibility
     let processed = image::imageops::contrast(&processed.to_luma8(), 30.0);
 
-    Ok(DynamicImage::ImageLuma8(processed))
+    DynamicImage::ImageLuma8(processed)
+}
+
+/// A recognized word and the engine's confidence in it (0–100).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OcrWord {
+    pub text: String,
+    pub confidence: f32,
+}
+
+/// Text recognized in one image.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OcrResult {
+    /// Recognized text with line and paragraph breaks.
+    pub text: String,
+    pub words: Vec<OcrWord>,
+}
+
+impl OcrResult {
+    /// Mean word confidence (0–100), or 0 without any words.
+    pub fn mean_confidence(&self) -> f32 {
+        if self.words.is_empty() {
+            return 0.0;
+        }
+        self.words.iter().map(|w| w.confidence).sum::<f32>() / self.words.len() as f32
+    }
+}
+
+/// An OCR engine turning a preprocessed image into text.
+pub trait OcrEngine: Send + Sync {
+    fn recognize(&self, image: &DynamicImage) -> Result<OcrResult, anyhow::Error>;
+
+    /// Short name for logs and messages.
+    fn name(&self) -> &'static str;
+}
+
+/// The configured OCR engine, with how scanned PDFs are rendered for it.
+#[derive(Clone)]
+pub struct Ocr {
+    engine: Arc<dyn OcrEngine>,
+    mode: OcrMode,
+    dpi: u32,
+    max_pages: u32,
+}
+
+impl Ocr {
+    /// The OCR configured in `settings`, if any. An engine the binary was
+    /// built without is reported and treated as none.
+    pub fn from_settings(settings: &OcrSettings) -> Option<Self> {
+        Some(Self {
+            engine: create_engine(settings)?,
+            mode: settings.mode,
+            dpi: settings.dpi,
+            max_pages: settings.max_pages,
+        })
+    }
+
+    pub fn engine(&self) -> &dyn OcrEngine {
+        self.engine.as_ref()
+    }
+
+    /// The configured mode, for batches that do not set their own.
+    pub fn mode(&self) -> OcrMode {
+        self.mode
+    }
+
+    /// Pages of a scanned PDF that are recognized; 0 for all of them.
+    pub fn max_pages(&self) -> u32 {
+        self.max_pages
+    }
+
+    /// Recognize an encoded image (e.g. a rendered page or an upload).
+    pub fn recognize_encoded(&self, bytes: &[u8]) -> Result<OcrResult, anyhow::Error> {
+        let img = image::load_from_memory(bytes)
+            .map_err(|e| anyhow::anyhow!("Failed to decode image: {e}"))?;
+        self.engine.recognize(&preprocess(img))
+    }
+
+    /// Windows over a scanned PDF of `page_count` pages, up to the
+    /// configured page limit, for [`Ocr::recognize_pdf`].
+    pub fn page_windows(&self, page_count: Option<u32>) -> PageWindows {
+        PageWindows::new(OCR_WINDOW_PAGES, page_count, self.max_pages)
+    }
+
+    /// Render a scanned PDF with `renderer` one window at a time and
+    /// recognize each page. Pages are separated by a blank line in the text.
+    pub fn recognize_pdf(
+        &self,
+        pdf_path: &Path,
+        renderer: &dyn PdfRenderer,
+        windows: &mut PageWindows,
+    ) -> Result<OcrResult, anyhow::Error> {
+        let mut result = OcrResult::default();
+        let mut page_number = 0;
+        while let Some(rendered) = windows.render_next(renderer, pdf_path, self.dpi)? {
+            for jpeg in &rendered.pages {
+                page_number += 1;
+                let page = self
+                    .recognize_encoded(jpeg)
+                    .map_err(|e| anyhow::anyhow!("Page {page_number}: {e}"))?;
+                debug!(
+                    "{}: page {} has {} words",
+                    self.engine.name(),
+                    page_number,
+                    page.words.len()
+                );
+
+                if !result.text.is_empty() && !page.text.is_empty() {
+                    result.text.push_str("\n\n");
+                }
+                result.text.push_str(&page.text);
+                result.words.extend(page.words);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Render one 1-based `page` of a PDF with `renderer` and recognize it.
+    pub fn recognize_pdf_page(
+        &self,
+        pdf_path: &Path,
+        renderer: &dyn PdfRenderer,
+        page: u32,
+    ) -> Result<OcrResult, anyhow::Error> {
+        let rendered = renderer.render(pdf_path, self.dpi, page..=page)?;
+        match rendered.pages.first() {
+            Some(jpeg) => self.recognize_encoded(jpeg),
+            None => Ok(OcrResult::default()),
+        }
+    }
+}
+
+fn create_engine(settings: &OcrSettings) -> Option<Arc<dyn OcrEngine>> {
+    match settings.engine {
+        OcrEngineKind::None => None,
+        #[cfg(feature = "tesseract")]
+        OcrEngineKind::Tesseract => Some(Arc::new(TesseractEngine::new(settings))),
+        #[cfg(not(feature = "tesseract"))]
+        OcrEngineKind::Tesseract => {
+            warn!("OCR engine 'tesseract' is configured but harvex was built without the tesseract feature; OCR is disabled");
+            None
+        }
+    }
+}
+
+/// OCR with Tesseract through leptess.
+#[cfg(feature = "tesseract")]
+pub struct TesseractEngine {
+    data_path: Option<String>,
+    language: String,
+    dpi: i32,
+}
+
+#[cfg(feature = "tesseract")]
+impl TesseractEngine {
+    pub fn new(settings: &OcrSettings) -> Self {
+        Self {
+            data_path: (!settings.tessdata_path.is_empty()).then(|| settings.tessdata_path.clone()),
+            language: settings.language.clone(),
+            dpi: settings.dpi as i32,
+        }
+    }
+}
+
+#[cfg(feature = "tesseract")]
+impl OcrEngine for TesseractEngine {
+    fn recognize(&self, image: &DynamicImage) -> Result<OcrResult, anyhow::Error> {
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+
+        // A handle per call: Tesseract handles are not thread-safe, and
+        // initialization is cheap next to recognition
+        let mut tess = leptess::LepTess::new(self.data_path.as_deref(), &self.language)
+            .map_err(|e| anyhow::anyhow!("Failed to initialize Tesseract ({}): {e}", self.language))?;
+        tess.set_image_from_mem(&png)
+            .map_err(|e| anyhow::anyhow!("Tesseract could not read the image: {e}"))?;
+        tess.set_source_resolution(self.dpi);
+
+        let tsv = tess.get_tsv_text(0)?;
+        Ok(parse_tsv(&tsv))
+    }
+
+    fn name(&self) -> &'static str {
+        "tesseract"
+    }
+}
+
+/// Build an [`OcrResult`] from Tesseract's TSV output: one row per page,
+/// block, paragraph, line and word, of which only words (level 5) carry text.
+#[cfg(any(feature = "tesseract", test))]
+fn parse_tsv(tsv: &str) -> OcrResult {
+    let mut result = OcrResult::default();
+    let mut last_line: Option<(&str, &str, &str)> = None;
+
+    for row in tsv.lines() {
+        let cols: Vec<&str> = row.split('\t').collect();
+        // level page block par line word left top width height conf text
+        if cols.len() < 12 || cols[0] != "5" {
+            continue;
+        }
+        let text = cols[11].trim();
+        let confidence: f32 = cols[10].parse().unwrap_or(-1.0);
+        if text.is_empty() || confidence < 0.0 {
+            continue;
+        }
+
+        let line = (cols[2], cols[3], cols[4]);
+        match last_line {
+            None => {}
+            Some(prev) if (prev.0, prev.1) != (line.0, line.1) => result.text.push_str("\n\n"),
+            Some(prev) if prev.2 != line.2 => result.text.push('\n'),
+            Some(_) => result.text.push(' '),
+        }
+        last_line = Some(line);
+
+        result.text.push_str(text);
+        result.words.push(OcrWord {
+            text: text.to_string(),
+            confidence,
+        });
+    }
+    result
 }
 
-/// Extract text from an image file.
+/// Extract text from an image file with `engine`.
 ///
-/// Currently returns the image metadata since Tesseract is not installed.
-/// When Tesseract (leptess) or LLM vision is available, this will perform
-/// actual OCR. For now, the image can be passed to the LLM in Phase 6.
-pub fn extract_text(file_path: &Path) -> Result<ExtractedImage, anyhow::Error> {
+/// Without an engine, or when it finds no text, the image is flagged for
+/// LLM vision processing instead.
+pub fn extract_text(
+    file_path: &Path,
+    engine: Option<&dyn OcrEngine>,
+) -> Result<ExtractedImage, anyhow::Error> {
     debug!("Extracting text from image: {}", file_path.display());
 
-    let img = image::open(file_path)
-        .map_err(|e| anyhow::anyhow!("Failed to open image: {e}"))?;
+    let img = image_prep::open_image(file_path, true)?;
 
     let (w, h) = (img.width(), img.height());
 
-    warn!(
-        "OCR not available (Tesseract not installed). \
-         Image {}x{} will need LLM vision processing.",
-        w, h
+    let Some(engine) = engine else {
+        warn!(
+            "OCR not configured. Image {}x{} will need LLM vision processing.",
+            w, h
+        );
+        return Ok(ExtractedImage {
+            text: String::new(),
+            width: w,
+            height: h,
+            needs_llm_vision: true,
+            words: Vec::new(),
+        });
+    };
+
+    let result = engine.recognize(&preprocess(img))?;
+    debug!(
+        "{}: {} words, mean confidence {:.0}",
+        engine.name(),
+        result.words.len(),
+        result.mean_confidence()
     );
 
     Ok(ExtractedImage {
-        text: String::new(),
+        needs_llm_vision: result.text.trim().is_empty(),
+        text: result.text,
         width: w,
         height: h,
-        needs_llm_vision: true,
+        words: result.words,
     })
 }
 
@@ -68,4 +322,49 @@ pub struct ExtractedImage {
     pub width: u32,
     pub height: u32,
     pub needs_llm_vision: bool,
+    /// Recognized words with their confidence; empty without OCR.
+    pub words: Vec<OcrWord>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TSV: &str = "\
+1\t1\t0\t0\t0\t0\t0\t0\t800\t600\t-1\t
+2\t1\t1\t0\t0\t0\t10\t10\t300\t40\t-1\t
+5\t1\t1\t1\t1\t1\t10\t10\t80\t20\t96.5\tInvoice
+5\t1\t1\t1\t1\t2\t95\t10\t40\t20\t91\tNo.
+5\t1\t1\t1\t2\t1\t10\t40\t60\t20\t88\t2024-17
+5\t1\t2\t1\t1\t1\t10\t200\t60\t20\t42.25\tTotal
+5\t1\t2\t1\t1\t2\t75\t200\t60\t20\t-1\t
+5\t1\t2\t1\t1\t3\t140\t200\t60\t20\t77\t12,50";
+
+    #[test]
+    fn tsv_words_keep_layout_and_confidence() {
+        let result = parse_tsv(TSV);
+
+        assert_eq!(result.text, "Invoice No.\n2024-17\n\nTotal 12,50");
+        assert_eq!(result.words.len(), 5);
+        assert_eq!(result.words[3], OcrWord { text: "Total".into(), confidence: 42.25 });
+        assert!((result.mean_confidence() - 78.95).abs() < 0.01);
+    }
+
+    #[test]
+    fn tsv_header_and_empty_input_are_ignored() {
+        let header = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext";
+        assert!(parse_tsv(header).words.is_empty());
+        assert_eq!(parse_tsv("").mean_confidence(), 0.0);
+    }
+
+    #[test]
+    fn no_engine_means_vision() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blank.png");
+        DynamicImage::new_luma8(40, 20).save(&path).unwrap();
+
+        let extracted = extract_text(&path, None).unwrap();
+        assert!(extracted.needs_llm_vision);
+        assert_eq!((extracted.width, extracted.height), (40, 20));
+    }
 }
@@ -1,20 +1,28 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use serde::Serialize;
 use tokio::sync::broadcast;
-use tracing::{info, warn};
+use tokio::task::{self, JoinError, JoinSet};
+use tracing::{debug, info, warn};
 
-use harvex_config::LlmSettings;
-use harvex_db::models::Document;
+use harvex_config::{ImageSettings, LlmSettings, OcrMode, ProcessingSettings};
+use harvex_db::models::{Batch, Document, Job, NewExtractionPage};
 use harvex_db::DbPool;
 
-use crate::dao::{BatchDao, DocumentDao, ExtractionDao};
-use crate::llm::LlmEngine;
+use crate::dao::{BatchDao, DocumentDao, ExtractionDao, ExtractionPageDao, JobDao};
+use crate::doctypes::{DocumentTypeCatalog, FALLBACK_TYPE};
+use crate::llm::validate;
+use crate::llm::{LlmCallError, LlmEngine, LlmResponse, PageResult, ValidationReport};
 
+use super::control::{BatchControl, BatchSignal};
 use super::detector::FileType;
-use super::{excel, ocr, pdf, pdf_render, word};
+use super::{einvoice, email, excel, image_prep, ocr, pdf, pdf_render, word};
+use super::einvoice::EInvoice;
+use super::ocr::Ocr;
+use super::pdf_render::PdfRenderer;
 
 /// Progress event sent via SSE to clients.
 #[derive(Debug, Clone, Serialize)]
@@ -26,6 +34,8 @@ pub struct ProgressEvent {
     pub message: String,
     pub processed: i32,
     pub failed: i32,
+    /// Processed documents whose LLM step failed (subset of `processed`).
+    pub extracted_only: i32,
     pub total: i32,
 }
 
@@ -33,30 +43,127 @@ pub struct ProgressEvent {
 enum ExtractedContent {
     /// Text was extracted successfully; proceed with text LLM.
     Text(String),
+    /// PDF whose scanned pages were read by OCR; `skipped_pages` were not,
+    /// being past the OCR page limit or failing OCR.
+    OcrPdf { text: String, skipped_pages: Vec<u32> },
     /// Scanned PDF — needs vision LLM. Contains the file path.
     NeedsVisionPdf(PathBuf),
-    /// Image file — needs vision LLM. Contains the raw image bytes.
-    NeedsVisionImage(Vec<u8>),
+    /// Image file — needs vision LLM. Contains the file path.
+    NeedsVisionImage(PathBuf),
+    /// PDF with both text and scanned pages — the scanned ones need vision.
+    MixedPdf(MixedPdf),
+    /// Machine-readable e-invoice — already structured, no LLM needed.
+    EInvoice(EInvoice),
+}
+
+/// A PDF whose text pages were extracted and whose scanned pages were not.
+struct MixedPdf {
+    path: PathBuf,
+    /// Text of each page; little or none on scanned pages.
+    pages: Vec<String>,
+    /// 1-based numbers of the scanned pages.
+    scanned_pages: Vec<u32>,
+}
+
+impl MixedPdf {
+    /// The text of the text pages, blank-line separated.
+    fn text(&self) -> String {
+        (1..)
+            .zip(&self.pages)
+            .filter(|(page, _)| !self.scanned_pages.contains(page))
+            .map(|(_, text)| text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// Per-document overrides for a processing run, taken from the document's job.
+#[derive(Debug, Clone, Default)]
+pub struct DocumentOptions {
+    /// Model to use instead of the configured one (the vision model for
+    /// scanned documents and images).
+    pub model_name: Option<String>,
+    /// Document type to use instead of classifying the document.
+    pub document_type: Option<String>,
+    /// OCR mode to use instead of the configured one; taken from the batch.
+    pub ocr_mode: Option<OcrMode>,
+}
+
+impl DocumentOptions {
+    fn from_job(job: &Job, batch: &Batch) -> Self {
+        Self {
+            model_name: job.model_override.clone(),
+            document_type: job.document_type_override.clone(),
+            ocr_mode: batch.ocr_mode.as_deref().and_then(|m| m.parse().ok()),
+        }
+    }
+}
+
+/// A document cannot be queued with other overrides while a worker is
+/// already processing it with its current ones.
+#[derive(Debug, thiserror::Error)]
+#[error("Document {document_id} is already being processed with other settings")]
+pub struct DocumentBusy {
+    pub document_id: String,
+}
+
+/// A batch that has already finished cannot be cancelled.
+#[derive(Debug, thiserror::Error)]
+#[error("Batch {batch_id} is already {status}")]
+pub struct BatchFinished {
+    pub batch_id: String,
+    pub status: String,
 }
 
 /// The processing pipeline. Holds a broadcast sender for progress events
 /// and an LLM engine for structured data extraction.
+///
+/// Work is driven by the durable `jobs` table: every document of a batch is
+/// queued as a job, and a per-batch worker leases and processes jobs until the
+/// queue is drained. Because the queue lives in DuckDB, a batch interrupted by
+/// a restart is picked up again by [`Pipeline::resume_interrupted`].
 pub struct Pipeline {
     db: DbPool,
-    max_concurrent: usize,
+    settings: ProcessingSettings,
     llm: Arc<LlmEngine>,
+    readers: Readers,
     progress_tx: broadcast::Sender<ProgressEvent>,
+    /// Batches that currently have a worker draining their jobs.
+    running: Mutex<HashMap<String, Arc<BatchControl>>>,
+}
+
+/// What documents are read with besides the LLM, shared by all document tasks.
+#[derive(Clone)]
+struct Readers {
+    /// OCR for images and scanned PDFs, if configured.
+    ocr: Option<Ocr>,
+    renderer: Arc<dyn PdfRenderer>,
+    image: ImageSettings,
+}
+
+/// Why a batch worker stopped leasing jobs.
+enum RunOutcome {
+    Finished,
+    Paused,
+    Cancelled,
 }
 
 impl Pipeline {
-    pub fn new(db: DbPool, max_concurrent: usize, llm_settings: LlmSettings) -> Self {
+    pub fn new(db: DbPool, settings: ProcessingSettings, llm_settings: LlmSettings) -> Self {
         let (progress_tx, _) = broadcast::channel(256);
         let llm = Arc::new(LlmEngine::new(llm_settings));
+        let readers = Readers {
+            ocr: Ocr::from_settings(&settings.ocr),
+            renderer: pdf_render::create_renderer(&settings.pdf_render),
+            image: settings.image.clone(),
+        };
         Self {
             db,
-            max_concurrent,
+            settings,
             llm,
+            readers,
             progress_tx,
+            running: Mutex::new(HashMap::new()),
         }
     }
 
@@ -70,16 +177,222 @@ impl Pipeline {
         self.progress_tx.clone()
     }
 
+    /// Whether a worker is currently draining this batch's jobs.
+    pub fn is_running(&self, batch_id: &str) -> bool {
+        self.running.lock().unwrap().contains_key(batch_id)
+    }
+
+    /// Resume batches that were interrupted (e.g. by a pod restart).
+    ///
+    /// Returns leases left behind by the previous process to the queue and
+    /// spawns a worker for every batch that still has open jobs, except
+    /// batches the user paused. Call once at startup. Returns the number of
+    /// resumed batches.
+    pub fn resume_interrupted(self: &Arc<Self>) -> Result<usize, anyhow::Error> {
+        let released = JobDao::release_all_leases(&self.db)?;
+        if released > 0 {
+            info!("Returned {} interrupted jobs to the queue", released);
+        }
+
+        let mut resumed = 0;
+        for batch_id in JobDao::batches_with_open_jobs(&self.db)? {
+            let paused = BatchDao::get_by_id(&self.db, &batch_id)
+                .map(|b| b.status == "paused")
+                .unwrap_or(false);
+            if paused {
+                continue;
+            }
+
+            info!("Resuming interrupted batch {}", batch_id);
+            self.spawn_batch(batch_id);
+            resumed += 1;
+        }
+
+        Ok(resumed)
+    }
+
+    /// Pause a running batch: no new documents are started, in-flight ones finish.
+    ///
+    /// Returns `false` if the batch has no running worker.
+    pub fn pause_batch(&self, batch_id: &str) -> bool {
+        match self.running.lock().unwrap().get(batch_id) {
+            Some(control) => {
+                control.pause();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Resume a paused batch.
+    ///
+    /// If the worker is still draining in-flight documents it simply carries
+    /// on; otherwise a new worker picks up the remaining queued jobs.
+    /// Returns `false` if the batch is neither running nor paused.
+    pub fn resume_batch(self: &Arc<Self>, batch_id: &str) -> Result<bool, anyhow::Error> {
+        if let Some(control) = self.running.lock().unwrap().get(batch_id) {
+            if control.signal() == BatchSignal::Cancel {
+                return Ok(false);
+            }
+            control.resume();
+            return Ok(true);
+        }
+
+        let batch = BatchDao::get_by_id(&self.db, batch_id)
+            .map_err(|_| anyhow::anyhow!("Batch {batch_id} not found"))?;
+        if batch.status != "paused" {
+            return Ok(false);
+        }
+
+        self.spawn_batch(batch_id.to_string());
+        Ok(true)
+    }
+
+    /// Cancel a batch.
+    ///
+    /// A running worker is signalled and aborts its in-flight documents; a
+    /// batch without a worker (pending or paused) has its queued jobs cancelled
+    /// directly. Returns `true` if a running worker was signalled. A batch
+    /// that is neither pending, paused nor processing fails with
+    /// [`BatchFinished`] and is left unchanged.
+    pub fn cancel_batch(&self, batch_id: &str) -> Result<bool, anyhow::Error> {
+        let running = self.running.lock().unwrap();
+        if let Some(control) = running.get(batch_id) {
+            control.cancel();
+            return Ok(true);
+        }
+
+        let status = BatchDao::get_by_id(&self.db, batch_id)?.status;
+        if !matches!(status.as_str(), "pending" | "paused" | "processing") {
+            return Err(BatchFinished {
+                batch_id: batch_id.to_string(),
+                status,
+            }
+            .into());
+        }
+
+        // Hold the lock so no worker starts while the queue is being cancelled.
+        self.finish_cancelled(batch_id)?;
+        drop(running);
+        Ok(false)
+    }
+
+    /// Cancel a batch and wait until its worker, if any, has stopped. A batch
+    /// that has already finished has nothing to stop.
+    pub async fn cancel_and_wait(&self, batch_id: &str) -> Result<(), anyhow::Error> {
+        let control = self.running.lock().unwrap().get(batch_id).cloned();
+        match control {
+            Some(control) => {
+                control.cancel();
+                control.stopped().await;
+                Ok(())
+            }
+            None => match self.cancel_batch(batch_id) {
+                Err(e) if e.is::<BatchFinished>() => Ok(()),
+                result => result.map(|_| ()),
+            },
+        }
+    }
+
+    /// Queue documents of a batch for reprocessing and make sure a worker is
+    /// draining the batch, unless the batch is paused: then the documents
+    /// wait in the queue until it is resumed.
+    ///
+    /// Each new extraction supersedes the document's previous one instead of
+    /// adding a duplicate. A document that is already queued keeps its job,
+    /// which takes the new overrides; one already being processed with other
+    /// overrides fails the whole call with [`DocumentBusy`] before anything
+    /// is queued.
+    pub fn reprocess_documents(
+        self: &Arc<Self>,
+        batch_id: &str,
+        document_ids: &[String],
+        options: &DocumentOptions,
+    ) -> Result<Vec<Job>, anyhow::Error> {
+        let model = options.model_name.as_deref();
+        let document_type = options.document_type.as_deref();
+        let same_overrides = |job: &Job| {
+            job.model_override.as_deref() == model
+                && job.document_type_override.as_deref() == document_type
+        };
+
+        for document_id in document_ids {
+            if let Some(job) = JobDao::open_for_document(&self.db, document_id)?
+                && job.status == "leased"
+                && !same_overrides(&job)
+            {
+                return Err(DocumentBusy {
+                    document_id: document_id.clone(),
+                }
+                .into());
+            }
+        }
+
+        let mut jobs = Vec::with_capacity(document_ids.len());
+        for document_id in document_ids {
+            let mut job = JobDao::enqueue_with(
+                &self.db,
+                batch_id,
+                document_id,
+                self.settings.job_max_attempts,
+                model,
+                document_type,
+            )?;
+            if !same_overrides(&job) {
+                // Leased since the check above
+                if !JobDao::set_overrides(&self.db, &job.id, model, document_type)? {
+                    return Err(DocumentBusy {
+                        document_id: document_id.clone(),
+                    }
+                    .into());
+                }
+                job = JobDao::get_by_id(&self.db, &job.id)?;
+            }
+            DocumentDao::update_status(&self.db, document_id, "pending", None)?;
+            jobs.push(job);
+        }
+
+        let paused = BatchDao::get_by_id(&self.db, batch_id)?.status == "paused";
+        if !jobs.is_empty() && !paused && !self.is_running(batch_id) {
+            self.spawn_batch(batch_id.to_string());
+        }
+
+        Ok(jobs)
+    }
+
     /// Process all documents in a batch.
     ///
+    /// If the batch has no open jobs, every document is queued again (a full
+    /// run). If it still has open jobs — it was interrupted or paused — only
+    /// those are processed, so completed documents are not repeated.
+    ///
     /// For each document: extract text, call LLM for structured data, store results.
     /// Documents are processed with limited concurrency using a semaphore.
     pub async fn process_batch(&self, batch_id: &str) -> Result<(), anyhow::Error> {
         let batch = BatchDao::get_by_id(&self.db, batch_id)
             .map_err(|_| anyhow::anyhow!("Batch {batch_id} not found"))?;
 
-        if batch.status == "processing" {
-            return Err(anyhow::anyhow!("Batch is already being processed"));
+        let control = Arc::new(BatchControl::new());
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.contains_key(batch_id) {
+                return Err(anyhow::anyhow!("Batch is already being processed"));
+            }
+            running.insert(batch_id.to_string(), control.clone());
+        }
+        let mut guard = RunGuard {
+            running: &self.running,
+            batch_id: batch_id.to_string(),
+            control: control.clone(),
+            released: false,
+        };
+
+        if JobDao::count_open(&self.db, batch_id)? == 0 {
+            let documents = DocumentDao::list_by_batch(&self.db, batch_id)?;
+            for doc in &documents {
+                JobDao::enqueue(&self.db, batch_id, &doc.id, self.settings.job_max_attempts)?;
+                DocumentDao::update_status(&self.db, &doc.id, "pending", None)?;
+            }
         }
 
         BatchDao::update_status(&self.db, batch_id, "processing")?;
@@ -88,114 +401,300 @@ impl Pipeline {
             batch.name, batch.total_files
         );
 
-        let documents = DocumentDao::list_by_batch(&self.db, batch_id)?;
-        let total = documents.len() as i32;
+        let total = DocumentDao::list_by_batch(&self.db, batch_id)?.len() as i32;
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(self.settings.max_concurrent));
+        let mut tasks = JoinSet::new();
+        // Job and document of each spawned task, to settle a task that panicked
+        let mut leased = HashMap::new();
 
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(self.max_concurrent));
-        let processed = Arc::new(std::sync::atomic::AtomicI32::new(0));
-        let failed = Arc::new(std::sync::atomic::AtomicI32::new(0));
+        let outcome = loop {
+            while let Some(joined) = tasks.try_join_next_with_id() {
+                self.reap_task(batch_id, &mut leased, joined)?;
+            }
+            JobDao::requeue_expired(&self.db)?;
+
+            let permit = semaphore.clone().acquire_owned().await?;
+            let job = if control.signal() == BatchSignal::Run {
+                JobDao::lease_next(&self.db, batch_id, self.settings.job_lease_secs)?
+            } else {
+                None
+            };
+
+            let Some(job) = job else {
+                drop(permit);
+                // Nothing to start: wait for an in-flight document, which may
+                // re-queue itself for another attempt.
+                if let Some(joined) = tasks.join_next_with_id().await {
+                    self.reap_task(batch_id, &mut leased, joined)?;
+                    continue;
+                }
+
+                // Release under the lock so a concurrent enqueue either sees
+                // this worker (and its job is picked up here) or starts a new one,
+                // and a pause/resume either reaches this worker or the DB status.
+                {
+                    let mut running = self.running.lock().unwrap();
+                    match control.signal() {
+                        BatchSignal::Pause => {
+                            BatchDao::update_status(&self.db, batch_id, "paused")?;
+                            guard.release(&mut running);
+                            break RunOutcome::Paused;
+                        }
+                        BatchSignal::Cancel => {
+                            self.finish_cancelled(batch_id)?;
+                            guard.release(&mut running);
+                            break RunOutcome::Cancelled;
+                        }
+                        BatchSignal::Run => {
+                            if JobDao::count_open(&self.db, batch_id)? == 0 {
+                                guard.release(&mut running);
+                                break RunOutcome::Finished;
+                            }
+                        }
+                    }
+                }
 
-        let mut handles = Vec::new();
+                // Open jobs remain but none could be leased (e.g. a lease held
+                // by a crashed task); wait for it to expire.
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            };
+
+            let doc = match DocumentDao::get_by_id(&self.db, &job.document_id) {
+                Ok(doc) => doc,
+                Err(_) => {
+                    warn!("Job {} refers to a missing document, dropping it", job.id);
+                    JobDao::abandon(&self.db, &job.id, "Document no longer exists")?;
+                    continue;
+                }
+            };
 
-        for doc in documents {
             let db = self.db.clone();
             let tx = self.progress_tx.clone();
-            let sem = semaphore.clone();
-            let proc_count = processed.clone();
-            let fail_count = failed.clone();
             let batch_id = batch_id.to_string();
             let llm = self.llm.clone();
-
-            let handle = tokio::spawn(async move {
-                let _permit = sem.acquire().await.expect("semaphore closed");
-                let result = process_document(&db, &doc, &llm).await;
-
-                match result {
-                    Ok(msg) => {
-                        let p =
-                            proc_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
-                        let f = fail_count.load(std::sync::atomic::Ordering::Relaxed);
-                        let _ = BatchDao::update_progress(&db, &batch_id, p, f);
-
-                        let _ = tx.send(ProgressEvent {
-                            batch_id: batch_id.clone(),
-                            document_id: doc.id.clone(),
-                            document_name: doc.original_name.clone(),
-                            status: "completed".into(),
-                            message: msg,
-                            processed: p,
-                            failed: f,
-                            total,
-                        });
+            let readers = self.readers.clone();
+            let control = control.clone();
+            let options = DocumentOptions::from_job(&job, &batch);
+            let task_job = (job.id.clone(), doc.id.clone());
+
+            let handle = tasks.spawn(async move {
+                let _permit = permit;
+                let result = tokio::select! {
+                    result = process_document(&db, &doc, &llm, readers, &options) => Some(result),
+                    _ = control.cancelled() => None,
+                };
+
+                let (status, message) = match result {
+                    None => {
+                        // Aborted mid-flight (possibly mid-LLM-call): drop the
+                        // partial extraction written during this attempt.
+                        let _ = JobDao::cancel(&db, &job.id);
+                        let _ = ExtractionDao::delete_by_document_since(&db, &doc.id, &job.updated_at);
+                        let _ = DocumentDao::update_status(&db, &doc.id, "cancelled", None);
+                        ("cancelled", "Cancelled while processing".to_string())
                     }
-                    Err(e) => {
-                        let f =
-                            fail_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
-                        let p = proc_count.load(std::sync::atomic::Ordering::Relaxed);
-                        let _ = BatchDao::update_progress(&db, &batch_id, p, f);
-
-                        let _ = tx.send(ProgressEvent {
-                            batch_id: batch_id.clone(),
-                            document_id: doc.id.clone(),
-                            document_name: doc.original_name.clone(),
-                            status: "failed".into(),
-                            message: format!("Failed: {e}"),
-                            processed: p,
-                            failed: f,
-                            total,
-                        });
+                    Some(Ok((status, msg))) => {
+                        let _ = JobDao::complete(&db, &job.id);
+                        (status, msg)
                     }
-                }
+                    Some(Err(e)) => {
+                        let error = e.to_string();
+                        let requeued = if is_retryable(&e) {
+                            JobDao::fail(&db, &job.id, &error).unwrap_or(false)
+                        } else {
+                            let _ = JobDao::abandon(&db, &job.id, &error);
+                            false
+                        };
+                        if requeued {
+                            warn!(
+                                "Processing {} failed (attempt {}/{}), retrying: {error}",
+                                doc.original_name, job.attempts, job.max_attempts
+                            );
+                            let _ = DocumentDao::update_status(&db, &doc.id, "pending", Some(&error));
+                            ("retrying", format!("Attempt {} failed: {error}", job.attempts))
+                        } else {
+                            let _ = DocumentDao::update_status(&db, &doc.id, "failed", Some(&error));
+                            ("failed", format!("Failed: {error}"))
+                        }
+                    }
+                };
+
+                let (p, f) = BatchDao::recount_progress(&db, &batch_id).unwrap_or_default();
+                let x = DocumentDao::count_by_status(&db, &batch_id, "extracted_only").unwrap_or_default();
+                let _ = tx.send(ProgressEvent {
+                    batch_id,
+                    document_id: doc.id.clone(),
+                    document_name: doc.original_name.clone(),
+                    status: status.into(),
+                    message,
+                    processed: p,
+                    failed: f,
+                    extracted_only: x,
+                    total,
+                });
             });
+            leased.insert(handle.id(), task_job);
+        };
 
-            handles.push(handle);
-        }
-
-        // Wait for all documents to finish
-        for handle in handles {
-            let _ = handle.await;
-        }
-
-        // Determine final batch status
-        let p = processed.load(std::sync::atomic::Ordering::Relaxed);
-        let f = failed.load(std::sync::atomic::Ordering::Relaxed);
+        let (p, f) = BatchDao::recount_progress(&self.db, batch_id)?;
+        let x = DocumentDao::count_by_status(&self.db, batch_id, "extracted_only")?;
 
-        let final_status = if f == 0 {
-            "completed"
-        } else if p == 0 {
-            "failed"
-        } else {
-            "partially_completed"
+        let (final_status, message) = match outcome {
+            RunOutcome::Paused => {
+                info!("Batch {} paused: {} processed, {} failed", batch_id, p, f);
+                ("paused", format!("Batch paused: {p} processed, {f} failed"))
+            }
+            RunOutcome::Cancelled => {
+                info!("Batch {} cancelled: {} processed, {} failed", batch_id, p, f);
+                ("cancelled", format!("Batch cancelled: {p} processed, {f} failed"))
+            }
+            RunOutcome::Finished => {
+                // Documents left cancelled or pending by an earlier run were
+                // not processed by this one, e.g. after reprocessing a single
+                // document of a cancelled batch
+                let unprocessed = DocumentDao::count_by_status(&self.db, batch_id, "cancelled")?
+                    + DocumentDao::count_by_status(&self.db, batch_id, "pending")?;
+                let final_status = final_batch_status(p, f, unprocessed);
+
+                BatchDao::update_status(&self.db, batch_id, final_status)?;
+                info!(
+                    "Batch {} finished: {} processed ({} without LLM structuring), {} failed",
+                    batch_id, p, x, f
+                );
+                (
+                    final_status,
+                    format!("Batch complete: {p} processed ({x} without LLM structuring), {f} failed"),
+                )
+            }
         };
 
-        BatchDao::update_status(&self.db, batch_id, final_status)?;
-        info!(
-            "Batch {} finished: {} processed, {} failed",
-            batch_id, p, f
-        );
-
         // Send final event
         let _ = self.progress_tx.send(ProgressEvent {
             batch_id: batch_id.to_string(),
             document_id: String::new(),
             document_name: String::new(),
             status: final_status.into(),
-            message: format!("Batch complete: {p} processed, {f} failed"),
+            message,
             processed: p,
             failed: f,
+            extracted_only: x,
             total,
         });
 
         Ok(())
     }
+
+    /// Settle a finished document task. A task that panicked never recorded
+    /// its outcome, so its job is failed here instead of staying leased until
+    /// the lease expires.
+    fn reap_task(
+        &self,
+        batch_id: &str,
+        leased: &mut HashMap<task::Id, (String, String)>,
+        joined: Result<(task::Id, ()), JoinError>,
+    ) -> Result<(), anyhow::Error> {
+        let e = match joined {
+            Ok((id, ())) => {
+                leased.remove(&id);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        let Some((job_id, document_id)) = leased.remove(&e.id()) else {
+            return Ok(());
+        };
+
+        let error = format!("Processing aborted: {e}");
+        warn!("Job {job_id} of batch {batch_id}: {error}");
+        JobDao::abandon(&self.db, &job_id, &error)?;
+        DocumentDao::update_status(&self.db, &document_id, "failed", Some(&error))?;
+        BatchDao::recount_progress(&self.db, batch_id)?;
+        Ok(())
+    }
+
+    /// Spawn a background worker for a batch.
+    fn spawn_batch(self: &Arc<Self>, batch_id: String) {
+        let pipeline = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = pipeline.process_batch(&batch_id).await {
+                tracing::error!("Batch processing failed for {batch_id}: {e}");
+            }
+        });
+    }
+
+    /// Cancel every open job of a batch and mark its unfinished documents and
+    /// the batch itself as cancelled.
+    fn finish_cancelled(&self, batch_id: &str) -> Result<(), anyhow::Error> {
+        JobDao::cancel_open(&self.db, batch_id)?;
+        DocumentDao::cancel_unfinished(&self.db, batch_id)?;
+        BatchDao::recount_progress(&self.db, batch_id)?;
+        BatchDao::update_status(&self.db, batch_id, "cancelled")?;
+        Ok(())
+    }
+}
+
+/// Removes a batch from the running set and marks its control as stopped
+/// when the worker exits, including on error.
+struct RunGuard<'a> {
+    running: &'a Mutex<HashMap<String, Arc<BatchControl>>>,
+    batch_id: String,
+    control: Arc<BatchControl>,
+    released: bool,
+}
+
+impl RunGuard<'_> {
+    /// Remove the batch from an already-locked running set.
+    fn release(&mut self, running: &mut HashMap<String, Arc<BatchControl>>) {
+        running.remove(&self.batch_id);
+        self.released = true;
+    }
+}
+
+impl Drop for RunGuard<'_> {
+    fn drop(&mut self) {
+        if !self.released {
+            self.running.lock().unwrap().remove(&self.batch_id);
+        }
+        self.control.mark_stopped();
+    }
+}
+
+/// Whether a failed attempt may succeed when tried again. LLM calls, the
+/// database and timed-out I/O can recover; an unsupported, missing or
+/// unreadable file or an unknown document type fails the same way every time.
+fn is_retryable(e: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+
+    e.chain().any(|cause| {
+        cause.is::<LlmCallError>()
+            || cause.is::<reqwest::Error>()
+            || cause.is::<duckdb::Error>()
+            || cause.is::<tokio::time::error::Elapsed>()
+            || cause.downcast_ref::<std::io::Error>().is_some_and(|io| {
+                matches!(
+                    io.kind(),
+                    ErrorKind::TimedOut
+                        | ErrorKind::Interrupted
+                        | ErrorKind::WouldBlock
+                        | ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                )
+            })
+    })
 }
 
 /// Process a single document: detect type → extract text → LLM inference → store.
+///
+/// Returns the document's final status — `completed`, or `extracted_only` when
+/// the text was extracted but the LLM could not structure it — and a progress message.
 async fn process_document(
     db: &DbPool,
     doc: &Document,
     llm: &LlmEngine,
-) -> Result<String, anyhow::Error> {
+    readers: Readers,
+    options: &DocumentOptions,
+) -> Result<(&'static str, String), anyhow::Error> {
     let file_path = Path::new(&doc.file_path);
 
     if !file_path.exists() {
@@ -210,20 +709,113 @@ async fn process_document(
 
     let start = Instant::now();
 
-    // Step 1: Extract text based on file type (blocking I/O)
+    // Step 1: Extract text based on file type (blocking I/O). Images and
+    // scanned PDFs are OCRed here only when there is no vision model to read
+    // them; in hybrid mode the vision paths OCR the pages they send.
+    let Readers { ocr, renderer, image: image_settings } = readers;
+    let ocr_mode = options
+        .ocr_mode
+        .or(ocr.as_ref().map(Ocr::mode))
+        .unwrap_or_default();
+    if ocr_mode == OcrMode::Hybrid && ocr.is_none() {
+        warn!(
+            "Hybrid OCR mode requested for {} but no OCR engine is configured; using vision only",
+            doc.original_name
+        );
+    }
+    let hybrid_ocr = ocr
+        .clone()
+        .filter(|_| ocr_mode == OcrMode::Hybrid && llm.has_vision());
     let path = file_path.to_path_buf();
     let ft = file_type.clone();
+    let ocr = ocr.filter(|_| !llm.has_vision());
+    let pdf_renderer = renderer.clone();
 
     let extracted =
         tokio::task::spawn_blocking(move || -> Result<ExtractedContent, anyhow::Error> {
             match ft {
                 FileType::Pdf => {
-                    let result = pdf::extract_text(&path)?;
-                    if result.is_scanned && result.text.is_empty() {
+                    // Parsed once for the e-invoice lookup, text and page count
+                    let pdf_doc = pdf::load(&path)?;
+                    if let Some(invoice) = einvoice::from_pdf(&pdf_doc) {
+                        info!("PDF carries a {} e-invoice", invoice.format.label());
+                        return Ok(ExtractedContent::EInvoice(invoice));
+                    }
+                    let result = pdf::extract_text(&pdf_doc)?;
+                    drop(pdf_doc);
+                    if result.is_scanned {
+                        if let Some(ocr) = &ocr {
+                            let mut windows = ocr.page_windows(result.page_count);
+                            let skipped_pages = windows.skipped_pages();
+                            if !skipped_pages.is_empty() {
+                                warn!(
+                                    "Scanned PDF has {} pages; OCR reads the first {}",
+                                    result.page_count.unwrap_or_default(),
+                                    windows.limit().unwrap_or_default()
+                                );
+                            }
+                            let recognized =
+                                ocr.recognize_pdf(&path, pdf_renderer.as_ref(), &mut windows)?;
+                            info!(
+                                "OCR ({}) read {} words from scanned PDF, mean confidence {:.0}",
+                                ocr.engine().name(),
+                                recognized.words.len(),
+                                recognized.mean_confidence()
+                            );
+                            if !recognized.text.trim().is_empty() {
+                                return Ok(ExtractedContent::OcrPdf {
+                                    text: recognized.text,
+                                    skipped_pages,
+                                });
+                            }
+                        }
                         warn!("Scanned PDF detected, no text extracted. Needs LLM vision.");
                         Ok(ExtractedContent::NeedsVisionPdf(path))
-                    } else {
+                    } else if result.scanned_pages.is_empty() {
                         Ok(ExtractedContent::Text(result.text))
+                    } else if let Some(ocr) = &ocr {
+                        // Without a vision model the scanned pages are OCRed
+                        // in place, keeping the page order, up to the OCR
+                        // page limit
+                        let cap = match ocr.max_pages() {
+                            0 => result.scanned_pages.len(),
+                            cap => (cap as usize).min(result.scanned_pages.len()),
+                        };
+                        let (to_read, past_cap) = result.scanned_pages.split_at(cap);
+                        if !past_cap.is_empty() {
+                            warn!(
+                                "PDF has {} scanned pages; OCR reads the first {}",
+                                result.scanned_pages.len(),
+                                cap
+                            );
+                        }
+                        let mut pages = result.pages;
+                        let mut skipped_pages = Vec::new();
+                        for &page in to_read {
+                            match ocr.recognize_pdf_page(&path, pdf_renderer.as_ref(), page) {
+                                Ok(recognized) => pages[page as usize - 1] = recognized.text,
+                                Err(e) => {
+                                    warn!("OCR failed for page {page}: {e}");
+                                    skipped_pages.push(page);
+                                }
+                            }
+                        }
+                        skipped_pages.extend_from_slice(past_cap);
+                        Ok(ExtractedContent::OcrPdf {
+                            text: join_page_texts(&pages),
+                            skipped_pages,
+                        })
+                    } else {
+                        info!(
+                            "Mixed PDF: {} of {} pages are scanned",
+                            result.scanned_pages.len(),
+                            result.pages.len()
+                        );
+                        Ok(ExtractedContent::MixedPdf(MixedPdf {
+                            path,
+                            pages: result.pages,
+                            scanned_pages: result.scanned_pages,
+                        }))
                     }
                 }
                 FileType::Excel => {
@@ -235,14 +827,26 @@ async fn process_document(
                     Ok(ExtractedContent::Text(result.text))
                 }
                 FileType::Image => {
-                    let result = ocr::extract_text(&path)?;
+                    let result = ocr::extract_text(&path, ocr.as_ref().map(Ocr::engine))?;
                     if result.needs_llm_vision {
-                        let bytes = std::fs::read(&path)?;
-                        Ok(ExtractedContent::NeedsVisionImage(bytes))
+                        Ok(ExtractedContent::NeedsVisionImage(path))
                     } else {
                         Ok(ExtractedContent::Text(result.text))
                     }
                 }
+                FileType::Xml => {
+                    let bytes = std::fs::read(&path)?;
+                    let xml = String::from_utf8_lossy(&bytes);
+                    match einvoice::parse_xml(xml.trim_start_matches('\u{feff}')) {
+                        Some(invoice) => Ok(ExtractedContent::EInvoice(invoice)),
+                        None => Ok(ExtractedContent::Text(xml.into_owned())),
+                    }
+                }
+                FileType::Email => {
+                    // Attachments were stored as documents of their own on upload
+                    let email = email::extract(&path)?;
+                    Ok(ExtractedContent::Text(email.text()))
+                }
                 FileType::Unknown(ext) => {
                     Err(anyhow::anyhow!("Unsupported file type: .{ext}"))
                 }
@@ -252,28 +856,76 @@ async fn process_document(
 
     let extract_elapsed_ms = start.elapsed().as_millis() as i64;
 
-    match extracted {
+    // Loaded per document so type changes apply without a restart
+    let catalog = DocumentTypeCatalog::load(db)?;
+    let ctx = DocumentContext {
+        db,
+        doc,
+        llm,
+        options,
+        catalog: &catalog,
+        hybrid_ocr: hybrid_ocr.as_ref(),
+        renderer: &renderer,
+        image_settings: &image_settings,
+    };
+
+    let result = match extracted {
         ExtractedContent::Text(raw_text) => {
-            process_text_path(db, doc, llm, &raw_text, extract_elapsed_ms).await
+            process_text_path(&ctx, &raw_text, &[], extract_elapsed_ms).await
+        }
+        ExtractedContent::OcrPdf { text, skipped_pages } => {
+            process_text_path(&ctx, &text, &skipped_pages, extract_elapsed_ms).await
         }
         ExtractedContent::NeedsVisionPdf(pdf_path) => {
-            process_vision_pdf_path(db, doc, llm, &pdf_path, extract_elapsed_ms).await
+            process_vision_pdf_path(&ctx, &pdf_path, extract_elapsed_ms).await
         }
-        ExtractedContent::NeedsVisionImage(image_bytes) => {
-            process_vision_image_path(db, doc, llm, &image_bytes, extract_elapsed_ms).await
+        ExtractedContent::NeedsVisionImage(image_path) => {
+            process_vision_image_path(&ctx, &image_path, extract_elapsed_ms).await
         }
-    }
+        ExtractedContent::MixedPdf(mixed) => {
+            process_mixed_pdf_path(&ctx, &mixed, extract_elapsed_ms).await
+        }
+        ExtractedContent::EInvoice(invoice) => {
+            process_einvoice_path(&ctx, &invoice, extract_elapsed_ms)
+        }
+    };
+
+    // The extraction just written replaces any earlier run's result, also
+    // when this attempt failed
+    ExtractionDao::supersede_older(db, &doc.id)?;
+
+    result
+}
+
+/// Everything the extraction paths need to process one document.
+struct DocumentContext<'a> {
+    db: &'a DbPool,
+    doc: &'a Document,
+    llm: &'a LlmEngine,
+    options: &'a DocumentOptions,
+    catalog: &'a DocumentTypeCatalog,
+    /// OCR for the pages sent to the vision model, in hybrid mode only.
+    hybrid_ocr: Option<&'a Ocr>,
+    renderer: &'a Arc<dyn PdfRenderer>,
+    /// How images are prepared for the vision model.
+    image_settings: &'a ImageSettings,
 }
 
 /// Text path: classify → text LLM → store (existing behavior).
+///
+/// `skipped_pages` are pages of the document not in `raw_text`; the
+/// extraction is marked partial if there are any.
 async fn process_text_path(
-    db: &DbPool,
-    doc: &Document,
-    llm: &LlmEngine,
+    ctx: &DocumentContext<'_>,
     raw_text: &str,
+    skipped_pages: &[u32],
     extract_elapsed_ms: i64,
-) -> Result<String, anyhow::Error> {
-    let doc_type = classify_document_type(raw_text);
+) -> Result<(&'static str, String), anyhow::Error> {
+    let DocumentContext { db, doc, llm, options, catalog, .. } = *ctx;
+    let doc_type = options
+        .document_type
+        .as_deref()
+        .unwrap_or_else(|| catalog.classify(raw_text).name.as_str());
 
     let extraction = ExtractionDao::create(
         db,
@@ -286,28 +938,33 @@ async fn process_text_path(
         None,
         extract_elapsed_ms,
     )?;
-
-    let llm_result = llm.extract_structured(raw_text, doc_type).await;
+    let partial_note = mark_partial(db, &extraction.id, skipped_pages)?;
+
+    // An attachment's email tells the model who sent it and when
+    let prompt_text = match (&doc.parent_id, &doc.email) {
+        (Some(_), Some(metadata)) => format!(
+            "Attached to an email:\n{}\n{raw_text}",
+            email::email_header(metadata)
+        ),
+        _ => raw_text.to_string(),
+    };
+    let llm_result = llm
+        .extract_structured(&prompt_text, catalog, doc_type, options.model_name.as_deref())
+        .await;
 
     match llm_result {
         Ok(response) => {
-            ExtractionDao::update_structured(
-                db,
-                &extraction.id,
-                &response.document_type,
-                Some(&response.structured_data),
-                response.confidence,
-                Some(&response.model_used),
-                extract_elapsed_ms + response.processing_time_ms,
-            )?;
-
-            DocumentDao::update_status(db, &doc.id, "completed", None)?;
-
-            Ok(format!(
-                "Extracted {} chars, LLM structured as {} (confidence: {:.0}%)",
-                raw_text.len(),
-                response.document_type,
-                response.confidence * 100.0
+            let document_type = store_structured(ctx, &extraction.id, &response, extract_elapsed_ms)?;
+
+            Ok((
+                "completed",
+                format!(
+                    "Extracted {} chars, LLM structured as {} (confidence: {:.0}%{})",
+                    raw_text.len(),
+                    document_type,
+                    response.confidence * 100.0,
+                    partial_note
+                ),
             ))
         }
         Err(e) => {
@@ -315,11 +972,12 @@ async fn process_text_path(
                 "LLM inference failed for {}: {e}. Keeping raw text extraction.",
                 doc.original_name
             );
-            DocumentDao::update_status(db, &doc.id, "completed", None)?;
+            ExtractionDao::set_llm_attempts(db, &extraction.id, LlmCallError::attempts_of(&e))?;
+            mark_extracted_only(db, doc, &extraction.id, &e.to_string())?;
 
-            Ok(format!(
-                "Extracted {} chars (LLM unavailable: {e})",
-                raw_text.len()
+            Ok((
+                "extracted_only",
+                format!("Extracted {} chars{partial_note} (LLM unavailable: {e})", raw_text.len()),
             ))
         }
     }
@@ -327,12 +985,13 @@ async fn process_text_path(
 
 /// Vision PDF path: render pages → vision LLM → store.
 async fn process_vision_pdf_path(
-    db: &DbPool,
-    doc: &Document,
-    llm: &LlmEngine,
+    ctx: &DocumentContext<'_>,
     pdf_path: &Path,
     extract_elapsed_ms: i64,
-) -> Result<String, anyhow::Error> {
+) -> Result<(&'static str, String), anyhow::Error> {
+    let DocumentContext { db, doc, llm, options, catalog, .. } = *ctx;
+    let doc_type = options.document_type.as_deref().unwrap_or(FALLBACK_TYPE);
+
     if !llm.has_vision() {
         warn!(
             "Scanned PDF {} needs vision LLM but no vision model configured. Skipping.",
@@ -344,7 +1003,7 @@ async fn process_vision_pdf_path(
             db,
             &doc.id,
             &doc.batch_id,
-            "other",
+            doc_type,
             Some(raw_text),
             None,
             0.0,
@@ -352,33 +1011,78 @@ async fn process_vision_pdf_path(
             extract_elapsed_ms,
         )?;
 
-        DocumentDao::update_status(db, &doc.id, "completed", None)?;
+        mark_extracted_only(db, doc, &extraction.id, "Vision model not configured")?;
 
-        return Ok(format!(
-            "Scanned PDF — vision model not configured (extraction {})",
-            extraction.id
+        return Ok((
+            "extracted_only",
+            format!(
+                "Scanned PDF — vision model not configured (extraction {})",
+                extraction.id
+            ),
         ));
     }
 
     let settings = llm.settings();
     let dpi = settings.vision_dpi;
-    let max_pages = settings.vision_max_pages;
-
-    // Render PDF pages to JPEG (blocking I/O)
     let path = pdf_path.to_path_buf();
-    let rendered = tokio::task::spawn_blocking(move || {
-        pdf_render::render_pdf_pages(&path, dpi, max_pages)
+    let page_count = tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || pdf::page_count(&path)
     })
-    .await??;
+    .await?;
+    let mut windows = pdf_render::PageWindows::new(settings.vision_max_pages, page_count, settings.vision_page_cap);
+    let skipped_pages = windows.skipped_pages();
+    if !skipped_pages.is_empty() {
+        warn!(
+            "{}: sending the first {} of {} pages, the vision page cap",
+            doc.original_name,
+            settings.vision_page_cap,
+            page_count.unwrap_or_default()
+        );
+    }
+
+    // Render each window of pages to JPEG (blocking I/O), OCR it in hybrid
+    // mode and send it to the vision model before rendering the next, so
+    // only one window's images are held at once
+    let total_pages = page_count.and(windows.limit()).map(|n| n as usize);
+    let mut vision = llm.start_vision(doc_type, total_pages, options.model_name.as_deref())?;
+    let mut page_texts = Vec::new();
+    let mut rendered_pages = 0;
+    loop {
+        let renderer = ctx.renderer.clone();
+        let path = path.clone();
+        let (returned, rendered) = tokio::task::spawn_blocking(move || {
+            let rendered = windows.render_next(renderer.as_ref(), &path, dpi);
+            (windows, rendered)
+        })
+        .await?;
+        windows = returned;
+        let Some(images) = rendered?.map(|window| window.pages) else {
+            break;
+        };
 
-    let page_count = rendered.pages.len();
-    let raw_text = format!("[Vision: {} pages processed]", page_count);
+        let texts = recognize_pages(ctx.hybrid_ocr, &images).await;
+        llm.extract_vision_pages(&mut vision, &images, &texts, catalog).await;
+        rendered_pages += images.len();
+        page_texts.extend(texts);
+    }
+
+    let pages_label = match page_count {
+        Some(total) if !skipped_pages.is_empty() => format!("{rendered_pages} of {total}"),
+        _ => rendered_pages.to_string(),
+    };
+    let ocr_text = join_page_texts(&page_texts);
+    let raw_text = if ocr_text.is_empty() {
+        format!("[Vision: {pages_label} pages processed]")
+    } else {
+        ocr_text.clone()
+    };
 
     let extraction = ExtractionDao::create(
         db,
         &doc.id,
         &doc.batch_id,
-        "other",
+        doc_type,
         Some(&raw_text),
         None,
         0.0,
@@ -386,56 +1090,222 @@ async fn process_vision_pdf_path(
         extract_elapsed_ms,
     )?;
 
-    // Vision LLM extraction
-    let llm_result = llm
-        .extract_structured_with_vision(&rendered.pages, "other")
-        .await;
+    let partial_note = mark_partial(db, &extraction.id, &skipped_pages)?;
+
+    // Merge the pages' results, or fall back to the OCR text
+    let vision_result = llm.finish_vision(vision, catalog).await;
+    let llm_result = with_ocr_text_fallback(ctx, vision_result, &ocr_text).await;
 
     match llm_result {
         Ok(response) => {
-            ExtractionDao::update_structured(
-                db,
-                &extraction.id,
-                &response.document_type,
-                Some(&response.structured_data),
-                response.confidence,
-                Some(&response.model_used),
-                extract_elapsed_ms + response.processing_time_ms,
-            )?;
+            let document_type = store_structured(ctx, &extraction.id, &response, extract_elapsed_ms)?;
+
+            Ok((
+                "completed",
+                format!(
+                    "Vision: {} pages, structured as {} (confidence: {:.0}%, model: {}{})",
+                    pages_label,
+                    document_type,
+                    response.confidence * 100.0,
+                    response.model_used,
+                    partial_note
+                ),
+            ))
+        }
+        Err(e) => {
+            warn!(
+                "Vision LLM failed for {}: {e}",
+                doc.original_name
+            );
+            ExtractionDao::set_llm_attempts(db, &extraction.id, LlmCallError::attempts_of(&e))?;
+            store_pages(db, &extraction.id, LlmCallError::pages_of(&e))?;
+            mark_extracted_only(db, doc, &extraction.id, &e.to_string())?;
+
+            Ok((
+                "extracted_only",
+                format!("Vision: {pages_label} pages rendered{partial_note} (LLM failed: {e})"),
+            ))
+        }
+    }
+}
+
+/// Mixed PDF path: text pages → text LLM, scanned pages → vision LLM,
+/// results merged → store. Without a vision model only the text pages are
+/// extracted and the extraction is marked partial.
+async fn process_mixed_pdf_path(
+    ctx: &DocumentContext<'_>,
+    mixed: &MixedPdf,
+    extract_elapsed_ms: i64,
+) -> Result<(&'static str, String), anyhow::Error> {
+    let DocumentContext { db, doc, llm, options, catalog, .. } = *ctx;
+    let text = mixed.text();
+
+    if !llm.has_vision() {
+        warn!(
+            "PDF {} has scanned pages but no vision model is configured; extracting its text pages only",
+            doc.original_name
+        );
+        return process_text_path(ctx, &text, &mixed.scanned_pages, extract_elapsed_ms).await;
+    }
+
+    let settings = llm.settings();
+    let dpi = settings.vision_dpi;
+    let cap = match settings.vision_page_cap {
+        0 => mixed.scanned_pages.len(),
+        cap => (cap as usize).min(mixed.scanned_pages.len()),
+    };
+    let (scanned_pages, skipped_pages) = mixed.scanned_pages.split_at(cap);
+
+    let doc_type = options
+        .document_type
+        .as_deref()
+        .unwrap_or_else(|| catalog.classify(&text).name.as_str());
+
+    // Render only the scanned pages (blocking I/O), a window at a time, and
+    // send each window to the vision model before rendering the next
+    let mut vision =
+        llm.start_vision(doc_type, Some(scanned_pages.len()), options.model_name.as_deref())?;
+    let mut page_texts = Vec::with_capacity(scanned_pages.len());
+    for window in scanned_pages.chunks(settings.vision_max_pages.max(1) as usize) {
+        let path = mixed.path.clone();
+        let renderer = ctx.renderer.clone();
+        let pages_to_render = window.to_vec();
+        let images = tokio::task::spawn_blocking(move || {
+            pdf_render::render_pages(renderer.as_ref(), &path, dpi, &pages_to_render)
+        })
+        .await??;
+
+        let texts = recognize_pages(ctx.hybrid_ocr, &images).await;
+        llm.extract_vision_pages(&mut vision, &images, &texts, catalog).await;
+        page_texts.extend(texts);
+    }
+
+    // Raw text in page order, with OCR text or a marker for scanned pages
+    let raw_pages: Vec<String> = (1..)
+        .zip(&mixed.pages)
+        .map(|(page, page_text)| match scanned_pages.iter().position(|&p| p == page) {
+            Some(i) => match page_texts.get(i).map(|t| t.trim()).filter(|t| !t.is_empty()) {
+                Some(ocr_text) => ocr_text.to_string(),
+                None => format!("[Page {page}: scanned, read by vision]"),
+            },
+            None if mixed.scanned_pages.contains(&page) => {
+                format!("[Page {page}: scanned, skipped]")
+            }
+            None => page_text.clone(),
+        })
+        .collect();
+    let raw_text = join_page_texts(&raw_pages);
+
+    let extraction = ExtractionDao::create(
+        db,
+        &doc.id,
+        &doc.batch_id,
+        doc_type,
+        Some(&raw_text),
+        None,
+        0.0,
+        None,
+        extract_elapsed_ms,
+    )?;
+    let partial_note = mark_partial(db, &extraction.id, skipped_pages)?;
 
-            DocumentDao::update_status(db, &doc.id, "completed", None)?;
+    let llm_result = llm
+        .extract_structured_mixed(
+            &text,
+            vision,
+            scanned_pages,
+            mixed.pages.len() as u32,
+            catalog,
+            doc_type,
+        )
+        .await;
 
-            Ok(format!(
-                "Vision: {} pages, structured as {} (confidence: {:.0}%, model: {})",
-                page_count,
-                response.document_type,
-                response.confidence * 100.0,
-                response.model_used
+    match llm_result {
+        Ok(response) => {
+            let document_type = store_structured(ctx, &extraction.id, &response, extract_elapsed_ms)?;
+
+            Ok((
+                "completed",
+                format!(
+                    "Mixed PDF: {} text + {} scanned pages, structured as {} (confidence: {:.0}%, model: {}{})",
+                    mixed.pages.len() - mixed.scanned_pages.len(),
+                    scanned_pages.len(),
+                    document_type,
+                    response.confidence * 100.0,
+                    response.model_used,
+                    partial_note
+                ),
             ))
         }
         Err(e) => {
             warn!(
-                "Vision LLM failed for {}: {e}",
+                "LLM failed for mixed PDF {}: {e}",
                 doc.original_name
             );
-            DocumentDao::update_status(db, &doc.id, "completed", None)?;
+            ExtractionDao::set_llm_attempts(db, &extraction.id, LlmCallError::attempts_of(&e))?;
+            store_pages(db, &extraction.id, LlmCallError::pages_of(&e))?;
+            mark_extracted_only(db, doc, &extraction.id, &e.to_string())?;
 
-            Ok(format!(
-                "Vision: {} pages rendered (LLM failed: {e})",
-                page_count
+            Ok((
+                "extracted_only",
+                format!("Mixed PDF: {} chars extracted{partial_note} (LLM failed: {e})", text.len()),
             ))
         }
     }
 }
 
+/// E-invoice path: XML mapped to the invoice schema → store, without the LLM.
+fn process_einvoice_path(
+    ctx: &DocumentContext<'_>,
+    invoice: &EInvoice,
+    extract_elapsed_ms: i64,
+) -> Result<(&'static str, String), anyhow::Error> {
+    let DocumentContext { db, doc, catalog, .. } = *ctx;
+    let issues = validate::validate(&catalog.resolve("invoice").output_schema(), &invoice.data);
+    if !issues.is_empty() {
+        warn!(
+            "E-invoice {} is missing invoice fields: {}",
+            doc.original_name,
+            issues.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+        );
+    }
+    let validation = ValidationReport {
+        valid: issues.is_empty(),
+        errors: issues,
+        ..ValidationReport::default()
+    };
+
+    let model_used = format!("e-invoice ({})", invoice.format.label());
+    let extraction = ExtractionDao::create(
+        db,
+        &doc.id,
+        &doc.batch_id,
+        "invoice",
+        Some(&invoice.xml),
+        Some(&invoice.data),
+        1.0,
+        Some(&model_used),
+        extract_elapsed_ms,
+    )?;
+    ExtractionDao::set_validation(db, &extraction.id, &serde_json::to_value(&validation)?)?;
+
+    DocumentDao::update_status(db, &doc.id, "completed", None)?;
+
+    Ok((
+        "completed",
+        format!("Read {} e-invoice, structured as invoice (confidence: 100%)", invoice.format.label()),
+    ))
+}
+
 /// Vision image path: send image bytes → vision LLM → store.
 async fn process_vision_image_path(
-    db: &DbPool,
-    doc: &Document,
-    llm: &LlmEngine,
-    image_bytes: &[u8],
+    ctx: &DocumentContext<'_>,
+    image_path: &Path,
     extract_elapsed_ms: i64,
-) -> Result<String, anyhow::Error> {
+) -> Result<(&'static str, String), anyhow::Error> {
+    let DocumentContext { db, doc, llm, options, .. } = *ctx;
+    let doc_type = options.document_type.as_deref().unwrap_or(FALLBACK_TYPE);
+
     if !llm.has_vision() {
         warn!(
             "Image {} needs vision LLM but no vision model configured. Skipping.",
@@ -443,11 +1313,11 @@ async fn process_vision_image_path(
         );
         let raw_text = "[Image — requires vision LLM. Vision model not configured.]";
 
-        ExtractionDao::create(
+        let extraction = ExtractionDao::create(
             db,
             &doc.id,
             &doc.batch_id,
-            "other",
+            doc_type,
             Some(raw_text),
             None,
             0.0,
@@ -455,18 +1325,34 @@ async fn process_vision_image_path(
             extract_elapsed_ms,
         )?;
 
-        DocumentDao::update_status(db, &doc.id, "completed", None)?;
+        mark_extracted_only(db, doc, &extraction.id, "Vision model not configured")?;
 
-        return Ok("Image — vision model not configured".to_string());
+        return Ok((
+            "extracted_only",
+            "Image — vision model not configured".to_string(),
+        ));
     }
 
-    let raw_text = format!("[Vision: 1 image processed ({} bytes)]", image_bytes.len());
+    // Prepared only now that the image is sent to the vision model
+    let image_bytes = {
+        let path = image_path.to_path_buf();
+        let settings = ctx.image_settings.clone();
+        tokio::task::spawn_blocking(move || prepare_image(&path, &settings)).await??
+    };
+    let images = [image_bytes];
+    let page_texts = recognize_pages(ctx.hybrid_ocr, &images).await;
+    let ocr_text = join_page_texts(&page_texts);
+    let raw_text = if ocr_text.is_empty() {
+        format!("[Vision: 1 image processed ({} bytes)]", images[0].len())
+    } else {
+        ocr_text.clone()
+    };
 
     let extraction = ExtractionDao::create(
         db,
         &doc.id,
         &doc.batch_id,
-        "other",
+        doc_type,
         Some(&raw_text),
         None,
         0.0,
@@ -474,29 +1360,20 @@ async fn process_vision_image_path(
         extract_elapsed_ms,
     )?;
 
-    let llm_result = llm
-        .extract_structured_with_vision(&[image_bytes.to_vec()], "other")
-        .await;
+    let llm_result = extract_with_vision(ctx, &images, &page_texts, &ocr_text).await;
 
     match llm_result {
         Ok(response) => {
-            ExtractionDao::update_structured(
-                db,
-                &extraction.id,
-                &response.document_type,
-                Some(&response.structured_data),
-                response.confidence,
-                Some(&response.model_used),
-                extract_elapsed_ms + response.processing_time_ms,
-            )?;
-
-            DocumentDao::update_status(db, &doc.id, "completed", None)?;
-
-            Ok(format!(
-                "Vision image: structured as {} (confidence: {:.0}%, model: {})",
-                response.document_type,
-                response.confidence * 100.0,
-                response.model_used
+            let document_type = store_structured(ctx, &extraction.id, &response, extract_elapsed_ms)?;
+
+            Ok((
+                "completed",
+                format!(
+                    "Vision image: structured as {} (confidence: {:.0}%, model: {})",
+                    document_type,
+                    response.confidence * 100.0,
+                    response.model_used
+                ),
             ))
         }
         Err(e) => {
@@ -504,41 +1381,295 @@ async fn process_vision_image_path(
                 "Vision LLM failed for image {}: {e}",
                 doc.original_name
             );
-            DocumentDao::update_status(db, &doc.id, "completed", None)?;
-
-            Ok(format!("Image processed (LLM failed: {e})"))
-        }
-    }
-}
-
-/// Simple heuristic to classify document type based on extracted text.
-fn classify_document_type(text: &str) -> &'static str {
-    let lower = text.to_lowercase();
-
-    if lower.contains("invoice")
-        || lower.contains("faktura")
-        || lower.contains("bill to")
-        || lower.contains("invoice number")
-        || lower.contains("inv no")
-    {
-        "invoice"
-    } else if lower.contains("bank statement")
-        || lower.contains("account statement")
-        || lower.contains("transaction history")
-        || (lower.contains("balance") && (lower.contains("debit") || lower.contains("credit")))
-    {
-        "bank_statement"
-    } else if lower.contains("payment")
-        || lower.contains("paid")
-        || lower.contains("amount due")
-    {
-        "payment"
-    } else if lower.contains("receipt")
-        || lower.contains("cash register")
-        || (lower.contains("total") && lower.contains("tax"))
-    {
-        "receipt"
-    } else {
-        "other"
+            ExtractionDao::set_llm_attempts(db, &extraction.id, LlmCallError::attempts_of(&e))?;
+            store_pages(db, &extraction.id, LlmCallError::pages_of(&e))?;
+            mark_extracted_only(db, doc, &extraction.id, &e.to_string())?;
+
+            Ok(("extracted_only", format!("Image processed (LLM failed: {e})")))
+        }
+    }
+}
+
+/// The image as sent to the vision model, prepared per `settings` and kept
+/// next to the upload for preview. An image that cannot be prepared is sent
+/// unchanged.
+fn prepare_image(path: &Path, settings: &ImageSettings) -> Result<Vec<u8>, anyhow::Error> {
+    match image_prep::prepare_for_vision(path, settings) {
+        Ok(bytes) => {
+            if let Err(e) = std::fs::write(image_prep::processed_path(path), &bytes) {
+                warn!("Could not store the prepared image for preview: {e}");
+            }
+            Ok(bytes)
+        }
+        Err(e) => {
+            warn!(
+                "Could not prepare {} for vision, sending it unchanged: {e}",
+                path.display()
+            );
+            Ok(std::fs::read(path)?)
+        }
+    }
+}
+
+/// OCR the images about to be sent to the vision model, in hybrid mode.
+///
+/// The text only serves as a cross-check, so a page that cannot be
+/// recognized is logged and left empty instead of failing the document.
+async fn recognize_pages(ocr: Option<&Ocr>, images: &[Vec<u8>]) -> Vec<String> {
+    let Some(ocr) = ocr.cloned() else {
+        return Vec::new();
+    };
+    let images = images.to_vec();
+
+    let recognized = tokio::task::spawn_blocking(move || {
+        images
+            .iter()
+            .enumerate()
+            .map(|(i, bytes)| match ocr.recognize_encoded(bytes) {
+                Ok(result) => {
+                    debug!(
+                        "Hybrid OCR: page {} has {} words, mean confidence {:.0}",
+                        i + 1,
+                        result.words.len(),
+                        result.mean_confidence()
+                    );
+                    result.text
+                }
+                Err(e) => {
+                    warn!("Hybrid OCR failed for page {}: {e}", i + 1);
+                    String::new()
+                }
+            })
+            .collect()
+    })
+    .await;
+
+    recognized.unwrap_or_else(|e| {
+        warn!("Hybrid OCR task failed: {e}");
+        Vec::new()
+    })
+}
+
+/// The recognized text of all pages, blank-line separated.
+fn join_page_texts(page_texts: &[String]) -> String {
+    page_texts
+        .iter()
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Mark the extraction partial if pages were skipped. Returns a note for
+/// the progress message, empty if none were.
+fn mark_partial(db: &DbPool, extraction_id: &str, skipped_pages: &[u32]) -> Result<String, anyhow::Error> {
+    if skipped_pages.is_empty() {
+        return Ok(String::new());
+    }
+    ExtractionDao::set_partial(db, extraction_id, skipped_pages)?;
+    Ok(format!(", partial: skipped pages {}", page_ranges(skipped_pages)))
+}
+
+/// Page numbers as compact ranges, e.g. `[3, 4, 5, 9]` → `3-5, 9`.
+fn page_ranges(pages: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &page in pages {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == page => *end = page,
+            _ => ranges.push((page, page)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Extract with the vision model, passing each page's OCR text along in
+/// hybrid mode. If vision fails and there is OCR text, the text model
+/// extracts from that text instead.
+async fn extract_with_vision(
+    ctx: &DocumentContext<'_>,
+    images: &[Vec<u8>],
+    page_texts: &[String],
+    ocr_text: &str,
+) -> Result<LlmResponse, anyhow::Error> {
+    let DocumentContext { llm, options, catalog, .. } = *ctx;
+    let doc_type = options.document_type.as_deref().unwrap_or(FALLBACK_TYPE);
+
+    let vision_result = llm
+        .extract_structured_with_vision(
+            images,
+            page_texts,
+            catalog,
+            doc_type,
+            options.model_name.as_deref(),
+        )
+        .await;
+    with_ocr_text_fallback(ctx, vision_result, ocr_text).await
+}
+
+/// The vision model's result, or if it failed and there is OCR text, the
+/// text model's extraction from that text.
+async fn with_ocr_text_fallback(
+    ctx: &DocumentContext<'_>,
+    vision_result: Result<LlmResponse, anyhow::Error>,
+    ocr_text: &str,
+) -> Result<LlmResponse, anyhow::Error> {
+    let DocumentContext { doc, llm, options, catalog, .. } = *ctx;
+
+    let vision_error = match vision_result {
+        Ok(response) => return Ok(response),
+        Err(e) if ocr_text.is_empty() => return Err(e),
+        Err(e) => e,
+    };
+
+    warn!(
+        "Vision LLM failed for {}: {vision_error}. Extracting from the OCR text instead.",
+        doc.original_name
+    );
+    let vision_attempts = LlmCallError::attempts_of(&vision_error);
+    // The vision pages' outcome is kept whichever way the fallback goes
+    let vision_pages = LlmCallError::pages_of(&vision_error).to_vec();
+    // The model override names a vision model, so the text model is the configured one
+    let text_doc_type = options
+        .document_type
+        .as_deref()
+        .unwrap_or_else(|| catalog.classify(ocr_text).name.as_str());
+
+    match llm.extract_structured(ocr_text, catalog, text_doc_type, None).await {
+        Ok(mut response) => {
+            response.attempts += vision_attempts;
+            response.pages = vision_pages;
+            Ok(response)
+        }
+        Err(e) => Err(LlmCallError {
+            attempts: vision_attempts + LlmCallError::attempts_of(&e),
+            message: format!("{vision_error}; OCR text fallback failed: {e}"),
+            status: None,
+            pages: vision_pages,
+        }
+        .into()),
+    }
+}
+
+/// Store an LLM result in the extraction and mark the document completed.
+/// Returns the type it was stored as: a user-chosen type wins over the
+/// LLM's own classification.
+fn store_structured<'a>(
+    ctx: &DocumentContext<'a>,
+    extraction_id: &str,
+    response: &'a LlmResponse,
+    extract_elapsed_ms: i64,
+) -> Result<&'a str, anyhow::Error> {
+    let DocumentContext { db, doc, options, .. } = *ctx;
+    let document_type = options
+        .document_type
+        .as_deref()
+        .unwrap_or(&response.document_type);
+
+    ExtractionDao::update_structured(
+        db,
+        extraction_id,
+        document_type,
+        Some(&response.structured_data),
+        response.confidence,
+        Some(&response.model_used),
+        extract_elapsed_ms + response.processing_time_ms,
+    )?;
+    ExtractionDao::set_llm_attempts(db, extraction_id, response.attempts)?;
+    ExtractionDao::set_validation(db, extraction_id, &serde_json::to_value(&response.validation)?)?;
+    ExtractionDao::set_usage(
+        db,
+        extraction_id,
+        response.usage.prompt_tokens,
+        response.usage.completion_tokens,
+        response.cost_usd,
+    )?;
+    store_pages(db, extraction_id, &response.pages)?;
+
+    DocumentDao::update_status(db, &doc.id, "completed", None)?;
+    Ok(document_type)
+}
+
+/// Persist the per-page outcome of a vision extraction.
+fn store_pages(db: &DbPool, extraction_id: &str, pages: &[PageResult]) -> Result<(), anyhow::Error> {
+    for page in pages {
+        ExtractionPageDao::create(
+            db,
+            extraction_id,
+            &NewExtractionPage {
+                page_number: page.page_number,
+                status: page.status,
+                error: page.error.as_deref(),
+                raw_output: page.raw_output.as_deref(),
+                latency_ms: page.latency_ms,
+                attempts: page.attempts,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Status of a batch whose run finished with `processed` and `failed`
+/// documents, and `unprocessed` ones it never reached.
+fn final_batch_status(processed: i32, failed: i32, unprocessed: i32) -> &'static str {
+    match (processed, failed, unprocessed) {
+        (_, 0, 0) => "completed",
+        (0, 0, _) => "cancelled",
+        (0, _, _) => "failed",
+        _ => "partially_completed",
+    }
+}
+
+/// Record that a document's content was extracted but not structured by the LLM.
+fn mark_extracted_only(
+    db: &DbPool,
+    doc: &Document,
+    extraction_id: &str,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    ExtractionDao::mark_llm_failed(db, extraction_id, error)?;
+    DocumentDao::update_status(db, &doc.id, "extracted_only", Some(error))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_ranges_are_compact() {
+        assert_eq!(page_ranges(&[6, 7, 8, 9]), "6-9");
+        assert_eq!(page_ranges(&[3, 4, 5, 9, 11, 12]), "3-5, 9, 11-12");
+        assert_eq!(page_ranges(&[]), "");
+    }
+
+    #[test]
+    fn unprocessed_documents_keep_a_batch_from_completing() {
+        assert_eq!(final_batch_status(3, 0, 0), "completed");
+        assert_eq!(final_batch_status(1, 0, 2), "partially_completed");
+        assert_eq!(final_batch_status(0, 0, 2), "cancelled");
+        assert_eq!(final_batch_status(0, 1, 0), "failed");
+        assert_eq!(final_batch_status(2, 1, 0), "partially_completed");
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        let timeout = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        assert!(is_retryable(&anyhow::Error::new(timeout).context("reading upload")));
+        let llm = LlmCallError { attempts: 3, message: "503".into(), status: Some(503), pages: Vec::new() };
+        assert!(is_retryable(&llm.into()));
+
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(!is_retryable(&missing.into()));
+        assert!(!is_retryable(&anyhow::anyhow!("Unsupported file type: .xyz")));
     }
 }
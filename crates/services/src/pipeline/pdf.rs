@@ -1,55 +1,137 @@
 use std::path::Path;
 use tracing::{debug, warn};
 
-/// Extract text from a PDF file.
+/// Pages with less text than this are taken to be scanned images.
+const MIN_PAGE_TEXT_CHARS: usize = 20;
+
+/// Parse a PDF file, decrypting it if it is encrypted without a password,
+/// so the steps that read it can share one parse.
+pub fn load(file_path: &Path) -> Result<pdf_extract::Document, anyhow::Error> {
+    let mut doc = pdf_extract::Document::load(file_path)
+        .map_err(|e| anyhow::anyhow!("Could not parse PDF: {e}"))?;
+    if doc.is_encrypted() {
+        doc.decrypt("")
+            .map_err(|e| anyhow::anyhow!("Could not decrypt PDF: {e}"))?;
+    }
+    Ok(doc)
+}
+
+/// Extract text from a loaded PDF, page by page.
 ///
-/// Uses `pdf-extract` for text-based PDFs. If the extracted text is empty
-/// or very short (likely a scanned/image PDF), returns an indication
-/// that OCR is needed.
-pub fn extract_text(file_path: &Path) -> Result<ExtractedPdf, anyhow::Error> {
-    debug!("Extracting text from PDF: {}", file_path.display());
-
-    let bytes = std::fs::read(file_path)?;
-    let text = pdf_extract::extract_text_from_mem(&bytes)
-        .map_err(|e| anyhow::anyhow!("PDF text extraction failed: {e}"))?;
-
-    let trimmed = text.trim().to_string();
-
-    if trimmed.is_empty() || trimmed.len() < 20 {
-        warn!(
-            "PDF has little/no extractable text ({} chars), likely scanned",
-            trimmed.len()
+/// Uses `pdf-extract` for the text layer. Pages with little or no text
+/// (likely scanned images) are listed in `scanned_pages` so they can be
+/// OCRed or sent to the vision model on their own.
+pub fn extract_text(doc: &pdf_extract::Document) -> Result<ExtractedPdf, anyhow::Error> {
+    let page_count = doc.get_pages().len() as u32;
+    debug!("Extracting text from {page_count} PDF pages");
+
+    let mut pages = Vec::new();
+    for page in 1..=page_count {
+        let mut text = String::new();
+        let extracted = pdf_extract::output_doc_page(doc, &mut pdf_extract::PlainTextOutput::new(&mut text), page);
+        if let Err(e) = extracted {
+            debug!("Stopped extracting PDF text at page {page}: {e}");
+            break;
+        }
+        pages.push(text);
+    }
+
+    let result = classify_pages(pages, Some(page_count));
+    if result.is_scanned {
+        warn!("PDF has no page with extractable text, likely scanned");
+    } else if !result.scanned_pages.is_empty() {
+        debug!(
+            "PDF has {} scanned of {} pages: {:?}",
+            result.scanned_pages.len(),
+            result.pages.len(),
+            result.scanned_pages
         );
-        Ok(ExtractedPdf {
-            text: trimmed,
-            is_scanned: true,
-            page_count: count_pages(&bytes),
-        })
-    } else {
-        Ok(ExtractedPdf {
-            text: trimmed,
-            is_scanned: false,
-            page_count: count_pages(&bytes),
-        })
+    }
+    Ok(result)
+}
+
+/// Split extracted `pages` into text and scanned pages. Pages past the last
+/// one `pdf-extract` could read, up to `page_count`, count as scanned.
+fn classify_pages(pages: Vec<String>, page_count: Option<u32>) -> ExtractedPdf {
+    let mut pages: Vec<String> = pages.into_iter().map(|p| p.trim().to_string()).collect();
+    if let Some(count) = page_count {
+        pages.resize(pages.len().max(count as usize), String::new());
+    }
+
+    let scanned_pages: Vec<u32> = (1..)
+        .zip(&pages)
+        .filter(|(_, text)| text.len() < MIN_PAGE_TEXT_CHARS)
+        .map(|(page, _)| page)
+        .collect();
+    let text = pages
+        .iter()
+        .filter(|text| text.len() >= MIN_PAGE_TEXT_CHARS)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    ExtractedPdf {
+        is_scanned: text.is_empty(),
+        text,
+        page_count: page_count.or((!pages.is_empty()).then_some(pages.len() as u32)),
+        pages,
+        scanned_pages,
     }
 }
 
 pub struct ExtractedPdf {
+    /// Text of the text pages, blank-line separated.
     pub text: String,
+    /// No page has a text layer.
     pub is_scanned: bool,
-    pub page_count: Option<usize>,
+    pub page_count: Option<u32>,
+    /// Extracted text of each page; little or none on scanned pages.
+    pub pages: Vec<String>,
+    /// 1-based numbers of the pages without a usable text layer.
+    pub scanned_pages: Vec<u32>,
 }
 
-/// Rough page count from PDF bytes by counting "/Type /Page" occurrences.
-fn count_pages(bytes: &[u8]) -> Option<usize> {
-    let content = String::from_utf8_lossy(bytes);
-    let count = content.matches("/Type /Page").count();
-    // Subtract 1 for the /Type /Pages catalog entry (if present)
-    if count > 1 {
-        Some(count - 1)
-    } else if count == 1 {
-        Some(1)
-    } else {
-        None
+/// Number of pages of a PDF file, if it can be parsed.
+pub fn page_count(file_path: &Path) -> Option<u32> {
+    match pdf_extract::Document::load(file_path) {
+        Ok(doc) => Some(doc.get_pages().len() as u32),
+        Err(e) => {
+            warn!("Could not count the pages of {}: {e}", file_path.display());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Invoice 2024-17, total due 1.250,00 EUR";
+
+    #[test]
+    fn text_and_scanned_pages_are_told_apart() {
+        let result = classify_pages(vec![TEXT.into(), "  \n".into(), TEXT.into()], Some(3));
+
+        assert!(!result.is_scanned);
+        assert_eq!(result.scanned_pages, [2]);
+        assert_eq!(result.text, format!("{TEXT}\n\n{TEXT}"));
+    }
+
+    #[test]
+    fn pages_not_extracted_count_as_scanned() {
+        let result = classify_pages(vec![TEXT.into()], Some(3));
+
+        assert_eq!(result.pages.len(), 3);
+        assert_eq!(result.scanned_pages, [2, 3]);
+    }
+
+    #[test]
+    fn a_pdf_without_text_pages_is_scanned() {
+        let result = classify_pages(vec!["p. 1".into(), String::new()], None);
+
+        assert!(result.is_scanned);
+        assert!(result.text.is_empty());
+        assert_eq!(result.scanned_pages, [1, 2]);
+        assert_eq!(result.page_count, Some(2));
     }
 }
@@ -1,4 +1,11 @@
+use std::io::Read;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use harvex_config::{PdfRenderSettings, PdfRendererKind};
 use tracing::{debug, info, warn};
 
 /// Rendered pages from a PDF.
@@ -7,95 +14,558 @@ pub struct RenderedPages {
     pub pages: Vec<Vec<u8>>,
 }
 
-/// Render PDF pages to JPEG images using `pdftoppm` (poppler-utils).
+/// Renders PDF pages to JPEG images.
+pub trait PdfRenderer: Send + Sync {
+    /// Render the 1-based `pages` of `pdf_path` at `dpi`. Pages past the end
+    /// of the document are not rendered.
+    fn render(
+        &self,
+        pdf_path: &Path,
+        dpi: u32,
+        pages: RangeInclusive<u32>,
+    ) -> Result<RenderedPages, anyhow::Error>;
+
+    /// Short name for logs and messages.
+    fn name(&self) -> &'static str;
+}
+
+/// Create the renderer configured in `settings`. `auto` prefers pdftoppm and
+/// falls back to pdfium when pdftoppm is not installed.
+pub fn create_renderer(settings: &PdfRenderSettings) -> Arc<dyn PdfRenderer> {
+    let page_timeout = Duration::from_secs(settings.page_timeout_secs.max(1));
+    let pdftoppm = Arc::new(PdftoppmRenderer { page_timeout });
+
+    match settings.renderer {
+        PdfRendererKind::Pdftoppm => pdftoppm,
+        PdfRendererKind::Auto if pdftoppm_installed() => pdftoppm,
+        #[cfg(feature = "pdfium")]
+        PdfRendererKind::Auto | PdfRendererKind::Pdfium => {
+            if settings.renderer == PdfRendererKind::Auto {
+                info!("pdftoppm not found, rendering PDFs with pdfium");
+            }
+            Arc::new(PdfiumRenderer {
+                library_path: (!settings.pdfium_library_path.is_empty())
+                    .then(|| settings.pdfium_library_path.clone()),
+                page_timeout,
+            })
+        }
+        #[cfg(not(feature = "pdfium"))]
+        PdfRendererKind::Auto => {
+            warn!("pdftoppm not found and harvex was built without the pdfium feature; scanned PDFs cannot be rendered");
+            pdftoppm
+        }
+        #[cfg(not(feature = "pdfium"))]
+        PdfRendererKind::Pdfium => {
+            warn!("PDF renderer 'pdfium' is configured but harvex was built without the pdfium feature; using pdftoppm");
+            pdftoppm
+        }
+    }
+}
+
+fn pdftoppm_installed() -> bool {
+    Command::new("pdftoppm")
+        .arg("-v")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+/// Renders a PDF `window` pages at a time, up to `page_cap` pages (0 = all
+/// of them), so each window can be processed and dropped before the next
+/// one is rendered.
 ///
-/// # Arguments
-/// * `pdf_path` - Path to the PDF file
-/// * `dpi` - Resolution for rendering (e.g. 200)
-/// * `max_pages` - Maximum number of pages to render
-pub fn render_pdf_pages(
+/// Without a `page_count`, windows are rendered until one comes back short
+/// or fails past the first page, and no pages are reported as skipped.
+pub struct PageWindows {
+    window: u32,
+    page_count: Option<u32>,
+    cap: Option<u32>,
+    first: u32,
+    done: bool,
+}
+
+impl PageWindows {
+    pub fn new(window: u32, page_count: Option<u32>, page_cap: u32) -> Self {
+        Self {
+            window: window.max(1),
+            page_count,
+            cap: (page_cap > 0).then_some(page_cap),
+            first: 1,
+            done: false,
+        }
+    }
+
+    /// Pages in the document, when it could be counted.
+    pub fn page_count(&self) -> Option<u32> {
+        self.page_count
+    }
+
+    /// Pages that will be rendered, when known.
+    pub fn limit(&self) -> Option<u32> {
+        match (self.page_count, self.cap) {
+            (Some(count), Some(cap)) => Some(count.min(cap)),
+            (count, cap) => count.or(cap),
+        }
+    }
+
+    /// 1-based numbers of the pages past `page_cap`, which are not rendered.
+    pub fn skipped_pages(&self) -> Vec<u32> {
+        match (self.page_count, self.cap) {
+            (Some(count), Some(cap)) if count > cap => (cap + 1..=count).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Render the next window, or return `None` once all are rendered.
+    pub fn render_next(
+        &mut self,
+        renderer: &dyn PdfRenderer,
+        pdf_path: &Path,
+        dpi: u32,
+    ) -> Result<Option<RenderedPages>, anyhow::Error> {
+        let limit = self.limit();
+        let first = self.first;
+        if self.done || limit.is_some_and(|limit| first > limit) {
+            return Ok(None);
+        }
+
+        let last = limit.map_or(first + self.window - 1, |limit| (first + self.window - 1).min(limit));
+        let rendered = match renderer.render(pdf_path, dpi, first..=last) {
+            Ok(rendered) => rendered.pages,
+            // Past the end of a document that could not be counted
+            Err(e) if self.page_count.is_none() && first > 1 => {
+                debug!("Stopped rendering {} at page {first}: {e}", pdf_path.display());
+                self.done = true;
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if self.page_count.is_none() && (rendered.len() as u32) < last - first + 1 {
+            self.done = true;
+        }
+        self.first = last + 1;
+        Ok((!rendered.is_empty()).then_some(RenderedPages { pages: rendered }))
+    }
+}
+
+/// Render the given 1-based `pages` of `pdf_path`, one at a time, e.g. the
+/// scanned pages of a PDF that otherwise has text.
+pub fn render_pages(
+    renderer: &dyn PdfRenderer,
     pdf_path: &Path,
     dpi: u32,
-    max_pages: u32,
-) -> Result<RenderedPages, anyhow::Error> {
-    let tmp_dir = tempfile::TempDir::new()?;
-    let output_prefix = tmp_dir.path().join("page");
-
-    debug!(
-        "Rendering PDF pages: path={}, dpi={}, max_pages={}",
-        pdf_path.display(),
-        dpi,
-        max_pages
-    );
-
-    let output = std::process::Command::new("pdftoppm")
-        .args([
-            "-jpeg",
-            "-jpegopt",
-            "quality=85",
-            "-r",
-            &dpi.to_string(),
-            "-l",
-            &max_pages.to_string(),
-        ])
-        .arg(pdf_path)
-        .arg(&output_prefix)
-        .output()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                anyhow::anyhow!(
-                    "pdftoppm not found. Install poppler-utils: apt-get install poppler-utils"
-                )
-            } else {
-                anyhow::anyhow!("Failed to run pdftoppm: {e}")
-            }
-        })?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow::anyhow!("pdftoppm failed: {stderr}"));
-    }
-
-    // pdftoppm outputs files like: page-1.jpg, page-2.jpg, ...
-    // or page-01.jpg, page-02.jpg, ... depending on page count
-    let mut page_files: Vec<_> = std::fs::read_dir(tmp_dir.path())?
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| {
-            entry
-                .path()
-                .extension()
-                .is_some_and(|ext| ext == "jpg")
+    pages: &[u32],
+) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+    pages
+        .iter()
+        .map(|&page| {
+            renderer
+                .render(pdf_path, dpi, page..=page)?
+                .pages
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Page {page} was not rendered"))
         })
-        .collect();
+        .collect()
+}
+
+/// Start of pdftoppm's error for a first page after the document's last.
+const PAST_LAST_PAGE: &str = "Wrong page range given";
+
+/// Renders with `pdftoppm` (poppler-utils), one process per page so each
+/// page can be given its own time limit.
+pub struct PdftoppmRenderer {
+    page_timeout: Duration,
+}
+
+impl PdfRenderer for PdftoppmRenderer {
+    fn render(
+        &self,
+        pdf_path: &Path,
+        dpi: u32,
+        pages: RangeInclusive<u32>,
+    ) -> Result<RenderedPages, anyhow::Error> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let output_prefix = tmp_dir.path().join("page");
+        let (first, last) = (*pages.start(), *pages.end());
+
+        debug!(
+            "Rendering PDF pages: path={}, dpi={}, pages={}-{}",
+            pdf_path.display(),
+            dpi,
+            first,
+            last
+        );
+
+        // Callers clamp the range to the document when they know its page
+        // count; otherwise pdftoppm reports the first page past the end
+        for page in first..=last {
+            match self.run(pdf_path, dpi, page, page, &output_prefix, self.page_timeout) {
+                Ok(()) => {}
+                Err(e) if page > first && e.to_string().contains(PAST_LAST_PAGE) => break,
+                Err(e) => return Err(anyhow::anyhow!("Page {page}: {e}")),
+            }
+        }
+
+        // pdftoppm outputs files like: page-1.jpg, page-2.jpg, ...
+        // or page-01.jpg, page-02.jpg, ... depending on page count
+        let mut page_files: Vec<_> = std::fs::read_dir(tmp_dir.path())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "jpg")
+            })
+            .collect();
+
+        // Sort by page number to ensure correct page order; rendered one at a
+        // time, the numbers are not zero-padded to the same width
+        page_files.sort_by_key(|e| page_order(&e.file_name().to_string_lossy()));
+
+        let mut pages = Vec::new();
+        for entry in &page_files {
+            let bytes = std::fs::read(entry.path())?;
+            debug!(
+                "Rendered page: {} ({} bytes)",
+                entry.file_name().to_string_lossy(),
+                bytes.len()
+            );
+            pages.push(bytes);
+        }
+
+        if pages.is_empty() {
+            warn!("pdftoppm produced no output files for {}", pdf_path.display());
+            return Err(anyhow::anyhow!(
+                "pdftoppm produced no output for {}",
+                pdf_path.display()
+            ));
+        }
+
+        info!(
+            "Rendered {} pages from {} (dpi={})",
+            pages.len(),
+            pdf_path.display(),
+            dpi
+        );
+
+        Ok(RenderedPages { pages })
+    }
+
+    fn name(&self) -> &'static str {
+        "pdftoppm"
+    }
+}
+
+impl PdftoppmRenderer {
+    /// Render pages `first..=last`, killing pdftoppm after `timeout`.
+    fn run(
+        &self,
+        pdf_path: &Path,
+        dpi: u32,
+        first: u32,
+        last: u32,
+        output_prefix: &Path,
+        timeout: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let mut child = Command::new("pdftoppm")
+            .args([
+                "-jpeg",
+                "-jpegopt",
+                "quality=85",
+                "-r",
+                &dpi.to_string(),
+                "-f",
+                &first.to_string(),
+                "-l",
+                &last.to_string(),
+            ])
+            .arg(pdf_path)
+            .arg(output_prefix)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    anyhow::anyhow!(
+                        "pdftoppm not found. Install poppler-utils (apt-get install poppler-utils) or build with the pdfium feature"
+                    )
+                } else {
+                    anyhow::anyhow!("Failed to run pdftoppm: {e}")
+                }
+            })?;
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow::anyhow!(
+                    "pdftoppm timed out after {}s",
+                    timeout.as_secs()
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr);
+            }
+            return Err(anyhow::anyhow!("pdftoppm failed: {}", stderr.trim()));
+        }
+        Ok(())
+    }
+}
+
+/// Sort key of a pdftoppm output file: its page number, then its name.
+fn page_order(file_name: &str) -> (u32, String) {
+    let number = file_name
+        .trim_start_matches("page-")
+        .trim_end_matches(".jpg")
+        .parse()
+        .unwrap_or(u32::MAX);
+    (number, file_name.to_string())
+}
+
+/// Renders in process with pdfium, for hosts without poppler-utils.
+#[cfg(feature = "pdfium")]
+pub struct PdfiumRenderer {
+    library_path: Option<String>,
+    page_timeout: Duration,
+}
 
-    // Sort by filename to ensure correct page order
-    page_files.sort_by_key(|e| e.file_name());
+/// pdfium is not safe to use from several threads at once.
+#[cfg(feature = "pdfium")]
+static PDFIUM_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
-    let mut pages = Vec::new();
-    for entry in &page_files {
-        let bytes = std::fs::read(entry.path())?;
+#[cfg(feature = "pdfium")]
+impl PdfRenderer for PdfiumRenderer {
+    fn render(
+        &self,
+        pdf_path: &Path,
+        dpi: u32,
+        pages: RangeInclusive<u32>,
+    ) -> Result<RenderedPages, anyhow::Error> {
+        use std::sync::mpsc;
+
+        let first = *pages.start();
         debug!(
-            "Rendered page: {} ({} bytes)",
-            entry.file_name().to_string_lossy(),
-            bytes.len()
+            "Rendering PDF pages with pdfium: path={}, dpi={}, pages={}-{}",
+            pdf_path.display(),
+            dpi,
+            pages.start(),
+            pages.end()
         );
-        pages.push(bytes);
+
+        // pdfium cannot be interrupted, so it renders on its own thread and a
+        // page that takes too long is abandoned there
+        let (tx, rx) = mpsc::channel();
+        let library_path = self.library_path.clone();
+        let path = pdf_path.to_path_buf();
+        std::thread::spawn(move || {
+            let _lock = PDFIUM_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = render_with_pdfium(library_path.as_deref(), &path, dpi, pages, &tx) {
+                let _ = tx.send(Err(e));
+            }
+        });
+
+        let mut rendered = Vec::new();
+        loop {
+            match rx.recv_timeout(self.page_timeout) {
+                Ok(Ok(page)) => rendered.push(page),
+                Ok(Err(e)) => return Err(e),
+                // The thread is done: every page was sent
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    return Err(anyhow::anyhow!(
+                        "Page {}: pdfium timed out after {}s",
+                        first + rendered.len() as u32,
+                        self.page_timeout.as_secs()
+                    ));
+                }
+            }
+        }
+
+        if rendered.is_empty() {
+            return Err(anyhow::anyhow!("pdfium rendered no pages for {}", pdf_path.display()));
+        }
+
+        info!(
+            "Rendered {} pages from {} with pdfium (dpi={})",
+            rendered.len(),
+            pdf_path.display(),
+            dpi
+        );
+
+        Ok(RenderedPages { pages: rendered })
+    }
+
+    fn name(&self) -> &'static str {
+        "pdfium"
+    }
+}
+
+/// Render pages one by one, sending each as JPEG as soon as it is done.
+#[cfg(feature = "pdfium")]
+fn render_with_pdfium(
+    library_path: Option<&str>,
+    pdf_path: &Path,
+    dpi: u32,
+    pages: RangeInclusive<u32>,
+    tx: &std::sync::mpsc::Sender<Result<Vec<u8>, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    use pdfium_render::prelude::*;
+
+    let bindings = match library_path {
+        Some(path) => Pdfium::bind_to_library(path),
+        None => Pdfium::bind_to_system_library(),
+    }
+    .map_err(|e| anyhow::anyhow!("Failed to load the pdfium library: {e}"))?;
+    let pdfium = Pdfium::new(bindings);
+
+    let document = pdfium
+        .load_pdf_from_file(pdf_path, None)
+        .map_err(|e| anyhow::anyhow!("pdfium could not open the PDF: {e}"))?;
+    // PDF user space is 72 units per inch
+    let config = PdfRenderConfig::new().scale_page_by_factor(dpi as f32 / 72.0);
+
+    let first = pages.start().saturating_sub(1) as usize;
+    let count = (pages.end() + 1).saturating_sub(*pages.start()) as usize;
+    for (index, page) in document.pages().iter().enumerate().skip(first).take(count) {
+        let image = page
+            .render_with_config(&config)
+            .map_err(|e| anyhow::anyhow!("pdfium failed on page {}: {e}", index + 1))?
+            .as_image();
+
+        let mut jpeg = Vec::new();
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 85);
+        image.to_rgb8().write_with_encoder(encoder)?;
+
+        // The receiver gave up (timeout): stop rendering
+        if tx.send(Ok(jpeg)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_sort_by_number_not_name() {
+        let mut names = vec!["page-10.jpg", "page-9.jpg", "page-01.jpg", "page-2.jpg"];
+        names.sort_by_key(|n| page_order(n));
+        assert_eq!(names, ["page-01.jpg", "page-2.jpg", "page-9.jpg", "page-10.jpg"]);
+    }
+
+    /// Renders a fake document of `pages` pages, recording the ranges asked for.
+    struct FakeRenderer {
+        pages: u32,
+        calls: std::sync::Mutex<Vec<(u32, u32)>>,
     }
 
-    if pages.is_empty() {
-        warn!("pdftoppm produced no output files for {}", pdf_path.display());
-        return Err(anyhow::anyhow!(
-            "pdftoppm produced no output for {}",
-            pdf_path.display()
-        ));
+    impl FakeRenderer {
+        fn new(pages: u32) -> Self {
+            Self { pages, calls: Default::default() }
+        }
+
+        fn calls(&self) -> Vec<(u32, u32)> {
+            self.calls.lock().unwrap().clone()
+        }
     }
 
-    info!(
-        "Rendered {} pages from {} (dpi={})",
-        pages.len(),
-        pdf_path.display(),
-        dpi
-    );
+    impl PdfRenderer for FakeRenderer {
+        fn render(
+            &self,
+            _pdf_path: &Path,
+            _dpi: u32,
+            pages: RangeInclusive<u32>,
+        ) -> Result<RenderedPages, anyhow::Error> {
+            self.calls.lock().unwrap().push((*pages.start(), *pages.end()));
+            if *pages.start() > self.pages {
+                anyhow::bail!("Wrong page range given");
+            }
+            let rendered = (*pages.start()..=(*pages.end()).min(self.pages))
+                .map(|page| vec![page as u8])
+                .collect();
+            Ok(RenderedPages { pages: rendered })
+        }
+
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+    }
+
+    /// Render every window, returning the pages of each.
+    fn render_all(renderer: &FakeRenderer, windows: &mut PageWindows) -> Vec<Vec<Vec<u8>>> {
+        let mut rendered = Vec::new();
+        while let Some(window) = windows.render_next(renderer, Path::new("a.pdf"), 200).unwrap() {
+            rendered.push(window.pages);
+        }
+        rendered
+    }
 
-    Ok(RenderedPages { pages })
+    #[test]
+    fn all_pages_are_rendered_in_windows() {
+        let renderer = FakeRenderer::new(12);
+        let mut windows = PageWindows::new(5, Some(12), 0);
+        let rendered = render_all(&renderer, &mut windows);
+
+        assert_eq!(renderer.calls(), [(1, 5), (6, 10), (11, 12)]);
+        assert_eq!(rendered.len(), 3);
+        assert_eq!(rendered[2], [[11], [12]]);
+        assert!(windows.skipped_pages().is_empty());
+    }
+
+    #[test]
+    fn pages_past_the_cap_are_skipped() {
+        let renderer = FakeRenderer::new(9);
+        let mut windows = PageWindows::new(5, Some(9), 7);
+        let rendered = render_all(&renderer, &mut windows);
+
+        assert_eq!(renderer.calls(), [(1, 5), (6, 7)]);
+        assert_eq!(rendered.iter().map(Vec::len).sum::<usize>(), 7);
+        assert_eq!(windows.limit(), Some(7));
+        assert_eq!(windows.skipped_pages(), [8, 9]);
+    }
+
+    #[test]
+    fn uncounted_documents_render_until_the_end() {
+        let renderer = FakeRenderer::new(10);
+        let mut windows = PageWindows::new(5, None, 0);
+        let rendered = render_all(&renderer, &mut windows);
+
+        // The last full window cannot tell it was the last
+        assert_eq!(renderer.calls(), [(1, 5), (6, 10), (11, 15)]);
+        assert_eq!(rendered.iter().map(Vec::len).sum::<usize>(), 10);
+        assert!(windows.skipped_pages().is_empty());
+    }
+
+    #[test]
+    fn single_pages_are_rendered_in_the_order_given() {
+        let renderer = FakeRenderer::new(6);
+        let pages = render_pages(&renderer, Path::new("a.pdf"), 200, &[2, 5]).unwrap();
+
+        assert_eq!(renderer.calls(), [(2, 2), (5, 5)]);
+        assert_eq!(pages, [vec![2], vec![5]]);
+        assert!(render_pages(&renderer, Path::new("a.pdf"), 200, &[7]).is_err());
+    }
+
+    #[test]
+    fn explicit_pdftoppm_is_used_as_configured() {
+        let settings = PdfRenderSettings {
+            renderer: PdfRendererKind::Pdftoppm,
+            ..PdfRenderSettings::default()
+        };
+        assert_eq!(create_renderer(&settings).name(), "pdftoppm");
+    }
 }
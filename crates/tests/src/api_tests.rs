@@ -46,6 +46,27 @@ mod batch_api {
         assert_eq!(json["model_name"], "qwen2.5:3b");
     }
 
+    #[tokio::test]
+    async fn create_batch_with_ocr_mode() {
+        let app = TestApp::new();
+        let (status, json) = app
+            .post(
+                "/api/batch",
+                &serde_json::json!({ "name": "Hybrid Batch", "ocr_mode": "hybrid" }),
+            )
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(json["ocr_mode"], "hybrid");
+
+        let (status, _) = app
+            .post(
+                "/api/batch",
+                &serde_json::json!({ "name": "Bad Batch", "ocr_mode": "ocr-only" }),
+            )
+            .await;
+        assert_eq!(status, 400);
+    }
+
     #[tokio::test]
     async fn list_batches_empty() {
         let app = TestApp::new();
@@ -75,6 +96,9 @@ mod batch_api {
         assert_eq!(status, 200);
         assert_eq!(json["id"], id);
         assert_eq!(json["name"], "Get Me");
+        assert_eq!(json["prompt_tokens"], 0);
+        assert_eq!(json["completion_tokens"], 0);
+        assert_eq!(json["cost_usd"], 0.0);
     }
 
     #[tokio::test]
@@ -105,6 +129,128 @@ mod batch_api {
         assert_eq!(status, 404);
     }
 
+    #[tokio::test]
+    async fn cancel_idle_batch() {
+        let app = TestApp::new();
+        let (batch_id, doc_id) = app
+            .upload_test_file("cancel.pdf", b"content", "Cancel Test")
+            .await;
+
+        let (status, json) = app
+            .post(&format!("/api/batch/{batch_id}/cancel"), &serde_json::json!({}))
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(json["status"], "cancelled");
+
+        let (_, batch) = app.get(&format!("/api/batch/{batch_id}")).await;
+        assert_eq!(batch["status"], "cancelled");
+
+        let (_, doc) = app.get(&format!("/api/document/{doc_id}")).await;
+        assert_eq!(doc["status"], "cancelled");
+    }
+
+    #[tokio::test]
+    async fn cancel_completed_batch_rejected() {
+        let app = TestApp::new();
+        let id = app.create_batch("Done").await;
+        harvex_services::BatchDao::update_status(&app.db, &id, "completed").unwrap();
+
+        let (status, _) = app
+            .post(&format!("/api/batch/{id}/cancel"), &serde_json::json!({}))
+            .await;
+        assert_eq!(status, 409);
+
+        let (_, batch) = app.get(&format!("/api/batch/{id}")).await;
+        assert_eq!(batch["status"], "completed");
+    }
+
+    #[tokio::test]
+    async fn pause_idle_batch_rejected() {
+        let app = TestApp::new();
+        let id = app.create_batch("Pause Me").await;
+
+        let (status, _) = app
+            .post(&format!("/api/batch/{id}/pause"), &serde_json::json!({}))
+            .await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn resume_unpaused_batch_rejected() {
+        let app = TestApp::new();
+        let id = app.create_batch("Resume Me").await;
+
+        let (status, _) = app
+            .post(&format!("/api/batch/{id}/resume"), &serde_json::json!({}))
+            .await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn cancel_nonexistent_batch() {
+        let app = TestApp::new();
+        let (status, _) = app
+            .post("/api/batch/nonexistent/cancel", &serde_json::json!({}))
+            .await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn retry_failed_without_failures() {
+        let app = TestApp::new();
+        let id = app.create_batch("Retry Me").await;
+
+        let (status, json) = app
+            .post(&format!("/api/batch/{id}/retry-failed"), &serde_json::json!({}))
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(json["queued"], 0);
+    }
+
+    #[tokio::test]
+    async fn retry_failed_applies_overrides_and_keeps_paused_batch_paused() {
+        let app = TestApp::new();
+        let (batch_id, doc_id) = app
+            .upload_test_file("retry.pdf", b"retry content", "Retry Overrides")
+            .await;
+        harvex_services::DocumentDao::update_status(&app.db, &doc_id, "failed", Some("boom")).unwrap();
+        harvex_services::BatchDao::update_status(&app.db, &batch_id, "paused").unwrap();
+
+        let (status, json) = app
+            .post(
+                &format!("/api/batch/{batch_id}/retry-failed"),
+                &serde_json::json!({"model_name": "other-model", "document_type": "receipt"}),
+            )
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(json["status"], "paused");
+        assert_eq!(json["queued"], 1);
+
+        let jobs = harvex_services::JobDao::list_by_batch(&app.db, &batch_id).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].status, "queued");
+        assert_eq!(jobs[0].model_override.as_deref(), Some("other-model"));
+        assert_eq!(jobs[0].document_type_override.as_deref(), Some("receipt"));
+
+        // No worker was started for the paused batch
+        let (_, json) = app.get(&format!("/api/batch/{batch_id}")).await;
+        assert_eq!(json["status"], "paused");
+    }
+
+    #[tokio::test]
+    async fn retry_failed_rejects_unknown_document_type() {
+        let app = TestApp::new();
+        let id = app.create_batch("Retry Unknown Type").await;
+
+        let (status, _) = app
+            .post(
+                &format!("/api/batch/{id}/retry-failed"),
+                &serde_json::json!({"document_type": "no_such_type"}),
+            )
+            .await;
+        assert_eq!(status, 400);
+    }
+
     #[tokio::test]
     async fn process_nonexistent_batch() {
         let app = TestApp::new();
@@ -117,7 +263,8 @@ mod batch_api {
 
 #[cfg(test)]
 mod document_api {
-    use crate::helpers::TestApp;
+    use crate::helpers::{page_result, TestApp};
+    use harvex_services::{DocumentDao, ExtractionDao, ExtractionPageDao};
 
     #[tokio::test]
     async fn upload_single_file() {
@@ -160,6 +307,81 @@ mod document_api {
         assert_eq!(docs[0]["original_name"], "a.pdf");
     }
 
+    #[tokio::test]
+    async fn upload_zip_expands_into_documents() {
+        use std::io::Write;
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for name in ["march/a.pdf", "b.pdf"] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(b"fake pdf content").unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let app = TestApp::new();
+        let (batch_id, _) = app
+            .upload_test_file("receipts.zip", &data, "Zip Upload")
+            .await;
+
+        let (_, json) = app.get(&format!("/api/batch/{batch_id}")).await;
+        assert_eq!(json["total_files"], 2);
+
+        let (_, json) = app
+            .get(&format!("/api/document?batch_id={batch_id}"))
+            .await;
+        let mut names: Vec<&str> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["original_name"].as_str().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["b.pdf", "march/a.pdf"]);
+    }
+
+    #[tokio::test]
+    async fn upload_email_stores_attachments_as_children() {
+        let eml = concat!(
+            "From: billing@acme.example\r\n",
+            "Subject: Invoice INV-7\r\n",
+            "Date: Tue, 5 Mar 2024 10:15:00 +0000\r\n",
+            "Content-Type: multipart/mixed; boundary=\"b1\"\r\n",
+            "\r\n",
+            "--b1\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "Invoice attached.\r\n",
+            "--b1\r\n",
+            "Content-Type: application/pdf\r\n",
+            "Content-Disposition: attachment; filename=\"INV-7.pdf\"\r\n",
+            "\r\n",
+            "fake pdf content\r\n",
+            "--b1--\r\n",
+        );
+
+        let app = TestApp::new();
+        let (batch_id, email_id) = app
+            .upload_test_file("invoice.eml", eml.as_bytes(), "Email Upload")
+            .await;
+
+        let (_, json) = app.get(&format!("/api/batch/{batch_id}")).await;
+        assert_eq!(json["total_files"], 2);
+
+        let (_, json) = app
+            .get(&format!("/api/document?batch_id={batch_id}"))
+            .await;
+        let docs = json.as_array().unwrap();
+        let attachment = docs
+            .iter()
+            .find(|d| d["original_name"] == "INV-7.pdf")
+            .unwrap();
+        assert_eq!(attachment["parent_id"], email_id);
+        assert_eq!(attachment["email"]["from"], "billing@acme.example");
+        assert_eq!(attachment["email"]["subject"], "Invoice INV-7");
+    }
+
     #[tokio::test]
     async fn get_document() {
         let app = TestApp::new();
@@ -173,6 +395,53 @@ mod document_api {
         assert_eq!(json["original_name"], "get.pdf");
     }
 
+    #[tokio::test]
+    async fn list_documents_filtered_by_status() {
+        let app = TestApp::new();
+        let (batch_id, doc_id) = app
+            .upload_test_file("b.pdf", b"bbb", "Status Filter")
+            .await;
+        harvex_services::DocumentDao::update_status(&app.db, &doc_id, "extracted_only", Some("LLM down"))
+            .unwrap();
+
+        let (_, json) = app
+            .get(&format!("/api/document?batch_id={batch_id}&status=extracted_only"))
+            .await;
+        let docs = json.as_array().unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0]["error_message"], "LLM down");
+
+        let (_, json) = app
+            .get(&format!("/api/document?batch_id={batch_id}&status=completed"))
+            .await;
+        assert!(json.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn preview_prepared_image() {
+        let app = TestApp::new();
+        let (_, doc_id) = app
+            .upload_test_file("receipt.jpg", b"not really a jpeg", "Preview Test")
+            .await;
+        let uri = format!("/api/document/{doc_id}/preview");
+
+        // Nothing to show before the image was processed
+        let (status, _) = app.get(&uri).await;
+        assert_eq!(status, 404);
+
+        let (_, doc) = app.get(&format!("/api/document/{doc_id}")).await;
+        let prepared = format!("{}.processed.jpg", doc["file_path"].as_str().unwrap());
+        std::fs::write(&prepared, b"prepared").unwrap();
+
+        let req = axum::http::Request::builder()
+            .uri(&uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let (status, body) = app.request(req).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, b"prepared");
+    }
+
     #[tokio::test]
     async fn get_nonexistent_document() {
         let app = TestApp::new();
@@ -180,6 +449,67 @@ mod document_api {
         assert_eq!(status, 404);
     }
 
+    #[tokio::test]
+    async fn reprocess_updates_queued_job_and_rejects_leased_one() {
+        use harvex_services::{BatchDao, JobDao};
+
+        let app = TestApp::new();
+        let (batch_id, doc_id) = app
+            .upload_test_file("queued.pdf", b"queued content", "Open Job")
+            .await;
+        BatchDao::update_status(&app.db, &batch_id, "paused").unwrap();
+        let queued = JobDao::enqueue(&app.db, &batch_id, &doc_id, 3).unwrap();
+
+        // A queued job takes the new overrides
+        let (status, json) = app
+            .post(
+                &format!("/api/document/{doc_id}/reprocess"),
+                &serde_json::json!({"model_name": "other-model"}),
+            )
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(json["job_id"], queued.id);
+        let job = JobDao::get_by_id(&app.db, &queued.id).unwrap();
+        assert_eq!(job.model_override.as_deref(), Some("other-model"));
+
+        // A job being processed cannot change its settings
+        JobDao::lease_next(&app.db, &batch_id, 60).unwrap().unwrap();
+        let (status, _) = app
+            .post(
+                &format!("/api/document/{doc_id}/reprocess"),
+                &serde_json::json!({"model_name": "third-model"}),
+            )
+            .await;
+        assert_eq!(status, 409);
+        let job = JobDao::get_by_id(&app.db, &queued.id).unwrap();
+        assert_eq!(job.model_override.as_deref(), Some("other-model"));
+    }
+
+    #[tokio::test]
+    async fn reprocess_nonexistent_document() {
+        let app = TestApp::new();
+        let (status, _) = app
+            .post("/api/document/nonexistent/reprocess", &serde_json::json!({}))
+            .await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn reprocess_rejects_unknown_document_type() {
+        let app = TestApp::new();
+        let (_, doc_id) = app
+            .upload_test_file("re.pdf", b"content", "Reprocess Test")
+            .await;
+
+        let (status, _) = app
+            .post(
+                &format!("/api/document/{doc_id}/reprocess"),
+                &serde_json::json!({"document_type": "spaceship"}),
+            )
+            .await;
+        assert_eq!(status, 400);
+    }
+
     #[tokio::test]
     async fn delete_document() {
         let app = TestApp::new();
@@ -200,13 +530,156 @@ mod document_api {
             .get(&format!("/api/document?batch_id={batch_id}"))
             .await;
         assert!(list_json.as_array().unwrap().is_empty());
+
+        let (_, batch) = app.get(&format!("/api/batch/{batch_id}")).await;
+        assert_eq!(batch["total_files"], 0);
+    }
+
+    #[tokio::test]
+    async fn delete_document_being_processed_is_rejected() {
+        use harvex_services::JobDao;
+
+        let app = TestApp::new();
+        let (batch_id, doc_id) = app
+            .upload_test_file("busy.pdf", b"busy content", "Busy Doc Test")
+            .await;
+        JobDao::enqueue(&app.db, &batch_id, &doc_id, 3).unwrap();
+        JobDao::lease_next(&app.db, &batch_id, 60).unwrap().unwrap();
+
+        let (status, _) = app.delete(&format!("/api/document/{doc_id}")).await;
+        assert_eq!(status, 409);
+        let (status, _) = app.get(&format!("/api/document/{doc_id}")).await;
+        assert_eq!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn delete_document_removes_extractions_and_attachments() {
+        let app = TestApp::new();
+        let (batch_id, doc_id) = app
+            .upload_test_file("mail.pdf", b"mail content", "Del Parent Test")
+            .await;
+        let attachment = DocumentDao::create(
+            &app.db, &batch_id, "a.pdf", "a.pdf", "application/pdf", 3, "/nonexistent/a.pdf",
+        )
+        .unwrap();
+        DocumentDao::set_source(&app.db, &attachment.id, Some(&doc_id), None).unwrap();
+        let extraction = ExtractionDao::create(
+            &app.db, &doc_id, &batch_id, "other", Some("hi"), None, 0.0, None, 0,
+        )
+        .unwrap();
+        ExtractionPageDao::create(&app.db, &extraction.id, &page_result(1, "ok")).unwrap();
+        ExtractionDao::create(&app.db, &attachment.id, &batch_id, "invoice", None, None, 0.0, None, 0)
+            .unwrap();
+
+        let (status, _) = app.delete(&format!("/api/document/{doc_id}")).await;
+        assert_eq!(status, 200);
+
+        let (status, _) = app.get(&format!("/api/document/{}", attachment.id)).await;
+        assert_eq!(status, 404);
+        assert!(ExtractionDao::list_by_batch(&app.db, &batch_id).unwrap().is_empty());
+        assert!(ExtractionPageDao::list_by_extraction(&app.db, &extraction.id).unwrap().is_empty());
     }
 }
 
 #[cfg(test)]
-mod extraction_api {
+mod document_type_api {
     use crate::helpers::TestApp;
-    use harvex_services::ExtractionDao;
+
+    fn purchase_order() -> serde_json::Value {
+        serde_json::json!({
+            "name": "purchase_order",
+            "label": "purchase order",
+            "instructions": "PO numbers start with PO-",
+            "json_schema": {
+                "type": "object",
+                "properties": {"po_number": {"type": "string"}, "total": {"type": "number"}}
+            },
+            "keywords": ["purchase order"]
+        })
+    }
+
+    #[tokio::test]
+    async fn list_includes_builtin_types() {
+        let app = TestApp::new();
+        let (status, json) = app.get("/api/document-type").await;
+        assert_eq!(status, 200);
+
+        let names: Vec<&str> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert!(names.contains(&"invoice"));
+        assert!(names.contains(&"other"));
+    }
+
+    #[tokio::test]
+    async fn create_update_delete() {
+        let app = TestApp::new();
+        let (status, json) = app.post("/api/document-type", &purchase_order()).await;
+        assert_eq!(status, 200);
+        assert_eq!(json["priority"], 100);
+
+        let (status, _) = app.post("/api/document-type", &purchase_order()).await;
+        assert_eq!(status, 400);
+
+        let mut body = purchase_order();
+        body["label"] = "PO".into();
+        let (status, json) = app.put("/api/document-type/purchase_order", &body).await;
+        assert_eq!(status, 200);
+        assert_eq!(json["label"], "PO");
+
+        let (status, json) = app.get("/api/document-type/purchase_order").await;
+        assert_eq!(status, 200);
+        assert_eq!(json["builtin"], false);
+
+        let (status, _) = app.delete("/api/document-type/purchase_order").await;
+        assert_eq!(status, 200);
+        let (status, _) = app.get("/api/document-type/purchase_order").await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn invalid_schema_rejected() {
+        let app = TestApp::new();
+        let mut body = purchase_order();
+        body["json_schema"] = serde_json::json!({"type": "string"});
+        let (status, _) = app.post("/api/document-type", &body).await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn builtin_cannot_be_deleted() {
+        let app = TestApp::new();
+        let (status, _) = app.delete("/api/document-type/invoice").await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn reprocess_accepts_custom_type() {
+        let app = TestApp::new();
+        app.post("/api/document-type", &purchase_order()).await;
+        let (_, doc_id) = app
+            .upload_test_file("po.pdf", b"content", "Custom Type")
+            .await;
+
+        let (status, json) = app
+            .post(
+                &format!("/api/document/{doc_id}/reprocess"),
+                &serde_json::json!({"document_type": "purchase_order"}),
+            )
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(json["status"], "queued");
+    }
+}
+
+#[cfg(test)]
+mod extraction_api {
+    use crate::helpers::{failing_llm_server, page_result, TestApp};
+    use harvex_db::models::NewExtractionPage;
+    use harvex_services::{ExtractionDao, ExtractionPageDao};
 
     #[tokio::test]
     async fn list_extractions_empty() {
@@ -277,6 +750,74 @@ mod extraction_api {
             .await;
         assert_eq!(status, 404);
     }
+
+    #[tokio::test]
+    async fn list_extraction_pages() {
+        let app = TestApp::new();
+        let (batch_id, doc_id) = app
+            .upload_test_file("scan.pdf", b"content", "Pages")
+            .await;
+
+        let ext = ExtractionDao::create(
+            &app.db, &doc_id, &batch_id, "invoice",
+            None, None, 0.5, Some("vision-model"), 300,
+        )
+        .unwrap();
+        ExtractionPageDao::create(&app.db, &ext.id, &NewExtractionPage {
+            raw_output: Some("{}"),
+            latency_ms: 900,
+            ..page_result(1, "ok")
+        })
+        .unwrap();
+        ExtractionPageDao::create(&app.db, &ext.id, &NewExtractionPage {
+            error: Some("timeout"),
+            latency_ms: 30000,
+            attempts: 3,
+            ..page_result(2, "failed")
+        })
+        .unwrap();
+
+        let (status, json) = app
+            .get(&format!("/api/batch/{batch_id}/extraction/{}/pages", ext.id))
+            .await;
+        assert_eq!(status, 200);
+        let pages = json.as_array().unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1]["page_number"], 2);
+        assert_eq!(pages[1]["status"], "failed");
+        assert_eq!(pages[1]["error"], "timeout");
+
+        let (status, _) = app
+            .get(&format!("/api/batch/{batch_id}/extraction/nonexistent/pages"))
+            .await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn pages_of_a_failed_vision_extraction_are_listed() {
+        let api_url = failing_llm_server(500).await;
+        let app = TestApp::with_settings(|settings| {
+            settings.llm.api_url = api_url;
+            settings.llm.vision_model_name = "vision-model".into();
+        });
+        let (batch_id, _) = app.upload_test_file("scan.png", b"not really a png", "Failing").await;
+
+        let (status, _) = app.post(&format!("/api/batch/{batch_id}/process"), &serde_json::json!({})).await;
+        assert_eq!(status, 200);
+        app.wait_for_batch(&batch_id).await;
+
+        let (_, extractions) = app.get(&format!("/api/batch/{batch_id}/extraction")).await;
+        let extraction = &extractions[0];
+        assert!(extraction["structured_data"].is_null());
+        let (status, pages) = app
+            .get(&format!("/api/batch/{batch_id}/extraction/{}/pages", extraction["id"].as_str().unwrap()))
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(pages.as_array().unwrap().len(), 1);
+        assert_eq!(pages[0]["page_number"], 1);
+        assert_eq!(pages[0]["status"], "failed");
+        assert!(pages[0]["error"].as_str().unwrap().contains("500"));
+    }
 }
 
 #[cfg(test)]
@@ -343,6 +884,43 @@ mod export_api {
         assert!(csv.contains("confidence"));
     }
 
+    #[tokio::test]
+    async fn export_filtered_by_status() {
+        let app = TestApp::new();
+        let (batch_id, doc_id) = app
+            .upload_test_file("status.pdf", b"content", "Export Status")
+            .await;
+
+        let data = serde_json::json!({"amount": 10.0});
+        ExtractionDao::create(
+            &app.db, &doc_id, &batch_id, "invoice",
+            Some("text"), Some(&data), 0.9, None, 100,
+        )
+        .unwrap();
+        let failed = ExtractionDao::create(
+            &app.db, &doc_id, &batch_id, "invoice",
+            Some("text"), None, 0.0, None, 100,
+        )
+        .unwrap();
+        ExtractionDao::mark_llm_failed(&app.db, &failed.id, "LLM API returned 503").unwrap();
+
+        let (status, body) = app
+            .request(
+                axum::http::Request::builder()
+                    .uri(&format!("/api/export/json/{batch_id}?status=llm_failed"))
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(status, 200);
+
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let extractions = json["extractions"].as_array().unwrap();
+        assert_eq!(extractions.len(), 1);
+        assert_eq!(extractions[0]["extraction_status"], "llm_failed");
+        assert_eq!(extractions[0]["llm_error"], "LLM API returned 503");
+    }
+
     #[tokio::test]
     async fn export_excel() {
         let app = TestApp::new();
@@ -401,6 +979,10 @@ mod model_api {
         assert!(json["context_size"].is_number());
         assert!(json["temperature"].is_number());
         assert!(json["max_tokens"].is_number());
+        assert_eq!(json["structured_output"], "json_object");
+        assert_eq!(json["structured_output_active"], "json_object");
+        assert_eq!(json["merge_mode"], "rules");
+        assert_eq!(json["tokenizer"], "heuristic");
     }
 
     #[tokio::test]
@@ -497,3 +1079,231 @@ mod batch_cascade_delete {
         assert!(exts.as_array().unwrap().is_empty());
     }
 }
+
+#[cfg(test)]
+mod ingest_api {
+    use crate::helpers::TestApp;
+    use harvex_config::{
+        ArchiveSettings, MailBatching, MailboxKind, MailboxSettings, WatchFolderSettings,
+    };
+    use harvex_services::ingest::{FolderWatcher, MailboxPoller};
+
+    fn message(subject: &str) -> String {
+        format!(
+            "From: billing@acme.example\r\nSubject: {subject}\r\nContent-Type: text/plain\r\n\r\nTotal: 42.00\r\n"
+        )
+    }
+
+    #[tokio::test]
+    async fn maildir_messages_go_into_a_daily_batch() {
+        let app = TestApp::new();
+        let maildir = tempfile::tempdir().unwrap();
+        for sub in ["new", "cur", "tmp"] {
+            std::fs::create_dir(maildir.path().join(sub)).unwrap();
+        }
+        std::fs::write(maildir.path().join("new/1.a.host"), message("Invoice 1")).unwrap();
+        std::fs::write(maildir.path().join("new/2.b.host"), message("Invoice 2")).unwrap();
+
+        let mailbox = MailboxSettings {
+            name: "ap".into(),
+            kind: MailboxKind::Maildir,
+            path: maildir.path().to_string_lossy().to_string(),
+            batching: MailBatching::Daily,
+            ..MailboxSettings::default()
+        };
+        let poller = MailboxPoller::new(
+            app.db.clone(),
+            app.pipeline.clone(),
+            mailbox,
+            app.upload_dir.path().to_path_buf(),
+            10 * 1024 * 1024,
+        );
+        assert_eq!(poller.poll().await.unwrap(), 2);
+        assert_eq!(poller.poll().await.unwrap(), 0);
+
+        assert!(maildir.path().join("cur/1.a.host:2,S").exists());
+        assert!(maildir.path().join("cur/2.b.host:2,S").exists());
+
+        let (_, sources) = app.get("/api/ingest/sources").await;
+        let source = &sources[0];
+        assert_eq!(source["name"], "ap");
+        assert_eq!(source["kind"], "maildir");
+        assert_eq!(source["items_ingested"], 2);
+        assert!(source["last_error"].is_null());
+
+        let batch_id = source["batch_id"].as_str().unwrap();
+        let (_, batch) = app.get(&format!("/api/batch/{batch_id}")).await;
+        assert_eq!(batch["total_files"], 2);
+        let (_, docs) = app.get(&format!("/api/document?batch_id={batch_id}")).await;
+        let mut names: Vec<_> = docs
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["original_name"].as_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["Invoice 1.eml", "Invoice 2.eml"]);
+        assert_eq!(docs[0]["email"]["from"], "billing@acme.example");
+    }
+
+    #[tokio::test]
+    async fn messages_over_the_size_limit_are_skipped() {
+        let app = TestApp::new();
+        let maildir = tempfile::tempdir().unwrap();
+        for sub in ["new", "cur", "tmp"] {
+            std::fs::create_dir(maildir.path().join(sub)).unwrap();
+        }
+        std::fs::write(maildir.path().join("new/1.a.host"), message("Invoice 1")).unwrap();
+        std::fs::write(maildir.path().join("new/2.b.host"), message(&"x".repeat(200))).unwrap();
+
+        let mailbox = MailboxSettings {
+            name: "ap".into(),
+            kind: MailboxKind::Maildir,
+            path: maildir.path().to_string_lossy().to_string(),
+            ..MailboxSettings::default()
+        };
+        let poller = MailboxPoller::new(
+            app.db.clone(),
+            app.pipeline.clone(),
+            mailbox,
+            app.upload_dir.path().to_path_buf(),
+            200,
+        );
+        assert_eq!(poller.poll().await.unwrap(), 1);
+        // Not read again
+        assert!(maildir.path().join("cur/2.b.host:2,S").exists());
+
+        let (_, sources) = app.get("/api/ingest/sources").await;
+        assert_eq!(sources[0]["items_ingested"], 1);
+        assert!(sources[0]["last_error"].is_null());
+    }
+
+    #[tokio::test]
+    async fn failed_poll_is_recorded() {
+        let app = TestApp::new();
+        let mailbox = MailboxSettings {
+            name: "missing".into(),
+            kind: MailboxKind::Maildir,
+            path: app.upload_dir.path().join("nowhere").to_string_lossy().to_string(),
+            ..MailboxSettings::default()
+        };
+        let poller = MailboxPoller::new(
+            app.db.clone(),
+            app.pipeline.clone(),
+            mailbox,
+            app.upload_dir.path().to_path_buf(),
+            10 * 1024 * 1024,
+        );
+        assert!(poller.poll().await.is_err());
+
+        let (_, sources) = app.get("/api/ingest/sources").await;
+        assert_eq!(sources[0]["items_ingested"], 0);
+        assert!(sources[0]["last_error"].as_str().unwrap().contains("not a maildir"));
+    }
+
+    #[tokio::test]
+    async fn watched_folder_files_are_batched_by_folder_and_archived() {
+        let app = TestApp::new();
+        let watched = tempfile::tempdir().unwrap();
+        let root = watched.path();
+        std::fs::create_dir(root.join("march")).unwrap();
+        std::fs::write(root.join("scan-1.pdf"), "%PDF-1.4 one").unwrap();
+        std::fs::write(root.join("march/a.pdf"), "%PDF-1.4 a").unwrap();
+        std::fs::write(root.join("march/b.pdf"), "%PDF-1.4 b").unwrap();
+        std::fs::write(root.join("notes.xyz"), "not a document").unwrap();
+        std::fs::write(root.join("huge.pdf"), "%PDF-1.4 ".repeat(200)).unwrap();
+
+        let folder = WatchFolderSettings {
+            name: "scanner".into(),
+            path: root.to_string_lossy().to_string(),
+            stable_secs: 0,
+            ..WatchFolderSettings::default()
+        };
+        let mut watcher = FolderWatcher::new(
+            app.db.clone(),
+            app.pipeline.clone(),
+            folder,
+            app.upload_dir.path().to_path_buf(),
+            ArchiveSettings::default(),
+            1024,
+        );
+        assert_eq!(watcher.scan().unwrap(), 3);
+        // Archived originals are not taken again
+        assert_eq!(watcher.scan().unwrap(), 0);
+
+        assert!(root.join("archive/scan-1.pdf").exists());
+        assert!(root.join("archive/march/a.pdf").exists());
+        assert!(root.join("archive/march/b.pdf").exists());
+        assert!(root.join("failed/notes.xyz").exists());
+        assert!(root.join("failed/huge.pdf").exists());
+        assert!(!root.join("march/a.pdf").exists());
+
+        let (_, batches) = app.get("/api/batch").await;
+        let batch = |name: &str| {
+            batches
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|b| b["name"] == name)
+                .cloned()
+                .unwrap()
+        };
+        assert_eq!(batch("scanner")["total_files"], 1);
+        let march = batch("scanner: march");
+        assert_eq!(march["total_files"], 2);
+
+        let (_, docs) = app
+            .get(&format!("/api/document?batch_id={}", march["id"].as_str().unwrap()))
+            .await;
+        let mut names: Vec<_> = docs
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["original_name"].as_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["march/a.pdf", "march/b.pdf"]);
+
+        let (_, sources) = app.get("/api/ingest/sources").await;
+        assert_eq!(sources[0]["kind"], "folder");
+        assert_eq!(sources[0]["items_ingested"], 3);
+    }
+
+    #[tokio::test]
+    async fn folders_keep_their_batch_when_files_alternate_between_them() {
+        let app = TestApp::new();
+        let watched = tempfile::tempdir().unwrap();
+        let root = watched.path();
+        std::fs::create_dir(root.join("a")).unwrap();
+        std::fs::create_dir(root.join("b")).unwrap();
+
+        let folder = WatchFolderSettings {
+            name: "scanner".into(),
+            path: root.to_string_lossy().to_string(),
+            stable_secs: 0,
+            ..WatchFolderSettings::default()
+        };
+        let mut watcher = FolderWatcher::new(
+            app.db.clone(),
+            app.pipeline.clone(),
+            folder,
+            app.upload_dir.path().to_path_buf(),
+            ArchiveSettings::default(),
+            10 * 1024 * 1024,
+        );
+        for path in ["a/1.pdf", "b/2.pdf", "a/3.pdf"] {
+            std::fs::write(root.join(path), "%PDF-1.4").unwrap();
+            assert_eq!(watcher.scan().unwrap(), 1);
+        }
+
+        let (_, batches) = app.get("/api/batch").await;
+        let mut batches: Vec<_> = batches
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| (b["name"].as_str().unwrap().to_string(), b["total_files"].as_i64().unwrap()))
+            .collect();
+        batches.sort();
+        assert_eq!(batches, [("scanner: a".to_string(), 2), ("scanner: b".to_string(), 1)]);
+    }
+}
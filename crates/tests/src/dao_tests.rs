@@ -1,7 +1,7 @@
 #[cfg(test)]
 mod batch_dao {
     use harvex_db::DbPool;
-    use harvex_services::BatchDao;
+    use harvex_services::{BatchDao, DocumentDao};
 
     fn pool() -> DbPool {
         DbPool::new_in_memory().unwrap()
@@ -31,6 +31,24 @@ mod batch_dao {
         assert!(batch.model_name.is_none());
     }
 
+    #[test]
+    fn set_ocr_mode() {
+        let pool = pool();
+        let batch = BatchDao::create(&pool, "Hybrid", None).unwrap();
+        assert!(batch.ocr_mode.is_none());
+
+        BatchDao::set_ocr_mode(&pool, &batch.id, Some("hybrid")).unwrap();
+        let fetched = BatchDao::get_by_id(&pool, &batch.id).unwrap();
+        assert_eq!(fetched.ocr_mode.as_deref(), Some("hybrid"));
+
+        BatchDao::set_ocr_mode(&pool, &batch.id, None).unwrap();
+        let fetched = BatchDao::get_by_id(&pool, &batch.id).unwrap();
+        assert!(fetched.ocr_mode.is_none());
+
+        BatchDao::set_ocr_mode(&pool, &batch.id, Some("auto")).unwrap();
+        assert!(BatchDao::delete(&pool, &batch.id).unwrap());
+    }
+
     #[test]
     fn list_batches() {
         let pool = pool();
@@ -74,6 +92,41 @@ mod batch_dao {
         assert_eq!(updated.total_files, 10);
     }
 
+    #[test]
+    fn recount_progress() {
+        let pool = pool();
+        let batch = BatchDao::create(&pool, "Recount Test", None).unwrap();
+        let a = DocumentDao::create(&pool, &batch.id, "a", "a", "text/csv", 1, "/a").unwrap();
+        let b = DocumentDao::create(&pool, &batch.id, "b", "b", "text/csv", 1, "/b").unwrap();
+        DocumentDao::create(&pool, &batch.id, "c", "c", "text/csv", 1, "/c").unwrap();
+        DocumentDao::update_status(&pool, &a.id, "completed", None).unwrap();
+        DocumentDao::update_status(&pool, &b.id, "failed", Some("boom")).unwrap();
+
+        let (processed, failed) = BatchDao::recount_progress(&pool, &batch.id).unwrap();
+        assert_eq!((processed, failed), (1, 1));
+
+        let updated = BatchDao::get_by_id(&pool, &batch.id).unwrap();
+        assert_eq!(updated.processed_files, 1);
+        assert_eq!(updated.failed_files, 1);
+    }
+
+    #[test]
+    fn extracted_only_counts_as_processed() {
+        let pool = pool();
+        let batch = BatchDao::create(&pool, "Extracted Only", None).unwrap();
+        let a = DocumentDao::create(&pool, &batch.id, "a", "a", "text/csv", 1, "/a").unwrap();
+        let b = DocumentDao::create(&pool, &batch.id, "b", "b", "text/csv", 1, "/b").unwrap();
+        DocumentDao::update_status(&pool, &a.id, "completed", None).unwrap();
+        DocumentDao::update_status(&pool, &b.id, "extracted_only", Some("LLM API returned 503")).unwrap();
+
+        let (processed, failed) = BatchDao::recount_progress(&pool, &batch.id).unwrap();
+        assert_eq!((processed, failed), (2, 0));
+
+        let updated = BatchDao::get_by_id(&pool, &batch.id).unwrap();
+        assert_eq!(updated.extracted_only_files, 1);
+        assert_eq!(DocumentDao::count_by_status(&pool, &batch.id, "extracted_only").unwrap(), 1);
+    }
+
     #[test]
     fn delete_batch() {
         let pool = pool();
@@ -103,6 +156,7 @@ mod batch_dao {
 
 #[cfg(test)]
 mod document_dao {
+    use harvex_db::models::EmailMetadata;
     use harvex_db::DbPool;
     use harvex_services::{BatchDao, DocumentDao};
 
@@ -202,12 +256,42 @@ mod document_dao {
         assert_eq!(updated.status, "failed");
         assert_eq!(updated.error_message.as_deref(), Some("Parse error"));
     }
+
+    #[test]
+    fn set_source() {
+        let (pool, batch_id) = pool_with_batch();
+        let email = DocumentDao::create(&pool, &batch_id, "m.eml", "m.eml", "message/rfc822", 100, "/m").unwrap();
+        let attachment = DocumentDao::create(&pool, &batch_id, "i.pdf", "i.pdf", "application/pdf", 100, "/i").unwrap();
+        assert!(email.parent_id.is_none());
+        assert!(email.email.is_none());
+
+        let metadata = EmailMetadata {
+            from: Some("billing@acme.example".into()),
+            subject: Some("Invoice".into()),
+            date: None,
+        };
+        DocumentDao::set_source(&pool, &email.id, None, Some(&metadata)).unwrap();
+        DocumentDao::set_source(&pool, &attachment.id, Some(&email.id), Some(&metadata)).unwrap();
+
+        let fetched = DocumentDao::get_by_id(&pool, &attachment.id).unwrap();
+        assert_eq!(fetched.parent_id.as_deref(), Some(email.id.as_str()));
+        assert_eq!(fetched.email, Some(metadata));
+
+        DocumentDao::delete_by_batch(&pool, &batch_id).unwrap();
+        let conn = pool.conn();
+        let sources: i64 = conn
+            .query_row("SELECT COUNT(*) FROM document_sources", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(sources, 0);
+    }
 }
 
 #[cfg(test)]
 mod extraction_dao {
+    use crate::helpers::page_result;
+    use harvex_db::models::NewExtractionPage;
     use harvex_db::DbPool;
-    use harvex_services::{BatchDao, DocumentDao, ExtractionDao};
+    use harvex_services::{BatchDao, DocumentDao, ExtractionDao, ExtractionPageDao};
 
     fn pool_with_doc() -> (DbPool, String, String) {
         let pool = DbPool::new_in_memory().unwrap();
@@ -225,6 +309,36 @@ mod extraction_dao {
         (pool, batch.id, doc.id)
     }
 
+    #[test]
+    fn status_column_backfill_marks_extract_only_results() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let data = serde_json::json!({"total": 1.0});
+        let structured = ExtractionDao::create(
+            &pool, &doc_id, &batch_id, "invoice", Some("text"), Some(&data), 0.9, None, 10,
+        )
+        .unwrap();
+        let extract_only = ExtractionDao::create(
+            &pool, &doc_id, &batch_id, "invoice", Some("text"), None, 0.0, None, 10,
+        )
+        .unwrap();
+
+        DocumentDao::update_status(&pool, &doc_id, "completed", None).unwrap();
+
+        // A database from before extraction statuses existed
+        pool.conn()
+            .execute_batch("ALTER TABLE extractions DROP COLUMN status")
+            .unwrap();
+        harvex_db::migrations::run(&pool).unwrap();
+
+        let status = |id: &str| ExtractionDao::get_by_id(&pool, id).unwrap().status;
+        assert_eq!(status(&structured.id), "structured");
+        assert_eq!(status(&extract_only.id), "llm_failed");
+        assert_eq!(
+            DocumentDao::get_by_id(&pool, &doc_id).unwrap().status,
+            "extracted_only"
+        );
+    }
+
     #[test]
     fn create_and_get() {
         let (pool, batch_id, doc_id) = pool_with_doc();
@@ -285,7 +399,7 @@ mod extraction_dao {
         ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.9, None, 100).unwrap();
         ExtractionDao::create(&pool, &doc_id, &batch_id, "receipt", None, None, 0.7, None, 200).unwrap();
 
-        let invoices = ExtractionDao::list_by_batch_filtered(&pool, &batch_id, Some("invoice"), None).unwrap();
+        let invoices = ExtractionDao::list_by_batch_filtered(&pool, &batch_id, Some("invoice"), None, None).unwrap();
         assert_eq!(invoices.len(), 1);
         assert_eq!(invoices[0].document_type, "invoice");
     }
@@ -296,7 +410,7 @@ mod extraction_dao {
         ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.9, None, 100).unwrap();
         ExtractionDao::create(&pool, &doc_id, &batch_id, "receipt", None, None, 0.3, None, 200).unwrap();
 
-        let high_conf = ExtractionDao::list_by_batch_filtered(&pool, &batch_id, None, Some(0.5)).unwrap();
+        let high_conf = ExtractionDao::list_by_batch_filtered(&pool, &batch_id, None, Some(0.5), None).unwrap();
         assert_eq!(high_conf.len(), 1);
         assert!((high_conf[0].confidence - 0.9).abs() < 0.01);
     }
@@ -308,7 +422,7 @@ mod extraction_dao {
         ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.3, None, 200).unwrap();
         ExtractionDao::create(&pool, &doc_id, &batch_id, "receipt", None, None, 0.8, None, 150).unwrap();
 
-        let result = ExtractionDao::list_by_batch_filtered(&pool, &batch_id, Some("invoice"), Some(0.5)).unwrap();
+        let result = ExtractionDao::list_by_batch_filtered(&pool, &batch_id, Some("invoice"), Some(0.5), None).unwrap();
         assert_eq!(result.len(), 1);
         assert_eq!(result[0].document_type, "invoice");
         assert!(result[0].confidence >= 0.5);
@@ -345,4 +459,377 @@ mod extraction_dao {
         let exts = ExtractionDao::list_by_batch(&pool, &batch_id).unwrap();
         assert!(exts.is_empty());
     }
+
+    #[test]
+    fn mark_llm_failed_and_filter_by_status() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let data = serde_json::json!({"total": 1.0});
+        ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, Some(&data), 0.9, None, 100).unwrap();
+        let pending = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", Some("text"), None, 0.0, None, 100).unwrap();
+        assert_eq!(pending.status, "pending");
+
+        ExtractionDao::mark_llm_failed(&pool, &pending.id, "LLM API returned 503").unwrap();
+        let failed = ExtractionDao::get_by_id(&pool, &pending.id).unwrap();
+        assert_eq!(failed.status, "llm_failed");
+        assert_eq!(failed.llm_error.as_deref(), Some("LLM API returned 503"));
+
+        let structured =
+            ExtractionDao::list_by_batch_filtered(&pool, &batch_id, None, None, Some("structured")).unwrap();
+        assert_eq!(structured.len(), 1);
+        let llm_failed =
+            ExtractionDao::list_by_batch_filtered(&pool, &batch_id, None, None, Some("llm_failed")).unwrap();
+        assert_eq!(llm_failed.len(), 1);
+        assert_eq!(llm_failed[0].id, pending.id);
+    }
+
+    #[test]
+    fn set_llm_attempts() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let ext = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.0, None, 0).unwrap();
+        assert_eq!(ext.llm_attempts, 0);
+
+        ExtractionDao::set_llm_attempts(&pool, &ext.id, 3).unwrap();
+        let fetched = ExtractionDao::get_by_id(&pool, &ext.id).unwrap();
+        assert_eq!(fetched.llm_attempts, 3);
+    }
+
+    #[test]
+    fn set_usage() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let ext = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.0, None, 0).unwrap();
+        assert_eq!((ext.prompt_tokens, ext.completion_tokens), (0, 0));
+        assert_eq!(ext.cost_usd, 0.0);
+
+        ExtractionDao::set_usage(&pool, &ext.id, 1200, 350, 0.0025).unwrap();
+        let fetched = ExtractionDao::get_by_id(&pool, &ext.id).unwrap();
+        assert_eq!((fetched.prompt_tokens, fetched.completion_tokens), (1200, 350));
+        assert_eq!(fetched.cost_usd, 0.0025);
+    }
+
+    #[test]
+    fn set_partial() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let ext = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.0, None, 0).unwrap();
+        assert!(!ext.partial);
+        assert!(ext.skipped_pages.is_empty());
+
+        ExtractionDao::set_partial(&pool, &ext.id, &[21, 22, 23]).unwrap();
+        let fetched = ExtractionDao::get_by_id(&pool, &ext.id).unwrap();
+        assert!(fetched.partial);
+        assert_eq!(fetched.skipped_pages, [21, 22, 23]);
+    }
+
+    #[test]
+    fn batch_sums_usage_of_all_extractions() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let first = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.0, None, 0).unwrap();
+        ExtractionDao::set_usage(&pool, &first.id, 1000, 200, 0.5).unwrap();
+        let second = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.0, None, 0).unwrap();
+        ExtractionDao::set_usage(&pool, &second.id, 500, 100, 0.25).unwrap();
+        ExtractionDao::supersede_older(&pool, &doc_id).unwrap();
+
+        let batch = BatchDao::get_by_id(&pool, &batch_id).unwrap();
+        assert_eq!((batch.prompt_tokens, batch.completion_tokens), (1500, 300));
+        assert_eq!(batch.cost_usd, 0.75);
+    }
+
+    #[test]
+    fn set_validation() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let ext = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.0, None, 0).unwrap();
+        assert!(ext.validation.is_none());
+
+        let report = serde_json::json!({
+            "valid": false,
+            "errors": [{"path": "$.total_amount", "message": "required field is missing"}],
+            "repair_attempts": 2
+        });
+        ExtractionDao::set_validation(&pool, &ext.id, &report).unwrap();
+        let fetched = ExtractionDao::get_by_id(&pool, &ext.id).unwrap();
+        assert_eq!(fetched.validation, Some(report));
+    }
+
+    #[test]
+    fn supersede_older_hides_previous_extraction() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let old = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.5, None, 100).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let new = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.9, None, 100).unwrap();
+
+        let superseded = ExtractionDao::supersede_older(&pool, &doc_id).unwrap();
+        assert_eq!(superseded, 1);
+
+        let exts = ExtractionDao::list_by_batch(&pool, &batch_id).unwrap();
+        assert_eq!(exts.len(), 1);
+        assert_eq!(exts[0].id, new.id);
+
+        let history = ExtractionDao::list_by_document(&pool, &doc_id).unwrap();
+        assert_eq!(history.len(), 2);
+        let old = history.iter().find(|e| e.id == old.id).unwrap();
+        assert_eq!(old.superseded_by.as_deref(), Some(new.id.as_str()));
+    }
+
+    #[test]
+    fn pages_are_listed_in_order() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let ext = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.0, None, 0).unwrap();
+        ExtractionPageDao::create(&pool, &ext.id, &NewExtractionPage {
+            error: Some("LLM API returned 503"),
+            latency_ms: 1500,
+            attempts: 3,
+            ..page_result(2, "failed")
+        })
+        .unwrap();
+        ExtractionPageDao::create(&pool, &ext.id, &NewExtractionPage {
+            raw_output: Some("{}"),
+            latency_ms: 800,
+            ..page_result(1, "ok")
+        })
+        .unwrap();
+
+        let pages = ExtractionPageDao::list_by_extraction(&pool, &ext.id).unwrap();
+        assert_eq!(pages.iter().map(|p| p.page_number).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(pages[1].status, "failed");
+        assert_eq!(pages[1].error.as_deref(), Some("LLM API returned 503"));
+        assert_eq!((pages[1].latency_ms, pages[1].attempts), (1500, 3));
+        assert_eq!(pages[0].raw_output.as_deref(), Some("{}"));
+    }
+
+    #[test]
+    fn delete_by_batch_removes_pages() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let ext = ExtractionDao::create(&pool, &doc_id, &batch_id, "invoice", None, None, 0.0, None, 0).unwrap();
+        ExtractionPageDao::create(&pool, &ext.id, &page_result(1, "ok")).unwrap();
+
+        ExtractionDao::delete_by_batch(&pool, &batch_id).unwrap();
+        assert!(ExtractionPageDao::list_by_extraction(&pool, &ext.id).unwrap().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod job_dao {
+    use harvex_db::DbPool;
+    use harvex_services::{BatchDao, DocumentDao, JobDao};
+
+    fn pool_with_doc() -> (DbPool, String, String) {
+        let pool = DbPool::new_in_memory().unwrap();
+        let batch = BatchDao::create(&pool, "Jobs", None).unwrap();
+        let doc = DocumentDao::create(
+            &pool,
+            &batch.id,
+            "job.pdf",
+            "job.pdf",
+            "application/pdf",
+            100,
+            "/job",
+        )
+        .unwrap();
+        (pool, batch.id, doc.id)
+    }
+
+    #[test]
+    fn enqueue_is_idempotent_while_open() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let first = JobDao::enqueue(&pool, &batch_id, &doc_id, 3).unwrap();
+        let second = JobDao::enqueue(&pool, &batch_id, &doc_id, 3).unwrap();
+
+        assert_eq!(first.id, second.id);
+        assert_eq!(first.status, "queued");
+        assert_eq!(first.attempts, 0);
+        assert_eq!(JobDao::count_open(&pool, &batch_id).unwrap(), 1);
+    }
+
+    #[test]
+    fn enqueue_with_overrides() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        let job = JobDao::enqueue_with(&pool, &batch_id, &doc_id, 3, Some("other-model"), Some("receipt"))
+            .unwrap();
+
+        assert_eq!(job.model_override.as_deref(), Some("other-model"));
+        assert_eq!(job.document_type_override.as_deref(), Some("receipt"));
+
+        let plain = JobDao::enqueue(&pool, &batch_id, &doc_id, 3).unwrap();
+        assert_eq!(plain.id, job.id);
+    }
+
+    #[test]
+    fn lease_and_complete() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        JobDao::enqueue(&pool, &batch_id, &doc_id, 3).unwrap();
+
+        let job = JobDao::lease_next(&pool, &batch_id, 60).unwrap().unwrap();
+        assert_eq!(job.status, "leased");
+        assert_eq!(job.attempts, 1);
+        assert!(job.lease_expires_at.is_some());
+
+        // Nothing else queued
+        assert!(JobDao::lease_next(&pool, &batch_id, 60).unwrap().is_none());
+
+        JobDao::complete(&pool, &job.id).unwrap();
+        let done = JobDao::get_by_id(&pool, &job.id).unwrap();
+        assert_eq!(done.status, "done");
+        assert_eq!(JobDao::count_open(&pool, &batch_id).unwrap(), 0);
+    }
+
+    #[test]
+    fn fail_requeues_until_attempts_exhausted() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        JobDao::enqueue(&pool, &batch_id, &doc_id, 2).unwrap();
+
+        let job = JobDao::lease_next(&pool, &batch_id, 60).unwrap().unwrap();
+        assert!(JobDao::fail(&pool, &job.id, "first").unwrap());
+
+        let job = JobDao::lease_next(&pool, &batch_id, 60).unwrap().unwrap();
+        assert_eq!(job.attempts, 2);
+        assert!(!JobDao::fail(&pool, &job.id, "second").unwrap());
+
+        let failed = JobDao::get_by_id(&pool, &job.id).unwrap();
+        assert_eq!(failed.status, "failed");
+        assert_eq!(failed.last_error.as_deref(), Some("second"));
+        assert!(JobDao::lease_next(&pool, &batch_id, 60).unwrap().is_none());
+    }
+
+    #[test]
+    fn expired_lease_is_requeued() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        JobDao::enqueue(&pool, &batch_id, &doc_id, 3).unwrap();
+        JobDao::lease_next(&pool, &batch_id, 0).unwrap().unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(JobDao::requeue_expired(&pool).unwrap(), 1);
+
+        let job = JobDao::lease_next(&pool, &batch_id, 60).unwrap().unwrap();
+        assert_eq!(job.attempts, 2);
+    }
+
+    #[test]
+    fn release_all_leases_resumes_batch() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        JobDao::enqueue(&pool, &batch_id, &doc_id, 3).unwrap();
+        JobDao::lease_next(&pool, &batch_id, 3600).unwrap().unwrap();
+
+        assert_eq!(JobDao::release_all_leases(&pool).unwrap(), 1);
+        assert_eq!(
+            JobDao::batches_with_open_jobs(&pool).unwrap(),
+            vec![batch_id.clone()]
+        );
+        assert!(JobDao::lease_next(&pool, &batch_id, 60).unwrap().is_some());
+    }
+
+    #[test]
+    fn delete_by_batch() {
+        let (pool, batch_id, doc_id) = pool_with_doc();
+        JobDao::enqueue(&pool, &batch_id, &doc_id, 3).unwrap();
+
+        assert_eq!(JobDao::delete_by_batch(&pool, &batch_id).unwrap(), 1);
+        assert!(JobDao::list_by_batch(&pool, &batch_id).unwrap().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod document_type_dao {
+    use harvex_db::DbPool;
+    use harvex_services::{DocumentTypeCatalog, DocumentTypeDao};
+
+    fn schema() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {"po_number": {"type": "string"}, "total": {"type": "number"}}
+        })
+    }
+
+    #[test]
+    fn create_get_update_delete() {
+        let pool = DbPool::new_in_memory().unwrap();
+        let keywords = vec!["purchase order".to_string()];
+        let created = DocumentTypeDao::create(
+            &pool, "purchase_order", "purchase order", Some("PO numbers start with PO-"),
+            &schema(), &keywords, 5,
+        )
+        .unwrap();
+
+        assert_eq!(created.name, "purchase_order");
+        assert_eq!(created.keywords, keywords);
+        assert_eq!(created.json_schema["properties"]["total"]["type"], "number");
+
+        let by_name = DocumentTypeDao::get_by_name(&pool, "purchase_order").unwrap().unwrap();
+        assert_eq!(by_name.id, created.id);
+        assert!(DocumentTypeDao::get_by_name(&pool, "missing").unwrap().is_none());
+
+        let updated = DocumentTypeDao::update(
+            &pool, &created.id, "PO", None, &schema(), &["p.o.".to_string()], 50,
+        )
+        .unwrap();
+        assert_eq!(updated.label, "PO");
+        assert!(updated.instructions.is_none());
+        assert_eq!(updated.priority, 50);
+
+        assert!(DocumentTypeDao::delete(&pool, &created.id).unwrap());
+        assert!(DocumentTypeDao::list(&pool).unwrap().is_empty());
+    }
+
+    #[test]
+    fn catalog_merges_stored_types() {
+        let pool = DbPool::new_in_memory().unwrap();
+        DocumentTypeDao::create(
+            &pool, "purchase_order", "purchase order", None, &schema(),
+            &["purchase order".to_string()], 5,
+        )
+        .unwrap();
+        DocumentTypeDao::create(
+            &pool, "invoice", "supplier invoice", None, &schema(), &["rechnung".to_string()], 10,
+        )
+        .unwrap();
+
+        let catalog = DocumentTypeCatalog::load(&pool).unwrap();
+        assert_eq!(catalog.classify("Purchase Order PO-1").name, "purchase_order");
+        assert_eq!(catalog.classify("Rechnung Nr. 5").name, "invoice");
+        assert_eq!(catalog.get("invoice").unwrap().label, "supplier invoice");
+        assert!(!catalog.get("invoice").unwrap().builtin);
+        assert!(catalog.get("receipt").unwrap().builtin);
+    }
+}
+
+#[cfg(test)]
+mod ingest_source_dao {
+    use harvex_db::DbPool;
+    use harvex_services::{BatchDao, IngestSourceDao};
+
+    #[test]
+    fn tracks_poll_state() {
+        let pool = DbPool::new_in_memory().unwrap();
+        let source = IngestSourceDao::get_or_create(&pool, "ap", "imap").unwrap();
+        assert_eq!(source.items_ingested, 0);
+        assert!(source.uid_validity.is_none());
+        assert!(source.last_poll_at.is_none());
+
+        IngestSourceDao::set_imap_position(&pool, "ap", 1700, 42).unwrap();
+        let batch = BatchDao::create(&pool, "ap 2024-03-05", None).unwrap();
+        IngestSourceDao::set_batch(&pool, "ap", &batch.id, "2024-03-05").unwrap();
+        IngestSourceDao::record_poll(&pool, "ap", 3, None).unwrap();
+        IngestSourceDao::record_poll(&pool, "ap", 0, Some("connection refused")).unwrap();
+
+        // Polling again keeps the state
+        let source = IngestSourceDao::get_or_create(&pool, "ap", "imap").unwrap();
+        assert_eq!(source.uid_validity, Some(1700));
+        assert_eq!(source.last_uid, Some(42));
+        assert_eq!(source.batch_id.as_deref(), Some(batch.id.as_str()));
+        assert_eq!(source.batch_key.as_deref(), Some("2024-03-05"));
+        assert_eq!(source.items_ingested, 3);
+        assert_eq!(source.last_error.as_deref(), Some("connection refused"));
+        assert!(source.last_poll_at.is_some());
+
+        // Earlier keys keep their batch
+        let other = BatchDao::create(&pool, "ap 2024-03-06", None).unwrap();
+        IngestSourceDao::set_batch(&pool, "ap", &other.id, "2024-03-06").unwrap();
+        assert_eq!(
+            IngestSourceDao::batch_for_key(&pool, "ap", "2024-03-05").unwrap().as_deref(),
+            Some(batch.id.as_str())
+        );
+        assert!(IngestSourceDao::batch_for_key(&pool, "ap", "2024-03-07").unwrap().is_none());
+
+        IngestSourceDao::get_or_create(&pool, "scans", "maildir").unwrap();
+        let names: Vec<_> = IngestSourceDao::list(&pool).unwrap().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["ap", "scans"]);
+    }
 }
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use axum::body::Body;
 use axum::Router;
 use http_body_util::BodyExt;
@@ -5,21 +7,29 @@ use tower::ServiceExt;
 
 use harvex_api::state::AppState;
 use harvex_config::*;
+use harvex_db::models::NewExtractionPage;
 use harvex_db::DbPool;
+use harvex_services::Pipeline;
 
 /// Test application fixture with in-memory database and temp upload directory.
 pub struct TestApp {
     pub router: Router,
     pub db: DbPool,
+    pub pipeline: Arc<Pipeline>,
     pub upload_dir: tempfile::TempDir,
 }
 
 impl TestApp {
     pub fn new() -> Self {
+        Self::with_settings(|_| {})
+    }
+
+    /// A test app with its settings changed by `configure` first.
+    pub fn with_settings(configure: impl FnOnce(&mut Settings)) -> Self {
         let db = DbPool::new_in_memory().expect("Failed to create in-memory DB");
         let upload_dir = tempfile::tempdir().expect("Failed to create temp dir");
 
-        let config = Settings {
+        let mut config = Settings {
             server: ServerSettings {
                 host: "127.0.0.1".into(),
                 port: 0,
@@ -30,8 +40,17 @@ impl TestApp {
             storage: StorageSettings {
                 upload_dir: upload_dir.path().to_string_lossy().to_string(),
                 max_file_size_mb: 10,
+                archive: ArchiveSettings::default(),
+            },
+            ingest: IngestSettings::default(),
+            processing: ProcessingSettings {
+                max_concurrent: 1,
+                job_lease_secs: 3600,
+                job_max_attempts: 3,
+                ocr: OcrSettings::default(),
+                image: ImageSettings::default(),
+                pdf_render: PdfRenderSettings::default(),
             },
-            processing: ProcessingSettings { max_concurrent: 1 },
             llm: LlmSettings {
                 api_url: "http://localhost:99999/v1".into(), // unreachable on purpose
                 api_key: String::new(),
@@ -42,19 +61,47 @@ impl TestApp {
                 vision_model_name: String::new(),
                 vision_dpi: 200,
                 vision_max_pages: 5,
+                vision_page_cap: 0,
+                retry: RetrySettings {
+                    max_attempts: 1,
+                    ..RetrySettings::default()
+                },
+                max_repair_attempts: 2,
+                structured_output: StructuredOutputMode::JsonObject,
+                chunk_overlap_chars: 400,
+                merge_mode: MergeMode::Rules,
+                tokenizer_path: String::new(),
+                pricing: Default::default(),
+                vision_page_concurrency: 2,
+                vision_max_concurrent: 4,
             },
         };
 
+        configure(&mut config);
         let state = AppState::new(config, db.clone());
+        let pipeline = state.pipeline.clone();
         let router = harvex_api::build_router(state);
 
         Self {
             router,
             db,
+            pipeline,
             upload_dir,
         }
     }
 
+    /// Wait for a batch's worker to finish, and return the batch.
+    pub async fn wait_for_batch(&self, batch_id: &str) -> serde_json::Value {
+        for _ in 0..300 {
+            let (_, batch) = self.get(&format!("/api/batch/{batch_id}")).await;
+            if !matches!(batch["status"].as_str(), Some("pending" | "processing")) {
+                return batch;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("Batch {batch_id} did not finish");
+    }
+
     /// Send a request and return (status_code, response_bytes).
     pub async fn request(&self, req: axum::http::Request<Body>) -> (u16, Vec<u8>) {
         let response = self
@@ -107,6 +154,17 @@ impl TestApp {
         self.json_request(req).await
     }
 
+    /// Helper: PUT request with JSON body.
+    pub async fn put(&self, path: &str, body: &serde_json::Value) -> (u16, serde_json::Value) {
+        let req = axum::http::Request::builder()
+            .method("PUT")
+            .uri(path)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(body).unwrap()))
+            .unwrap();
+        self.json_request(req).await
+    }
+
     /// Helper: DELETE request returning JSON.
     pub async fn delete(&self, path: &str) -> (u16, serde_json::Value) {
         let req = axum::http::Request::builder()
@@ -180,3 +238,57 @@ impl TestApp {
         (batch_id, doc_id)
     }
 }
+
+/// An OpenAI-compatible API stand-in that answers every request with
+/// `status`. Returns its base URL.
+pub async fn failing_llm_server(status: u16) -> String {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut socket = BufReader::new(socket);
+                // Read the whole request before answering
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    if socket.read_line(&mut line).await.unwrap_or(0) == 0 {
+                        return;
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':')
+                        && name.eq_ignore_ascii_case("content-length")
+                    {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+                let mut body = vec![0; content_length];
+                let _ = socket.read_exact(&mut body).await;
+
+                let reply = r#"{"error":"model crashed"}"#;
+                let response = format!(
+                    "HTTP/1.1 {status} Error\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{reply}",
+                    reply.len()
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    format!("http://127.0.0.1:{port}/v1")
+}
+
+/// A vision page outcome with `status` and no error, output or latency.
+pub fn page_result(page_number: u32, status: &'static str) -> NewExtractionPage<'static> {
+    NewExtractionPage {
+        page_number,
+        status,
+        error: None,
+        raw_output: None,
+        latency_ms: 0,
+        attempts: 1,
+    }
+}
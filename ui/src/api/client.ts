@@ -12,6 +12,8 @@ async function request<T>(path: string, options?: RequestInit): Promise<T> {
   return res.json()
 }
 
+export type OcrMode = 'auto' | 'hybrid'
+
 export interface Batch {
   id: string
   name: string
@@ -19,7 +21,12 @@ export interface Batch {
   total_files: number
   processed_files: number
   failed_files: number
+  extracted_only_files: number
   model_name: string | null
+  ocr_mode: OcrMode | null
+  prompt_tokens: number
+  completion_tokens: number
+  cost_usd: number
   created_at: string
   updated_at: string
   completed_at: string | null
@@ -35,10 +42,18 @@ export interface Document {
   file_path: string
   status: string
   error_message: string | null
+  parent_id: string | null
+  email: EmailMetadata | null
   created_at: string
   updated_at: string
 }
 
+export interface EmailMetadata {
+  from: string | null
+  subject: string | null
+  date: string | null
+}
+
 export interface Extraction {
   id: string
   document_id: string
@@ -49,9 +64,42 @@ export interface Extraction {
   confidence: number
   model_used: string | null
   processing_time_ms: number
+  llm_attempts: number
+  status: string
+  llm_error: string | null
+  validation: ValidationReport | null
+  prompt_tokens: number
+  completion_tokens: number
+  cost_usd: number
+  partial: boolean
+  skipped_pages: number[]
   created_at: string
 }
 
+export interface ExtractionPage {
+  id: string
+  extraction_id: string
+  page_number: number
+  status: 'ok' | 'failed' | 'invalid_json'
+  error: string | null
+  raw_output: string | null
+  latency_ms: number
+  attempts: number
+  created_at: string
+}
+
+export interface ValidationIssue {
+  path: string
+  message: string
+}
+
+export interface ValidationReport {
+  valid: boolean
+  errors: ValidationIssue[]
+  repair_attempts: number
+  initial_errors?: ValidationIssue[]
+}
+
 export interface ProgressEvent {
   batch_id: string
   document_id: string
@@ -60,6 +108,7 @@ export interface ProgressEvent {
   message: string
   processed: number
   failed: number
+  extracted_only: number
   total: number
 }
 
@@ -82,10 +131,10 @@ export const api = {
 
   // Batches
   listBatches: () => request<Batch[]>('/api/batch'),
-  createBatch: (name: string, modelName?: string) =>
+  createBatch: (name: string, modelName?: string, ocrMode?: OcrMode) =>
     request<Batch>('/api/batch', {
       method: 'POST',
-      body: JSON.stringify({ name, model_name: modelName }),
+      body: JSON.stringify({ name, model_name: modelName, ocr_mode: ocrMode }),
     }),
   getBatch: (id: string) => request<Batch>(`/api/batch/${id}`),
   deleteBatch: (id: string) =>
@@ -102,13 +151,16 @@ export const api = {
   getDocument: (id: string) => request<Document>(`/api/document/${id}`),
   deleteDocument: (id: string) =>
     request<{ deleted: boolean; id: string }>(`/api/document/${id}`, { method: 'DELETE' }),
+  // Image as sent to the vision model (404 until the document was processed)
+  documentPreviewUrl: (id: string) => `/api/document/${id}/preview`,
 
   // Upload (multipart — no Content-Type header, browser sets boundary)
-  uploadFiles: async (files: File[], batchName?: string, modelName?: string) => {
+  uploadFiles: async (files: File[], batchName?: string, modelName?: string, ocrMode?: OcrMode) => {
     const form = new FormData()
     files.forEach((f) => form.append('files[]', f))
     if (batchName) form.append('batch_name', batchName)
     if (modelName) form.append('model_name', modelName)
+    if (ocrMode) form.append('ocr_mode', ocrMode)
     const res = await fetch('/api/document/upload', { method: 'POST', body: form })
     if (!res.ok) {
       const body = await res.json().catch(() => ({}))
@@ -122,6 +174,8 @@ export const api = {
     request<Extraction[]>(`/api/batch/${batchId}/extraction`),
   getExtraction: (batchId: string, extractionId: string) =>
     request<Extraction>(`/api/batch/${batchId}/extraction/${extractionId}`),
+  getExtractionPages: (batchId: string, extractionId: string) =>
+    request<ExtractionPage[]>(`/api/batch/${batchId}/extraction/${extractionId}/pages`),
 
   // Export URLs (for download links)
   exportJsonUrl: (batchId: string, filter?: { document_type?: string; min_confidence?: number }) => {
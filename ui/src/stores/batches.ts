@@ -88,6 +88,7 @@ export const useBatchesStore = defineStore('batches', () => {
         if (batch) {
           batch.processed_files = data.processed
           batch.failed_files = data.failed
+          batch.extracted_only_files = data.extracted_only
           batch.total_files = data.total
           if (data.status === 'completed' || data.status === 'failed' || data.status === 'partially_completed') {
             batch.status = data.status
@@ -98,6 +99,7 @@ export const useBatchesStore = defineStore('batches', () => {
         if (current.value?.id === batchId) {
           current.value.processed_files = data.processed
           current.value.failed_files = data.failed
+          current.value.extracted_only_files = data.extracted_only
           current.value.total_files = data.total
           if (data.status === 'completed' || data.status === 'failed' || data.status === 'partially_completed') {
             current.value.status = data.status